@@ -0,0 +1,214 @@
+//! Circuit breaker of the metadata hosts.
+//!
+//! When a gateway is down, each token fetched from it waits for its request
+//! to fail, dragging down the throughput of all the collections. After
+//! `failure_threshold` consecutive failures of a host, its circuit opens and
+//! the requests to the host fail fast during `cooldown`. The circuit is then
+//! half-open: a single request probes the host, closing the circuit if it
+//! succeeds, or opening it again for another cooldown if it fails.
+//!
+//! Sharing a `HostCircuitBreaker` between the `MetadataManager` instances
+//! shares the state of the hosts between them.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use url::Url;
+
+/// Default number of consecutive failures opening the circuit of a host.
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+/// Default time the requests to a host fail fast once its circuit is open.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive failures of a host opening its circuit (at least 1).
+    pub failure_threshold: u32,
+    /// Time the requests to a host fail fast once its circuit is open,
+    /// before probing the host again.
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            cooldown: DEFAULT_COOLDOWN,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CircuitState {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        since: Instant,
+    },
+    /// A probe request is in flight since the given instant.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+#[derive(Debug)]
+pub struct HostCircuitBreaker {
+    config: CircuitBreakerConfig,
+    hosts: Mutex<HashMap<String, CircuitState>>,
+}
+
+impl HostCircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config: CircuitBreakerConfig {
+                failure_threshold: config.failure_threshold.max(1),
+                ..config
+            },
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns true if a request can be sent to the host of the URL.
+    ///
+    /// Once the cooldown of an open circuit elapsed, only the first caller is
+    /// allowed to probe the host. A probe which never completes is replaced
+    /// by a new one after another cooldown. The URLs without host are always allowed.
+    pub fn allow_request(&self, url: &str) -> bool {
+        let host = match host_of(url) {
+            Some(host) => host,
+            None => return true,
+        };
+
+        let mut hosts = self.hosts.lock().unwrap();
+        match hosts.get(&host).copied() {
+            None | Some(CircuitState::Closed { .. }) => true,
+            Some(CircuitState::Open { since }) | Some(CircuitState::HalfOpen { since }) => {
+                if since.elapsed() < self.config.cooldown {
+                    return false;
+                }
+
+                info!("Probing metadata host {} with an half-open circuit", host);
+                hosts.insert(
+                    host,
+                    CircuitState::HalfOpen {
+                        since: Instant::now(),
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Closes the circuit of the host of the URL.
+    pub fn record_success(&self, url: &str) {
+        if let Some(host) = host_of(url) {
+            let mut hosts = self.hosts.lock().unwrap();
+            if let Some(CircuitState::HalfOpen { .. }) = hosts.get(&host) {
+                info!("Metadata host {} recovered, closing its circuit", host);
+            }
+            hosts.insert(host, CircuitState::Closed { failures: 0 });
+        }
+    }
+
+    /// Counts a failure of the host of the URL, opening its circuit once
+    /// the threshold is reached, or immediately if the host was probed.
+    pub fn record_failure(&self, url: &str) {
+        let host = match host_of(url) {
+            Some(host) => host,
+            None => return,
+        };
+
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = match hosts.get(&host).copied() {
+            None => CircuitState::Closed { failures: 1 },
+            Some(CircuitState::Closed { failures }) => CircuitState::Closed {
+                failures: failures + 1,
+            },
+            // A late failure of a request sent before the circuit opened.
+            Some(open @ CircuitState::Open { .. }) => open,
+            Some(CircuitState::HalfOpen { .. }) => CircuitState::Closed {
+                failures: self.config.failure_threshold,
+            },
+        };
+
+        let state = match state {
+            CircuitState::Closed { failures } if failures >= self.config.failure_threshold => {
+                warn!(
+                    "Metadata host {} failed {} times in a row, failing fast for {:?}",
+                    host, failures, self.config.cooldown
+                );
+                CircuitState::Open {
+                    since: Instant::now(),
+                }
+            }
+            state => state,
+        };
+
+        hosts.insert(host, state);
+    }
+}
+
+/// Returns the host of the URL, in lowercase.
+fn host_of(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()?
+        .host_str()
+        .map(|host| host.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://gateway.example/ipfs/cid/1.json";
+
+    fn breaker(cooldown: Duration) -> HostCircuitBreaker {
+        HostCircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            cooldown,
+        })
+    }
+
+    #[test]
+    fn test_circuit_opens_after_consecutive_failures() {
+        let breaker = breaker(Duration::from_secs(60));
+
+        breaker.record_failure(URL);
+        breaker.record_success(URL);
+        breaker.record_failure(URL);
+        assert!(breaker.allow_request(URL));
+
+        breaker.record_failure(URL);
+        assert!(!breaker.allow_request(URL));
+        assert!(!breaker.allow_request("https://GATEWAY.example/ipfs/cid/2.json"));
+
+        // The other hosts are not affected.
+        assert!(breaker.allow_request("https://other.example/1.json"));
+        assert!(breaker.allow_request("ipfs-without-host"));
+    }
+
+    #[test]
+    fn test_circuit_half_opens_after_cooldown() {
+        let breaker = breaker(Duration::from_millis(10));
+
+        breaker.record_failure(URL);
+        breaker.record_failure(URL);
+        assert!(!breaker.allow_request(URL));
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        // A single probe is allowed, and opens the circuit again on failure.
+        assert!(breaker.allow_request(URL));
+        assert!(!breaker.allow_request(URL));
+        breaker.record_failure(URL);
+        assert!(!breaker.allow_request(URL));
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(breaker.allow_request(URL));
+        breaker.record_success(URL);
+        assert!(breaker.allow_request(URL));
+        assert!(breaker.allow_request(URL));
+    }
+}
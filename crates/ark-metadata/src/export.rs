@@ -0,0 +1,190 @@
+//! Export of the metadata of a collection as newline-delimited JSON.
+//!
+//! Each line is a JSON object with a `type`: the `collection` metadata first,
+//! if any, then one `token` per token id, by ascending token id. The token
+//! ids are read by pages, so the memory used doesn't grow with the collection.
+use crate::storage::{Storage, DEFAULT_TOKEN_PAGE_SIZE};
+use crate::types::TokenMetadata;
+use anyhow::Result;
+use ark_starknet::CairoU256;
+use serde_json::{json, Value};
+use starknet::core::types::FieldElement;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::info;
+
+/// Writes the collection metadata and the metadata of its tokens to `writer`,
+/// one JSON record per line. The tokens keep both their normalized and raw
+/// metadata, the tokens without metadata yet being exported without them.
+/// The offloaded raw metadata are not read, only their `raw_metadata_key`
+/// is exported.
+///
+/// Returns the number of exported tokens.
+pub async fn export_collection<S: Storage, W: AsyncWrite + Unpin>(
+    storage: &S,
+    contract_address: FieldElement,
+    writer: &mut W,
+) -> Result<u64> {
+    let address = format!("0x{:064x}", contract_address);
+
+    if let Some(collection) = storage.get_collection_metadata(contract_address).await? {
+        let record = json!({
+            "type": "collection",
+            "contract_address": address,
+            "metadata": collection.normalized,
+            "raw_metadata": raw_json(&collection.raw),
+            "metadata_updated_at": collection.metadata_updated_at,
+        });
+        write_record(writer, &record).await?;
+    }
+
+    let mut exported = 0;
+    let mut last_evaluated_key = None;
+
+    loop {
+        let page = storage
+            .find_token_ids(
+                contract_address,
+                last_evaluated_key,
+                DEFAULT_TOKEN_PAGE_SIZE,
+            )
+            .await?;
+
+        for token_id in page.token_ids {
+            let metadata = storage
+                .get_token_metadata(contract_address, token_id.clone())
+                .await?;
+
+            write_record(writer, &token_record(&address, &token_id, metadata)).await?;
+            exported += 1;
+        }
+
+        last_evaluated_key = page.last_evaluated_key;
+        if last_evaluated_key.is_none() {
+            break;
+        }
+    }
+
+    writer.flush().await?;
+
+    info!("Exported {} tokens of {}", exported, address);
+
+    Ok(exported)
+}
+
+fn token_record(address: &str, token_id: &CairoU256, metadata: Option<TokenMetadata>) -> Value {
+    let (normalized, raw, raw_key, updated_at) = match metadata {
+        Some(m) => {
+            let raw = match (m.raw_key.is_some(), m.raw_value) {
+                (true, _) => Value::Null,
+                (false, Some(value)) => value,
+                (false, None) => raw_json(&m.raw),
+            };
+            (Some(m.normalized), raw, m.raw_key, m.metadata_updated_at)
+        }
+        None => (None, Value::Null, None, None),
+    };
+
+    json!({
+        "type": "token",
+        "contract_address": address,
+        "token_id": token_id.to_decimal(false),
+        "token_id_hex": token_id.to_hex(),
+        "metadata": normalized,
+        "raw_metadata": raw,
+        "raw_metadata_key": raw_key,
+        "metadata_updated_at": updated_at,
+    })
+}
+
+/// Returns the raw metadata as JSON, or as a string if it's not valid JSON.
+fn raw_json(raw: &str) -> Value {
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+async fn write_record<W: AsyncWrite + Unpin>(writer: &mut W, record: &Value) -> Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MockStorage;
+    use crate::types::{
+        CollectionMetadata, NormalizedCollectionMetadata, NormalizedMetadata, TokenIdsPage,
+    };
+
+    #[tokio::test]
+    async fn test_export_collection() {
+        let mut mock_storage = MockStorage::default();
+
+        mock_storage
+            .expect_get_collection_metadata()
+            .returning(|_| {
+                Ok(Some(CollectionMetadata {
+                    normalized: NormalizedCollectionMetadata {
+                        name: Some("Ducks".to_string()),
+                        ..Default::default()
+                    },
+                    raw: r#"{"name":"Ducks"}"#.to_string(),
+                    metadata_updated_at: None,
+                }))
+            });
+        mock_storage.expect_find_token_ids().returning(|_, _, _| {
+            Ok(TokenIdsPage {
+                token_ids: (1..=3).map(|low| CairoU256 { low, high: 0 }).collect(),
+                last_evaluated_key: None,
+            })
+        });
+        // The token 2 has no metadata yet, and the raw metadata of the
+        // token 3 are offloaded.
+        mock_storage
+            .expect_get_token_metadata()
+            .returning(|_, token_id| {
+                Ok(match token_id.low {
+                    1 => Some(TokenMetadata {
+                        normalized: NormalizedMetadata {
+                            name: Some("Duck #1".to_string()),
+                            ..Default::default()
+                        },
+                        raw: r#"{"name":"Duck #1","custom":true}"#.to_string(),
+                        ..Default::default()
+                    }),
+                    3 => Some(TokenMetadata {
+                        raw_key: Some("raw_metadata/3.json.gz".to_string()),
+                        ..Default::default()
+                    }),
+                    _ => None,
+                })
+            });
+
+        let mut output = Vec::new();
+        let exported = export_collection(&mock_storage, FieldElement::ONE, &mut output)
+            .await
+            .unwrap();
+        assert_eq!(exported, 3);
+
+        let records: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 4);
+
+        assert_eq!(records[0]["type"], "collection");
+        assert_eq!(records[0]["metadata"]["name"], "Ducks");
+
+        assert_eq!(records[1]["type"], "token");
+        assert_eq!(records[1]["token_id"], "1");
+        assert_eq!(records[1]["metadata"]["name"], "Duck #1");
+        assert_eq!(records[1]["raw_metadata"]["custom"], true);
+
+        assert_eq!(records[2]["token_id"], "2");
+        assert!(records[2]["metadata"].is_null());
+
+        assert!(records[3]["raw_metadata"].is_null());
+        assert_eq!(records[3]["raw_metadata_key"], "raw_metadata/3.json.gz");
+    }
+}
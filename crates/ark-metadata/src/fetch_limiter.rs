@@ -0,0 +1,94 @@
+//! Limits the number of concurrent metadata fetches per collection.
+//!
+//! A single collection minting a lot of tokens can monopolize the
+//! fetch budget, starving the other collections indexed at the same time.
+//! Sharing a `CollectionFetchLimiter` between the `MetadataManager`
+//! instances ensures each collection has its own concurrency cap.
+use starknet::core::types::FieldElement;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[derive(Debug)]
+pub struct CollectionFetchLimiter {
+    max_per_collection: usize,
+    semaphores: Mutex<HashMap<FieldElement, Arc<Semaphore>>>,
+}
+
+impl CollectionFetchLimiter {
+    /// Creates a new limiter allowing at most `max_per_collection`
+    /// concurrent fetches for each collection (at least 1).
+    pub fn new(max_per_collection: usize) -> Self {
+        Self {
+            max_per_collection: max_per_collection.max(1),
+            semaphores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits for a fetch slot of the given collection.
+    /// The slot is released when the returned permit is dropped.
+    pub async fn acquire(&self, contract_address: FieldElement) -> OwnedSemaphorePermit {
+        let semaphore = {
+            let mut semaphores = self.semaphores.lock().unwrap();
+            Arc::clone(
+                semaphores
+                    .entry(contract_address)
+                    .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_collection))),
+            )
+        };
+
+        // Safe to unwrap, the semaphore is never closed.
+        semaphore.acquire_owned().await.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_collections_fetch_cap() {
+        let limiter = Arc::new(CollectionFetchLimiter::new(2));
+        let collections = [FieldElement::ONE, FieldElement::TWO];
+
+        let in_flight: Arc<Vec<AtomicUsize>> =
+            Arc::new(vec![AtomicUsize::new(0), AtomicUsize::new(0)]);
+        let max_in_flight: Arc<Vec<AtomicUsize>> =
+            Arc::new(vec![AtomicUsize::new(0), AtomicUsize::new(0)]);
+        let done: Arc<Vec<AtomicUsize>> = Arc::new(vec![AtomicUsize::new(0), AtomicUsize::new(0)]);
+
+        let mut handles = vec![];
+        for _ in 0..10 {
+            for (i, collection) in collections.iter().enumerate() {
+                let limiter = Arc::clone(&limiter);
+                let in_flight = Arc::clone(&in_flight);
+                let max_in_flight = Arc::clone(&max_in_flight);
+                let done = Arc::clone(&done);
+                let collection = *collection;
+
+                handles.push(tokio::spawn(async move {
+                    let _permit = limiter.acquire(collection).await;
+
+                    let current = in_flight[i].fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight[i].fetch_max(current, Ordering::SeqCst);
+
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+
+                    in_flight[i].fetch_sub(1, Ordering::SeqCst);
+                    done[i].fetch_add(1, Ordering::SeqCst);
+                }));
+            }
+        }
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        for i in 0..collections.len() {
+            assert!(max_in_flight[i].load(Ordering::SeqCst) <= 2);
+            assert_eq!(done[i].load(Ordering::SeqCst), 10);
+        }
+    }
+}
@@ -1,11 +1,11 @@
 /// Provides file management capabilities.
 ///
-/// This module offers functionality to save files.
+/// This module offers functionality to save and read files.
 /// You may choose to implement the `FileManager` trait
 /// to save files remotely (i.g. AWS S3).
-use std::fs::{create_dir_all, File};
+use std::fs::{create_dir_all, metadata, read, File};
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, Context, Ok, Result};
 use async_trait::async_trait;
@@ -32,20 +32,40 @@ pub trait FileManager {
     /// Save the provided file.
     ///
     /// Implementors will provide the logic to save `file` and will return a `Result`.
+    /// A file already saved with the same content is not written again.
     async fn save(&self, file: &FileInfo) -> Result<String>;
+
+    /// Returns true if `file` is already saved under its key with the same content.
+    async fn exists(&self, file: &FileInfo) -> Result<bool>;
+
+    /// Reads the content of the file saved under `key`, as returned by `save`.
+    async fn read(&self, key: &str) -> Result<Vec<u8>>;
 }
 
 /// FileManager implementation that saves files locally.
 #[derive(Default)]
 pub struct LocalFileManager;
 
+impl LocalFileManager {
+    fn path(file: &FileInfo) -> PathBuf {
+        let dir_path = file.dir_path.clone().unwrap_or_else(|| "./tmp".into());
+        Path::new("images").join(dir_path.as_str()).join(&file.name)
+    }
+}
+
 #[async_trait]
 impl FileManager for LocalFileManager {
     async fn save(&self, file: &FileInfo) -> Result<String> {
-        let dir_path = file.dir_path.clone().unwrap_or_else(|| "./tmp".into());
+        let path = Self::path(file);
+        let path_str = path
+            .to_str()
+            .ok_or_else(|| anyhow!("Failed to convert path to string"))?
+            .to_string();
 
-        // Construct the path
-        let path = Path::new("images").join(dir_path.as_str()).join(&file.name);
+        if self.exists(file).await? {
+            info!("File unchanged, not saved again: {}", file.name);
+            return Ok(path_str);
+        }
 
         // Ensure directory exists
         create_dir_all(path.parent().unwrap()).context("Failed to create directory")?;
@@ -59,13 +79,29 @@ impl FileManager for LocalFileManager {
 
         info!("File saved: {}", file.name);
 
-        let path_str = path
-            .to_str()
-            .ok_or_else(|| anyhow!("Failed to convert path to string"))?
-            .to_string();
-
         Ok(path_str)
     }
+
+    async fn exists(&self, file: &FileInfo) -> Result<bool> {
+        let path = Self::path(file);
+        if !path.is_file() {
+            return Ok(false);
+        }
+
+        // The size is compared first to not read the files which changed.
+        let size = metadata(&path)
+            .context("Failed to read file metadata")?
+            .len();
+        if size != file.content.len() as u64 {
+            return Ok(false);
+        }
+
+        Ok(read(&path).context("Failed to read file")? == file.content)
+    }
+
+    async fn read(&self, key: &str) -> Result<Vec<u8>> {
+        read(key).with_context(|| format!("Failed to read file {}", key))
+    }
 }
 
 #[cfg(test)]
@@ -118,4 +154,48 @@ mod tests {
         fs::remove_file("./images/tmp/test_file.txt").unwrap();
         fs::remove_dir("./images/tmp").unwrap();
     }
+
+    #[tokio::test]
+    async fn test_local_file_exists() {
+        let mut file_info = FileInfo {
+            name: "test_file.txt".to_string(),
+            content: b"Hello, world!".to_vec(),
+            dir_path: Some("exists_subdir".to_string()),
+        };
+
+        let manager = LocalFileManager;
+        assert!(!manager.exists(&file_info).await.unwrap());
+
+        manager.save(&file_info).await.unwrap();
+        assert!(manager.exists(&file_info).await.unwrap());
+
+        // Same size, different content.
+        file_info.content = b"Hello, World!".to_vec();
+        assert!(!manager.exists(&file_info).await.unwrap());
+
+        // Clean up
+        fs::remove_file("./images/exists_subdir/test_file.txt").unwrap();
+        fs::remove_dir("./images/exists_subdir").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_file_read() {
+        let file_info = FileInfo {
+            name: "test_file.txt".to_string(),
+            content: b"Hello, world!".to_vec(),
+            dir_path: Some("read_subdir".to_string()),
+        };
+
+        let manager = LocalFileManager;
+        let key = manager.save(&file_info).await.unwrap();
+        assert_eq!(manager.read(&key).await.unwrap(), b"Hello, world!");
+        assert!(manager
+            .read("./images/read_subdir/missing.txt")
+            .await
+            .is_err());
+
+        // Clean up
+        fs::remove_file("./images/read_subdir/test_file.txt").unwrap();
+        fs::remove_dir("./images/read_subdir").unwrap();
+    }
 }
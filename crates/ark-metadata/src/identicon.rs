@@ -0,0 +1,75 @@
+//! Identicons of the tokens, used as placeholder images.
+//!
+//! The identicon of a token is a 5x5 grid, mirrored horizontally, whose cells
+//! and color are read from the keccak hash of the collection address and the
+//! token id. The same token always gets the same identicon, returned as an
+//! SVG data URI, so nothing has to be saved or served.
+use ark_starknet::CairoU256;
+use base64::{engine::general_purpose, Engine as _};
+use starknet::core::{types::FieldElement, utils::starknet_keccak};
+
+/// Number of cells of each side of the grid.
+const GRID_SIZE: usize = 5;
+
+/// Returns the identicon of the token, as an SVG data URI.
+pub fn identicon_data_uri(contract_address: FieldElement, token_id: &CairoU256) -> String {
+    let mut seed = contract_address.to_bytes_be().to_vec();
+    seed.extend_from_slice(&token_id.high.to_be_bytes());
+    seed.extend_from_slice(&token_id.low.to_be_bytes());
+    let hash = starknet_keccak(&seed).to_bytes_be();
+
+    // The first byte of the hash is masked by `starknet_keccak`.
+    let color = format!("#{:02x}{:02x}{:02x}", hash[1], hash[2], hash[3]);
+
+    let mut cells = String::new();
+    let half = GRID_SIZE / 2 + 1;
+    for row in 0..GRID_SIZE {
+        for column in 0..half {
+            let bit = row * half + column;
+            if hash[4 + bit / 8] & (1 << (bit % 8)) == 0 {
+                continue;
+            }
+
+            for x in [column, GRID_SIZE - 1 - column] {
+                cells.push_str(&format!(
+                    r#"<rect x="{}" y="{}" width="1" height="1"/>"#,
+                    x, row
+                ));
+                if x == GRID_SIZE - 1 - x {
+                    break;
+                }
+            }
+        }
+    }
+
+    let svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {size} {size}" shape-rendering="crispEdges"><rect width="{size}" height="{size}" fill="#f0f0f0"/><g fill="{color}">{cells}</g></svg>"#,
+        size = GRID_SIZE,
+        color = color,
+        cells = cells,
+    );
+
+    format!(
+        "data:image/svg+xml;base64,{}",
+        general_purpose::STANDARD.encode(svg)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identicon_is_deterministic() {
+        let token_id = CairoU256 { low: 1, high: 0 };
+        let identicon = identicon_data_uri(FieldElement::ONE, &token_id);
+
+        assert!(identicon.starts_with("data:image/svg+xml;base64,"));
+        assert_eq!(identicon, identicon_data_uri(FieldElement::ONE, &token_id));
+        assert_ne!(
+            identicon,
+            identicon_data_uri(FieldElement::ONE, &CairoU256 { low: 2, high: 0 })
+        );
+        assert_ne!(identicon, identicon_data_uri(FieldElement::TWO, &token_id));
+    }
+}
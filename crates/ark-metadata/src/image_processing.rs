@@ -0,0 +1,470 @@
+//! Optional processing applied on the media fetched from the metadata
+//! before being handed to the `FileManager`.
+//!
+//! Each processing step pulls its own rendering/decoding dependency,
+//! and is then gated behind a dedicated feature flag.
+#[cfg(any(
+    feature = "svg-raster",
+    feature = "thumbnails",
+    feature = "webp-conversion",
+    feature = "perceptual-hash"
+))]
+use anyhow::{anyhow, Result};
+
+/// Mime types sent by the hosts not knowing the type of the media.
+const GENERIC_MIME_TYPES: [&str; 3] = [
+    "application/octet-stream",
+    "binary/octet-stream",
+    "text/plain",
+];
+
+/// Maximum width and height in pixels of the images decoded or rasterized,
+/// a few bytes of PNG or SVG being able to claim a huge canvas.
+#[cfg(any(
+    feature = "svg-raster",
+    feature = "thumbnails",
+    feature = "webp-conversion",
+    feature = "perceptual-hash"
+))]
+pub const MAX_IMAGE_DIMENSION: u32 = 16_384;
+
+/// Maximum memory in bytes allocated while decoding an image.
+#[cfg(any(
+    feature = "thumbnails",
+    feature = "webp-conversion",
+    feature = "perceptual-hash"
+))]
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+
+/// Runs the processing of the given media on the blocking thread pool,
+/// decoding and resizing images being too slow for the async workers.
+#[cfg(any(
+    feature = "svg-raster",
+    feature = "thumbnails",
+    feature = "webp-conversion",
+    feature = "perceptual-hash"
+))]
+pub async fn process_blocking<T, F>(media: &[u8], process: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&[u8]) -> Result<T> + Send + 'static,
+{
+    let media = media.to_vec();
+    tokio::task::spawn_blocking(move || process(&media))
+        .await
+        .map_err(|e| anyhow!("Image processing task failed: {}", e))?
+}
+
+/// Rasterizes the given SVG document into a PNG image.
+///
+/// The image is scaled to fit `width` pixels, the height being computed
+/// to preserve the aspect ratio of the SVG view box.
+///
+/// # Arguments
+/// * `svg` - The raw bytes of the SVG document.
+/// * `width` - The width in pixels of the PNG to generate.
+#[cfg(feature = "svg-raster")]
+pub fn rasterize_svg(svg: &[u8], width: u32) -> Result<Vec<u8>> {
+    use resvg::{tiny_skia, usvg, usvg::TreeParsing};
+
+    if width == 0 {
+        return Err(anyhow!("Rasterization width must be greater than 0"));
+    }
+
+    let tree = usvg::Tree::from_data(svg, &usvg::Options::default())
+        .map_err(|e| anyhow!("Failed to parse SVG: {}", e))?;
+
+    let size = tree.size;
+    let scale = width as f32 / size.width();
+    let height = (size.height() * scale).ceil().max(1.0) as u32;
+    if width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION {
+        return Err(anyhow!(
+            "Rasterized SVG of {}x{} exceeds the {}px limit",
+            width,
+            height,
+            MAX_IMAGE_DIMENSION
+        ));
+    }
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| anyhow!("Failed to allocate a {}x{} pixmap", width, height))?;
+
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    pixmap
+        .encode_png()
+        .map_err(|e| anyhow!("Failed to encode PNG: {}", e))
+}
+
+/// Generates a WebP thumbnail of the given raster image.
+///
+/// The image is scaled down to fit in a `size` x `size` box, preserving
+/// its aspect ratio. Smaller images are not upscaled. For animated GIFs,
+/// only the first frame is kept.
+///
+/// # Arguments
+/// * `image` - The raw bytes of the image (PNG, JPEG, GIF or WebP).
+/// * `size` - The maximum width and height in pixels of the thumbnail.
+#[cfg(feature = "thumbnails")]
+pub fn generate_thumbnail(image: &[u8], size: u32) -> Result<Vec<u8>> {
+    use image::imageops::FilterType;
+
+    if size == 0 {
+        return Err(anyhow!("Thumbnail size must be greater than 0"));
+    }
+
+    let image = decode_image(image)?;
+
+    let image = if image.width() > size || image.height() > size {
+        image.resize(size, size, FilterType::Lanczos3)
+    } else {
+        image
+    };
+
+    encode_webp(&image)
+}
+
+/// Converts the given PNG or JPEG image into a lossless WebP image.
+///
+/// Returns `None` if the WebP image is not smaller than the original,
+/// which can be the case of the photos already compressed as JPEG.
+#[cfg(feature = "webp-conversion")]
+pub fn convert_to_webp(image: &[u8]) -> Result<Option<Vec<u8>>> {
+    let decoded = decode_image(image)?;
+
+    let webp = encode_webp(&decoded)?;
+
+    Ok((webp.len() < image.len()).then_some(webp))
+}
+
+/// Decodes the given raster image, refusing the images larger than
+/// `MAX_IMAGE_DIMENSION` before allocating their pixels.
+#[cfg(any(
+    feature = "thumbnails",
+    feature = "webp-conversion",
+    feature = "perceptual-hash"
+))]
+fn decode_image(image: &[u8]) -> Result<image::DynamicImage> {
+    use image::io::{Limits, Reader};
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+
+    let mut reader = Reader::new(std::io::Cursor::new(image))
+        .with_guessed_format()
+        .map_err(|e| anyhow!("Failed to read image: {}", e))?;
+    reader.limits(limits);
+
+    reader
+        .decode()
+        .map_err(|e| anyhow!("Failed to decode image: {}", e))
+}
+
+#[cfg(any(feature = "thumbnails", feature = "webp-conversion"))]
+fn encode_webp(image: &image::DynamicImage) -> Result<Vec<u8>> {
+    use image::{codecs::webp::WebPEncoder, ColorType};
+
+    let rgba = image.to_rgba8();
+    let mut webp = Vec::new();
+    WebPEncoder::new_lossless(&mut webp)
+        .encode(rgba.as_raw(), rgba.width(), rgba.height(), ColorType::Rgba8)
+        .map_err(|e| anyhow!("Failed to encode WebP: {}", e))?;
+
+    Ok(webp)
+}
+
+/// Computes the difference hash (dHash) of the given raster image.
+///
+/// The image is reduced to a 9x8 grayscale image, each bit of the hash
+/// telling if a pixel is brighter than its right neighbour. Similar
+/// images have hashes at a small hamming distance, whatever their size
+/// or compression.
+#[cfg(feature = "perceptual-hash")]
+pub fn compute_dhash(image: &[u8]) -> Result<u64> {
+    use image::imageops::FilterType;
+
+    let decoded = decode_image(image)?;
+    let pixels = decoded.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0_u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    Ok(hash)
+}
+
+/// Returns the number of different bits between two perceptual hashes.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Returns the mime type of the image from its first bytes, for the
+/// PNG, JPEG, GIF, WebP and AVIF images.
+pub fn sniff_image_mime_type(content: &[u8]) -> Option<&'static str> {
+    if content.starts_with(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]) {
+        Some("image/png")
+    } else if content.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if content.starts_with(b"GIF87a") || content.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if content.len() >= 12 && &content[..4] == b"RIFF" && &content[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if content.len() >= 12
+        && &content[4..8] == b"ftyp"
+        && matches!(&content[8..12], b"avif" | b"avis")
+    {
+        Some("image/avif")
+    } else {
+        None
+    }
+}
+
+/// Returns the mime type of the media: the given one, unless it's missing
+/// or generic (`application/octet-stream`...) and the content is an image.
+pub fn media_mime_type(content_type: &str, content: &[u8]) -> String {
+    let is_generic = content_type.trim().is_empty()
+        || GENERIC_MIME_TYPES
+            .iter()
+            .any(|t| content_type.starts_with(t));
+
+    match sniff_image_mime_type(content) {
+        Some(mime_type) if is_generic => mime_type.to_string(),
+        _ => content_type.to_string(),
+    }
+}
+
+/// Returns true if the given mime type is a raster image that can be resized.
+pub fn is_resizable_mime_type(mime_type: &str) -> bool {
+    [
+        "image/png",
+        "image/jpeg",
+        "image/jpg",
+        "image/gif",
+        "image/webp",
+    ]
+    .iter()
+    .any(|t| mime_type.starts_with(t))
+}
+
+/// Returns true if the given mime type is an SVG image.
+pub fn is_svg_mime_type(mime_type: &str) -> bool {
+    mime_type.starts_with("image/svg+xml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_svg_mime_type() {
+        assert!(is_svg_mime_type("image/svg+xml"));
+        assert!(is_svg_mime_type("image/svg+xml; charset=utf-8"));
+        assert!(!is_svg_mime_type("image/png"));
+    }
+
+    #[test]
+    fn test_sniff_image_mime_type() {
+        assert_eq!(
+            sniff_image_mime_type(b"RIFF\x24\x00\x00\x00WEBPVP8L"),
+            Some("image/webp")
+        );
+        assert_eq!(
+            sniff_image_mime_type(b"\x00\x00\x00\x1cftypavif\x00\x00\x00\x00"),
+            Some("image/avif")
+        );
+        assert_eq!(sniff_image_mime_type(b"GIF89a"), Some("image/gif"));
+        assert_eq!(sniff_image_mime_type(b"<svg></svg>"), None);
+    }
+
+    #[test]
+    fn test_media_mime_type() {
+        let webp = b"RIFF\x24\x00\x00\x00WEBPVP8L";
+
+        assert_eq!(
+            media_mime_type("application/octet-stream", webp),
+            "image/webp"
+        );
+        assert_eq!(media_mime_type("", webp), "image/webp");
+        // A specific type is trusted.
+        assert_eq!(media_mime_type("image/png", webp), "image/png");
+        assert_eq!(
+            media_mime_type("application/octet-stream", b"data"),
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn test_is_resizable_mime_type() {
+        assert!(is_resizable_mime_type("image/png"));
+        assert!(is_resizable_mime_type("image/gif"));
+        assert!(!is_resizable_mime_type("image/svg+xml"));
+        assert!(!is_resizable_mime_type("video/mp4"));
+    }
+
+    #[cfg(any(
+        feature = "thumbnails",
+        feature = "webp-conversion",
+        feature = "perceptual-hash"
+    ))]
+    fn encode_png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbaImage::new(width, height)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        png.into_inner()
+    }
+
+    #[cfg(feature = "thumbnails")]
+    #[test]
+    fn test_generate_thumbnail() {
+        let png = encode_png(400, 200);
+
+        let thumbnail = generate_thumbnail(&png, 128).expect("Failed to generate thumbnail");
+        let thumbnail = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (128, 64));
+
+        // Smaller images are kept at their original size.
+        let thumbnail = generate_thumbnail(&png, 512).expect("Failed to generate thumbnail");
+        let thumbnail = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (400, 200));
+    }
+
+    #[cfg(feature = "thumbnails")]
+    #[test]
+    fn test_generate_thumbnail_from_animated_gif() {
+        use image::{codecs::gif::GifEncoder, Frame, RgbaImage};
+
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            for _ in 0..2 {
+                encoder
+                    .encode_frame(Frame::new(RgbaImage::new(300, 300)))
+                    .unwrap();
+            }
+        }
+
+        let thumbnail = generate_thumbnail(&gif, 256).expect("Failed to generate thumbnail");
+        let thumbnail = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (256, 256));
+    }
+
+    #[cfg(feature = "webp-conversion")]
+    #[test]
+    fn test_convert_to_webp() {
+        let png = encode_png(64, 64);
+
+        let webp = convert_to_webp(&png)
+            .expect("Failed to convert image")
+            .expect("WebP image larger than the PNG");
+        assert_eq!(sniff_image_mime_type(&webp), Some("image/webp"));
+
+        let image = image::load_from_memory(&webp).unwrap();
+        assert_eq!((image.width(), image.height()), (64, 64));
+    }
+
+    #[test]
+    fn test_hamming_distance() {
+        assert_eq!(hamming_distance(0b1011, 0b1011), 0);
+        assert_eq!(hamming_distance(0b1011, 0b0010), 2);
+        assert_eq!(hamming_distance(0, u64::MAX), 64);
+    }
+
+    #[cfg(feature = "perceptual-hash")]
+    #[test]
+    fn test_compute_dhash() {
+        use image::{GrayImage, Luma};
+
+        // Horizontal gradient, getting darker from left to right.
+        let gradient = |width: u32, height: u32| {
+            let image =
+                GrayImage::from_fn(width, height, |x, _| Luma([255 - (x * 255 / width) as u8]));
+            let mut png = std::io::Cursor::new(Vec::new());
+            image
+                .write_to(&mut png, image::ImageOutputFormat::Png)
+                .unwrap();
+            png.into_inner()
+        };
+
+        // The same image at another size has the same hash.
+        let hash = compute_dhash(&gradient(90, 80)).unwrap();
+        assert_eq!(hash, u64::MAX);
+        assert!(hamming_distance(hash, compute_dhash(&gradient(360, 160)).unwrap()) <= 4);
+
+        // A flat image has no brighter pixel.
+        assert_eq!(compute_dhash(&encode_png(64, 64)).unwrap(), 0);
+        assert!(compute_dhash(b"<svg></svg>").is_err());
+    }
+
+    #[cfg(feature = "thumbnails")]
+    #[test]
+    fn test_generate_thumbnail_invalid_image() {
+        assert!(generate_thumbnail(b"<svg></svg>", 128).is_err());
+    }
+
+    #[cfg(feature = "thumbnails")]
+    #[test]
+    fn test_generate_thumbnail_too_large_image() {
+        let png = encode_png(MAX_IMAGE_DIMENSION + 1, 1);
+
+        assert!(generate_thumbnail(&png, 128).is_err());
+    }
+
+    #[cfg(feature = "thumbnails")]
+    #[tokio::test]
+    async fn test_process_blocking() {
+        let png = encode_png(400, 200);
+
+        let thumbnail = process_blocking(&png, |png| generate_thumbnail(png, 128))
+            .await
+            .expect("Failed to generate thumbnail");
+        let thumbnail = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (128, 64));
+    }
+
+    #[cfg(feature = "svg-raster")]
+    #[test]
+    fn test_rasterize_svg() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="20" viewBox="0 0 10 20"><rect width="10" height="20" fill="red"/></svg>"#;
+
+        let png = rasterize_svg(svg, 100).expect("Failed to rasterize SVG");
+
+        // PNG signature.
+        assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]);
+
+        // Width and height are stored big endian in the IHDR chunk.
+        let width = u32::from_be_bytes(png[16..20].try_into().unwrap());
+        let height = u32::from_be_bytes(png[20..24].try_into().unwrap());
+        assert_eq!(width, 100);
+        assert_eq!(height, 200);
+    }
+
+    #[cfg(feature = "svg-raster")]
+    #[test]
+    fn test_rasterize_invalid_svg() {
+        assert!(rasterize_svg(b"not an svg", 100).is_err());
+        assert!(rasterize_svg(b"<svg></svg>", 0).is_err());
+    }
+
+    #[cfg(feature = "svg-raster")]
+    #[test]
+    fn test_rasterize_too_large_svg() {
+        // A 100px wide raster of this view box would be 100,000px high.
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="1" height="1000" viewBox="0 0 1 1000"/>"#;
+
+        assert!(rasterize_svg(svg, 100).is_err());
+        assert!(rasterize_svg(svg, 10).is_ok());
+    }
+}
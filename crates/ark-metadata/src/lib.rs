@@ -1,5 +1,21 @@
+pub mod circuit_breaker;
+pub mod export;
+pub mod fetch_limiter;
 pub mod file_manager;
+pub mod identicon;
+pub mod image_processing;
+pub mod media_key;
+pub mod metadata_cache;
+pub mod metadata_fetcher;
 pub mod metadata_manager;
+pub mod metrics;
+pub mod object_store;
+pub mod rarity;
+pub mod single_flight;
+pub mod similarity;
+pub mod spam;
 pub mod storage;
+#[cfg(test)]
+mod test_server;
 pub mod types;
 mod utils;
@@ -0,0 +1,206 @@
+//! Templates of the keys of the saved token media.
+//!
+//! By default, the media of a token is saved as `{token_id}.{ext}`, and its
+//! thumbnails as `{token_id}/{size}.webp`. A `MediaKeyTemplate` replaces this
+//! layout to fit an existing storage or CDN, like
+//! `{network}/{collection}/{token_id}/{size}.{ext}`, with the placeholders:
+//!
+//! - `{network}`: network of the collection (`mainnet`, `sepolia`).
+//! - `{collection}`: address of the collection, as 64 hex characters prefixed with `0x`.
+//! - `{token_id}`: token id, in decimal.
+//! - `{size}`: size of a thumbnail (in pixels), `original` for the media and its conversions.
+//! - `{ext}`: file extension, like `png` or `webp`.
+//!
+//! The part of the key before the last `/` is the `dir_path` of the saved file.
+use crate::file_manager::FileInfo;
+use ark_starknet::{network::Network, CairoU256};
+use starknet::core::types::FieldElement;
+
+const PLACEHOLDERS: [&str; 5] = ["network", "collection", "token_id", "size", "ext"];
+
+/// Value of `{size}` for the media and its conversions.
+pub const ORIGINAL_SIZE: &str = "original";
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum MediaKeyTemplateError {
+    #[error("Unknown placeholder {{{0}}} in media key template")]
+    UnknownPlaceholder(String),
+
+    #[error("Unclosed placeholder in media key template: {0}")]
+    UnclosedPlaceholder(String),
+
+    /// Without this placeholder, the keys of different media would collide.
+    #[error("Media key template must contain {{{0}}}")]
+    MissingPlaceholder(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaKeyTemplate {
+    segments: Vec<Segment>,
+    network: Network,
+}
+
+impl MediaKeyTemplate {
+    /// Parses and validates the template. It must contain `{token_id}` and `{ext}`.
+    pub fn new(template: &str, network: Network) -> Result<Self, MediaKeyTemplateError> {
+        let mut segments = vec![];
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            if rest[..start].contains('}') {
+                return Err(MediaKeyTemplateError::UnclosedPlaceholder(
+                    template.to_string(),
+                ));
+            }
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| MediaKeyTemplateError::UnclosedPlaceholder(template.to_string()))?
+                + start;
+
+            let name = &rest[start + 1..end];
+            let placeholder = PLACEHOLDERS
+                .iter()
+                .find(|p| **p == name)
+                .ok_or_else(|| MediaKeyTemplateError::UnknownPlaceholder(name.to_string()))?;
+            segments.push(Segment::Placeholder(*placeholder));
+
+            rest = &rest[end + 1..];
+        }
+
+        if rest.contains('}') {
+            return Err(MediaKeyTemplateError::UnclosedPlaceholder(
+                template.to_string(),
+            ));
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        let template = MediaKeyTemplate { segments, network };
+        for required in ["token_id", "ext"] {
+            if !template.has_placeholder(required) {
+                return Err(MediaKeyTemplateError::MissingPlaceholder(required));
+            }
+        }
+
+        Ok(template)
+    }
+
+    /// Returns true if the template contains the given placeholder, without braces.
+    pub fn has_placeholder(&self, name: &str) -> bool {
+        self.segments
+            .iter()
+            .any(|s| matches!(s, Segment::Placeholder(p) if *p == name))
+    }
+
+    /// Returns the key of a token media, `size` being set for its thumbnails.
+    pub fn resolve(
+        &self,
+        contract_address: FieldElement,
+        token_id: &CairoU256,
+        size: Option<u32>,
+        ext: &str,
+    ) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.clone(),
+                Segment::Placeholder("network") => self.network.to_string(),
+                Segment::Placeholder("collection") => format!("0x{:064x}", contract_address),
+                Segment::Placeholder("token_id") => token_id.to_decimal(false),
+                Segment::Placeholder("size") => size
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| ORIGINAL_SIZE.to_string()),
+                // `{ext}`, the only other placeholder.
+                Segment::Placeholder(_) => ext.to_string(),
+            })
+            .collect()
+    }
+
+    /// Returns the file of a token media, saved under the resolved key.
+    pub fn file_info(
+        &self,
+        contract_address: FieldElement,
+        token_id: &CairoU256,
+        size: Option<u32>,
+        ext: &str,
+        content: Vec<u8>,
+    ) -> FileInfo {
+        let key = self.resolve(contract_address, token_id, size, ext);
+        let key = key.trim_start_matches('/');
+
+        match key.rsplit_once('/') {
+            Some((dir_path, name)) => FileInfo {
+                name: name.to_string(),
+                content,
+                dir_path: Some(dir_path.to_string()),
+            },
+            None => FileInfo {
+                name: key.to_string(),
+                content,
+                dir_path: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_media_key() {
+        let template = MediaKeyTemplate::new(
+            "{network}/{collection}/{token_id}/{size}.{ext}",
+            Network::Sepolia,
+        )
+        .unwrap();
+        let token_id = CairoU256 { low: 42, high: 0 };
+
+        let file = template.file_info(FieldElement::ONE, &token_id, Some(128), "webp", vec![]);
+        assert_eq!(
+            file.dir_path.unwrap(),
+            format!("sepolia/0x{:064x}/42", FieldElement::ONE)
+        );
+        assert_eq!(file.name, "128.webp");
+
+        let file = template.file_info(FieldElement::ONE, &token_id, None, "png", vec![]);
+        assert_eq!(file.name, "original.png");
+
+        let template = MediaKeyTemplate::new("{token_id}.{ext}", Network::Mainnet).unwrap();
+        let file = template.file_info(FieldElement::ONE, &token_id, None, "png", vec![]);
+        assert_eq!((file.name.as_str(), file.dir_path), ("42.png", None));
+    }
+
+    #[test]
+    fn test_invalid_media_key_template() {
+        assert_eq!(
+            MediaKeyTemplate::new("{collection}/{token}.{ext}", Network::Mainnet),
+            Err(MediaKeyTemplateError::UnknownPlaceholder(
+                "token".to_string()
+            ))
+        );
+        assert!(matches!(
+            MediaKeyTemplate::new("{token_id}.{ext", Network::Mainnet),
+            Err(MediaKeyTemplateError::UnclosedPlaceholder(_))
+        ));
+        assert!(matches!(
+            MediaKeyTemplate::new("{token_id}}.{ext}", Network::Mainnet),
+            Err(MediaKeyTemplateError::UnclosedPlaceholder(_))
+        ));
+        assert_eq!(
+            MediaKeyTemplate::new("{collection}/{token_id}.png", Network::Mainnet),
+            Err(MediaKeyTemplateError::MissingPlaceholder("ext"))
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! On-disk cache of the fetched metadata documents.
+//!
+//! The documents are saved in a directory, one file per metadata URI, and
+//! are read back instead of being fetched again. The cache is keyed by the
+//! normalized URI: the `ipfs://` URIs and the IPFS gateway URLs
+//! (`https://<gateway>/ipfs/<cid>/1.json`) share the same key
+//! (`ipfs://<cid>/1.json`), whatever the gateway used.
+//!
+//! The IPFS and Arweave documents are immutable, and are kept forever.
+//! The HTTP documents can change, and are only cached when a TTL is set
+//! with `MetadataCache::with_http_ttl`.
+use anyhow::{Context, Result};
+use starknet::core::utils::starknet_keccak;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{trace, warn};
+
+/// Extension of the cached documents, the other files of the directory
+/// being ignored and never removed by `MetadataCache::clear`.
+const CACHE_FILE_EXTENSION: &str = "json";
+
+#[derive(Debug, Clone)]
+pub struct MetadataCache {
+    dir: PathBuf,
+    http_ttl: Option<Duration>,
+}
+
+impl MetadataCache {
+    /// Creates a cache saving the documents in the given directory,
+    /// created on the first document saved.
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            http_ttl: None,
+        }
+    }
+
+    /// Caches the HTTP documents for the given time. They are not cached by default.
+    pub fn with_http_ttl(mut self, ttl: Duration) -> Self {
+        self.http_ttl = Some(ttl);
+        self
+    }
+
+    /// Returns the cached document of the given URI, if any and not expired.
+    pub async fn get(&self, uri: &str) -> Option<String> {
+        let key = normalize_cache_key(uri);
+        let ttl = self.ttl(&key)?;
+        let path = self.path(&key);
+
+        let modified = tokio::fs::metadata(&path).await.ok()?.modified().ok()?;
+        if let Some(ttl) = ttl {
+            let age = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default();
+            if age > ttl {
+                trace!("Cached metadata of {} expired", key);
+                return None;
+            }
+        }
+
+        match tokio::fs::read_to_string(&path).await {
+            Ok(raw) => {
+                trace!("Metadata of {} read from cache", key);
+                Some(raw)
+            }
+            Err(e) => {
+                warn!("Can't read cached metadata of {}: {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Saves the document of the given URI, if its URI can be cached.
+    pub async fn put(&self, uri: &str, raw: &str) -> Result<()> {
+        let key = normalize_cache_key(uri);
+        if self.ttl(&key).is_none() {
+            return Ok(());
+        }
+
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .context("Failed to create metadata cache directory")?;
+
+        // Written aside then renamed, to never read a partial document.
+        let path = self.path(&key);
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, raw)
+            .await
+            .context("Failed to write cached metadata")?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .context("Failed to write cached metadata")?;
+
+        trace!("Metadata of {} saved in cache", key);
+        Ok(())
+    }
+
+    /// Removes all the cached documents, returning the number of documents removed.
+    pub async fn clear(&self) -> Result<usize> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e).context("Failed to read metadata cache directory"),
+        };
+
+        let mut removed = 0;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) == Some(CACHE_FILE_EXTENSION) {
+                tokio::fs::remove_file(&path)
+                    .await
+                    .context("Failed to remove cached metadata")?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Returns the TTL of the given key, `Some(None)` if it never expires,
+    /// or `None` if it must not be cached.
+    fn ttl(&self, key: &str) -> Option<Option<Duration>> {
+        if key.starts_with("ipfs://") || key.starts_with("ar://") {
+            Some(None)
+        } else if key.starts_with("http://") || key.starts_with("https://") {
+            self.http_ttl.map(Some)
+        } else {
+            // The on-chain metadata are never fetched.
+            None
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let hash = starknet_keccak(key.as_bytes());
+        self.dir
+            .join(format!("{:064x}.{}", hash, CACHE_FILE_EXTENSION))
+    }
+}
+
+/// Returns the cache key of a metadata URI: the IPFS gateway URLs are
+/// rewritten as `ipfs://` URIs, as the content of a CID never changes.
+pub fn normalize_cache_key(uri: &str) -> String {
+    let uri = uri.trim();
+
+    if let Some(path) = uri.strip_prefix("ipfs://") {
+        return format!("ipfs://{}", path.trim_start_matches("ipfs/"));
+    }
+
+    if uri.starts_with("http://") || uri.starts_with("https://") {
+        if let Some((_, path)) = uri.split_once("/ipfs/") {
+            return format!("ipfs://{}", path);
+        }
+    }
+
+    uri.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_cache_key() {
+        let key = "ipfs://QmCid/1.json";
+        assert_eq!(normalize_cache_key("ipfs://QmCid/1.json"), key);
+        assert_eq!(normalize_cache_key("ipfs://ipfs/QmCid/1.json"), key);
+        assert_eq!(
+            normalize_cache_key("https://gateway.pinata.cloud/ipfs/QmCid/1.json"),
+            key
+        );
+        assert_eq!(
+            normalize_cache_key("https://example.com/token/1"),
+            "https://example.com/token/1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_metadata_cache() {
+        let dir = std::env::temp_dir().join(format!("ark-metadata-cache-{}", std::process::id()));
+        let cache = MetadataCache::new(&dir);
+
+        cache.put("ipfs://QmCid/1.json", "{}").await.unwrap();
+        assert_eq!(
+            cache
+                .get("https://ipfs.io/ipfs/QmCid/1.json")
+                .await
+                .as_deref(),
+            Some("{}")
+        );
+
+        // The HTTP documents are only cached with a TTL.
+        let uri = "https://example.com/token/1";
+        cache.put(uri, "{}").await.unwrap();
+        assert_eq!(cache.get(uri).await, None);
+
+        let cache = cache.with_http_ttl(Duration::from_secs(60));
+        cache.put(uri, r#"{"name":"1"}"#).await.unwrap();
+        assert_eq!(cache.get(uri).await.as_deref(), Some(r#"{"name":"1"}"#));
+
+        let expired = MetadataCache::new(&dir).with_http_ttl(Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(expired.get(uri).await, None);
+
+        assert_eq!(cache.clear().await.unwrap(), 2);
+        assert_eq!(cache.get("ipfs://QmCid/1.json").await, None);
+
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
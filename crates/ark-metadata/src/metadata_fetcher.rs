@@ -0,0 +1,412 @@
+/// Provides the fetching of the metadata documents.
+///
+/// The `MetadataManager` fetches the metadata over HTTP by default.
+/// You may choose to implement the `MetadataFetcher` trait to fetch them
+/// from other sources (i.g. an IPFS node, or a cache), or to compose
+/// several fetchers.
+use crate::circuit_breaker::HostCircuitBreaker;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, warn};
+use url::Url;
+
+#[cfg(any(test, feature = "mock"))]
+use mockall::automock;
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum MetadataFetchError {
+    /// The response is not a JSON document, like the HTML error or
+    /// challenge pages returned with a `200` by some gateways.
+    #[error("Response is not JSON (Content-Type: {content_type:?}). URI: {uri}")]
+    NotJson {
+        uri: String,
+        content_type: Option<String>,
+    },
+    /// The circuit of the host is open after consecutive failures,
+    /// the request is not sent.
+    #[error("Circuit open for the host of {uri}, request not sent")]
+    CircuitOpen { uri: String },
+}
+
+/// Returns true if the error is a `MetadataFetchError::NotJson`.
+pub fn is_not_json_error(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<MetadataFetchError>(),
+        Some(MetadataFetchError::NotJson { .. })
+    )
+}
+
+/// A trait that defines the fetching of the metadata.
+#[cfg_attr(any(test, feature = "mock"), automock)]
+#[async_trait]
+pub trait MetadataFetcher: Send + Sync {
+    /// Fetches the metadata at the given URI.
+    ///
+    /// Returns the raw metadata document, which is stored as is
+    /// and normalized by the `MetadataManager`. The IPFS and Arweave URIs
+    /// are already resolved using the configured gateways.
+    async fn fetch(&self, uri: &str) -> Result<String>;
+}
+
+/// Headers sent to some hosts on top of the default headers, replacing
+/// the default ones with the same name. Some gateways reject the requests
+/// without a specific `Accept`, `Origin` or `Referer` header.
+///
+/// A host also matches its subdomains (i.g. `nftstorage.link` matches
+/// `<cid>.ipfs.nftstorage.link`), the most specific host being used.
+#[derive(Debug, Clone, Default)]
+pub struct HostHeaders {
+    hosts: HashMap<String, HeaderMap>,
+}
+
+impl HostHeaders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the headers of the given host, replacing the previous ones.
+    /// The values are marked as sensitive, and are never logged.
+    pub fn with_host(mut self, host: &str, mut headers: HeaderMap) -> Self {
+        headers.values_mut().for_each(|v| v.set_sensitive(true));
+        self.hosts.insert(host.trim().to_lowercase(), headers);
+        self
+    }
+
+    /// Returns the headers of the host of the given URL, if any.
+    pub fn for_url(&self, url: &str) -> Option<&HeaderMap> {
+        if self.hosts.is_empty() {
+            return None;
+        }
+
+        let url = Url::parse(url).ok()?;
+        let mut host = url.host_str()?.to_lowercase();
+
+        loop {
+            if let Some(headers) = self.hosts.get(&host) {
+                return Some(headers);
+            }
+
+            host = host.split_once('.')?.1.to_string();
+        }
+    }
+
+    /// Adds the headers of the host of the given URL to the request.
+    pub fn apply(&self, request: RequestBuilder, url: &str) -> RequestBuilder {
+        match self.for_url(url) {
+            Some(headers) => request.headers(headers.clone()),
+            None => request,
+        }
+    }
+}
+
+/// MetadataFetcher implementation requesting the metadata over HTTP.
+pub struct HttpMetadataFetcher {
+    client: Client,
+    identity_client: Option<Client>,
+    timeout: Duration,
+    referrer: String,
+    host_headers: HostHeaders,
+    circuit_breaker: Option<Arc<HostCircuitBreaker>>,
+}
+
+impl HttpMetadataFetcher {
+    pub fn new(client: Client, timeout: Duration, referrer: &str) -> Self {
+        Self {
+            client,
+            identity_client: None,
+            timeout,
+            referrer: referrer.to_string(),
+            host_headers: HostHeaders::default(),
+            circuit_breaker: None,
+        }
+    }
+
+    /// Fails fast the requests to the hosts failing repeatedly.
+    pub fn with_circuit_breaker(mut self, circuit_breaker: Arc<HostCircuitBreaker>) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// Sets the headers sent to some hosts, replacing the default ones.
+    pub fn with_host_headers(mut self, host_headers: HostHeaders) -> Self {
+        self.host_headers = host_headers;
+        self
+    }
+
+    /// Sets a client without automatic decompression, used to request the
+    /// metadata again when their body can't be decompressed, like the
+    /// uncompressed bodies sent with a bogus `Content-Encoding`.
+    pub fn with_identity_client(mut self, client: Client) -> Self {
+        self.identity_client = Some(client);
+        self
+    }
+
+    async fn request(&self, client: &Client, uri: &str) -> Result<String> {
+        let request = client
+            .get(uri)
+            .header("User-Agent", "Mozilla/5.0 (compatible; YourClient/1.0)")
+            .header("Referrer", &self.referrer)
+            .timeout(self.timeout);
+        let request = self.host_headers.apply(request, uri);
+
+        let response = request.send().await.map_err(|e| {
+            error!("Request Failed: {:?}", e);
+            self.record_outcome(uri, false);
+            anyhow!("Request Failed. URI: {}", uri)
+        })?;
+
+        debug!("Response status: {}", response.status());
+        // Only the errors of the host count, not the missing documents.
+        self.record_outcome(
+            uri,
+            !(response.status().is_server_error()
+                || response.status() == StatusCode::TOO_MANY_REQUESTS),
+        );
+        if !response.status().is_success() {
+            error!("Request Failed. URI: {}", uri);
+            return Err(anyhow!("Request Failed"));
+        }
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let raw_metadata = response.text().await?;
+
+        if is_html_response(content_type.as_deref(), &raw_metadata)
+            || !looks_like_json(&raw_metadata)
+        {
+            error!(
+                "Request returned a document which is not JSON (Content-Type: {:?}). URI: {}",
+                content_type, uri
+            );
+            return Err(MetadataFetchError::NotJson {
+                uri: uri.to_string(),
+                content_type,
+            }
+            .into());
+        }
+
+        Ok(raw_metadata)
+    }
+
+    fn record_outcome(&self, uri: &str, success: bool) {
+        match (&self.circuit_breaker, success) {
+            (Some(circuit_breaker), true) => circuit_breaker.record_success(uri),
+            (Some(circuit_breaker), false) => circuit_breaker.record_failure(uri),
+            (None, _) => (),
+        }
+    }
+}
+
+#[async_trait]
+impl MetadataFetcher for HttpMetadataFetcher {
+    async fn fetch(&self, uri: &str) -> Result<String> {
+        if let Some(circuit_breaker) = &self.circuit_breaker {
+            if !circuit_breaker.allow_request(uri) {
+                debug!("Circuit open, not requesting {}", uri);
+                return Err(MetadataFetchError::CircuitOpen {
+                    uri: uri.to_string(),
+                }
+                .into());
+            }
+        }
+
+        match (self.request(&self.client, uri).await, &self.identity_client) {
+            (Err(e), Some(identity_client)) if is_decode_error(&e) => {
+                warn!(
+                    "Failed to decompress the response, requesting it again without decompression. URI: {}",
+                    uri
+                );
+                self.request(identity_client, uri).await
+            }
+            (result, _) => result,
+        }
+    }
+}
+
+/// Returns true if the error comes from the decoding of the response body.
+fn is_decode_error(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .map_or(false, |e| e.is_decode())
+}
+
+/// Returns true if the response is an HTML page, like the interstitial
+/// or captcha pages returned by some rate limited gateways.
+fn is_html_response(content_type: Option<&str>, body: &str) -> bool {
+    if content_type.map_or(false, |c| c.trim_start().starts_with("text/html")) {
+        return true;
+    }
+
+    let start: String = body.trim_start().chars().take(9).collect();
+    let start = start.to_ascii_lowercase();
+    start.starts_with("<!doctype") || start.starts_with("<html")
+}
+
+/// Returns true if the body starts like a JSON object or array,
+/// unlike the plain text or XML error pages.
+fn looks_like_json(body: &str) -> bool {
+    body.trim_start_matches('\u{feff}')
+        .trim_start()
+        .starts_with(['{', '['])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::TestServer;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    const METADATA: &str = r#"{"name":"Duck"}"#;
+
+    /// Serves the given body as JSON, with the given `Content-Encoding`, to every request.
+    async fn serve(content_encoding: &'static str, body: Vec<u8>) -> String {
+        TestServer::ok(
+            &[
+                ("Content-Type", "application/json"),
+                ("Content-Encoding", content_encoding),
+                ("Connection", "close"),
+            ],
+            &body,
+        )
+        .await
+        .url("/1.json")
+    }
+
+    fn fetcher() -> HttpMetadataFetcher {
+        HttpMetadataFetcher::new(Client::new(), Duration::from_secs(5), "")
+    }
+
+    #[tokio::test]
+    async fn test_fetch_gzip_metadata() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(METADATA.as_bytes()).unwrap();
+        let uri = serve("gzip", encoder.finish().unwrap()).await;
+
+        assert_eq!(fetcher().fetch(&uri).await.unwrap(), METADATA);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_metadata_with_bogus_content_encoding() {
+        let uri = serve("gzip", METADATA.as_bytes().to_vec()).await;
+
+        assert!(fetcher().fetch(&uri).await.is_err());
+
+        let identity_client = Client::builder()
+            .no_gzip()
+            .no_brotli()
+            .no_deflate()
+            .build()
+            .unwrap();
+        let fetcher = fetcher().with_identity_client(identity_client);
+
+        assert_eq!(fetcher.fetch(&uri).await.unwrap(), METADATA);
+    }
+
+    #[test]
+    fn test_host_headers_for_url() {
+        let mut ipfs_headers = HeaderMap::new();
+        ipfs_headers.insert("Accept", "application/json".parse().unwrap());
+        let mut gateway_headers = HeaderMap::new();
+        gateway_headers.insert("Origin", "https://arkproject.dev".parse().unwrap());
+
+        let host_headers = HostHeaders::new()
+            .with_host("Gateway.example", gateway_headers.clone())
+            .with_host("ipfs.gateway.example", ipfs_headers.clone());
+
+        assert_eq!(
+            host_headers.for_url("https://gateway.example:8080/1.json"),
+            Some(&gateway_headers)
+        );
+        assert_eq!(
+            host_headers.for_url("https://cid.ipfs.gateway.example/1.json"),
+            Some(&ipfs_headers)
+        );
+        assert_eq!(host_headers.for_url("https://example/1.json"), None);
+        assert_eq!(host_headers.for_url("ipfs://cid/1.json"), None);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_host_headers() {
+        // Only answers the metadata with the expected `Origin`.
+        let uri = TestServer::with_handler(|request| {
+            let (status, body) = if request.contains("origin: https://arkproject.dev") {
+                ("200 OK", METADATA)
+            } else {
+                ("403 Forbidden", "")
+            };
+            TestServer::response(
+                status,
+                &[
+                    ("Content-Type", "application/json"),
+                    ("Connection", "close"),
+                ],
+                body.as_bytes(),
+            )
+        })
+        .await
+        .url("/1.json");
+
+        assert!(fetcher().fetch(&uri).await.is_err());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Origin", "https://arkproject.dev".parse().unwrap());
+        let fetcher =
+            fetcher().with_host_headers(HostHeaders::new().with_host("127.0.0.1", headers));
+
+        assert_eq!(fetcher.fetch(&uri).await.unwrap(), METADATA);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_fails_fast_with_open_circuit() {
+        use crate::circuit_breaker::CircuitBreakerConfig;
+
+        // Nothing listens on this port once the listener is dropped.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}/1.json", listener.local_addr().unwrap());
+        drop(listener);
+
+        let circuit_breaker = Arc::new(HostCircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            cooldown: Duration::from_secs(60),
+        }));
+        let fetcher = fetcher().with_circuit_breaker(Arc::clone(&circuit_breaker));
+
+        for _ in 0..2 {
+            let e = fetcher.fetch(&uri).await.unwrap_err();
+            assert!(e.downcast_ref::<MetadataFetchError>().is_none());
+        }
+
+        let e = fetcher.fetch(&uri).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<MetadataFetchError>(),
+            Some(MetadataFetchError::CircuitOpen { .. })
+        ));
+    }
+
+    #[test]
+    fn test_looks_like_json() {
+        assert!(looks_like_json("\u{feff} {\"name\":\"Duck\"}"));
+        assert!(looks_like_json("[]"));
+        assert!(!looks_like_json("Rate limit exceeded"));
+        assert!(!looks_like_json("<?xml version=\"1.0\"?><Error></Error>"));
+    }
+
+    #[test]
+    fn test_is_html_response() {
+        assert!(is_html_response(Some("text/html; charset=utf-8"), "{}"));
+        assert!(is_html_response(None, "  <!doctype html><html></html>"));
+        assert!(is_html_response(Some("text/plain"), "<HTML></HTML>"));
+        assert!(!is_html_response(
+            Some("application/json"),
+            r#"{"name":"<html>"}"#
+        ));
+    }
+}
@@ -1,16 +1,43 @@
 use crate::{
+    circuit_breaker::HostCircuitBreaker,
+    fetch_limiter::CollectionFetchLimiter,
     file_manager::{FileInfo, FileManager},
-    storage::Storage,
-    types::StorageError,
-    utils::{extract_metadata_from_headers, file_extension_from_mime_type, get_token_metadata},
+    identicon::identicon_data_uri,
+    image_processing::media_mime_type,
+    media_key::{MediaKeyTemplate, MediaKeyTemplateError},
+    metadata_cache::MetadataCache,
+    metadata_fetcher::{HostHeaders, HttpMetadataFetcher, MetadataFetcher},
+    single_flight::{InFlightFetches, SingleFlightFetcher},
+    storage::{Storage, DEFAULT_TOKEN_PAGE_SIZE},
+    types::{
+        AttributeOrder, BaseUriSuffix, CollectionMetadata, DuplicateTraitPolicy, FallbackImage,
+        ImageThumbnail, NormalizationProfile, NormalizedMetadata, StorageError, TokenIdRange,
+        TokenMetadata,
+    },
+    utils::{
+        apply_attributes_path, apply_duplicate_trait_policy, apply_normalization_profile,
+        clean_attributes, compress_raw_metadata, decode_data_uri, decompress_raw_metadata,
+        extract_metadata_from_headers, file_extension_from_mime_type, get_token_metadata,
+        keep_stored_media, metadata_content_hash, normalize_collection_metadata,
+        raw_metadata_value, resolve_base_token_uri, resolve_gateway_uri, sort_attributes,
+    },
 };
 use anyhow::{anyhow, Result};
-use ark_starknet::{cairo_string_parser::parse_cairo_string, client::StarknetClient, CairoU256};
-use reqwest::Client as ReqwestClient;
+use ark_starknet::{
+    cairo_string_parser::parse_cairo_string, client::StarknetClient, format::log_preview, CairoU256,
+};
+use reqwest::{
+    header::{HeaderMap, CONTENT_TYPE},
+    Client as ReqwestClient, ClientBuilder, StatusCode,
+};
 use starknet::core::types::{BlockId, BlockTag, FieldElement};
 use starknet::macros::selector;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn};
+
+pub use crate::utils::{normalize_metadata, DEFAULT_ARWEAVE_GATEWAY_URI};
 
 /// `MetadataManager` is responsible for managing metadata information related to tokens.
 /// It works with the underlying storage and Starknet client to fetch and update token metadata.
@@ -18,7 +45,11 @@ pub struct MetadataManager<'a, T: Storage, C: StarknetClient, F: FileManager> {
     storage: &'a T,
     starknet_client: &'a C,
     request_client: ReqwestClient,
+    /// Client without automatic decompression, for the bogus `Content-Encoding`.
+    identity_request_client: ReqwestClient,
     file_manager: &'a F,
+    metadata_fetcher: Option<&'a dyn MetadataFetcher>,
+    config: MetadataManagerConfig,
 }
 
 pub struct MetadataMedia {
@@ -26,14 +57,237 @@ pub struct MetadataMedia {
     pub content_length: u64,
     pub is_cache_updated: bool,
     pub media_key: Option<String>,
+    /// Key of the PNG generated from an SVG media, if any.
+    pub raster_media_key: Option<String>,
+    /// Key of the WebP converted from a large PNG or JPEG media, if any.
+    pub webp_media_key: Option<String>,
+    /// Perceptual hash of a raster image media, as 16 hex characters.
+    pub perceptual_hash: Option<String>,
+    /// Resized variants of a raster image media.
+    pub thumbnails: Vec<ImageThumbnail>,
+}
+
+/// Normalized metadata of a token before and after its reprocessing.
+#[derive(Debug)]
+pub struct TokenMetadataReprocessing {
+    pub status: MetadataRefreshStatus,
+    pub before: Option<NormalizedMetadata>,
+    pub after: Option<NormalizedMetadata>,
+}
+
+/// Result of a token metadata refresh.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MetadataRefreshStatus {
+    /// The metadata changed, and were saved.
+    Updated,
+    /// The metadata didn't change since the last refresh, nothing was saved.
+    Unchanged,
+    /// The metadata fetch is disabled, the token was marked with the
+    /// `METADATA_STATUS_SKIPPED` status.
+    Skipped,
 }
 
+/// Default maximum size (in bytes) of the downloaded media.
+pub const DEFAULT_MAX_MEDIA_SIZE: u64 = 50 * 1024 * 1024;
+
+/// Default maximum number of idle connections kept open to each host.
+pub const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 64;
+
+/// Default time an idle connection is kept open.
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Default size (in bytes) up to which the raw metadata are still stored
+/// inline when offloaded, see `RawMetadataOffload`.
+pub const DEFAULT_RAW_METADATA_INLINE_MAX_SIZE: usize = 4 * 1024;
+
+/// Default directory of the offloaded raw metadata.
+pub const DEFAULT_RAW_METADATA_DIR: &str = "raw_metadata";
+
+/// Metadata status of the tokens whose metadata are not fetched,
+/// as `MetadataManagerConfig::skip_metadata_fetch` is set.
+pub const METADATA_STATUS_SKIPPED: &str = "SKIPPED";
+
 #[derive(Copy, Clone)]
 pub enum ImageCacheOption {
     Save,
     DoNotSave,
 }
 
+/// Options of the HTTP client fetching the metadata and media.
+///
+/// The connections are reused between the requests to a same host, which
+/// matters during the backfills, most requests going to a few gateways.
+/// HTTP/2 is negotiated with the hosts supporting it, multiplexing the
+/// concurrent requests over one connection.
+#[derive(Debug, Clone, Default)]
+pub struct HttpClientConfig {
+    /// Maximum number of idle connections kept open to each host, to be
+    /// reused by the next requests. Should be about the number of concurrent
+    /// requests to a host. Defaults to `DEFAULT_POOL_MAX_IDLE_PER_HOST`.
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Time an idle connection is kept open. Defaults to `DEFAULT_POOL_IDLE_TIMEOUT`.
+    pub pool_idle_timeout: Option<Duration>,
+    /// Only uses HTTP/1.1, for the hosts misbehaving over HTTP/2.
+    pub http1_only: bool,
+}
+
+impl HttpClientConfig {
+    /// Returns a client builder with these options.
+    pub fn client_builder(&self) -> ClientBuilder {
+        let builder = ReqwestClient::builder()
+            .pool_max_idle_per_host(
+                self.pool_max_idle_per_host
+                    .unwrap_or(DEFAULT_POOL_MAX_IDLE_PER_HOST),
+            )
+            .pool_idle_timeout(self.pool_idle_timeout.unwrap_or(DEFAULT_POOL_IDLE_TIMEOUT));
+
+        if self.http1_only {
+            builder.http1_only()
+        } else {
+            builder.http2_adaptive_window(true)
+        }
+    }
+}
+
+/// Offload of the large raw token metadata to the `FileManager` (e.g. S3).
+///
+/// The raw metadata larger than `inline_max_size` are saved gzip-compressed as
+/// `{dir_path}/0x{contract address}/{token id}.json.gz`, and only their key is
+/// stored with the token, as `TokenMetadata::raw_key`, the `raw` metadata
+/// being left empty. They are read with `MetadataManager::read_raw_metadata`.
+#[derive(Debug, Clone, Default)]
+pub struct RawMetadataOffload {
+    /// Raw metadata up to this size (in bytes) are still stored inline.
+    /// Defaults to `DEFAULT_RAW_METADATA_INLINE_MAX_SIZE`.
+    pub inline_max_size: Option<usize>,
+    /// Directory of the saved raw metadata. Defaults to `DEFAULT_RAW_METADATA_DIR`.
+    pub dir_path: Option<String>,
+}
+
+/// Options to tune the processing of the media fetched by the `MetadataManager`.
+#[derive(Debug, Clone, Default)]
+pub struct MetadataManagerConfig {
+    /// When set, SVG images saved in cache are also rasterized into
+    /// a PNG of the given width (in pixels). The original SVG is kept.
+    /// Requires the `svg-raster` feature, ignored otherwise.
+    pub svg_raster_width: Option<u32>,
+    /// When set, caps the number of concurrent fetches of each collection.
+    /// Share the same limiter between the managers running concurrently.
+    pub collection_fetch_limiter: Option<Arc<CollectionFetchLimiter>>,
+    /// When set, PNG and JPEG images saved in cache larger than the given
+    /// size (in bytes) are also converted into a lossless WebP, saved as
+    /// `{token_id}.webp` if smaller than the original. The original is kept.
+    /// Requires the `webp-conversion` feature, ignored otherwise.
+    pub webp_conversion_min_size: Option<u64>,
+    /// Computes the perceptual hash (dHash) of the raster images saved in
+    /// cache, stored as `image_phash` to find the near-duplicate tokens.
+    /// Requires the `perceptual-hash` feature, ignored otherwise.
+    pub compute_perceptual_hash: bool,
+    /// Sizes (in pixels) of the WebP thumbnails generated for the raster
+    /// images saved in cache, each one saved as `{token_id}/{size}.webp`.
+    /// Requires the `thumbnails` feature, ignored otherwise.
+    pub thumbnail_sizes: Vec<u32>,
+    /// Layout of the keys of the saved token media and their conversions and
+    /// thumbnails, see `media_key`. Defaults to `{token_id}.{ext}`, and
+    /// `{token_id}/{size}.webp` for the thumbnails. The collection images
+    /// are still saved as `collection/{name}.{ext}`.
+    pub media_key_template: Option<MediaKeyTemplate>,
+    /// IPFS gateways used, in order, when the gateway given to
+    /// `refresh_token_metadata` fails or returns an HTML page.
+    pub ipfs_fallback_gateways: Vec<String>,
+    /// Gateway used to fetch the `ar://` metadata and media, with a trailing
+    /// slash. Defaults to `DEFAULT_ARWEAVE_GATEWAY_URI`. The `ar://` URIs are
+    /// stored as is, only the requests use the gateway.
+    pub arweave_gateway_uri: Option<String>,
+    /// Arweave gateways used, in order, when `arweave_gateway_uri` fails
+    /// or doesn't return a JSON document, with a trailing slash.
+    pub arweave_fallback_gateways: Vec<String>,
+    /// Keeps the attributes appearing several times with the same `trait_type`
+    /// and value, for collections intentionally repeating them.
+    pub keep_duplicate_attributes: bool,
+    /// Policy applied to the attributes sharing the same `trait_type`.
+    pub duplicate_trait_policy: DuplicateTraitPolicy,
+    /// Order of the saved attributes. Defaults to `AttributeOrder::Source`.
+    pub attribute_order: AttributeOrder,
+    /// Image saved as the `image` of the tokens without image, or whose
+    /// image is not found (`404` or `410`), flagged with `image_is_placeholder`.
+    /// The fallback image itself is never fetched.
+    pub fallback_image: Option<FallbackImage>,
+    /// Field name overrides of the collections deviating from the metadata
+    /// standard, by contract address. The other collections use the standard keys.
+    pub normalization_profiles: HashMap<FieldElement, NormalizationProfile>,
+    /// Suffix appended to the base token URIs (ending with a slash) of the
+    /// collections, by contract address. Defaults to `BaseUriSuffix::TokenId`.
+    pub base_uri_suffixes: HashMap<FieldElement, BaseUriSuffix>,
+    /// Token ids refreshed by `refresh_collection_token_metadata` and
+    /// `reindex_collection_token_metadata`, by contract address. The other
+    /// tokens of those collections are skipped. The collections without
+    /// range have all their tokens refreshed.
+    pub token_id_ranges: HashMap<FieldElement, TokenIdRange>,
+    /// Number of token ids read at once when reindexing a collection.
+    /// Defaults to `DEFAULT_TOKEN_PAGE_SIZE`.
+    pub token_page_size: Option<usize>,
+    /// Headers sent with every metadata and media request, like the API key
+    /// of a gated metadata host. They can be read from the environment using
+    /// `ark_starknet::client::http::parse_headers`.
+    /// The values are marked as sensitive, and are never logged.
+    pub request_headers: HeaderMap,
+    /// Headers sent to some metadata and media hosts, replacing the
+    /// `request_headers` with the same name, for the gateways requiring
+    /// specific headers. The values are marked as sensitive, and are never logged.
+    pub host_headers: HostHeaders,
+    /// When set, the metadata requests to a host failing repeatedly fail fast
+    /// for a cooldown, see `circuit_breaker`. Share the same breaker between
+    /// the managers running concurrently.
+    pub circuit_breaker: Option<Arc<HostCircuitBreaker>>,
+    /// Transfers-only mode: the token and contract URIs are never read, and
+    /// no metadata or media are fetched. The refreshed tokens are only marked
+    /// with the `METADATA_STATUS_SKIPPED` status.
+    pub skip_metadata_fetch: bool,
+    /// When set, the token and collection metadata documents are read from
+    /// this on-disk cache before being fetched, and saved in it once fetched,
+    /// see `metadata_cache`.
+    pub metadata_cache: Option<Arc<MetadataCache>>,
+    /// Maximum size (in bytes) of the downloaded media, the larger ones being
+    /// rejected with `MediaDownloadError::TooLarge` without being read entirely.
+    /// Defaults to `DEFAULT_MAX_MEDIA_SIZE`.
+    pub max_media_size: Option<u64>,
+    /// When set, the concurrent fetches of a same metadata URI share one
+    /// request and its result, see `single_flight`. Share the same instance
+    /// between the managers running concurrently.
+    pub in_flight_fetches: Option<Arc<InFlightFetches>>,
+    /// Path of the attributes array in the raw token metadata, as dotted keys
+    /// (`metadata.attributes`) or a JSON pointer (`/data/attributes`), for the
+    /// collections nesting them. Defaults to `DEFAULT_ATTRIBUTES_PATH`. The
+    /// `attributes` override of a normalization profile takes precedence.
+    pub attributes_path: Option<String>,
+    /// Connection pool and HTTP/2 options of the HTTP client.
+    pub http_client: HttpClientConfig,
+    /// When set, the large raw token metadata are offloaded to the
+    /// `FileManager`, see `RawMetadataOffload`. They are stored inline otherwise.
+    pub raw_metadata_offload: Option<RawMetadataOffload>,
+    /// Keeps the raw token metadata as a string in `TokenMetadata::raw`, along
+    /// with their JSON value in `TokenMetadata::raw_value`, for the consumers
+    /// depending on it. Only the JSON value is stored otherwise.
+    pub keep_raw_metadata_string: bool,
+}
+
+impl MetadataManagerConfig {
+    /// Checks the options depending on each other, to be called on startup.
+    /// The thumbnails require the `{size}` placeholder in `media_key_template`,
+    /// to not overwrite the media.
+    pub fn validate(&self) -> Result<(), MediaKeyTemplateError> {
+        match &self.media_key_template {
+            Some(template)
+                if !self.thumbnail_sizes.is_empty() && !template.has_placeholder("size") =>
+            {
+                Err(MediaKeyTemplateError::MissingPlaceholder("size"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 /// Represents possible errors that can arise while working with metadata in the manager.
 #[derive(Debug, thiserror::Error)]
 pub enum MetadataError {
@@ -51,17 +305,120 @@ pub enum MetadataError {
 
     #[error("Required environment variable is missing: {0}")]
     EnvVarMissingError(String),
+
+    #[error("Failed to build the HTTP client: {0}")]
+    HttpClientError(String),
+}
+
+/// Error of a media request answered with `404 Not Found` or `410 Gone`.
+#[derive(Debug, thiserror::Error)]
+#[error("Media not found: {0}")]
+pub struct MediaNotFoundError(pub String);
+
+/// Error of a media download exceeding its limits. The token metadata are
+/// still saved, without the media.
+#[derive(Debug, thiserror::Error)]
+pub enum MediaDownloadError {
+    #[error("Media larger than {max_size} bytes: {url}")]
+    TooLarge { url: String, max_size: u64 },
+
+    #[error("Media download timed out after {timeout:?}: {url}")]
+    Timeout { url: String, timeout: Duration },
+}
+
+/// Returns a `MediaNotFoundError` if the media request was answered with `404` or `410`.
+fn check_media_found(status: StatusCode, url: &str) -> Result<()> {
+    if status == StatusCode::NOT_FOUND || status == StatusCode::GONE {
+        return Err(MediaNotFoundError(log_preview(url).to_string()).into());
+    }
+    Ok(())
+}
+
+/// Returns true if the media can't be fetched by retrying: not found, or too large.
+fn is_final_media_error(err: &anyhow::Error) -> bool {
+    err.is::<MediaNotFoundError>()
+        || matches!(
+            err.downcast_ref::<MediaDownloadError>(),
+            Some(MediaDownloadError::TooLarge { .. })
+        )
+}
+
+/// Converts the timeouts of a media request into a `MediaDownloadError`.
+fn media_request_error(err: reqwest::Error, url: &str, timeout: Duration) -> anyhow::Error {
+    if err.is_timeout() {
+        MediaDownloadError::Timeout {
+            url: log_preview(url).to_string(),
+            timeout,
+        }
+        .into()
+    } else {
+        err.into()
+    }
 }
 
 impl<'a, T: Storage, C: StarknetClient, F: FileManager> MetadataManager<'a, T, C, F> {
     /// Creates a new instance of `MetadataManager` with the given storage, Starknet client, and a new request client.
-    pub fn new(storage: &'a T, starknet_client: &'a C, file_manager: &'a F) -> Self {
-        MetadataManager {
+    pub fn new(
+        storage: &'a T,
+        starknet_client: &'a C,
+        file_manager: &'a F,
+    ) -> Result<Self, MetadataError> {
+        Self::with_config(
             storage,
             starknet_client,
-            request_client: ReqwestClient::new(),
             file_manager,
-        }
+            MetadataManagerConfig::default(),
+        )
+    }
+
+    /// Creates a new instance of `MetadataManager` using the given media processing options.
+    /// Fails with `MetadataError::HttpClientError` if the HTTP client can't be built
+    /// from the options, like a TLS backend failing to initialize.
+    pub fn with_config(
+        storage: &'a T,
+        starknet_client: &'a C,
+        file_manager: &'a F,
+        mut config: MetadataManagerConfig,
+    ) -> Result<Self, MetadataError> {
+        config
+            .request_headers
+            .values_mut()
+            .for_each(|v| v.set_sensitive(true));
+
+        // The gzip, brotli and deflate responses are decompressed automatically.
+        let request_client = config
+            .http_client
+            .client_builder()
+            .default_headers(config.request_headers.clone())
+            .build()
+            .map_err(|e| MetadataError::HttpClientError(e.to_string()))?;
+
+        let identity_request_client = config
+            .http_client
+            .client_builder()
+            .default_headers(config.request_headers.clone())
+            .no_gzip()
+            .no_brotli()
+            .no_deflate()
+            .build()
+            .map_err(|e| MetadataError::HttpClientError(e.to_string()))?;
+
+        Ok(MetadataManager {
+            storage,
+            starknet_client,
+            request_client,
+            identity_request_client,
+            file_manager,
+            metadata_fetcher: None,
+            config,
+        })
+    }
+
+    /// Uses the given fetcher to fetch the token and collection metadata,
+    /// instead of requesting them over HTTP.
+    pub fn with_metadata_fetcher(mut self, metadata_fetcher: &'a dyn MetadataFetcher) -> Self {
+        self.metadata_fetcher = Some(metadata_fetcher);
+        self
     }
 
     /// Refreshes the metadata for a specific token within a given collection.
@@ -76,7 +433,21 @@ impl<'a, T: Storage, C: StarknetClient, F: FileManager> MetadataManager<'a, T, C
     /// - `cache`: Specifies whether the token's image should be cached.
     ///
     /// # Returns
-    /// - A `Result` indicating the success or failure of the metadata refresh operation.
+    /// - A `Result` indicating if the metadata were updated, or unchanged since the
+    ///   last refresh. Unchanged metadata are not saved, and their media are not fetched.
+    ///   The metadata are saved without their hash if a media failed, to fetch it again
+    ///   at the next refresh.
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "token_metadata",
+            skip_all,
+            fields(
+                collection_address = %format!("0x{:064x}", contract_address),
+                token_id = %token_id.to_decimal(false),
+            )
+        )
+    )]
     pub async fn refresh_token_metadata(
         &mut self,
         contract_address: FieldElement,
@@ -85,13 +456,22 @@ impl<'a, T: Storage, C: StarknetClient, F: FileManager> MetadataManager<'a, T, C
         ipfs_gateway_uri: &str,
         image_timeout: Duration,
         request_referrer: &str,
-    ) -> Result<(), MetadataError> {
+    ) -> Result<MetadataRefreshStatus, MetadataError> {
         trace!(
             "refresh_token_metadata(contract_address=0x{:064x}, token_id={})",
             contract_address,
             token_id.to_decimal(false),
         );
 
+        if self.config.skip_metadata_fetch {
+            self.storage
+                .update_token_metadata_status(contract_address, token_id, METADATA_STATUS_SKIPPED)
+                .await
+                .map_err(MetadataError::DatabaseError)?;
+
+            return Ok(MetadataRefreshStatus::Skipped);
+        }
+
         let token_uri = self
             .get_token_uri(&token_id, contract_address)
             .await
@@ -99,28 +479,89 @@ impl<'a, T: Storage, C: StarknetClient, F: FileManager> MetadataManager<'a, T, C
 
         trace!("Token URI: {}", token_uri);
 
+        let _fetch_permit = match &self.config.collection_fetch_limiter {
+            Some(limiter) => Some(limiter.acquire(contract_address).await),
+            None => None,
+        };
+
+        let ipfs_gateway_uris: Vec<&str> = std::iter::once(ipfs_gateway_uri)
+            .chain(
+                self.config
+                    .ipfs_fallback_gateways
+                    .iter()
+                    .map(String::as_str),
+            )
+            .collect();
+
+        let http_fetcher = self.http_fetcher(image_timeout, request_referrer);
         let mut token_metadata = get_token_metadata(
-            &self.request_client,
+            &self.fetcher(&http_fetcher),
             token_uri.as_str(),
-            ipfs_gateway_uri,
-            image_timeout,
-            request_referrer,
+            &ipfs_gateway_uris,
+            &self.arweave_gateway_uris(),
+            self.config.metadata_cache.as_deref(),
         )
         .await
         .map_err(|err| MetadataError::RequestTokenUriError(err.to_string()))?;
 
+        self.apply_normalization_rules(contract_address, &mut token_metadata, &token_uri);
+
+        let content_hash = metadata_content_hash(&token_metadata.normalized)
+            .map_err(|err| MetadataError::ParsingError(err.to_string()))?;
+
+        let stored_hash = self
+            .storage
+            .get_token_metadata_hash(contract_address, token_id.clone())
+            .await
+            .map_err(MetadataError::DatabaseError)?;
+
+        if stored_hash.as_ref() == Some(&content_hash) {
+            debug!(
+                "Metadata of token {} unchanged, skipping",
+                token_id.to_decimal(false)
+            );
+            return Ok(MetadataRefreshStatus::Unchanged);
+        }
+
+        let mut has_image = token_metadata.normalized.image.is_some()
+            || token_metadata.normalized.image_data.is_some();
+        let mut media_complete = true;
+
         // Check if there is an image to fetch in the metadata.
         if let Some(image_uri) = &token_metadata.normalized.image {
-            if let Ok(metadata_image) = self
+            let media = self
                 .fetch_metadata_media(
                     image_uri.as_str(),
                     cache,
+                    contract_address,
                     &token_id,
                     image_timeout,
                     ipfs_gateway_uri,
                 )
-                .await
-            {
+                .await;
+
+            if let Err(err) = &media {
+                if err.is::<MediaNotFoundError>() {
+                    warn!(
+                        "Image of token {} not found: {}",
+                        token_id.to_decimal(false),
+                        err
+                    );
+                    has_image = false;
+                } else if err.is::<MediaDownloadError>() {
+                    warn!(
+                        "Image of token {} skipped: {}",
+                        token_id.to_decimal(false),
+                        err
+                    );
+                }
+
+                if !is_final_media_error(err) {
+                    media_complete = false;
+                }
+            }
+
+            if let Ok(metadata_image) = media {
                 let is_video_type = matches!(
                     metadata_image.file_type.as_str(),
                     "video/mpeg"
@@ -139,37 +580,209 @@ impl<'a, T: Storage, C: StarknetClient, F: FileManager> MetadataManager<'a, T, C
                     token_metadata.normalized.animation_key = metadata_image.media_key;
                 } else {
                     token_metadata.normalized.image_key = metadata_image.media_key.clone();
+                    token_metadata.normalized.image_raster_key =
+                        metadata_image.raster_media_key.clone();
+                    token_metadata.normalized.image_webp_key =
+                        metadata_image.webp_media_key.clone();
+                    token_metadata.normalized.image_phash = metadata_image.perceptual_hash.clone();
+                    if !metadata_image.thumbnails.is_empty() {
+                        token_metadata.normalized.image_thumbnails =
+                            Some(metadata_image.thumbnails.clone());
+                    }
                     token_metadata.normalized.image_mime_type =
                         Some(metadata_image.file_type.clone());
 
                     if let Some(animation_uri) = &token_metadata.normalized.animation_url {
-                        if let Ok(metadata_animation) = self
+                        match self
                             .fetch_metadata_media(
                                 animation_uri.as_str(),
                                 cache,
+                                contract_address,
                                 &token_id,
                                 image_timeout,
                                 ipfs_gateway_uri,
                             )
                             .await
                         {
-                            token_metadata.normalized.animation_mime_type =
-                                Some(metadata_animation.file_type);
-                            token_metadata.normalized.animation_url =
-                                Some(animation_uri.to_string());
-                            token_metadata.normalized.animation_key = metadata_animation.media_key;
+                            Ok(metadata_animation) => {
+                                token_metadata.normalized.animation_mime_type =
+                                    Some(metadata_animation.file_type);
+                                token_metadata.normalized.animation_url =
+                                    Some(animation_uri.to_string());
+                                token_metadata.normalized.animation_key =
+                                    metadata_animation.media_key;
+                            }
+                            Err(err) => {
+                                if !is_final_media_error(&err) {
+                                    media_complete = false;
+                                }
+                            }
                         }
                     }
                 }
             }
         }
 
+        if !has_image {
+            self.apply_fallback_image(&mut token_metadata.normalized, contract_address, &token_id);
+        }
+
+        // Without their hash, the same metadata are not skipped by the next refresh.
+        if media_complete {
+            token_metadata.content_hash = Some(content_hash);
+        }
+
+        self.offload_raw_metadata(contract_address, &token_id, &mut token_metadata)
+            .await;
+        self.set_raw_metadata_value(&mut token_metadata);
+
         self.storage
             .register_token_metadata(&contract_address, token_id, token_metadata)
             .await
             .map_err(MetadataError::DatabaseError)?;
 
-        Ok(())
+        Ok(MetadataRefreshStatus::Updated)
+    }
+
+    /// Saves the raw metadata with the `FileManager` if they are larger than the
+    /// inline size of `raw_metadata_offload`, keeping only their key. They are
+    /// stored inline if they can't be saved.
+    async fn offload_raw_metadata(
+        &self,
+        contract_address: FieldElement,
+        token_id: &CairoU256,
+        token_metadata: &mut TokenMetadata,
+    ) {
+        let offload = match &self.config.raw_metadata_offload {
+            Some(offload) => offload,
+            None => return,
+        };
+
+        let inline_max_size = offload
+            .inline_max_size
+            .unwrap_or(DEFAULT_RAW_METADATA_INLINE_MAX_SIZE);
+        if token_metadata.raw.len() <= inline_max_size {
+            return;
+        }
+
+        let result = match compress_raw_metadata(&token_metadata.raw) {
+            Ok(content) => {
+                self.file_manager
+                    .save(&FileInfo {
+                        name: format!("{}.json.gz", token_id.to_decimal(false)),
+                        content,
+                        dir_path: Some(format!(
+                            "{}/0x{:064x}",
+                            offload
+                                .dir_path
+                                .as_deref()
+                                .unwrap_or(DEFAULT_RAW_METADATA_DIR)
+                                .trim_end_matches('/'),
+                            contract_address
+                        )),
+                    })
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(key) => {
+                debug!(
+                    "Raw metadata of token {} offloaded to {} ({} bytes)",
+                    token_id.to_decimal(false),
+                    key,
+                    token_metadata.raw.len()
+                );
+                token_metadata.raw_key = Some(key);
+                token_metadata.raw = String::new();
+            }
+            Err(e) => warn!(
+                "Failed to offload the raw metadata of token {}, stored inline: {}",
+                token_id.to_decimal(false),
+                e
+            ),
+        }
+    }
+
+    /// Stores the inline raw metadata as a JSON value, dropping their string
+    /// form unless `keep_raw_metadata_string` is set. The raw metadata which
+    /// are not a JSON document, or can't be represented exactly as a value,
+    /// are kept as a string.
+    fn set_raw_metadata_value(&self, token_metadata: &mut TokenMetadata) {
+        if token_metadata.raw_key.is_some() {
+            return;
+        }
+
+        token_metadata.raw_value = raw_metadata_value(&token_metadata.raw);
+        if token_metadata.raw_value.is_some() && !self.config.keep_raw_metadata_string {
+            token_metadata.raw = String::new();
+        }
+    }
+
+    /// Returns the raw metadata of a token, read from the `FileManager` if
+    /// they were offloaded (see `RawMetadataOffload`), or else stored inline,
+    /// as a string or a JSON value.
+    pub async fn read_raw_metadata(
+        &self,
+        token_metadata: &TokenMetadata,
+    ) -> Result<String, MetadataError> {
+        let key = match (&token_metadata.raw_key, &token_metadata.raw_value) {
+            (Some(key), _) => key,
+            (None, Some(value)) if token_metadata.raw.is_empty() => return Ok(value.to_string()),
+            (None, _) => return Ok(token_metadata.raw.clone()),
+        };
+
+        let content = self
+            .file_manager
+            .read(key)
+            .await
+            .map_err(|err| MetadataError::ParsingError(format!("{}: {}", key, err)))?;
+
+        decompress_raw_metadata(&content)
+            .map_err(|err| MetadataError::ParsingError(format!("{}: {}", key, err)))
+    }
+
+    /// Reprocesses the metadata of a single token, like `refresh_token_metadata`,
+    /// returning the stored normalized metadata before and after the refresh.
+    /// Useful to fix one token and check the result without reindexing its collection.
+    pub async fn reprocess_token_metadata(
+        &mut self,
+        contract_address: FieldElement,
+        token_id: CairoU256,
+        cache: ImageCacheOption,
+        ipfs_gateway_uri: &str,
+        image_timeout: Duration,
+        request_referrer: &str,
+    ) -> Result<TokenMetadataReprocessing, MetadataError> {
+        let before = self
+            .storage
+            .get_token_metadata(contract_address, token_id.clone())
+            .await
+            .map_err(MetadataError::DatabaseError)?;
+
+        let status = self
+            .refresh_token_metadata(
+                contract_address,
+                token_id.clone(),
+                cache,
+                ipfs_gateway_uri,
+                image_timeout,
+                request_referrer,
+            )
+            .await?;
+
+        let after = self
+            .storage
+            .get_token_metadata(contract_address, token_id)
+            .await
+            .map_err(MetadataError::DatabaseError)?;
+
+        Ok(TokenMetadataReprocessing {
+            status,
+            before: before.map(|m| m.normalized),
+            after: after.map(|m| m.normalized),
+        })
     }
 
     /// Refreshes the metadata for all tokens in a given collection.
@@ -198,6 +811,14 @@ impl<'a, T: Storage, C: StarknetClient, F: FileManager> MetadataManager<'a, T, C
             .map_err(MetadataError::DatabaseError)?;
 
         for (contract_address, token_id) in results {
+            if !self.is_in_token_id_range(contract_address, &token_id) {
+                trace!(
+                    "Token {} out of the token id range, skipping it",
+                    token_id.to_decimal(false)
+                );
+                continue;
+            }
+
             self.refresh_token_metadata(
                 contract_address,
                 token_id,
@@ -212,6 +833,248 @@ impl<'a, T: Storage, C: StarknetClient, F: FileManager> MetadataManager<'a, T, C
         Ok(())
     }
 
+    /// Refreshes the metadata of all the tokens of a collection, including the
+    /// ones already having metadata.
+    ///
+    /// Tokens are read by pages of `MetadataManagerConfig::token_page_size`
+    /// and processed by ascending token id. The last processed token id
+    /// is saved as a checkpoint of the given job. If the reindex is interrupted,
+    /// running it again with the same `job_id` resumes after the checkpoint.
+    /// The checkpoint is deleted once all the tokens are processed, running
+    /// the same job again starts from the first token.
+    ///
+    /// # Parameters
+    /// - `contract_address`: The address of the contract representing the token collection.
+    /// - `job_id`: The identifier of the reindex job, used to store its checkpoint.
+    /// - `cache`: Specifies whether the token's image should be cached.
+    ///
+    /// # Returns
+    /// - A `Result` indicating the success or failure of the reindex operation.
+    pub async fn reindex_collection_token_metadata(
+        &mut self,
+        contract_address: FieldElement,
+        job_id: &str,
+        cache: ImageCacheOption,
+        ipfs_gateway_uri: &str,
+        image_timeout: Duration,
+        request_referrer: &str,
+    ) -> Result<(), MetadataError> {
+        let checkpoint = self
+            .storage
+            .get_reindex_checkpoint(contract_address, job_id)
+            .await
+            .map_err(MetadataError::DatabaseError)?;
+
+        info!(
+            "Reindexing collection 0x{:064x} (job {}), resuming after token {}",
+            contract_address,
+            job_id,
+            checkpoint
+                .as_ref()
+                .map_or("none".to_string(), |c| c.to_decimal(false)),
+        );
+
+        let page_size = self
+            .config
+            .token_page_size
+            .unwrap_or(DEFAULT_TOKEN_PAGE_SIZE);
+        let range = self.config.token_id_ranges.get(&contract_address).cloned();
+
+        // The tokens before the range are not read.
+        let mut last_evaluated_key = match (checkpoint, range.as_ref()) {
+            (Some(checkpoint), Some(range)) if range.is_before(&checkpoint) => {
+                range.key_before_start()
+            }
+            (None, Some(range)) => range.key_before_start(),
+            (checkpoint, _) => checkpoint,
+        };
+        let (mut updated, mut unchanged) = (0, 0);
+
+        'pages: loop {
+            let page = self
+                .storage
+                .find_token_ids(contract_address, last_evaluated_key, page_size)
+                .await
+                .map_err(MetadataError::DatabaseError)?;
+
+            for token_id in page.token_ids {
+                // The tokens are read by ascending token id.
+                if range.as_ref().map_or(false, |r| r.is_after(&token_id)) {
+                    break 'pages;
+                }
+
+                match self
+                    .refresh_token_metadata(
+                        contract_address,
+                        token_id.clone(),
+                        cache,
+                        ipfs_gateway_uri,
+                        image_timeout,
+                        request_referrer,
+                    )
+                    .await?
+                {
+                    MetadataRefreshStatus::Updated => updated += 1,
+                    MetadataRefreshStatus::Unchanged | MetadataRefreshStatus::Skipped => {
+                        unchanged += 1
+                    }
+                }
+
+                self.storage
+                    .set_reindex_checkpoint(contract_address, job_id, token_id)
+                    .await
+                    .map_err(MetadataError::DatabaseError)?;
+            }
+
+            last_evaluated_key = page.last_evaluated_key;
+            if last_evaluated_key.is_none() {
+                break;
+            }
+        }
+
+        self.storage
+            .delete_reindex_checkpoint(contract_address, job_id)
+            .await
+            .map_err(MetadataError::DatabaseError)?;
+
+        info!(
+            "Reindex of collection 0x{:064x} (job {}) done: {} updated, {} unchanged",
+            contract_address, job_id, updated, unchanged
+        );
+
+        Ok(())
+    }
+
+    /// Normalizes again the stored metadata of all the tokens of a collection
+    /// with the current normalization rules, like after a parsing improvement,
+    /// without fetching the metadata or their media again.
+    ///
+    /// The normalized metadata are rebuilt from the stored raw metadata, keeping
+    /// the saved media of the unchanged media URLs, and only saved if they changed.
+    /// Tokens without stored metadata, or whose raw metadata is not a JSON
+    /// document or can't be read (see `read_raw_metadata`), are skipped.
+    /// The relative URLs can't be resolved without the metadata URI, and are
+    /// kept as stored.
+    ///
+    /// # Returns
+    /// - The number of tokens whose normalized metadata changed.
+    pub async fn renormalize_collection(
+        &self,
+        contract_address: FieldElement,
+    ) -> Result<usize, MetadataError> {
+        let page_size = self
+            .config
+            .token_page_size
+            .unwrap_or(DEFAULT_TOKEN_PAGE_SIZE);
+        let mut last_evaluated_key = None;
+        let (mut updated, mut unchanged, mut skipped) = (0, 0, 0);
+
+        loop {
+            let page = self
+                .storage
+                .find_token_ids(contract_address, last_evaluated_key, page_size)
+                .await
+                .map_err(MetadataError::DatabaseError)?;
+
+            for token_id in page.token_ids {
+                let stored = self
+                    .storage
+                    .get_token_metadata(contract_address, token_id.clone())
+                    .await
+                    .map_err(MetadataError::DatabaseError)?;
+
+                let stored = match stored {
+                    Some(stored) => stored,
+                    None => {
+                        skipped += 1;
+                        continue;
+                    }
+                };
+
+                let raw = match self.read_raw_metadata(&stored).await {
+                    Ok(raw) => serde_json::from_str::<serde_json::Value>(&raw).ok(),
+                    Err(e) => {
+                        warn!(
+                            "Failed to read the raw metadata of token {}: {}",
+                            token_id.to_decimal(false),
+                            e
+                        );
+                        None
+                    }
+                };
+                let raw = match raw {
+                    Some(raw) => raw,
+                    None => {
+                        skipped += 1;
+                        continue;
+                    }
+                };
+
+                let mut token_metadata = TokenMetadata {
+                    normalized: normalize_metadata(&raw, ""),
+                    ..stored.clone()
+                };
+                self.apply_normalization_rules(contract_address, &mut token_metadata, "");
+
+                let content_hash = metadata_content_hash(&token_metadata.normalized)
+                    .map_err(|err| MetadataError::ParsingError(err.to_string()))?;
+                if stored.content_hash.as_ref() == Some(&content_hash) {
+                    unchanged += 1;
+                    continue;
+                }
+
+                token_metadata.content_hash = Some(content_hash);
+                keep_stored_media(&mut token_metadata.normalized, &stored.normalized);
+
+                self.storage
+                    .register_token_metadata(&contract_address, token_id, token_metadata)
+                    .await
+                    .map_err(MetadataError::DatabaseError)?;
+                updated += 1;
+            }
+
+            last_evaluated_key = page.last_evaluated_key;
+            if last_evaluated_key.is_none() {
+                break;
+            }
+        }
+
+        info!(
+            "Renormalization of collection 0x{:064x} done: {} updated, {} unchanged, {} skipped",
+            contract_address, updated, unchanged, skipped
+        );
+
+        Ok(updated)
+    }
+
+    /// Applies the normalization rules of the collection on top of
+    /// `normalize_metadata`: the path of the attributes, its normalization
+    /// profile, and the cleaning, deduplication and ordering of the attributes.
+    fn apply_normalization_rules(
+        &self,
+        contract_address: FieldElement,
+        token_metadata: &mut TokenMetadata,
+        token_uri: &str,
+    ) {
+        if let Some(path) = &self.config.attributes_path {
+            apply_attributes_path(token_metadata, path);
+        }
+
+        if let Some(profile) = self.config.normalization_profiles.get(&contract_address) {
+            apply_normalization_profile(token_metadata, profile, token_uri);
+        }
+
+        clean_attributes(
+            &mut token_metadata.normalized,
+            !self.config.keep_duplicate_attributes,
+        );
+        apply_duplicate_trait_policy(
+            &mut token_metadata.normalized,
+            self.config.duplicate_trait_policy,
+        );
+        sort_attributes(&mut token_metadata.normalized, self.config.attribute_order);
+    }
+
     /// Fetches the media for a given token and optionally caches it.
     ///
     /// Depending on the provided `CacheOption`, this function might directly fetch
@@ -227,73 +1090,662 @@ impl<'a, T: Storage, C: StarknetClient, F: FileManager> MetadataManager<'a, T, C
     /// # Returns
     /// - A `Result` containing `MetadataImage` which provides details about the fetched media,
     ///   or an error if the media fetch operation fails.
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "media_fetch",
+            skip_all,
+            fields(token_id = %token_id.to_decimal(false), url = %log_preview(raw_url))
+        )
+    )]
     pub async fn fetch_metadata_media(
         &mut self,
         raw_url: &str,
         cache: ImageCacheOption,
+        contract_address: FieldElement,
         token_id: &CairoU256,
         timeout: Duration,
         ipfs_url: &str,
     ) -> Result<MetadataMedia> {
-        info!("Fetching media... {}", raw_url);
-
-        let url = raw_url.replace("ipfs://", ipfs_url);
-
-        match cache {
-            ImageCacheOption::DoNotSave => {
-                let response = self.request_client.head(url).send().await?;
-                let (content_type, content_length) =
-                    extract_metadata_from_headers(response.headers())?;
+        info!("Fetching media... {}", log_preview(raw_url));
+        trace!("Media URL: {}", raw_url);
+
+        if let (ImageCacheOption::DoNotSave, false) = (cache, raw_url.starts_with("data:")) {
+            let url = resolve_gateway_uri(raw_url, ipfs_url, self.arweave_gateway_uri());
+            let request = self.request_client.head(&url);
+            let response = self
+                .config
+                .host_headers
+                .apply(request, &url)
+                .timeout(timeout)
+                .send()
+                .await
+                .map_err(|e| media_request_error(e, &url, timeout))?;
+            check_media_found(response.status(), &url)?;
+            let (content_type, content_length) = extract_metadata_from_headers(response.headers())?;
+
+            return Ok(MetadataMedia {
+                file_type: content_type,
+                content_length,
+                is_cache_updated: false,
+                media_key: None,
+                raster_media_key: None,
+                webp_media_key: None,
+                perceptual_hash: None,
+                thumbnails: vec![],
+            });
+        }
 
-                Ok(MetadataMedia {
-                    file_type: content_type,
-                    content_length,
-                    is_cache_updated: false,
-                    media_key: None,
-                })
-            }
-            ImageCacheOption::Save => {
-                let response = self.request_client.get(url).timeout(timeout).send().await?;
+        let (content_type, content) = self.download_media(raw_url, timeout, ipfs_url).await?;
 
-                let headers = response.headers().clone();
-                let bytes = response.bytes().await?;
-                let (content_type, content_length) = extract_metadata_from_headers(&headers)?;
+        self.save_media(content_type, content, cache, contract_address, token_id)
+            .await
+    }
 
-                info!(
-                    "Image: Content-Type={}, Content-Length={}",
-                    content_type, content_length
-                );
+    /// Replaces the image of the token with the fallback image, if any,
+    /// flagging it as a placeholder.
+    fn apply_fallback_image(
+        &self,
+        metadata: &mut NormalizedMetadata,
+        contract_address: FieldElement,
+        token_id: &CairoU256,
+    ) {
+        let (image, mime_type) = match &self.config.fallback_image {
+            Some(FallbackImage::Url(url)) => (url.clone(), None),
+            Some(FallbackImage::Identicon) => (
+                identicon_data_uri(contract_address, token_id),
+                Some("image/svg+xml".to_string()),
+            ),
+            None => return,
+        };
+
+        metadata.image = Some(image);
+        metadata.image_mime_type = mime_type;
+        metadata.image_key = None;
+        metadata.image_is_placeholder = true;
+    }
 
-                let file_ext = file_extension_from_mime_type(content_type.as_str());
+    /// Returns true if the token is in the token id range of its collection, if any.
+    fn is_in_token_id_range(&self, contract_address: FieldElement, token_id: &CairoU256) -> bool {
+        self.config
+            .token_id_ranges
+            .get(&contract_address)
+            .map_or(true, |range| range.contains(token_id))
+    }
 
-                debug!(
-                    "Image: Content-Type={}, Content-Length={}, File-Ext={}",
-                    content_type, content_length, file_ext
-                );
+    /// Fetcher of the metadata: the given fetcher, or the HTTP one, sharing
+    /// the fetches in flight if `MetadataManagerConfig::in_flight_fetches` is set.
+    fn fetcher<'f>(&'f self, http_fetcher: &'f HttpMetadataFetcher) -> SingleFlightFetcher<'f> {
+        SingleFlightFetcher::new(
+            self.metadata_fetcher.unwrap_or(http_fetcher),
+            self.config.in_flight_fetches.as_deref(),
+        )
+    }
 
-                let media_key = self
-                    .file_manager
-                    .save(&FileInfo {
-                        name: format!("{}.{}", token_id.to_decimal(false), file_ext),
-                        content: bytes.to_vec(),
-                        dir_path: None,
-                    })
-                    .await?;
+    /// Fetcher of the metadata over HTTP, used when no other fetcher is given.
+    fn http_fetcher(&self, timeout: Duration, referrer: &str) -> HttpMetadataFetcher {
+        let fetcher = HttpMetadataFetcher::new(self.request_client.clone(), timeout, referrer)
+            .with_identity_client(self.identity_request_client.clone())
+            .with_host_headers(self.config.host_headers.clone());
 
-                Ok(MetadataMedia {
-                    file_type: content_type,
-                    content_length,
-                    is_cache_updated: true,
-                    media_key: Some(media_key),
-                })
-            }
+        match &self.config.circuit_breaker {
+            Some(circuit_breaker) => fetcher.with_circuit_breaker(Arc::clone(circuit_breaker)),
+            None => fetcher,
         }
     }
 
+    /// Gateway used to fetch the `ar://` metadata and media.
+    fn arweave_gateway_uri(&self) -> &str {
+        self.config
+            .arweave_gateway_uri
+            .as_deref()
+            .unwrap_or(DEFAULT_ARWEAVE_GATEWAY_URI)
+    }
+
+    /// Gateways used to fetch the `ar://` metadata, in order.
+    fn arweave_gateway_uris(&self) -> Vec<&str> {
+        std::iter::once(self.arweave_gateway_uri())
+            .chain(
+                self.config
+                    .arweave_fallback_gateways
+                    .iter()
+                    .map(String::as_str),
+            )
+            .collect()
+    }
+
+    /// Downloads the media at the given URL, or decodes it if it's a data URI.
+    /// Returns the content type and the content of the media.
+    ///
+    /// The type of the images sent without type or with a generic one
+    /// (`application/octet-stream`...) is detected from their content.
+    ///
+    /// The download is aborted with a `MediaDownloadError` once it exceeds
+    /// the timeout or `MetadataManagerConfig::max_media_size`.
+    async fn download_media(
+        &self,
+        raw_url: &str,
+        timeout: Duration,
+        ipfs_url: &str,
+    ) -> Result<(String, Vec<u8>)> {
+        let max_size = self.config.max_media_size.unwrap_or(DEFAULT_MAX_MEDIA_SIZE);
+
+        if raw_url.starts_with("data:") {
+            let (content_type, content) = decode_data_uri(raw_url)?;
+            if content.len() as u64 > max_size {
+                return Err(MediaDownloadError::TooLarge {
+                    url: log_preview(raw_url).to_string(),
+                    max_size,
+                }
+                .into());
+            }
+            return Ok((media_mime_type(&content_type, &content), content));
+        }
+
+        let url = resolve_gateway_uri(raw_url, ipfs_url, self.arweave_gateway_uri());
+
+        // The timeout covers the whole download, a server sending the body
+        // slowly being as harmful as a server not answering.
+        match tokio::time::timeout(timeout, self.download_url(&url, max_size)).await {
+            Ok(result) => result,
+            Err(_) => Err(MediaDownloadError::Timeout {
+                url: log_preview(&url).to_string(),
+                timeout,
+            }
+            .into()),
+        }
+    }
+
+    /// Downloads the media at the given URL, reading at most `max_size` bytes.
+    async fn download_url(&self, url: &str, max_size: u64) -> Result<(String, Vec<u8>)> {
+        let request = self.request_client.get(url);
+        let mut response = self.config.host_headers.apply(request, url).send().await?;
+        check_media_found(response.status(), url)?;
+
+        let too_large = || MediaDownloadError::TooLarge {
+            url: log_preview(url).to_string(),
+            max_size,
+        };
+
+        if response.content_length().unwrap_or_default() > max_size {
+            return Err(too_large().into());
+        }
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+
+        // Read by chunks, the announced length being possibly missing or wrong.
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if (bytes.len() + chunk.len()) as u64 > max_size {
+                return Err(too_large().into());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        let content_type = media_mime_type(&content_type, &bytes);
+
+        info!(
+            "Image: Content-Type={}, Content-Length={}",
+            content_type,
+            bytes.len()
+        );
+
+        Ok((content_type, bytes))
+    }
+
+    /// Refreshes the metadata of a collection, read from its `contractURI`.
+    ///
+    /// The logo (`image`), `banner_image` and `featured_image` are saved using the
+    /// `FileManager` if the cache option requires it, and their keys are stored
+    /// with the collection metadata. Saving the images is best-effort: an image
+    /// that can't be fetched is logged and stored without key.
+    ///
+    /// Nothing is done if the collection metadata didn't change since the last refresh.
+    ///
+    /// # Parameters
+    /// - `contract_address`: The address of the contract representing the collection.
+    /// - `cache`: Specifies whether the collection images should be cached.
+    pub async fn refresh_collection_metadata(
+        &mut self,
+        contract_address: FieldElement,
+        cache: ImageCacheOption,
+        ipfs_gateway_uri: &str,
+        image_timeout: Duration,
+        request_referrer: &str,
+    ) -> Result<(), MetadataError> {
+        trace!(
+            "refresh_collection_metadata(contract_address=0x{:064x})",
+            contract_address
+        );
+
+        if self.config.skip_metadata_fetch {
+            return Ok(());
+        }
+
+        let contract_uri = self
+            .get_contract_uri(contract_address)
+            .await
+            .map_err(|err| MetadataError::ParsingError(err.to_string()))?;
+
+        trace!("Contract URI: {}", contract_uri);
+
+        let ipfs_gateway_uris: Vec<&str> = std::iter::once(ipfs_gateway_uri)
+            .chain(
+                self.config
+                    .ipfs_fallback_gateways
+                    .iter()
+                    .map(String::as_str),
+            )
+            .collect();
+
+        let http_fetcher = self.http_fetcher(image_timeout, request_referrer);
+        let metadata = get_token_metadata(
+            &self.fetcher(&http_fetcher),
+            contract_uri.as_str(),
+            &ipfs_gateway_uris,
+            &self.arweave_gateway_uris(),
+            self.config.metadata_cache.as_deref(),
+        )
+        .await
+        .map_err(|err| MetadataError::RequestTokenUriError(err.to_string()))?;
+
+        let current_metadata = self
+            .storage
+            .get_collection_metadata(contract_address)
+            .await
+            .map_err(MetadataError::DatabaseError)?;
+
+        if let Some(current_metadata) = current_metadata {
+            if current_metadata.raw == metadata.raw {
+                debug!(
+                    "Collection metadata of 0x{:064x} didn't change",
+                    contract_address
+                );
+                return Ok(());
+            }
+        }
+
+        let mut normalized = normalize_collection_metadata(&metadata.raw, &contract_uri)
+            .map_err(|err| MetadataError::ParsingError(err.to_string()))?;
+
+        if let ImageCacheOption::Save = cache {
+            normalized.image_key = self
+                .save_collection_image(&normalized.image, "logo", image_timeout, ipfs_gateway_uri)
+                .await;
+            normalized.banner_image_key = self
+                .save_collection_image(
+                    &normalized.banner_image,
+                    "banner",
+                    image_timeout,
+                    ipfs_gateway_uri,
+                )
+                .await;
+            normalized.featured_image_key = self
+                .save_collection_image(
+                    &normalized.featured_image,
+                    "featured",
+                    image_timeout,
+                    ipfs_gateway_uri,
+                )
+                .await;
+        }
+
+        self.storage
+            .register_collection_metadata(
+                contract_address,
+                CollectionMetadata {
+                    normalized,
+                    raw: metadata.raw,
+                    metadata_updated_at: metadata.metadata_updated_at,
+                },
+            )
+            .await
+            .map_err(MetadataError::DatabaseError)?;
+
+        Ok(())
+    }
+
+    /// Saves a collection image as `collection/{name}.{ext}`, and returns its key.
+    /// Returns `None` if there is no image, or if it can't be fetched or saved.
+    async fn save_collection_image(
+        &self,
+        url: &Option<String>,
+        name: &str,
+        timeout: Duration,
+        ipfs_url: &str,
+    ) -> Option<String> {
+        let url = url.as_deref()?;
+
+        let result = match self.download_media(url, timeout, ipfs_url).await {
+            Ok((content_type, content)) => {
+                self.file_manager
+                    .save(&FileInfo {
+                        name: format!(
+                            "{}.{}",
+                            name,
+                            file_extension_from_mime_type(content_type.as_str())
+                        ),
+                        content,
+                        dir_path: Some("collection".to_string()),
+                    })
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(key) => Some(key),
+            Err(e) => {
+                warn!(
+                    "Failed to save collection {} image {}: {}",
+                    name,
+                    log_preview(url),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Saves the given media content using the `FileManager` if the cache option requires it.
+    ///
+    /// With the `svg-raster` feature, SVG images are also rasterized into a PNG
+    /// saved next to the original SVG.
+    async fn save_media(
+        &mut self,
+        content_type: String,
+        content: Vec<u8>,
+        cache: ImageCacheOption,
+        contract_address: FieldElement,
+        token_id: &CairoU256,
+    ) -> Result<MetadataMedia> {
+        let content_length = content.len() as u64;
+
+        if let ImageCacheOption::DoNotSave = cache {
+            return Ok(MetadataMedia {
+                file_type: content_type,
+                content_length,
+                is_cache_updated: false,
+                media_key: None,
+                raster_media_key: None,
+                webp_media_key: None,
+                perceptual_hash: None,
+                thumbnails: vec![],
+            });
+        }
+
+        let file_ext = file_extension_from_mime_type(content_type.as_str());
+
+        debug!(
+            "Image: Content-Type={}, Content-Length={}, File-Ext={}",
+            content_type, content_length, file_ext
+        );
+
+        #[cfg(feature = "svg-raster")]
+        let raster_media_key = match self.config.svg_raster_width {
+            Some(width) if crate::image_processing::is_svg_mime_type(&content_type) => {
+                match crate::image_processing::process_blocking(&content, move |svg| {
+                    crate::image_processing::rasterize_svg(svg, width)
+                })
+                .await
+                {
+                    Ok(png) => Some(
+                        self.file_manager
+                            .save(&self.media_file(contract_address, token_id, None, "png", png))
+                            .await?,
+                    ),
+                    Err(e) => {
+                        error!("Failed to rasterize SVG image: {}", e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        #[cfg(not(feature = "svg-raster"))]
+        let raster_media_key = {
+            if self.config.svg_raster_width.is_some() {
+                tracing::warn!("SVG rasterization requires the `svg-raster` feature, skipping");
+            }
+            None
+        };
+
+        let webp_media_key = self
+            .save_webp_conversion(&content_type, &content, contract_address, token_id)
+            .await?;
+
+        let thumbnails = self
+            .save_thumbnails(&content_type, &content, contract_address, token_id)
+            .await?;
+
+        let perceptual_hash = self.perceptual_hash(&content_type, &content).await;
+
+        let media_key = self
+            .file_manager
+            .save(&self.media_file(contract_address, token_id, None, file_ext, content))
+            .await?;
+
+        Ok(MetadataMedia {
+            file_type: content_type,
+            content_length,
+            is_cache_updated: true,
+            media_key: Some(media_key),
+            raster_media_key,
+            webp_media_key,
+            perceptual_hash,
+            thumbnails,
+        })
+    }
+
+    /// Returns the file of a token media, or of its thumbnail of the given size,
+    /// named by the `media_key_template` if any.
+    fn media_file(
+        &self,
+        contract_address: FieldElement,
+        token_id: &CairoU256,
+        size: Option<u32>,
+        ext: &str,
+        content: Vec<u8>,
+    ) -> FileInfo {
+        if let Some(template) = &self.config.media_key_template {
+            return template.file_info(contract_address, token_id, size, ext, content);
+        }
+
+        match size {
+            Some(size) => FileInfo {
+                name: format!("{}.{}", size, ext),
+                content,
+                dir_path: Some(token_id.to_decimal(false)),
+            },
+            None => FileInfo {
+                name: format!("{}.{}", token_id.to_decimal(false), ext),
+                content,
+                dir_path: None,
+            },
+        }
+    }
+
+    /// Converts a large PNG or JPEG image into WebP, and saves it.
+    /// Returns `None` if the media is not converted.
+    #[cfg(feature = "webp-conversion")]
+    async fn save_webp_conversion(
+        &self,
+        content_type: &str,
+        content: &[u8],
+        contract_address: FieldElement,
+        token_id: &CairoU256,
+    ) -> Result<Option<String>> {
+        let min_size = match self.config.webp_conversion_min_size {
+            Some(min_size) => min_size,
+            None => return Ok(None),
+        };
+
+        let is_convertible = ["image/png", "image/jpeg", "image/jpg"]
+            .iter()
+            .any(|t| content_type.starts_with(t));
+        if !is_convertible || (content.len() as u64) < min_size {
+            return Ok(None);
+        }
+
+        match crate::image_processing::process_blocking(
+            content,
+            crate::image_processing::convert_to_webp,
+        )
+        .await
+        {
+            Ok(Some(webp)) => Ok(Some(
+                self.file_manager
+                    .save(&self.media_file(contract_address, token_id, None, "webp", webp))
+                    .await?,
+            )),
+            Ok(None) => {
+                debug!("WebP conversion not smaller than the original, skipping it");
+                Ok(None)
+            }
+            Err(e) => {
+                error!("Failed to convert image to WebP: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    #[cfg(not(feature = "webp-conversion"))]
+    async fn save_webp_conversion(
+        &self,
+        _content_type: &str,
+        _content: &[u8],
+        _contract_address: FieldElement,
+        _token_id: &CairoU256,
+    ) -> Result<Option<String>> {
+        if self.config.webp_conversion_min_size.is_some() {
+            tracing::warn!("WebP conversion requires the `webp-conversion` feature, skipping");
+        }
+        Ok(None)
+    }
+
+    /// Computes the perceptual hash of a raster image.
+    /// Non-raster media (SVG, videos...) are skipped.
+    #[cfg(feature = "perceptual-hash")]
+    async fn perceptual_hash(&self, content_type: &str, content: &[u8]) -> Option<String> {
+        if !self.config.compute_perceptual_hash
+            || !crate::image_processing::is_resizable_mime_type(content_type)
+        {
+            return None;
+        }
+
+        match crate::image_processing::process_blocking(
+            content,
+            crate::image_processing::compute_dhash,
+        )
+        .await
+        {
+            Ok(hash) => Some(format!("{:016x}", hash)),
+            Err(e) => {
+                error!("Failed to compute perceptual hash: {}", e);
+                None
+            }
+        }
+    }
+
+    #[cfg(not(feature = "perceptual-hash"))]
+    async fn perceptual_hash(&self, _content_type: &str, _content: &[u8]) -> Option<String> {
+        if self.config.compute_perceptual_hash {
+            tracing::warn!("Perceptual hash requires the `perceptual-hash` feature, skipping");
+        }
+        None
+    }
+
+    /// Generates and saves the configured thumbnails of a raster image.
+    /// Non-raster media (SVG, videos...) are skipped.
+    #[cfg(feature = "thumbnails")]
+    async fn save_thumbnails(
+        &self,
+        content_type: &str,
+        content: &[u8],
+        contract_address: FieldElement,
+        token_id: &CairoU256,
+    ) -> Result<Vec<ImageThumbnail>> {
+        let mut thumbnails = vec![];
+
+        if !crate::image_processing::is_resizable_mime_type(content_type) {
+            return Ok(thumbnails);
+        }
+
+        for &size in &self.config.thumbnail_sizes {
+            match crate::image_processing::process_blocking(content, move |image| {
+                crate::image_processing::generate_thumbnail(image, size)
+            })
+            .await
+            {
+                Ok(webp) => {
+                    let key = self
+                        .file_manager
+                        .save(&self.media_file(
+                            contract_address,
+                            token_id,
+                            Some(size),
+                            "webp",
+                            webp,
+                        ))
+                        .await?;
+
+                    thumbnails.push(ImageThumbnail { size, key });
+                }
+                Err(e) => {
+                    error!("Failed to generate {}px thumbnail: {}", size, e);
+                }
+            }
+        }
+
+        Ok(thumbnails)
+    }
+
+    #[cfg(not(feature = "thumbnails"))]
+    async fn save_thumbnails(
+        &self,
+        _content_type: &str,
+        _content: &[u8],
+        _contract_address: FieldElement,
+        _token_id: &CairoU256,
+    ) -> Result<Vec<ImageThumbnail>> {
+        if !self.config.thumbnail_sizes.is_empty() {
+            tracing::warn!("Thumbnails generation requires the `thumbnails` feature, skipping");
+        }
+        Ok(vec![])
+    }
+
     /// Retrieves the URI for a token based on its ID and the contract address.
+    /// A base URI (ending with a slash) is completed with the token id, or the
+    /// suffix configured for the collection in `base_uri_suffixes`.
+    async fn get_token_uri(
+        &mut self,
+        token_id: &CairoU256,
+        contract_address: FieldElement,
+    ) -> Result<String> {
+        let token_uri = self.call_token_uri(token_id, contract_address).await?;
+
+        let suffix = self
+            .config
+            .base_uri_suffixes
+            .get(&contract_address)
+            .copied()
+            .unwrap_or_default();
+
+        Ok(resolve_base_token_uri(
+            &token_uri,
+            &token_id.to_decimal(false),
+            suffix,
+        ))
+    }
+
+    /// Reads the URI of a token from its contract.
     /// The function first checks the `tokenURI` selector and then the `token_uri` selector.
     /// If both checks fail, an error is returned indicating the token URI was not found.
-    async fn get_token_uri(
+    async fn call_token_uri(
         &mut self,
         token_id: &CairoU256,
         contract_address: FieldElement,
@@ -356,6 +1808,31 @@ impl<'a, T: Storage, C: StarknetClient, F: FileManager> MetadataManager<'a, T, C
         ))
     }
 
+    /// Retrieves the URI of the collection metadata, using the `contractURI`
+    /// selector and then the `contract_uri` selector.
+    async fn get_contract_uri(&mut self, contract_address: FieldElement) -> Result<String> {
+        for selector in [selector!("contractURI"), selector!("contract_uri")] {
+            match self
+                .get_contract_property_string(
+                    contract_address,
+                    selector,
+                    vec![],
+                    BlockId::Tag(BlockTag::Pending),
+                )
+                .await
+            {
+                Ok(uri) if self.is_valid_uri(&uri) => return Ok(uri),
+                Ok(uri) => trace!("Invalid contract URI: {}", uri),
+                Err(err) => trace!("Failed to retrieve contract URI: {:?}", err),
+            }
+        }
+
+        Err(anyhow!(
+            "Contract URI not found at contract address 0x{:064x}",
+            contract_address
+        ))
+    }
+
     /// Checks if the given URI is valid.
     /// A URI is considered invalid if it's "undefined" or empty.
     fn is_valid_uri(&self, uri: &str) -> bool {
@@ -392,9 +1869,16 @@ impl<'a, T: Storage, C: StarknetClient, F: FileManager> MetadataManager<'a, T, C
 mod tests {
     use super::*;
 
-    use crate::{file_manager::MockFileManager, storage::MockStorage};
+    use crate::{
+        file_manager::MockFileManager,
+        metadata_fetcher::MockMetadataFetcher,
+        storage::MockStorage,
+        test_server::TestServer,
+        types::{TokenIdsPage, TokenMetadata},
+    };
     use ark_starknet::client::MockStarknetClient;
     use mockall::predicate::*;
+    use mockall::Sequence;
     use reqwest::header::HeaderMap;
     use std::vec;
 
@@ -437,7 +1921,8 @@ mod tests {
                 ])
             });
 
-        let mut metadata_manager = MetadataManager::new(&storage_manager, &mock_client, &mock_file);
+        let mut metadata_manager =
+            MetadataManager::new(&storage_manager, &mock_client, &mock_file).unwrap();
 
         // EXECUTION: Call the function under test
         let result = metadata_manager
@@ -453,6 +1938,84 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_get_token_uri_long_string() {
+        let mut mock_client = MockStarknetClient::default();
+        let storage_manager = MockStorage::default();
+        let mock_file = MockFileManager::default();
+
+        let uri = "ipfs://bafybeieocsz5txpxgp7zrx7fexrdneyj4kzq2v4x5g3asx45m65cx7rgxu/1234";
+
+        // `tokenURI` is not implemented, `token_uri` returns a ByteArray.
+        mock_client
+            .expect_call_contract()
+            .with(always(), eq(selector!("tokenURI")), always(), always())
+            .returning(|_, _, _, _| {
+                Err(
+                    ark_starknet::client::StarknetClientError::EntrypointNotFound(
+                        "tokenURI".to_string(),
+                    ),
+                )
+            });
+        mock_client
+            .expect_call_contract()
+            .with(always(), eq(selector!("token_uri")), always(), always())
+            .returning(move |_, _, _, _| {
+                Ok(ark_starknet::byte_array::ByteArray::from_string(uri).to_felts())
+            });
+
+        let mut metadata_manager =
+            MetadataManager::new(&storage_manager, &mock_client, &mock_file).unwrap();
+
+        let token_uri = metadata_manager
+            .get_token_uri(&CairoU256 { low: 1234, high: 0 }, FieldElement::ONE)
+            .await
+            .unwrap();
+
+        assert_eq!(token_uri, uri);
+    }
+
+    #[tokio::test]
+    async fn test_get_token_uri_base_uri() {
+        let mut mock_client = MockStarknetClient::default();
+        let storage_manager = MockStorage::default();
+        let mock_file = MockFileManager::default();
+
+        mock_client.expect_call_contract().returning(|_, _, _, _| {
+            Ok(ark_starknet::byte_array::ByteArray::from_string("ipfs://QmHash/").to_felts())
+        });
+
+        let token_id = CairoU256 { low: 42, high: 0 };
+
+        let mut metadata_manager =
+            MetadataManager::new(&storage_manager, &mock_client, &mock_file).unwrap();
+        assert_eq!(
+            metadata_manager
+                .get_token_uri(&token_id, FieldElement::ONE)
+                .await
+                .unwrap(),
+            "ipfs://QmHash/42"
+        );
+
+        let mut metadata_manager = MetadataManager::with_config(
+            &storage_manager,
+            &mock_client,
+            &mock_file,
+            MetadataManagerConfig {
+                base_uri_suffixes: HashMap::from([(FieldElement::ONE, BaseUriSuffix::TokenIdJson)]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            metadata_manager
+                .get_token_uri(&token_id, FieldElement::ONE)
+                .await
+                .unwrap(),
+            "ipfs://QmHash/42.json"
+        );
+    }
+
     #[tokio::test]
     async fn test_refresh_collection_token_metadata() {
         // SETUP: Mocking and Initializing
@@ -485,13 +2048,17 @@ mod tests {
                 ])
             });
 
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(|_, _| Ok(None));
         mock_storage
             .expect_register_token_metadata()
             .times(1)
             .with(always(), always(), always())
             .returning(|_, _, _| Ok(()));
 
-        let mut metadata_manager = MetadataManager::new(&mock_storage, &mock_client, &mock_file);
+        let mut metadata_manager =
+            MetadataManager::new(&mock_storage, &mock_client, &mock_file).unwrap();
 
         // EXECUTION: Call the function under test
         let result = metadata_manager
@@ -507,6 +2074,1645 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_refresh_token_metadata_skips_unchanged() {
+        let mut mock_client = MockStarknetClient::default();
+        let mock_file = MockFileManager::default();
+
+        let uri = r#"data:application/json,{"name":"Duck"}"#;
+        mock_client
+            .expect_call_contract()
+            .returning(move |_, _, _, _| {
+                Ok(ark_starknet::byte_array::ByteArray::from_string(uri).to_felts())
+            });
+
+        let saved_hash = Arc::new(std::sync::Mutex::new(None));
+
+        // The token has no metadata yet, they are saved with their hash.
+        let mut mock_storage = MockStorage::default();
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(|_, _| Ok(None));
+        let hash = Arc::clone(&saved_hash);
+        mock_storage
+            .expect_register_token_metadata()
+            .times(1)
+            .returning(move |_, _, metadata| {
+                *hash.lock().unwrap() = metadata.content_hash;
+                Ok(())
+            });
+
+        let mut metadata_manager =
+            MetadataManager::new(&mock_storage, &mock_client, &mock_file).unwrap();
+        let status = metadata_manager
+            .refresh_token_metadata(
+                FieldElement::ONE,
+                CairoU256 { low: 1, high: 0 },
+                ImageCacheOption::DoNotSave,
+                "https://ipfs.example.com/",
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(status, MetadataRefreshStatus::Updated);
+
+        // Refreshing again the same metadata doesn't save anything.
+        let saved_hash = saved_hash.lock().unwrap().clone();
+        assert!(saved_hash.is_some());
+
+        let mut mock_storage = MockStorage::default();
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(move |_, _| Ok(saved_hash.clone()));
+        mock_storage.expect_register_token_metadata().never();
+
+        let mut metadata_manager =
+            MetadataManager::new(&mock_storage, &mock_client, &mock_file).unwrap();
+        let status = metadata_manager
+            .refresh_token_metadata(
+                FieldElement::ONE,
+                CairoU256 { low: 1, high: 0 },
+                ImageCacheOption::DoNotSave,
+                "https://ipfs.example.com/",
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(status, MetadataRefreshStatus::Unchanged);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_metadata_retries_failed_media() {
+        let mut mock_client = MockStarknetClient::default();
+        let mock_file = MockFileManager::default();
+        let mut mock_fetcher = MockMetadataFetcher::default();
+
+        // The first request of the image fails, the next ones succeed.
+        let requests = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let image_requests = Arc::clone(&requests);
+        let image = TestServer::with_handler(move |_| {
+            if image_requests.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                b"NOT HTTP\r\nConnection: close\r\n\r\n".to_vec()
+            } else {
+                TestServer::response("200 OK", &[("Content-Type", "image/png")], b"")
+            }
+        })
+        .await
+        .url("/1.png");
+
+        mock_client
+            .expect_call_contract()
+            .returning(move |_, _, _, _| {
+                Ok(
+                    ark_starknet::byte_array::ByteArray::from_string("https://example.com/1.json")
+                        .to_felts(),
+                )
+            });
+        let metadata = format!(r#"{{"name":"Duck","image":"{}"}}"#, image);
+        mock_fetcher
+            .expect_fetch()
+            .returning(move |_| Ok(metadata.clone()));
+
+        let saved_hash = Arc::new(std::sync::Mutex::new(Some("previous".to_string())));
+
+        // The media failed, the metadata are saved without their hash.
+        let mut mock_storage = MockStorage::default();
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(|_, _| Ok(None));
+        let hash = Arc::clone(&saved_hash);
+        mock_storage
+            .expect_register_token_metadata()
+            .withf(|_, _, metadata| metadata.normalized.image_mime_type.is_none())
+            .times(1)
+            .returning(move |_, _, metadata| {
+                *hash.lock().unwrap() = metadata.content_hash;
+                Ok(())
+            });
+
+        let mut metadata_manager = MetadataManager::new(&mock_storage, &mock_client, &mock_file)
+            .unwrap()
+            .with_metadata_fetcher(&mock_fetcher);
+        let status = metadata_manager
+            .refresh_token_metadata(
+                FieldElement::ONE,
+                CairoU256 { low: 1, high: 0 },
+                ImageCacheOption::DoNotSave,
+                "https://ipfs.example.com/",
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(status, MetadataRefreshStatus::Updated);
+
+        // Refreshing the same metadata fetches the media again, and saves the hash.
+        let saved_hash = saved_hash.lock().unwrap().clone();
+        assert!(saved_hash.is_none());
+
+        let mut mock_storage = MockStorage::default();
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(move |_, _| Ok(saved_hash.clone()));
+        mock_storage
+            .expect_register_token_metadata()
+            .withf(|_, _, metadata| {
+                metadata.normalized.image_mime_type.as_deref() == Some("image/png")
+                    && metadata.content_hash.is_some()
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut metadata_manager = MetadataManager::new(&mock_storage, &mock_client, &mock_file)
+            .unwrap()
+            .with_metadata_fetcher(&mock_fetcher);
+        let status = metadata_manager
+            .refresh_token_metadata(
+                FieldElement::ONE,
+                CairoU256 { low: 1, high: 0 },
+                ImageCacheOption::DoNotSave,
+                "https://ipfs.example.com/",
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(status, MetadataRefreshStatus::Updated);
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_metadata_skip_metadata_fetch() {
+        // No contract call nor metadata fetch is expected.
+        let mock_client = MockStarknetClient::default();
+        let mock_file = MockFileManager::default();
+
+        let mut mock_storage = MockStorage::default();
+        mock_storage
+            .expect_update_token_metadata_status()
+            .withf(|_, token_id, status| {
+                token_id.low == 1 && token_id.high == 0 && status == METADATA_STATUS_SKIPPED
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        mock_storage.expect_register_token_metadata().never();
+
+        let mut metadata_manager = MetadataManager::with_config(
+            &mock_storage,
+            &mock_client,
+            &mock_file,
+            MetadataManagerConfig {
+                skip_metadata_fetch: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let status = metadata_manager
+            .refresh_token_metadata(
+                FieldElement::ONE,
+                CairoU256 { low: 1, high: 0 },
+                ImageCacheOption::DoNotSave,
+                "https://ipfs.example.com/",
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(status, MetadataRefreshStatus::Skipped);
+
+        metadata_manager
+            .refresh_collection_metadata(
+                FieldElement::ONE,
+                ImageCacheOption::DoNotSave,
+                "https://ipfs.example.com/",
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_metadata_with_metadata_fetcher() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+        let mut mock_fetcher = MockMetadataFetcher::default();
+
+        let uri = "ipfs://QmHash/1.json";
+        mock_client
+            .expect_call_contract()
+            .returning(move |_, _, _, _| {
+                Ok(ark_starknet::byte_array::ByteArray::from_string(uri).to_felts())
+            });
+
+        // The fetcher receives the URI resolved with the IPFS gateway.
+        mock_fetcher
+            .expect_fetch()
+            .with(eq("https://ipfs.example.com/QmHash/1.json"))
+            .times(1)
+            .returning(|_| Ok(r#"{"name":"Duck"}"#.to_string()));
+
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(|_, _| Ok(None));
+        mock_storage
+            .expect_register_token_metadata()
+            .withf(|_, _, metadata| metadata.normalized.name.as_deref() == Some("Duck"))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut metadata_manager = MetadataManager::new(&mock_storage, &mock_client, &mock_file)
+            .unwrap()
+            .with_metadata_fetcher(&mock_fetcher);
+
+        let status = metadata_manager
+            .refresh_token_metadata(
+                FieldElement::ONE,
+                CairoU256 { low: 1, high: 0 },
+                ImageCacheOption::DoNotSave,
+                "https://ipfs.example.com/",
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(status, MetadataRefreshStatus::Updated);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_metadata_offloads_raw_metadata() {
+        use std::sync::{Arc, Mutex};
+
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+        let mut mock_file = MockFileManager::default();
+        let mut mock_fetcher = MockMetadataFetcher::default();
+
+        let uri = "ipfs://QmHash/1.json";
+        mock_client
+            .expect_call_contract()
+            .returning(move |_, _, _, _| {
+                Ok(ark_starknet::byte_array::ByteArray::from_string(uri).to_felts())
+            });
+
+        let raw = format!(
+            r#"{{"name":"Duck","description":"{}"}}"#,
+            "Quack".repeat(100)
+        );
+        let fetched = raw.clone();
+        mock_fetcher
+            .expect_fetch()
+            .returning(move |_| Ok(fetched.clone()));
+
+        let saved: Arc<Mutex<Vec<u8>>> = Arc::new(Mutex::new(vec![]));
+        let saved_ref = Arc::clone(&saved);
+        mock_file
+            .expect_save()
+            .withf(|file| {
+                file.name == "1.json.gz"
+                    && file.dir_path == Some(format!("raw/0x{:064x}", FieldElement::ONE))
+            })
+            .times(1)
+            .returning(move |file| {
+                *saved_ref.lock().unwrap() = file.content.clone();
+                Ok(format!("{}/{}", file.dir_path.clone().unwrap(), file.name))
+            });
+        let saved_ref = Arc::clone(&saved);
+        mock_file
+            .expect_read()
+            .returning(move |_| Ok(saved_ref.lock().unwrap().clone()));
+
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(|_, _| Ok(None));
+        mock_storage
+            .expect_register_token_metadata()
+            .withf(|_, _, metadata| {
+                metadata.raw.is_empty()
+                    && metadata.raw_key
+                        == Some(format!("raw/0x{:064x}/1.json.gz", FieldElement::ONE))
+                    && metadata.normalized.name.as_deref() == Some("Duck")
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut metadata_manager = MetadataManager::with_config(
+            &mock_storage,
+            &mock_client,
+            &mock_file,
+            MetadataManagerConfig {
+                raw_metadata_offload: Some(RawMetadataOffload {
+                    inline_max_size: Some(64),
+                    dir_path: Some("raw/".to_string()),
+                }),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .with_metadata_fetcher(&mock_fetcher);
+
+        metadata_manager
+            .refresh_token_metadata(
+                FieldElement::ONE,
+                CairoU256 { low: 1, high: 0 },
+                ImageCacheOption::DoNotSave,
+                "https://ipfs.example.com/",
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert!(saved.lock().unwrap().len() < raw.len());
+
+        // The offloaded raw metadata are read from the file manager.
+        let offloaded = TokenMetadata {
+            raw_key: Some(format!("raw/0x{:064x}/1.json.gz", FieldElement::ONE)),
+            ..Default::default()
+        };
+        assert_eq!(
+            metadata_manager
+                .read_raw_metadata(&offloaded)
+                .await
+                .unwrap(),
+            raw
+        );
+
+        // The small raw metadata are stored inline.
+        let inline = TokenMetadata {
+            raw: r#"{"name":"Duck"}"#.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            metadata_manager.read_raw_metadata(&inline).await.unwrap(),
+            inline.raw
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_metadata_stores_raw_metadata_value() {
+        let raw = r#"{"name":"Duck","attributes":[{"trait_type":"Level","value":3}],"layers":[["a","b"],[{"z":-1.5}]]}"#;
+
+        for keep_raw_metadata_string in [false, true] {
+            let mut mock_client = MockStarknetClient::default();
+            let mut mock_storage = MockStorage::default();
+            let mock_file = MockFileManager::default();
+            let mut mock_fetcher = MockMetadataFetcher::default();
+
+            let uri = "ipfs://QmHash/1.json";
+            mock_client
+                .expect_call_contract()
+                .returning(move |_, _, _, _| {
+                    Ok(ark_starknet::byte_array::ByteArray::from_string(uri).to_felts())
+                });
+            mock_fetcher
+                .expect_fetch()
+                .returning(move |_| Ok(raw.to_string()));
+
+            mock_storage
+                .expect_get_token_metadata_hash()
+                .returning(|_, _| Ok(None));
+            mock_storage
+                .expect_register_token_metadata()
+                .withf(move |_, _, metadata| {
+                    let value = metadata.raw_value.as_ref().unwrap();
+                    value["attributes"][0]["value"] == serde_json::json!(3)
+                        && value["layers"][1][0]["z"] == serde_json::json!(-1.5)
+                        && metadata.raw.is_empty() != keep_raw_metadata_string
+                })
+                .times(1)
+                .returning(|_, _, _| Ok(()));
+
+            let mut metadata_manager = MetadataManager::with_config(
+                &mock_storage,
+                &mock_client,
+                &mock_file,
+                MetadataManagerConfig {
+                    keep_raw_metadata_string,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .with_metadata_fetcher(&mock_fetcher);
+
+            metadata_manager
+                .refresh_token_metadata(
+                    FieldElement::ONE,
+                    CairoU256 { low: 1, high: 0 },
+                    ImageCacheOption::DoNotSave,
+                    "https://ipfs.example.com/",
+                    Duration::from_secs(5),
+                    "",
+                )
+                .await
+                .unwrap();
+        }
+
+        // The raw metadata stored as a JSON value are read back as a string.
+        let mock_client = MockStarknetClient::default();
+        let mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+        let metadata_manager =
+            MetadataManager::new(&mock_storage, &mock_client, &mock_file).unwrap();
+
+        let native = TokenMetadata {
+            raw_value: raw_metadata_value(raw),
+            ..Default::default()
+        };
+        let read = metadata_manager.read_raw_metadata(&native).await.unwrap();
+        assert_eq!(raw_metadata_value(&read), native.raw_value);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_metadata_fallback_image() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+        let mut mock_fetcher = MockMetadataFetcher::default();
+
+        mock_client
+            .expect_call_contract()
+            .returning(move |_, _, _, _| {
+                Ok(
+                    ark_starknet::byte_array::ByteArray::from_string("https://example.com/1.json")
+                        .to_felts(),
+                )
+            });
+        mock_fetcher
+            .expect_fetch()
+            .returning(|_| Ok(r#"{"name":"Duck"}"#.to_string()));
+
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(|_, _| Ok(None));
+        mock_storage
+            .expect_register_token_metadata()
+            .withf(|_, _, metadata| {
+                metadata.normalized.image.as_deref() == Some("https://example.com/placeholder.png")
+                    && metadata.normalized.image_is_placeholder
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut metadata_manager = MetadataManager::with_config(
+            &mock_storage,
+            &mock_client,
+            &mock_file,
+            MetadataManagerConfig {
+                fallback_image: Some(FallbackImage::Url(
+                    "https://example.com/placeholder.png".to_string(),
+                )),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .with_metadata_fetcher(&mock_fetcher);
+
+        let status = metadata_manager
+            .refresh_token_metadata(
+                FieldElement::ONE,
+                CairoU256 { low: 1, high: 0 },
+                ImageCacheOption::DoNotSave,
+                "https://ipfs.example.com/",
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(status, MetadataRefreshStatus::Updated);
+    }
+
+    #[tokio::test]
+    async fn test_reprocess_token_metadata() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+        let mut mock_fetcher = MockMetadataFetcher::default();
+        let mut seq = Sequence::new();
+
+        mock_client.expect_call_contract().returning(|_, _, _, _| {
+            Ok(
+                ark_starknet::byte_array::ByteArray::from_string("https://example.com/1.json")
+                    .to_felts(),
+            )
+        });
+        mock_fetcher
+            .expect_fetch()
+            .returning(|_| Ok(r#"{"name":"Duck"}"#.to_string()));
+
+        mock_storage
+            .expect_get_token_metadata()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| {
+                Ok(Some(TokenMetadata {
+                    normalized: NormalizedMetadata {
+                        name: Some("Goose".to_string()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }))
+            });
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(|_, _| Ok(None));
+        mock_storage
+            .expect_register_token_metadata()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _| Ok(()));
+        mock_storage
+            .expect_get_token_metadata()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| {
+                Ok(Some(TokenMetadata {
+                    normalized: NormalizedMetadata {
+                        name: Some("Duck".to_string()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }))
+            });
+
+        let mut metadata_manager = MetadataManager::new(&mock_storage, &mock_client, &mock_file)
+            .unwrap()
+            .with_metadata_fetcher(&mock_fetcher);
+
+        let reprocessing = metadata_manager
+            .reprocess_token_metadata(
+                FieldElement::ONE,
+                CairoU256 { low: 1, high: 0 },
+                ImageCacheOption::DoNotSave,
+                "https://ipfs.example.com/",
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(reprocessing.status, MetadataRefreshStatus::Updated);
+        assert_eq!(
+            reprocessing.before.and_then(|m| m.name).as_deref(),
+            Some("Goose")
+        );
+        assert_eq!(
+            reprocessing.after.and_then(|m| m.name).as_deref(),
+            Some("Duck")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_headers() {
+        let mock_client = MockStarknetClient::default();
+        let mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+
+        // Only answers the metadata if authenticated.
+        let uri = TestServer::with_handler(|request| {
+            let (status, body) = if request.contains("x-api-key: metadata-api-key") {
+                ("200 OK", r#"{"name":"Duck"}"#)
+            } else {
+                ("401 Unauthorized", "")
+            };
+            TestServer::response(
+                status,
+                &[
+                    ("Content-Type", "application/json"),
+                    ("Connection", "close"),
+                ],
+                body.as_bytes(),
+            )
+        })
+        .await
+        .url("/1.json");
+
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert("x-api-key", "metadata-api-key".parse().unwrap());
+
+        let metadata_manager = MetadataManager::with_config(
+            &mock_storage,
+            &mock_client,
+            &mock_file,
+            MetadataManagerConfig {
+                request_headers,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // The API key is never logged.
+        assert!(!format!("{:?}", metadata_manager.config).contains("metadata-api-key"));
+
+        let metadata = get_token_metadata(
+            &metadata_manager.http_fetcher(Duration::from_secs(5), ""),
+            &uri,
+            &[],
+            &[DEFAULT_ARWEAVE_GATEWAY_URI],
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(metadata.normalized.name.as_deref(), Some("Duck"));
+    }
+
+    fn mock_contract_uri(mock_client: &mut MockStarknetClient, metadata: serde_json::Value) {
+        let uri = format!("data:application/json,{}", metadata);
+
+        mock_client
+            .expect_call_contract()
+            .with(always(), eq(selector!("contractURI")), always(), always())
+            .returning(move |_, _, _, _| {
+                Ok(ark_starknet::byte_array::ByteArray::from_string(&uri).to_felts())
+            });
+    }
+
+    #[tokio::test]
+    async fn test_refresh_collection_metadata_saves_images() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+        let mut mock_file = MockFileManager::default();
+
+        mock_contract_uri(
+            &mut mock_client,
+            serde_json::json!({
+                "name": "Ducks",
+                "image": "data:image/png;base64,AAAA",
+                "banner_image": "data:image/jpeg;base64,AAAA",
+            }),
+        );
+
+        mock_storage
+            .expect_get_collection_metadata()
+            .returning(|_| Ok(None));
+        mock_storage
+            .expect_register_collection_metadata()
+            .times(1)
+            .withf(|_, metadata| {
+                let normalized = &metadata.normalized;
+                normalized.name.as_deref() == Some("Ducks")
+                    && normalized.image_key.as_deref() == Some("https://cdn/collection/logo.png")
+                    && normalized.banner_image_key.as_deref()
+                        == Some("https://cdn/collection/banner.jpg")
+                    && normalized.featured_image_key.is_none()
+            })
+            .returning(|_, _| Ok(()));
+
+        mock_file.expect_save().times(2).returning(|file| {
+            Ok(format!(
+                "https://cdn/{}/{}",
+                file.dir_path.as_deref().unwrap(),
+                file.name
+            ))
+        });
+
+        let mut metadata_manager =
+            MetadataManager::new(&mock_storage, &mock_client, &mock_file).unwrap();
+
+        metadata_manager
+            .refresh_collection_metadata(
+                FieldElement::ONE,
+                ImageCacheOption::Save,
+                "https://ipfs.example.com/",
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refresh_collection_metadata_unchanged() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+        let mut mock_file = MockFileManager::default();
+
+        let metadata = serde_json::json!({ "image": "data:image/png;base64,AAAA" });
+        mock_contract_uri(&mut mock_client, metadata.clone());
+
+        mock_storage
+            .expect_get_collection_metadata()
+            .returning(move |_| {
+                Ok(Some(CollectionMetadata {
+                    raw: metadata.to_string(),
+                    ..Default::default()
+                }))
+            });
+        mock_storage.expect_register_collection_metadata().never();
+        mock_file.expect_save().never();
+
+        let mut metadata_manager =
+            MetadataManager::new(&mock_storage, &mock_client, &mock_file).unwrap();
+
+        metadata_manager
+            .refresh_collection_metadata(
+                FieldElement::ONE,
+                ImageCacheOption::Save,
+                "https://ipfs.example.com/",
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+    }
+
+    #[cfg(feature = "thumbnails")]
+    #[tokio::test]
+    async fn test_fetch_metadata_media_thumbnails() {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let mock_client = MockStarknetClient::default();
+        let mock_storage = MockStorage::default();
+        let mut mock_file = MockFileManager::default();
+
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbaImage::new(300, 150)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        let uri = format!(
+            "data:image/png;base64,{}",
+            general_purpose::STANDARD.encode(png.into_inner())
+        );
+
+        mock_file.expect_save().times(3).returning(|file| {
+            Ok(match &file.dir_path {
+                Some(dir) => format!("{}/{}", dir, file.name),
+                None => file.name.clone(),
+            })
+        });
+
+        let mut metadata_manager = MetadataManager::with_config(
+            &mock_storage,
+            &mock_client,
+            &mock_file,
+            MetadataManagerConfig {
+                thumbnail_sizes: vec![128, 256],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let media = metadata_manager
+            .fetch_metadata_media(
+                &uri,
+                ImageCacheOption::Save,
+                FieldElement::ONE,
+                &CairoU256 { low: 7, high: 0 },
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(media.media_key, Some("7.png".to_string()));
+        assert_eq!(
+            media.thumbnails,
+            vec![
+                ImageThumbnail {
+                    size: 128,
+                    key: "7/128.webp".to_string()
+                },
+                ImageThumbnail {
+                    size: 256,
+                    key: "7/256.webp".to_string()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_metadata_media_detects_generic_image_type() {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let mock_client = MockStarknetClient::default();
+        let mock_storage = MockStorage::default();
+        let mut mock_file = MockFileManager::default();
+
+        let uri = format!(
+            "data:application/octet-stream;base64,{}",
+            general_purpose::STANDARD.encode(b"\x00\x00\x00\x1cftypavif\x00\x00\x00\x00")
+        );
+
+        mock_file
+            .expect_save()
+            .withf(|file| file.name == "7.avif")
+            .times(1)
+            .returning(|file| Ok(file.name.clone()));
+
+        let mut metadata_manager =
+            MetadataManager::new(&mock_storage, &mock_client, &mock_file).unwrap();
+
+        let media = metadata_manager
+            .fetch_metadata_media(
+                &uri,
+                ImageCacheOption::Save,
+                FieldElement::ONE,
+                &CairoU256 { low: 7, high: 0 },
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(media.file_type, "image/avif");
+    }
+
+    #[tokio::test]
+    async fn test_http_client_reuses_connections() {
+        let mock_client = MockStarknetClient::default();
+        let mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+
+        for (pool_max_idle_per_host, expected_connections) in [(None, 1), (Some(0), 3)] {
+            // Serves `{}` to the requests of kept-alive connections.
+            let server = TestServer::ok(&[], b"{}").await;
+            let manager = MetadataManager::with_config(
+                &mock_storage,
+                &mock_client,
+                &mock_file,
+                MetadataManagerConfig {
+                    http_client: HttpClientConfig {
+                        pool_max_idle_per_host,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            for _ in 0..3 {
+                let response = manager
+                    .request_client
+                    .get(server.url("/1.json"))
+                    .send()
+                    .await
+                    .unwrap();
+                assert_eq!(response.text().await.unwrap(), "{}");
+            }
+
+            assert_eq!(server.connections(), expected_connections);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_metadata_oversized_image() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+        let mut mock_fetcher = MockMetadataFetcher::default();
+
+        // Sent without Content-Length, the size is only known once read.
+        let mut response =
+            b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nConnection: close\r\n\r\n".to_vec();
+        response.extend_from_slice(&[0u8; 64]);
+        let image = TestServer::start(response).await.url("/1.png");
+
+        mock_client
+            .expect_call_contract()
+            .returning(move |_, _, _, _| {
+                Ok(
+                    ark_starknet::byte_array::ByteArray::from_string("https://example.com/1.json")
+                        .to_felts(),
+                )
+            });
+        let metadata = format!(r#"{{"name":"Duck","image":"{}"}}"#, image);
+        mock_fetcher
+            .expect_fetch()
+            .returning(move |_| Ok(metadata.clone()));
+
+        // The metadata are saved without the image, never saved with the `FileManager`.
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(|_, _| Ok(None));
+        let expected_image = image.clone();
+        mock_storage
+            .expect_register_token_metadata()
+            .withf(move |_, _, metadata| {
+                metadata.normalized.image.as_deref() == Some(expected_image.as_str())
+                    && metadata.normalized.image_key.is_none()
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut metadata_manager = MetadataManager::with_config(
+            &mock_storage,
+            &mock_client,
+            &mock_file,
+            MetadataManagerConfig {
+                max_media_size: Some(16),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .with_metadata_fetcher(&mock_fetcher);
+
+        let err = metadata_manager
+            .download_media(&image, Duration::from_secs(5), "")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MediaDownloadError>(),
+            Some(MediaDownloadError::TooLarge { max_size: 16, .. })
+        ));
+
+        let status = metadata_manager
+            .refresh_token_metadata(
+                FieldElement::ONE,
+                CairoU256 { low: 1, high: 0 },
+                ImageCacheOption::Save,
+                "https://ipfs.example.com/",
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(status, MetadataRefreshStatus::Updated);
+    }
+
+    #[tokio::test]
+    async fn test_download_media_announced_size_too_large() {
+        let mock_client = MockStarknetClient::default();
+        let mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+
+        // Rejected from its Content-Length, before the body is sent.
+        let response =
+            b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 1073741824\r\n\r\n"
+                .to_vec();
+        let image = TestServer::start(response).await.url("/1.png");
+
+        let metadata_manager = MetadataManager::with_config(
+            &mock_storage,
+            &mock_client,
+            &mock_file,
+            MetadataManagerConfig {
+                max_media_size: Some(1024),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let err = metadata_manager
+            .download_media(&image, Duration::from_secs(5), "")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MediaDownloadError>(),
+            Some(MediaDownloadError::TooLarge { max_size: 1024, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_download_media_hanging_server() {
+        use tokio::io::AsyncReadExt;
+
+        let mock_client = MockStarknetClient::default();
+        let mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+
+        // Accepts the connection, but never answers.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let image = format!("http://{}/1.png", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 4096];
+            let _ = socket.read(&mut request).await;
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+
+        let metadata_manager =
+            MetadataManager::new(&mock_storage, &mock_client, &mock_file).unwrap();
+
+        let err = metadata_manager
+            .download_media(&image, Duration::from_millis(200), "")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MediaDownloadError>(),
+            Some(MediaDownloadError::Timeout { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_metadata_media_with_key_template() {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let mock_client = MockStarknetClient::default();
+        let mock_storage = MockStorage::default();
+        let mut mock_file = MockFileManager::default();
+
+        let uri = format!(
+            "data:image/png;base64,{}",
+            general_purpose::STANDARD.encode(b"\x89PNG\r\n\x1a\n")
+        );
+
+        mock_file
+            .expect_save()
+            .withf(|file| {
+                file.name == "original.png"
+                    && file.dir_path == Some(format!("mainnet/0x{:064x}/7", FieldElement::ONE))
+            })
+            .times(1)
+            .returning(|file| Ok(format!("{}/{}", file.dir_path.clone().unwrap(), file.name)));
+
+        let config = MetadataManagerConfig {
+            media_key_template: Some(
+                MediaKeyTemplate::new(
+                    "{network}/{collection}/{token_id}/{size}.{ext}",
+                    ark_starknet::network::Network::Mainnet,
+                )
+                .unwrap(),
+            ),
+            thumbnail_sizes: vec![128],
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let mut metadata_manager =
+            MetadataManager::with_config(&mock_storage, &mock_client, &mock_file, config).unwrap();
+
+        let media = metadata_manager
+            .fetch_metadata_media(
+                &uri,
+                ImageCacheOption::Save,
+                FieldElement::ONE,
+                &CairoU256 { low: 7, high: 0 },
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            media.media_key,
+            Some(format!(
+                "mainnet/0x{:064x}/7/original.png",
+                FieldElement::ONE
+            ))
+        );
+    }
+
+    #[test]
+    fn test_validate_config_thumbnails_without_size() {
+        let config = MetadataManagerConfig {
+            media_key_template: Some(
+                MediaKeyTemplate::new(
+                    "{collection}/{token_id}.{ext}",
+                    ark_starknet::network::Network::Mainnet,
+                )
+                .unwrap(),
+            ),
+            thumbnail_sizes: vec![128],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.validate(),
+            Err(MediaKeyTemplateError::MissingPlaceholder("size"))
+        );
+    }
+
+    #[cfg(feature = "webp-conversion")]
+    #[tokio::test]
+    async fn test_fetch_metadata_media_webp_conversion() {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let mock_client = MockStarknetClient::default();
+        let mock_storage = MockStorage::default();
+        let mut mock_file = MockFileManager::default();
+
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbaImage::new(300, 150)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        let uri = format!(
+            "data:image/png;base64,{}",
+            general_purpose::STANDARD.encode(png.into_inner())
+        );
+
+        mock_file
+            .expect_save()
+            .times(2)
+            .returning(|file| Ok(file.name.clone()));
+
+        let mut metadata_manager = MetadataManager::with_config(
+            &mock_storage,
+            &mock_client,
+            &mock_file,
+            MetadataManagerConfig {
+                webp_conversion_min_size: Some(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let media = metadata_manager
+            .fetch_metadata_media(
+                &uri,
+                ImageCacheOption::Save,
+                FieldElement::ONE,
+                &CairoU256 { low: 7, high: 0 },
+                Duration::from_secs(5),
+                "",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(media.media_key, Some("7.png".to_string()));
+        assert_eq!(media.webp_media_key, Some("7.webp".to_string()));
+    }
+
+    #[cfg(feature = "perceptual-hash")]
+    #[tokio::test]
+    async fn test_fetch_metadata_media_perceptual_hash() {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let mock_client = MockStarknetClient::default();
+        let mock_storage = MockStorage::default();
+        let mut mock_file = MockFileManager::default();
+
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbaImage::new(64, 64)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        let png_uri = format!(
+            "data:image/png;base64,{}",
+            general_purpose::STANDARD.encode(png.into_inner())
+        );
+        let svg_uri = format!(
+            "data:image/svg+xml;base64,{}",
+            general_purpose::STANDARD.encode("<svg></svg>")
+        );
+
+        mock_file
+            .expect_save()
+            .returning(|file| Ok(file.name.clone()));
+
+        let mut metadata_manager = MetadataManager::with_config(
+            &mock_storage,
+            &mock_client,
+            &mock_file,
+            MetadataManagerConfig {
+                compute_perceptual_hash: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let mut hashes = vec![];
+        for uri in [png_uri, svg_uri] {
+            let media = metadata_manager
+                .fetch_metadata_media(
+                    &uri,
+                    ImageCacheOption::Save,
+                    FieldElement::ONE,
+                    &CairoU256 { low: 7, high: 0 },
+                    Duration::from_secs(5),
+                    "",
+                )
+                .await
+                .unwrap();
+            hashes.push(media.perceptual_hash);
+        }
+
+        // The SVG image is not hashed.
+        assert_eq!(hashes, vec![Some("0000000000000000".to_string()), None]);
+    }
+
+    #[tokio::test]
+    async fn test_renormalize_collection() {
+        let mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+
+        mock_storage.expect_find_token_ids().returning(|_, _, _| {
+            Ok(TokenIdsPage {
+                token_ids: (1..=3).map(|low| CairoU256 { low, high: 0 }).collect(),
+                last_evaluated_key: None,
+            })
+        });
+
+        // Token 1 was normalized before the `properties` support,
+        // token 2 has no JSON raw metadata, and token 3 no metadata.
+        mock_storage
+            .expect_get_token_metadata()
+            .returning(|_, token_id| {
+                Ok(match token_id.low {
+                    1 => Some(TokenMetadata {
+                        raw: r#"{"name":"Duck","image":"ipfs://QmHash/1.png","properties":{"Background":"Blue"}}"#.to_string(),
+                        normalized: NormalizedMetadata {
+                            name: Some("Duck".to_string()),
+                            image: Some("ipfs://QmHash/1.png".to_string()),
+                            image_key: Some("1.png".to_string()),
+                            image_mime_type: Some("image/png".to_string()),
+                            ..Default::default()
+                        },
+                        content_hash: Some("stale".to_string()),
+                        ..Default::default()
+                    }),
+                    2 => Some(TokenMetadata {
+                        raw: "<html></html>".to_string(),
+                        ..Default::default()
+                    }),
+                    _ => None,
+                })
+            });
+        mock_storage
+            .expect_register_token_metadata()
+            .withf(|_, token_id, metadata| {
+                let attributes = metadata.normalized.attributes.as_ref().unwrap();
+                token_id.low == 1
+                    && attributes[0].trait_type.as_deref() == Some("Background")
+                    && metadata.normalized.image_key.as_deref() == Some("1.png")
+                    && metadata.content_hash.as_deref() != Some("stale")
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let metadata_manager =
+            MetadataManager::new(&mock_storage, &mock_client, &mock_file).unwrap();
+
+        assert_eq!(
+            metadata_manager
+                .renormalize_collection(FieldElement::ONE)
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reindex_collection_skips_out_of_range_tokens() {
+        use std::sync::{Arc, Mutex};
+
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+
+        let contract_address = FieldElement::ONE;
+        let registered: Arc<Mutex<Vec<u128>>> = Arc::new(Mutex::new(vec![]));
+        let start_keys: Arc<Mutex<Vec<u128>>> = Arc::new(Mutex::new(vec![]));
+
+        mock_client.expect_call_contract().returning(|_, _, _, _| {
+            let uri = "data:application/json;base64,e30=";
+            let mut felts = vec![FieldElement::from(uri.len())];
+            felts.extend(uri.bytes().map(FieldElement::from));
+            Ok(felts)
+        });
+
+        // Pages of 2 token ids out of 10, by ascending token id.
+        let start_keys_ref = Arc::clone(&start_keys);
+        mock_storage
+            .expect_find_token_ids()
+            .returning(move |_, start, page_size| {
+                let start = start.map_or(0, |t| t.low);
+                start_keys_ref.lock().unwrap().push(start);
+                let token_ids: Vec<CairoU256> = (start + 1..=10)
+                    .take(page_size)
+                    .map(|low| CairoU256 { low, high: 0 })
+                    .collect();
+                let last_evaluated_key = token_ids.last().filter(|t| t.low < 10).cloned();
+                Ok(TokenIdsPage {
+                    token_ids,
+                    last_evaluated_key,
+                })
+            });
+        mock_storage
+            .expect_get_reindex_checkpoint()
+            .returning(|_, _| Ok(None));
+        mock_storage
+            .expect_set_reindex_checkpoint()
+            .returning(|_, _, _| Ok(()));
+        mock_storage
+            .expect_delete_reindex_checkpoint()
+            .times(1)
+            .returning(|_, _| Ok(()));
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(|_, _| Ok(None));
+
+        let registered_ref = Arc::clone(&registered);
+        mock_storage
+            .expect_register_token_metadata()
+            .returning(move |_, token_id, _| {
+                registered_ref.lock().unwrap().push(token_id.low);
+                Ok(())
+            });
+
+        let mut metadata_manager = MetadataManager::with_config(
+            &mock_storage,
+            &mock_client,
+            &mock_file,
+            MetadataManagerConfig {
+                token_page_size: Some(2),
+                token_id_ranges: HashMap::from([(
+                    contract_address,
+                    TokenIdRange::new(CairoU256 { low: 4, high: 0 }, CairoU256 { low: 6, high: 0 }),
+                )]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        metadata_manager
+            .reindex_collection_token_metadata(
+                contract_address,
+                "job-1",
+                ImageCacheOption::DoNotSave,
+                "https://ipfs.example.com",
+                Duration::from_secs(5),
+                "https://arkproject.dev",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(*registered.lock().unwrap(), vec![4, 5, 6]);
+        // The scan starts at the range, and stops after it.
+        assert_eq!(*start_keys.lock().unwrap(), vec![3, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_collection_token_metadata_skips_out_of_range_tokens() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+
+        mock_storage
+            .expect_find_token_ids_without_metadata()
+            .returning(|_| {
+                Ok((1..=3)
+                    .map(|low| (FieldElement::ONE, CairoU256 { low, high: 0 }))
+                    .collect())
+            });
+        mock_client.expect_call_contract().returning(|_, _, _, _| {
+            let uri = "data:application/json;base64,e30=";
+            let mut felts = vec![FieldElement::from(uri.len())];
+            felts.extend(uri.bytes().map(FieldElement::from));
+            Ok(felts)
+        });
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(|_, _| Ok(None));
+        mock_storage
+            .expect_register_token_metadata()
+            .withf(|_, token_id, _| token_id.low == 2)
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut metadata_manager = MetadataManager::with_config(
+            &mock_storage,
+            &mock_client,
+            &mock_file,
+            MetadataManagerConfig {
+                token_id_ranges: HashMap::from([(
+                    FieldElement::ONE,
+                    TokenIdRange::new(CairoU256 { low: 2, high: 0 }, CairoU256 { low: 2, high: 0 }),
+                )]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        metadata_manager
+            .refresh_collection_token_metadata(
+                FieldElement::ONE,
+                ImageCacheOption::DoNotSave,
+                "https://ipfs.example.com",
+                Duration::from_secs(5),
+                "https://arkproject.dev",
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reindex_collection_resumes_from_checkpoint() {
+        use std::sync::{Arc, Mutex};
+
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+
+        let contract_address = FieldElement::ONE;
+        let checkpoint: Arc<Mutex<Option<u128>>> = Arc::new(Mutex::new(None));
+        let registered: Arc<Mutex<Vec<u128>>> = Arc::new(Mutex::new(vec![]));
+
+        // On-chain metadata, returned as a Cairo long string.
+        mock_client.expect_call_contract().returning(|_, _, _, _| {
+            let uri = "data:application/json;base64,e30=";
+            let mut felts = vec![FieldElement::from(uri.len())];
+            felts.extend(uri.bytes().map(FieldElement::from));
+            Ok(felts)
+        });
+
+        // Pages of 2 token ids, by ascending token id.
+        mock_storage
+            .expect_find_token_ids()
+            .withf(|_, _, page_size| *page_size == 2)
+            .returning(|_, start, page_size| {
+                let start = start.map_or(0, |t| t.low);
+                let token_ids: Vec<CairoU256> = (start + 1..=5)
+                    .take(page_size)
+                    .map(|low| CairoU256 { low, high: 0 })
+                    .collect();
+                let last_evaluated_key = token_ids.last().filter(|t| t.low < 5).cloned();
+                Ok(TokenIdsPage {
+                    token_ids,
+                    last_evaluated_key,
+                })
+            });
+
+        let checkpoint_ref = Arc::clone(&checkpoint);
+        mock_storage
+            .expect_get_reindex_checkpoint()
+            .returning(move |_, _| {
+                Ok(checkpoint_ref
+                    .lock()
+                    .unwrap()
+                    .map(|low| CairoU256 { low, high: 0 }))
+            });
+
+        let checkpoint_ref = Arc::clone(&checkpoint);
+        mock_storage
+            .expect_set_reindex_checkpoint()
+            .with(eq(contract_address), eq("job-1"), always())
+            .returning(move |_, _, token_id| {
+                *checkpoint_ref.lock().unwrap() = Some(token_id.low);
+                Ok(())
+            });
+
+        // Only deleted once the reindex is done.
+        let checkpoint_ref = Arc::clone(&checkpoint);
+        mock_storage
+            .expect_delete_reindex_checkpoint()
+            .with(eq(contract_address), eq("job-1"))
+            .times(1)
+            .returning(move |_, _| {
+                assert_eq!(*checkpoint_ref.lock().unwrap(), Some(5));
+                *checkpoint_ref.lock().unwrap() = None;
+                Ok(())
+            });
+
+        // The registration of the token 3 fails once, interrupting the reindex.
+        let registered_ref = Arc::clone(&registered);
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(|_, _| Ok(None));
+        let mut failed = false;
+        mock_storage
+            .expect_register_token_metadata()
+            .returning(move |_, token_id, _| {
+                if token_id.low == 3 && !failed {
+                    failed = true;
+                    return Err(StorageError::DatabaseError("timeout".to_string()));
+                }
+                registered_ref.lock().unwrap().push(token_id.low);
+                Ok(())
+            });
+
+        for expect_ok in [false, true] {
+            let mut metadata_manager = MetadataManager::with_config(
+                &mock_storage,
+                &mock_client,
+                &mock_file,
+                MetadataManagerConfig {
+                    token_page_size: Some(2),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            let result = metadata_manager
+                .reindex_collection_token_metadata(
+                    contract_address,
+                    "job-1",
+                    ImageCacheOption::DoNotSave,
+                    "https://ipfs.example.com",
+                    Duration::from_secs(5),
+                    "https://arkproject.dev",
+                )
+                .await;
+
+            assert_eq!(result.is_ok(), expect_ok);
+        }
+
+        assert_eq!(*registered.lock().unwrap(), vec![1, 2, 3, 4, 5]);
+        assert_eq!(*checkpoint.lock().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_reindex_collection_runs_same_job_twice() {
+        use std::sync::{Arc, Mutex};
+
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+        let mock_file = MockFileManager::default();
+
+        let contract_address = FieldElement::ONE;
+        let checkpoint: Arc<Mutex<Option<u128>>> = Arc::new(Mutex::new(None));
+        let registered: Arc<Mutex<Vec<u128>>> = Arc::new(Mutex::new(vec![]));
+
+        mock_client.expect_call_contract().returning(|_, _, _, _| {
+            let uri = "data:application/json;base64,e30=";
+            let mut felts = vec![FieldElement::from(uri.len())];
+            felts.extend(uri.bytes().map(FieldElement::from));
+            Ok(felts)
+        });
+
+        mock_storage
+            .expect_find_token_ids()
+            .returning(|_, start, page_size| {
+                let start = start.map_or(0, |t| t.low);
+                let token_ids: Vec<CairoU256> = (start + 1..=3)
+                    .take(page_size)
+                    .map(|low| CairoU256 { low, high: 0 })
+                    .collect();
+                let last_evaluated_key = token_ids.last().filter(|t| t.low < 3).cloned();
+                Ok(TokenIdsPage {
+                    token_ids,
+                    last_evaluated_key,
+                })
+            });
+
+        let checkpoint_ref = Arc::clone(&checkpoint);
+        mock_storage
+            .expect_get_reindex_checkpoint()
+            .returning(move |_, _| {
+                Ok(checkpoint_ref
+                    .lock()
+                    .unwrap()
+                    .map(|low| CairoU256 { low, high: 0 }))
+            });
+        let checkpoint_ref = Arc::clone(&checkpoint);
+        mock_storage
+            .expect_set_reindex_checkpoint()
+            .returning(move |_, _, token_id| {
+                *checkpoint_ref.lock().unwrap() = Some(token_id.low);
+                Ok(())
+            });
+        let checkpoint_ref = Arc::clone(&checkpoint);
+        mock_storage
+            .expect_delete_reindex_checkpoint()
+            .times(2)
+            .returning(move |_, _| {
+                *checkpoint_ref.lock().unwrap() = None;
+                Ok(())
+            });
+
+        mock_storage
+            .expect_get_token_metadata_hash()
+            .returning(|_, _| Ok(None));
+        let registered_ref = Arc::clone(&registered);
+        mock_storage
+            .expect_register_token_metadata()
+            .returning(move |_, token_id, _| {
+                registered_ref.lock().unwrap().push(token_id.low);
+                Ok(())
+            });
+
+        let mut metadata_manager = MetadataManager::with_config(
+            &mock_storage,
+            &mock_client,
+            &mock_file,
+            MetadataManagerConfig {
+                token_page_size: Some(2),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // The second run starts again from the first token.
+        for _ in 0..2 {
+            metadata_manager
+                .reindex_collection_token_metadata(
+                    contract_address,
+                    "job-1",
+                    ImageCacheOption::DoNotSave,
+                    "https://ipfs.example.com",
+                    Duration::from_secs(5),
+                    "https://arkproject.dev",
+                )
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(*registered.lock().unwrap(), vec![1, 2, 3, 1, 2, 3]);
+        assert_eq!(*checkpoint.lock().unwrap(), None);
+    }
+
     #[tokio::test]
     async fn test_get_contract_property_string() {
         // SETUP: Mocking and Initializing
@@ -537,7 +3743,8 @@ mod tests {
             });
 
         let storage_manager = MockStorage::default();
-        let mut metadata_manager = MetadataManager::new(&storage_manager, &mock_client, &mock_file);
+        let mut metadata_manager =
+            MetadataManager::new(&storage_manager, &mock_client, &mock_file).unwrap();
 
         // EXECUTION: Call the function under test
         let result = metadata_manager
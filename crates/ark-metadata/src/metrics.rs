@@ -0,0 +1,43 @@
+//! Prometheus metrics of the metadata fetching.
+//!
+//! Metrics are registered in the prometheus default registry,
+//! in order to be exposed with the metrics of the other crates.
+use crate::types::MetadataType;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+use std::sync::OnceLock;
+
+/// Latency of the metadata fetching, labelled by metadata source.
+pub fn fetch_duration_seconds() -> &'static HistogramVec {
+    static METRIC: OnceLock<HistogramVec> = OnceLock::new();
+    METRIC.get_or_init(|| {
+        register_histogram_vec!(
+            "metadata_fetch_duration_seconds",
+            "Latency of the token metadata fetching",
+            &["source"]
+        )
+        .expect("metadata_fetch_duration_seconds can be registered")
+    })
+}
+
+/// Failed metadata fetching, labelled by metadata source.
+pub fn fetch_errors_total() -> &'static IntCounterVec {
+    static METRIC: OnceLock<IntCounterVec> = OnceLock::new();
+    METRIC.get_or_init(|| {
+        register_int_counter_vec!(
+            "metadata_fetch_errors_total",
+            "Number of failed token metadata fetching",
+            &["source"]
+        )
+        .expect("metadata_fetch_errors_total can be registered")
+    })
+}
+
+/// Label of the metadata source used by the metrics.
+pub(crate) fn source_label(metadata_type: &MetadataType) -> &'static str {
+    match metadata_type {
+        MetadataType::Http(_) => "http",
+        MetadataType::Ipfs(_) => "ipfs",
+        MetadataType::Arweave(_) => "arweave",
+        MetadataType::OnChain(_) => "onchain",
+    }
+}
@@ -0,0 +1,523 @@
+//! `FileManager` saving the files to an object storage (i.g. AWS S3).
+//!
+//! Files larger than the multipart threshold (animations, videos...) are
+//! uploaded in parts, each part being sent as a slice of the file content
+//! without copying it. Failing calls are retried with an exponential backoff,
+//! and a multipart upload that can't be completed is aborted.
+//!
+//! Before an upload, the ETag of the stored object is compared to the one
+//! the content would have, so the files saved again with the same content
+//! (i.g. on a refresh) are not uploaded twice.
+use crate::file_manager::{FileInfo, FileManager};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use md5::{Digest, Md5};
+use std::future::Future;
+use std::time::Duration;
+use tracing::{debug, error, warn};
+
+#[cfg(any(test, feature = "mock"))]
+use mockall::automock;
+
+/// Minimum size of a part of a multipart upload on S3, except the last one.
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// A part uploaded in a multipart upload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletedPart {
+    /// Number of the part, starting at 1.
+    pub part_number: i32,
+    pub etag: String,
+}
+
+/// Metadata of a stored object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectMetadata {
+    pub content_length: u64,
+    pub etag: Option<String>,
+}
+
+/// Server-side encryption of the uploaded objects.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerSideEncryption {
+    /// Encryption with keys managed by the storage (`AES256`).
+    Aes256,
+    /// Encryption with a KMS key (`aws:kms`), the default one of the
+    /// account if no key id is given.
+    AwsKms { key_id: Option<String> },
+}
+
+impl ServerSideEncryption {
+    /// Value of the `x-amz-server-side-encryption` header.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServerSideEncryption::Aes256 => "AES256",
+            ServerSideEncryption::AwsKms { .. } => "aws:kms",
+        }
+    }
+}
+
+/// Options of the uploaded objects, the storage defaults being used when unset.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectOptions {
+    pub server_side_encryption: Option<ServerSideEncryption>,
+    /// Canned ACL, like `private` or `public-read`.
+    pub acl: Option<String>,
+    /// Value of the `Cache-Control` header served with the object.
+    pub cache_control: Option<String>,
+}
+
+/// The calls to an object storage required to save and read files.
+#[cfg_attr(any(test, feature = "mock"), automock)]
+#[async_trait]
+pub trait ObjectStore {
+    /// Returns the metadata of the object (`HeadObject`), `None` if it doesn't exist.
+    async fn head_object(&self, key: &str) -> Result<Option<ObjectMetadata>>;
+
+    /// Returns the content of the object (`GetObject`), `None` if it doesn't exist.
+    async fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Uploads the object in one call.
+    async fn put_object(&self, key: &str, content: &[u8], options: &ObjectOptions) -> Result<()>;
+
+    /// Starts a multipart upload, returning its id.
+    /// The options apply to the object assembled from the parts.
+    async fn create_multipart_upload(&self, key: &str, options: &ObjectOptions) -> Result<String>;
+
+    /// Uploads one part of a multipart upload, returning its ETag.
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        content: &[u8],
+    ) -> Result<String>;
+
+    /// Assembles the uploaded parts into the object.
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[CompletedPart],
+    ) -> Result<()>;
+
+    /// Aborts a multipart upload, deleting the uploaded parts.
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<()>;
+}
+
+pub struct ObjectStoreConfig {
+    /// Size from which the files are uploaded in parts.
+    pub multipart_threshold: usize,
+    /// Size of the parts, at least `MIN_PART_SIZE` on S3.
+    pub part_size: usize,
+    /// Maximum number of retries of each call.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled at each attempt.
+    pub retry_delay: Duration,
+    /// Encryption, ACL and cache control of the uploaded objects.
+    pub object_options: ObjectOptions,
+}
+
+impl Default for ObjectStoreConfig {
+    fn default() -> Self {
+        ObjectStoreConfig {
+            multipart_threshold: MIN_PART_SIZE,
+            part_size: 8 * 1024 * 1024,
+            max_retries: 3,
+            retry_delay: Duration::from_millis(200),
+            object_options: ObjectOptions::default(),
+        }
+    }
+}
+
+/// Saves the files to an object storage, under the `{dir_path}/{name}` key.
+pub struct ObjectStoreFileManager<S: ObjectStore> {
+    store: S,
+    config: ObjectStoreConfig,
+}
+
+impl<S: ObjectStore + Send + Sync> ObjectStoreFileManager<S> {
+    pub fn new(store: S, config: ObjectStoreConfig) -> Self {
+        ObjectStoreFileManager { store, config }
+    }
+
+    /// Calls the given operation, retrying it on failure.
+    async fn with_retry<T, F, Fut>(&self, operation: &str, f: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut delay = self.config.retry_delay;
+        let mut attempt = 0;
+
+        loop {
+            match f().await {
+                Ok(r) => return Ok(r),
+                Err(e) if attempt < self.config.max_retries => {
+                    warn!("{} failed (attempt #{}): {}", operation, attempt + 1, e);
+                    attempt += 1;
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn key(file: &FileInfo) -> String {
+        match &file.dir_path {
+            Some(dir_path) => format!("{}/{}", dir_path.trim_end_matches('/'), file.name),
+            None => file.name.clone(),
+        }
+    }
+
+    /// ETag of the object uploaded with the given content: the MD5 of the
+    /// content, or for a multipart upload the MD5 of the MD5s of its parts
+    /// followed by the number of parts.
+    fn expected_etag(&self, content: &[u8]) -> String {
+        if content.len() <= self.config.multipart_threshold {
+            return format!("{:x}", Md5::digest(content));
+        }
+
+        let mut digests = vec![];
+        let mut parts = 0;
+        for chunk in content.chunks(self.config.part_size.max(1)) {
+            digests.extend_from_slice(&Md5::digest(chunk));
+            parts += 1;
+        }
+
+        format!("{:x}-{}", Md5::digest(&digests), parts)
+    }
+
+    async fn multipart_upload(&self, key: &str, content: &[u8]) -> Result<()> {
+        let upload_id = self
+            .with_retry("create_multipart_upload", || {
+                self.store
+                    .create_multipart_upload(key, &self.config.object_options)
+            })
+            .await?;
+
+        match self.upload_parts(key, &upload_id, content).await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                if let Err(abort_error) = self.store.abort_multipart_upload(key, &upload_id).await {
+                    error!(
+                        "Failed to abort multipart upload of {}: {}",
+                        key, abort_error
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn upload_parts(&self, key: &str, upload_id: &str, content: &[u8]) -> Result<()> {
+        let mut parts = vec![];
+
+        for (i, chunk) in content.chunks(self.config.part_size.max(1)).enumerate() {
+            let part_number = i as i32 + 1;
+            debug!(
+                "Uploading part #{} of {} ({} bytes)",
+                part_number,
+                key,
+                chunk.len()
+            );
+
+            let etag = self
+                .with_retry("upload_part", || {
+                    self.store.upload_part(key, upload_id, part_number, chunk)
+                })
+                .await?;
+
+            parts.push(CompletedPart { part_number, etag });
+        }
+
+        self.with_retry("complete_multipart_upload", || {
+            self.store.complete_multipart_upload(key, upload_id, &parts)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl<S: ObjectStore + Send + Sync> FileManager for ObjectStoreFileManager<S> {
+    async fn save(&self, file: &FileInfo) -> Result<String> {
+        let key = Self::key(file);
+
+        match self.exists(file).await {
+            Ok(true) => {
+                debug!("File unchanged, upload skipped: {}", key);
+                return Ok(key);
+            }
+            Ok(false) => {}
+            // Uploading the file again is only wasteful.
+            Err(e) => warn!("Failed to check if {} is already saved: {}", key, e),
+        }
+
+        if file.content.len() > self.config.multipart_threshold {
+            self.multipart_upload(&key, &file.content).await?;
+        } else {
+            self.with_retry("put_object", || {
+                self.store
+                    .put_object(&key, &file.content, &self.config.object_options)
+            })
+            .await?;
+        }
+
+        debug!("File saved: {} ({} bytes)", key, file.content.len());
+
+        Ok(key)
+    }
+
+    /// The objects encrypted with a KMS key having an ETag which is not the
+    /// MD5 of their content, they are never considered unchanged.
+    async fn exists(&self, file: &FileInfo) -> Result<bool> {
+        let key = Self::key(file);
+        let metadata = self
+            .with_retry("head_object", || self.store.head_object(&key))
+            .await?;
+
+        Ok(match metadata {
+            Some(metadata) => {
+                metadata.content_length == file.content.len() as u64
+                    && metadata.etag.as_deref().map(|etag| etag.trim_matches('"'))
+                        == Some(self.expected_etag(&file.content).as_str())
+            }
+            None => false,
+        })
+    }
+
+    async fn read(&self, key: &str) -> Result<Vec<u8>> {
+        self.with_retry("get_object", || self.store.get_object(key))
+            .await?
+            .ok_or_else(|| anyhow!("Object not found: {}", key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    fn config() -> ObjectStoreConfig {
+        ObjectStoreConfig {
+            multipart_threshold: 8,
+            part_size: 4,
+            max_retries: 2,
+            retry_delay: Duration::from_millis(1),
+            object_options: ObjectOptions::default(),
+        }
+    }
+
+    /// A store without any object saved.
+    fn store() -> MockObjectStore {
+        let mut store = MockObjectStore::default();
+        store.expect_head_object().returning(|_| Ok(None));
+        store
+    }
+
+    fn file(content: &[u8]) -> FileInfo {
+        FileInfo {
+            name: "1.mp4".to_string(),
+            content: content.to_vec(),
+            dir_path: Some("0x1".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_object_with_retry() {
+        let mut store = store();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&calls);
+        store
+            .expect_put_object()
+            .withf(|key, content, _| key == "0x1/1.mp4" && content == b"small")
+            .times(2)
+            .returning(move |_, _, _| {
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err(anyhow!("SlowDown"))
+                } else {
+                    Ok(())
+                }
+            });
+
+        let manager = ObjectStoreFileManager::new(store, config());
+        assert_eq!(manager.save(&file(b"small")).await.unwrap(), "0x1/1.mp4");
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload() {
+        let mut store = store();
+        let uploaded = Arc::new(Mutex::new(vec![]));
+
+        store
+            .expect_create_multipart_upload()
+            .times(1)
+            .returning(|_, _| Ok("upload-1".to_string()));
+
+        let parts = Arc::clone(&uploaded);
+        store
+            .expect_upload_part()
+            .times(3)
+            .returning(move |_, upload_id, part_number, content| {
+                assert_eq!(upload_id, "upload-1");
+                parts.lock().unwrap().push(content.to_vec());
+                Ok(format!("etag-{}", part_number))
+            });
+
+        store
+            .expect_complete_multipart_upload()
+            .withf(|_, upload_id, parts| {
+                upload_id == "upload-1"
+                    && parts.iter().map(|p| p.part_number).collect::<Vec<_>>() == vec![1, 2, 3]
+                    && parts[2].etag == "etag-3"
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        store.expect_put_object().never();
+        store.expect_abort_multipart_upload().never();
+
+        let manager = ObjectStoreFileManager::new(store, config());
+        manager.save(&file(b"0123456789")).await.unwrap();
+
+        assert_eq!(
+            *uploaded.lock().unwrap(),
+            vec![b"0123".to_vec(), b"4567".to_vec(), b"89".to_vec()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload_aborted() {
+        let mut store = store();
+
+        store
+            .expect_create_multipart_upload()
+            .returning(|_, _| Ok("upload-1".to_string()));
+        // The first part fails after all the retries.
+        store
+            .expect_upload_part()
+            .times(3)
+            .returning(|_, _, _, _| Err(anyhow!("InternalError")));
+        store.expect_complete_multipart_upload().never();
+        store
+            .expect_abort_multipart_upload()
+            .withf(|key, upload_id| key == "0x1/1.mp4" && upload_id == "upload-1")
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let manager = ObjectStoreFileManager::new(store, config());
+        assert!(manager.save(&file(b"0123456789")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_object_options() {
+        let options = ObjectOptions {
+            server_side_encryption: Some(ServerSideEncryption::AwsKms {
+                key_id: Some("key-1".to_string()),
+            }),
+            acl: Some("private".to_string()),
+            cache_control: Some("max-age=31536000".to_string()),
+        };
+        assert_eq!(
+            options.server_side_encryption.as_ref().unwrap().as_str(),
+            "aws:kms"
+        );
+
+        let mut store = store();
+        let expected = options.clone();
+        store
+            .expect_put_object()
+            .withf(move |_, _, options| *options == expected)
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        let expected = options.clone();
+        store
+            .expect_create_multipart_upload()
+            .withf(move |_, options| *options == expected)
+            .times(1)
+            .returning(|_, _| Ok("upload-1".to_string()));
+        store
+            .expect_upload_part()
+            .returning(|_, _, part_number, _| Ok(format!("etag-{}", part_number)));
+        store
+            .expect_complete_multipart_upload()
+            .returning(|_, _, _| Ok(()));
+
+        let manager = ObjectStoreFileManager::new(
+            store,
+            ObjectStoreConfig {
+                object_options: options,
+                ..config()
+            },
+        );
+        manager.save(&file(b"small")).await.unwrap();
+        manager.save(&file(b"0123456789")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_file_not_uploaded() {
+        let mut store = MockObjectStore::default();
+        store.expect_head_object().returning(|key| {
+            Ok(Some(match key {
+                "0x1/1.mp4" => ObjectMetadata {
+                    content_length: 5,
+                    etag: Some("\"eb5c1399a871211c7e7ed732d15e3a8b\"".to_string()),
+                },
+                _ => ObjectMetadata {
+                    content_length: 10,
+                    etag: Some("61e3716e3a7767581863b67c4e785584-3".to_string()),
+                },
+            }))
+        });
+        store.expect_put_object().never();
+        store.expect_create_multipart_upload().never();
+
+        let manager = ObjectStoreFileManager::new(store, config());
+        assert_eq!(manager.save(&file(b"small")).await.unwrap(), "0x1/1.mp4");
+
+        let mut multipart = file(b"0123456789");
+        multipart.name = "2.mp4".to_string();
+        assert!(manager.exists(&multipart).await.unwrap());
+        manager.save(&multipart).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_changed_file_uploaded() {
+        let mut store = MockObjectStore::default();
+        store.expect_head_object().returning(|_| {
+            Ok(Some(ObjectMetadata {
+                content_length: 5,
+                etag: Some("\"eb5c1399a871211c7e7ed732d15e3a8b\"".to_string()),
+            }))
+        });
+        store
+            .expect_put_object()
+            .withf(|_, content, _| content == b"smalL")
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let manager = ObjectStoreFileManager::new(store, config());
+        manager.save(&file(b"smalL")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_object_with_retry() {
+        let mut store = MockObjectStore::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&calls);
+        store.expect_get_object().returning(move |key| {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Err(anyhow!("SlowDown"));
+            }
+            Ok((key == "0x1/1.json.gz").then(|| b"content".to_vec()))
+        });
+
+        let manager = ObjectStoreFileManager::new(store, config());
+        assert_eq!(manager.read("0x1/1.json.gz").await.unwrap(), b"content");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(manager.read("0x1/2.json.gz").await.is_err());
+    }
+}
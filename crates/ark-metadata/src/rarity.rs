@@ -0,0 +1,230 @@
+//! Rarity of the tokens of a collection, computed from their attributes.
+//!
+//! The score of a token is the sum, for each trait type of the collection,
+//! of the inverse of the frequency of its value: `tokens / tokens with this value`.
+//! A token without a trait type has the "missing" value of this trait type,
+//! which is rare if most tokens have it. The rarest token has the rank 1,
+//! tokens with the same score sharing the same rank.
+use crate::storage::{Storage, DEFAULT_TOKEN_PAGE_SIZE};
+use crate::types::{MetadataAttribute, StorageError, TokenRarity};
+use ark_starknet::CairoU256;
+use starknet::core::types::FieldElement;
+use std::collections::{BTreeMap, HashMap};
+use tracing::{debug, info};
+
+/// Computes the rarity of the tokens of the collection having metadata,
+/// and stores it with each token.
+///
+/// The tokens without metadata yet are skipped: the rarity of a collection
+/// being partially indexed can be recomputed once more tokens are indexed.
+pub async fn compute_collection_rarity<S: Storage>(
+    storage: &S,
+    contract_address: FieldElement,
+) -> Result<Vec<(CairoU256, TokenRarity)>, StorageError> {
+    let mut tokens = vec![];
+    let mut last_evaluated_key = None;
+
+    loop {
+        let page = storage
+            .find_token_ids(
+                contract_address,
+                last_evaluated_key,
+                DEFAULT_TOKEN_PAGE_SIZE,
+            )
+            .await?;
+
+        for token_id in page.token_ids {
+            match storage
+                .get_token_metadata(contract_address, token_id.clone())
+                .await?
+            {
+                Some(metadata) => {
+                    tokens.push((token_id, metadata.normalized.attributes.unwrap_or_default()))
+                }
+                None => debug!(
+                    "No metadata for token {} of 0x{:064x}, skipping it",
+                    token_id.to_decimal(false),
+                    contract_address
+                ),
+            }
+        }
+
+        last_evaluated_key = page.last_evaluated_key;
+        if last_evaluated_key.is_none() {
+            break;
+        }
+    }
+
+    let rarities = compute_rarity(&tokens);
+
+    for (token_id, rarity) in rarities.iter() {
+        storage
+            .register_token_rarity(contract_address, token_id.clone(), rarity.clone())
+            .await?;
+    }
+
+    info!(
+        "Rarity computed for {} tokens of 0x{:064x}",
+        rarities.len(),
+        contract_address
+    );
+
+    Ok(rarities)
+}
+
+/// Computes the rarity of the given tokens, returned in the same order.
+pub fn compute_rarity(
+    tokens: &[(CairoU256, Vec<MetadataAttribute>)],
+) -> Vec<(CairoU256, TokenRarity)> {
+    // The traits are sorted, so the scores are summed in the same order.
+    let token_traits: Vec<BTreeMap<String, String>> = tokens
+        .iter()
+        .map(|(_, attributes)| {
+            attributes
+                .iter()
+                .filter_map(|a| {
+                    let trait_type = a.trait_type.as_ref()?;
+                    Some((trait_type.clone(), a.value.to_strings().join(",")))
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut counts: BTreeMap<&str, HashMap<Option<&str>, usize>> = BTreeMap::new();
+    for traits in token_traits.iter() {
+        for (trait_type, value) in traits.iter() {
+            *counts
+                .entry(trait_type.as_str())
+                .or_default()
+                .entry(Some(value.as_str()))
+                .or_default() += 1;
+        }
+    }
+
+    let total = tokens.len() as f64;
+    for values in counts.values_mut() {
+        let missing = tokens.len() - values.values().sum::<usize>();
+        if missing > 0 {
+            values.insert(None, missing);
+        }
+    }
+
+    let scores: Vec<f64> = token_traits
+        .iter()
+        .map(|traits| {
+            counts
+                .iter()
+                .map(|(trait_type, values)| {
+                    let value = traits.get(*trait_type).map(String::as_str);
+                    total / values[&value] as f64
+                })
+                .sum()
+        })
+        .collect();
+
+    scores
+        .iter()
+        .zip(tokens.iter())
+        .map(|(score, (token_id, _))| {
+            let rank = 1 + scores.iter().filter(|s| *s > score).count() as u64;
+            (
+                token_id.clone(),
+                TokenRarity {
+                    score: *score,
+                    rank,
+                },
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MockStorage;
+    use crate::types::{MetadataTraitValue, NormalizedMetadata, TokenIdsPage, TokenMetadata};
+
+    fn attributes(traits: &[(&str, &str)]) -> Vec<MetadataAttribute> {
+        traits
+            .iter()
+            .map(|(trait_type, value)| MetadataAttribute {
+                display_type: None,
+                trait_type: Some(trait_type.to_string()),
+                value: MetadataTraitValue::String(value.to_string()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compute_rarity() {
+        let tokens = vec![
+            (
+                CairoU256 { low: 1, high: 0 },
+                attributes(&[("Background", "Blue"), ("Hat", "Cap")]),
+            ),
+            (
+                CairoU256 { low: 2, high: 0 },
+                attributes(&[("Background", "Blue"), ("Hat", "Cap")]),
+            ),
+            (
+                CairoU256 { low: 3, high: 0 },
+                attributes(&[("Background", "Blue")]),
+            ),
+            (
+                CairoU256 { low: 4, high: 0 },
+                attributes(&[("Background", "Gold"), ("Hat", "Crown")]),
+            ),
+        ];
+
+        let rarities = compute_rarity(&tokens);
+
+        // Background: Blue 3/4, Gold 1/4. Hat: Cap 2/4, Crown 1/4, missing 1/4.
+        let scores: Vec<f64> = rarities.iter().map(|(_, r)| r.score).collect();
+        assert_eq!(
+            scores,
+            vec![4.0 / 3.0 + 2.0, 4.0 / 3.0 + 2.0, 4.0 / 3.0 + 4.0, 8.0]
+        );
+
+        let ranks: Vec<u64> = rarities.iter().map(|(_, r)| r.rank).collect();
+        assert_eq!(ranks, vec![3, 3, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_compute_collection_rarity_skips_tokens_without_metadata() {
+        let mut mock_storage = MockStorage::default();
+
+        mock_storage.expect_find_token_ids().returning(|_, _, _| {
+            Ok(TokenIdsPage {
+                token_ids: (1..=3).map(|low| CairoU256 { low, high: 0 }).collect(),
+                last_evaluated_key: None,
+            })
+        });
+        mock_storage
+            .expect_get_token_metadata()
+            .returning(|_, token_id| {
+                Ok((token_id.low != 3).then(|| TokenMetadata {
+                    normalized: NormalizedMetadata {
+                        attributes: Some(attributes(&[(
+                            "Background",
+                            if token_id.low == 1 { "Blue" } else { "Gold" },
+                        )])),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }))
+            });
+        mock_storage
+            .expect_register_token_rarity()
+            .withf(|_, token_id, rarity| {
+                token_id.low != 3 && rarity.score == 2.0 && rarity.rank == 1
+            })
+            .times(2)
+            .returning(|_, _, _| Ok(()));
+
+        let rarities = compute_collection_rarity(&mock_storage, FieldElement::ONE)
+            .await
+            .unwrap();
+
+        assert_eq!(rarities.len(), 2);
+    }
+}
@@ -0,0 +1,156 @@
+//! Near-duplicate tokens of a collection, found from the perceptual hash
+//! (`image_phash`) of their image.
+//!
+//! Two images are near-duplicates when the hamming distance between their
+//! hashes is small: the same art resized, recompressed or slightly edited.
+//! The tokens without hash (not raster, or not hashed yet) are skipped.
+use crate::image_processing::hamming_distance;
+use crate::storage::{Storage, DEFAULT_TOKEN_PAGE_SIZE};
+use crate::types::StorageError;
+use ark_starknet::CairoU256;
+use starknet::core::types::FieldElement;
+use tracing::debug;
+
+/// Maximum hamming distance between the hashes of near-duplicate images.
+pub const DEFAULT_MAX_HAMMING_DISTANCE: u32 = 10;
+
+/// A token whose image is a near-duplicate of the image of a previous token.
+#[derive(Debug, Clone)]
+pub struct NearDuplicate {
+    pub token_id: CairoU256,
+    pub duplicate_of: CairoU256,
+    /// Number of different bits between the two hashes.
+    pub distance: u32,
+}
+
+/// Returns the pairs of tokens whose hashes are within `max_distance`,
+/// each token being compared to the tokens before it.
+///
+/// All the pairs are compared, which is fine for the size of a collection
+/// but not to compare several collections together.
+pub fn find_near_duplicates(hashes: &[(CairoU256, u64)], max_distance: u32) -> Vec<NearDuplicate> {
+    let mut duplicates = vec![];
+
+    for (i, (token_id, hash)) in hashes.iter().enumerate() {
+        for (other_id, other_hash) in hashes[..i].iter() {
+            let distance = hamming_distance(*hash, *other_hash);
+            if distance <= max_distance {
+                duplicates.push(NearDuplicate {
+                    token_id: token_id.clone(),
+                    duplicate_of: other_id.clone(),
+                    distance,
+                });
+            }
+        }
+    }
+
+    duplicates
+}
+
+/// Reads the perceptual hashes of the tokens of the collection,
+/// and returns its near-duplicate tokens by ascending token id.
+pub async fn find_collection_near_duplicates<S: Storage>(
+    storage: &S,
+    contract_address: FieldElement,
+    max_distance: u32,
+) -> Result<Vec<NearDuplicate>, StorageError> {
+    let mut hashes = vec![];
+    let mut last_evaluated_key = None;
+
+    loop {
+        let page = storage
+            .find_token_ids(
+                contract_address,
+                last_evaluated_key,
+                DEFAULT_TOKEN_PAGE_SIZE,
+            )
+            .await?;
+
+        for token_id in page.token_ids {
+            let hash = storage
+                .get_token_metadata(contract_address, token_id.clone())
+                .await?
+                .and_then(|m| m.normalized.image_phash)
+                .and_then(|h| u64::from_str_radix(&h, 16).ok());
+
+            match hash {
+                Some(hash) => hashes.push((token_id, hash)),
+                None => debug!(
+                    "No perceptual hash for token {} of 0x{:064x}, skipping it",
+                    token_id.to_decimal(false),
+                    contract_address
+                ),
+            }
+        }
+
+        last_evaluated_key = page.last_evaluated_key;
+        if last_evaluated_key.is_none() {
+            break;
+        }
+    }
+
+    Ok(find_near_duplicates(&hashes, max_distance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MockStorage;
+    use crate::types::{NormalizedMetadata, TokenIdsPage, TokenMetadata};
+
+    fn token_id(low: u128) -> CairoU256 {
+        CairoU256 { low, high: 0 }
+    }
+
+    fn pairs(duplicates: &[NearDuplicate]) -> Vec<(u128, u128, u32)> {
+        duplicates
+            .iter()
+            .map(|d| (d.token_id.low, d.duplicate_of.low, d.distance))
+            .collect()
+    }
+
+    #[test]
+    fn test_find_near_duplicates() {
+        let hashes = vec![
+            (token_id(1), 0xff00_ff00_ff00_ff00),
+            (token_id(2), 0x0f0f_0f0f_0f0f_0f0f),
+            // Token 1 with 3 different bits.
+            (token_id(3), 0xff00_ff00_ff00_ff07),
+        ];
+
+        assert_eq!(
+            pairs(&find_near_duplicates(&hashes, DEFAULT_MAX_HAMMING_DISTANCE)),
+            vec![(3, 1, 3)]
+        );
+        assert!(find_near_duplicates(&hashes, 2).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_find_collection_near_duplicates_skips_tokens_without_hash() {
+        let mut mock_storage = MockStorage::default();
+
+        mock_storage.expect_find_token_ids().returning(|_, _, _| {
+            Ok(TokenIdsPage {
+                token_ids: (1..=3).map(token_id).collect(),
+                last_evaluated_key: None,
+            })
+        });
+        mock_storage
+            .expect_get_token_metadata()
+            .returning(|_, token_id| {
+                Ok(Some(TokenMetadata {
+                    normalized: NormalizedMetadata {
+                        image_phash: (token_id.low != 2).then(|| "00000000000000ff".to_string()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }))
+            });
+
+        let duplicates = find_collection_near_duplicates(&mock_storage, FieldElement::ONE, 0)
+            .await
+            .unwrap();
+
+        assert_eq!(pairs(&duplicates), vec![(3, 1, 0)]);
+    }
+}
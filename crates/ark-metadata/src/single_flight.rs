@@ -0,0 +1,187 @@
+//! De-duplication of the concurrent fetches of a same metadata document.
+//!
+//! Two transfers of a same token processed concurrently trigger two
+//! identical metadata fetches. Sharing an `InFlightFetches` between the
+//! `MetadataManager` instances makes the concurrent fetches of a same URI
+//! share one request and its result. The URIs are normalized like the keys
+//! of the `metadata_cache`, an IPFS document fetched from two gateways
+//! being fetched once.
+//!
+//! Only the fetches in flight are shared: a fetch started once the previous
+//! one completed sends a new request.
+use crate::metadata_cache::normalize_cache_key;
+use crate::metadata_fetcher::{MetadataFetchError, MetadataFetcher};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+use tracing::trace;
+
+type SharedResult = Result<String, Arc<anyhow::Error>>;
+
+#[derive(Debug, Default)]
+pub struct InFlightFetches {
+    fetches: Mutex<HashMap<String, Arc<OnceCell<SharedResult>>>>,
+}
+
+impl InFlightFetches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fetches the document of the given URI with `fetch`, or waits for the
+    /// fetch of the same URI already in flight and returns its result.
+    pub async fn fetch<Fut>(&self, uri: &str, fetch: impl FnOnce() -> Fut) -> Result<String>
+    where
+        Fut: Future<Output = Result<String>>,
+    {
+        let key = normalize_cache_key(uri);
+
+        let cell = {
+            let mut fetches = self.fetches.lock().unwrap();
+            Arc::clone(fetches.entry(key.clone()).or_default())
+        };
+
+        // If the fetch in flight is cancelled, the next waiting one fetches.
+        let result = cell
+            .get_or_init(|| async move { fetch().await.map_err(Arc::new) })
+            .await
+            .clone();
+
+        {
+            let mut fetches = self.fetches.lock().unwrap();
+            if fetches
+                .get(&key)
+                .map_or(false, |current| Arc::ptr_eq(current, &cell))
+            {
+                trace!("Metadata fetch of {} completed", key);
+                fetches.remove(&key);
+            }
+        }
+
+        result.map_err(|e| shared_error(&e))
+    }
+
+    /// Returns a fetcher sharing the fetches in flight of the given fetcher.
+    pub fn fetcher<'f>(&'f self, fetcher: &'f dyn MetadataFetcher) -> SingleFlightFetcher<'f> {
+        SingleFlightFetcher {
+            fetcher,
+            in_flight: Some(self),
+        }
+    }
+}
+
+/// Copies the error of a shared fetch, keeping the `MetadataFetchError`
+/// typed for the gateway fallbacks.
+fn shared_error(e: &anyhow::Error) -> anyhow::Error {
+    match e.downcast_ref::<MetadataFetchError>() {
+        Some(e) => e.clone().into(),
+        None => anyhow!("{:#}", e),
+    }
+}
+
+/// `MetadataFetcher` sharing the fetches in flight of another fetcher,
+/// or fetching directly without `InFlightFetches`.
+pub struct SingleFlightFetcher<'f> {
+    fetcher: &'f dyn MetadataFetcher,
+    in_flight: Option<&'f InFlightFetches>,
+}
+
+impl<'f> SingleFlightFetcher<'f> {
+    pub fn new(fetcher: &'f dyn MetadataFetcher, in_flight: Option<&'f InFlightFetches>) -> Self {
+        Self { fetcher, in_flight }
+    }
+}
+
+#[async_trait]
+impl MetadataFetcher for SingleFlightFetcher<'_> {
+    async fn fetch(&self, uri: &str) -> Result<String> {
+        match self.in_flight {
+            Some(in_flight) => in_flight.fetch(uri, || self.fetcher.fetch(uri)).await,
+            None => self.fetcher.fetch(uri).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata_fetcher::is_not_json_error;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct SlowFetcher {
+        requests: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl MetadataFetcher for SlowFetcher {
+        async fn fetch(&self, uri: &str) -> Result<String> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            if uri.ends_with(".html") {
+                return Err(MetadataFetchError::NotJson {
+                    uri: uri.to_string(),
+                    content_type: Some("text/html".to_string()),
+                }
+                .into());
+            }
+            Ok(format!(r#"{{"uri":"{}"}}"#, uri))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_fetches_share_one_request() {
+        let in_flight = Arc::new(InFlightFetches::new());
+        let fetcher = Arc::new(SlowFetcher::default());
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for i in 0..20 {
+            let in_flight = Arc::clone(&in_flight);
+            let fetcher = Arc::clone(&fetcher);
+            // The same document, from several IPFS gateways.
+            let uri = match i % 2 {
+                0 => "ipfs://QmCid/1.json",
+                _ => "https://ipfs.io/ipfs/QmCid/1.json",
+            };
+
+            tasks.spawn(async move { in_flight.fetcher(fetcher.as_ref()).fetch(uri).await });
+        }
+
+        let mut results = vec![];
+        while let Some(result) = tasks.join_next().await {
+            results.push(result.unwrap().unwrap());
+        }
+
+        assert_eq!(fetcher.requests.load(Ordering::SeqCst), 1);
+        assert_eq!(results.len(), 20);
+        assert!(results.iter().all(|r| r == &results[0]));
+
+        // Once completed, the document is fetched again.
+        in_flight
+            .fetcher(fetcher.as_ref())
+            .fetch("ipfs://QmCid/1.json")
+            .await
+            .unwrap();
+        assert_eq!(fetcher.requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_shared_fetch_error_keeps_type() {
+        let in_flight = InFlightFetches::new();
+        let fetcher = SlowFetcher::default();
+        let fetcher = in_flight.fetcher(&fetcher);
+
+        let (first, second) = tokio::join!(
+            fetcher.fetch("https://example.com/1.html"),
+            fetcher.fetch("https://example.com/1.html")
+        );
+
+        assert!(is_not_json_error(&first.unwrap_err()));
+        assert!(is_not_json_error(&second.unwrap_err()));
+    }
+}
@@ -0,0 +1,352 @@
+//! Heuristics flagging the spam collections.
+//!
+//! Each heuristic scores a sample of the collection from 0 (legit) to 1 (spam).
+//! The spam score of the collection is the weighted average of those scores,
+//! and the collection is flagged as spam once it reaches the threshold.
+//! Nothing is deleted: the score and the flag are saved with the collection,
+//! for the frontends to filter the spam collections.
+use crate::storage::{Storage, DEFAULT_TOKEN_PAGE_SIZE};
+use crate::types::{CollectionMetadata, CollectionSpam, StorageError, TokenMetadata};
+use ark_starknet::CairoU256;
+use starknet::core::types::FieldElement;
+use std::collections::{HashMap, HashSet};
+use tracing::info;
+
+/// Data of a collection evaluated by the heuristics.
+#[derive(Debug, Default, Clone)]
+pub struct CollectionSample {
+    pub collection: Option<CollectionMetadata>,
+    /// Tokens of the collection, without metadata if not fetched yet.
+    pub tokens: Vec<(CairoU256, Option<TokenMetadata>)>,
+    /// Mint timestamps of the tokens, in seconds, as known by the indexer.
+    pub mint_timestamps: Vec<u64>,
+}
+
+pub trait SpamHeuristic: Send + Sync {
+    /// Name of the heuristic, used as key of its score in the signals.
+    fn name(&self) -> &str;
+
+    /// Returns the score of the collection, from 0 (legit) to 1 (spam).
+    fn score(&self, sample: &CollectionSample) -> f64;
+}
+
+/// Scores the collections minted faster than `max_mints_per_hour` on average.
+pub struct MintRateHeuristic {
+    pub max_mints_per_hour: f64,
+}
+
+impl SpamHeuristic for MintRateHeuristic {
+    fn name(&self) -> &str {
+        "mint_rate"
+    }
+
+    fn score(&self, sample: &CollectionSample) -> f64 {
+        let (first, last) = match (
+            sample.mint_timestamps.iter().min(),
+            sample.mint_timestamps.iter().max(),
+        ) {
+            (Some(first), Some(last)) if sample.mint_timestamps.len() > 1 => (*first, *last),
+            _ => return 0.0,
+        };
+
+        // A whole mint within the same hour is measured over one hour.
+        let hours = ((last - first) as f64 / 3600.0).max(1.0);
+        let mints_per_hour = sample.mint_timestamps.len() as f64 / hours;
+
+        if mints_per_hour <= self.max_mints_per_hour {
+            0.0
+        } else {
+            1.0 - self.max_mints_per_hour / mints_per_hour
+        }
+    }
+}
+
+/// Scores the share of tokens whose image is also the image of another
+/// token, or of another collection through `known_images`.
+///
+/// The images are compared by their URI, as their content isn't hashed.
+#[derive(Default)]
+pub struct DuplicateImageHeuristic {
+    /// Images of the tokens of the other collections.
+    pub known_images: HashSet<String>,
+}
+
+impl SpamHeuristic for DuplicateImageHeuristic {
+    fn name(&self) -> &str {
+        "duplicate_images"
+    }
+
+    fn score(&self, sample: &CollectionSample) -> f64 {
+        let images: Vec<&str> = sample
+            .tokens
+            .iter()
+            .filter_map(|(_, metadata)| metadata.as_ref()?.normalized.image.as_deref())
+            .collect();
+
+        if images.is_empty() {
+            return 0.0;
+        }
+
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for image in images.iter() {
+            *counts.entry(*image).or_default() += 1;
+        }
+
+        let duplicates = images
+            .iter()
+            .filter(|image| counts[*image] > 1 || self.known_images.contains(**image))
+            .count();
+
+        duplicates as f64 / images.len() as f64
+    }
+}
+
+/// Scores the share of tokens without metadata, or with neither a name nor an image.
+pub struct MissingMetadataHeuristic;
+
+impl SpamHeuristic for MissingMetadataHeuristic {
+    fn name(&self) -> &str {
+        "missing_metadata"
+    }
+
+    fn score(&self, sample: &CollectionSample) -> f64 {
+        if sample.tokens.is_empty() {
+            return 0.0;
+        }
+
+        let missing = sample
+            .tokens
+            .iter()
+            .filter(|(_, metadata)| match metadata {
+                Some(m) => m.normalized.name.is_none() && m.normalized.image.is_none(),
+                None => true,
+            })
+            .count();
+
+        missing as f64 / sample.tokens.len() as f64
+    }
+}
+
+/// Weighted heuristics, flagging a collection as spam from `threshold`.
+pub struct SpamDetector {
+    heuristics: Vec<(Box<dyn SpamHeuristic>, f64)>,
+    threshold: f64,
+}
+
+impl Default for SpamDetector {
+    /// The built-in heuristics with the same weight, flagging
+    /// the collections with a spam score of at least 0.5.
+    fn default() -> Self {
+        SpamDetector::new(0.5)
+            .with_heuristic(
+                MintRateHeuristic {
+                    max_mints_per_hour: 1000.0,
+                },
+                1.0,
+            )
+            .with_heuristic(DuplicateImageHeuristic::default(), 1.0)
+            .with_heuristic(MissingMetadataHeuristic, 1.0)
+    }
+}
+
+impl SpamDetector {
+    /// Initializes a detector without any heuristic.
+    pub fn new(threshold: f64) -> Self {
+        SpamDetector {
+            heuristics: vec![],
+            threshold,
+        }
+    }
+
+    pub fn with_heuristic<H: SpamHeuristic + 'static>(mut self, heuristic: H, weight: f64) -> Self {
+        self.heuristics.push((Box::new(heuristic), weight));
+        self
+    }
+
+    /// Evaluates the collection with every heuristic.
+    pub fn evaluate(&self, sample: &CollectionSample) -> CollectionSpam {
+        let mut spam = CollectionSpam::default();
+        let mut total_weight = 0.0;
+        let mut weighted_score = 0.0;
+
+        for (heuristic, weight) in self.heuristics.iter() {
+            let score = heuristic.score(sample).clamp(0.0, 1.0);
+            spam.signals.insert(heuristic.name().to_string(), score);
+            weighted_score += score * weight;
+            total_weight += weight;
+        }
+
+        if total_weight > 0.0 {
+            spam.spam_score = weighted_score / total_weight;
+        }
+        spam.is_spam = !self.heuristics.is_empty() && spam.spam_score >= self.threshold;
+
+        spam
+    }
+}
+
+/// Evaluates the collection with its tokens and their metadata,
+/// and saves the result with the collection.
+///
+/// The mint timestamps are given by the caller, as they are indexed
+/// with the tokens and not with their metadata.
+pub async fn evaluate_collection_spam<S: Storage>(
+    storage: &S,
+    contract_address: FieldElement,
+    detector: &SpamDetector,
+    mint_timestamps: Vec<u64>,
+) -> Result<CollectionSpam, StorageError> {
+    let mut sample = CollectionSample {
+        collection: storage.get_collection_metadata(contract_address).await?,
+        tokens: vec![],
+        mint_timestamps,
+    };
+
+    let mut last_evaluated_key = None;
+    loop {
+        let page = storage
+            .find_token_ids(
+                contract_address,
+                last_evaluated_key,
+                DEFAULT_TOKEN_PAGE_SIZE,
+            )
+            .await?;
+
+        for token_id in page.token_ids {
+            let metadata = storage
+                .get_token_metadata(contract_address, token_id.clone())
+                .await?;
+            sample.tokens.push((token_id, metadata));
+        }
+
+        last_evaluated_key = page.last_evaluated_key;
+        if last_evaluated_key.is_none() {
+            break;
+        }
+    }
+
+    let spam = detector.evaluate(&sample);
+
+    storage
+        .register_collection_spam(contract_address, spam.clone())
+        .await?;
+
+    info!(
+        "Spam score of 0x{:064x}: {:.2} (spam: {})",
+        contract_address, spam.spam_score, spam.is_spam
+    );
+
+    Ok(spam)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MockStorage;
+    use crate::types::{NormalizedMetadata, TokenIdsPage};
+
+    fn token(
+        low: u128,
+        name: Option<&str>,
+        image: Option<&str>,
+    ) -> (CairoU256, Option<TokenMetadata>) {
+        (
+            CairoU256 { low, high: 0 },
+            Some(TokenMetadata {
+                normalized: NormalizedMetadata {
+                    name: name.map(String::from),
+                    image: image.map(String::from),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+        )
+    }
+
+    #[test]
+    fn test_heuristics() {
+        let sample = CollectionSample {
+            collection: None,
+            tokens: vec![
+                token(1, Some("#1"), Some("ipfs://a")),
+                token(2, Some("#2"), Some("ipfs://a")),
+                token(3, None, None),
+                token(4, Some("#4"), Some("ipfs://stolen")),
+            ],
+            // 4 mints within 2 hours.
+            mint_timestamps: vec![0, 3600, 3600, 7200],
+        };
+
+        assert_eq!(MissingMetadataHeuristic.score(&sample), 0.25);
+
+        let duplicates = DuplicateImageHeuristic {
+            known_images: HashSet::from(["ipfs://stolen".to_string()]),
+        };
+        assert_eq!(duplicates.score(&sample), 1.0);
+
+        let mint_rate = |max_mints_per_hour| MintRateHeuristic { max_mints_per_hour };
+        assert_eq!(mint_rate(2.0).score(&sample), 0.0);
+        assert_eq!(mint_rate(1.0).score(&sample), 0.5);
+        assert_eq!(mint_rate(1.0).score(&CollectionSample::default()), 0.0);
+    }
+
+    #[test]
+    fn test_detector_weights() {
+        let sample = CollectionSample {
+            tokens: vec![token(1, None, None), token(2, Some("#2"), None)],
+            ..Default::default()
+        };
+
+        // Missing metadata 0.5, mint rate 0.
+        let detector = SpamDetector::new(0.4)
+            .with_heuristic(MissingMetadataHeuristic, 3.0)
+            .with_heuristic(
+                MintRateHeuristic {
+                    max_mints_per_hour: 10.0,
+                },
+                1.0,
+            );
+
+        let spam = detector.evaluate(&sample);
+        assert_eq!(spam.spam_score, 0.375);
+        assert!(!spam.is_spam);
+        assert_eq!(spam.signals["missing_metadata"], 0.5);
+        assert_eq!(spam.signals["mint_rate"], 0.0);
+
+        assert!(!SpamDetector::new(0.0).evaluate(&sample).is_spam);
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_collection_spam() {
+        let mut mock_storage = MockStorage::default();
+
+        mock_storage
+            .expect_get_collection_metadata()
+            .returning(|_| Ok(None));
+        mock_storage.expect_find_token_ids().returning(|_, _, _| {
+            Ok(TokenIdsPage {
+                token_ids: (1..=2).map(|low| CairoU256 { low, high: 0 }).collect(),
+                last_evaluated_key: None,
+            })
+        });
+        mock_storage
+            .expect_get_token_metadata()
+            .returning(|_, _| Ok(None));
+        mock_storage
+            .expect_register_collection_spam()
+            .withf(|_, spam| spam.is_spam && spam.signals["missing_metadata"] == 1.0)
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let spam = evaluate_collection_spam(
+            &mock_storage,
+            FieldElement::ONE,
+            &SpamDetector::new(0.5).with_heuristic(MissingMetadataHeuristic, 1.0),
+            vec![],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(spam.spam_score, 1.0);
+    }
+}
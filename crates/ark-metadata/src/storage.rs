@@ -1,4 +1,6 @@
-use crate::types::{StorageError, TokenMetadata};
+use crate::types::{
+    CollectionMetadata, CollectionSpam, StorageError, TokenIdsPage, TokenMetadata, TokenRarity,
+};
 use anyhow::Result;
 use ark_starknet::CairoU256;
 use async_trait::async_trait;
@@ -6,6 +8,9 @@ use async_trait::async_trait;
 use mockall::automock;
 use starknet::core::types::FieldElement;
 
+/// Number of token ids read at once when listing the tokens of a collection.
+pub const DEFAULT_TOKEN_PAGE_SIZE: usize = 1000;
+
 #[cfg_attr(any(test, feature = "mock"), automock)]
 #[async_trait]
 pub trait Storage {
@@ -16,6 +21,20 @@ pub trait Storage {
         token_metadata: TokenMetadata,
     ) -> Result<(), StorageError>;
 
+    /// Returns the content hash of the stored token metadata, if any.
+    async fn get_token_metadata_hash(
+        &self,
+        contract_address: FieldElement,
+        token_id: CairoU256,
+    ) -> Result<Option<String>, StorageError>;
+
+    /// Returns the stored token metadata, if any.
+    async fn get_token_metadata(
+        &self,
+        contract_address: FieldElement,
+        token_id: CairoU256,
+    ) -> Result<Option<TokenMetadata>, StorageError>;
+
     async fn has_token_metadata(
         &self,
         contract_address: FieldElement,
@@ -27,6 +46,64 @@ pub trait Storage {
         contract_address_filter: Option<FieldElement>,
     ) -> Result<Vec<(FieldElement, CairoU256)>, StorageError>;
 
+    /// Returns a page of at most `page_size` token ids of the given collection,
+    /// by ascending token id, starting after `exclusive_start_key` if any.
+    async fn find_token_ids(
+        &self,
+        contract_address: FieldElement,
+        exclusive_start_key: Option<CairoU256>,
+        page_size: usize,
+    ) -> Result<TokenIdsPage, StorageError>;
+
+    /// Returns the last token id processed by the given reindex job, if any.
+    async fn get_reindex_checkpoint(
+        &self,
+        contract_address: FieldElement,
+        job_id: &str,
+    ) -> Result<Option<CairoU256>, StorageError>;
+
+    /// Saves the last token id processed by the given reindex job.
+    async fn set_reindex_checkpoint(
+        &self,
+        contract_address: FieldElement,
+        job_id: &str,
+        token_id: CairoU256,
+    ) -> Result<(), StorageError>;
+
+    /// Deletes the checkpoint of the given reindex job, once it's done.
+    async fn delete_reindex_checkpoint(
+        &self,
+        contract_address: FieldElement,
+        job_id: &str,
+    ) -> Result<(), StorageError>;
+
+    /// Returns the metadata of the given collection, if any.
+    async fn get_collection_metadata(
+        &self,
+        contract_address: FieldElement,
+    ) -> Result<Option<CollectionMetadata>, StorageError>;
+
+    async fn register_collection_metadata(
+        &self,
+        contract_address: FieldElement,
+        collection_metadata: CollectionMetadata,
+    ) -> Result<(), StorageError>;
+
+    /// Saves the rarity of the token within its collection.
+    async fn register_token_rarity(
+        &self,
+        contract_address: FieldElement,
+        token_id: CairoU256,
+        rarity: TokenRarity,
+    ) -> Result<(), StorageError>;
+
+    /// Saves the spam evaluation of the collection, keeping its metadata.
+    async fn register_collection_spam(
+        &self,
+        contract_address: FieldElement,
+        spam: CollectionSpam,
+    ) -> Result<(), StorageError>;
+
     async fn update_token_metadata_status(
         &self,
         contract_address: FieldElement,
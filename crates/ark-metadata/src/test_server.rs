@@ -0,0 +1,84 @@
+//! Local HTTP server of the tests, answering every request with a raw response.
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+pub(crate) struct TestServer {
+    addr: SocketAddr,
+    connections: Arc<AtomicUsize>,
+}
+
+impl TestServer {
+    /// Answers every request with the given raw response. The connections
+    /// are kept open for the next requests, unless the response has a
+    /// `Connection: close` header.
+    pub(crate) async fn start(response: Vec<u8>) -> Self {
+        Self::with_handler(move |_| response.clone()).await
+    }
+
+    /// Answers every request with the raw response built by the handler from
+    /// the request head, lowercased.
+    pub(crate) async fn with_handler<F>(handler: F) -> Self
+    where
+        F: Fn(&str) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let handler = Arc::new(handler);
+
+        let accepted = Arc::clone(&connections);
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let handler = Arc::clone(&handler);
+                tokio::spawn(async move {
+                    let mut request = vec![0u8; 4096];
+                    while let Ok(n) = socket.read(&mut request).await {
+                        if n == 0 {
+                            break;
+                        }
+                        let head = String::from_utf8_lossy(&request[..n]).to_lowercase();
+                        let response = handler(&head);
+                        let close = String::from_utf8_lossy(&response)
+                            .to_lowercase()
+                            .contains("\r\nconnection: close\r\n");
+                        if socket.write_all(&response).await.is_err() || close {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        Self { addr, connections }
+    }
+
+    /// Builds a raw response with the given status, headers and body, its
+    /// `Content-Length` being added.
+    pub(crate) fn response(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {}\r\n", status).into_bytes();
+        for (name, value) in headers {
+            response.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        response.extend_from_slice(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes());
+        response.extend_from_slice(body);
+        response
+    }
+
+    /// Answers every request with a `200 OK` with the given headers and body.
+    pub(crate) async fn ok(headers: &[(&str, &str)], body: &[u8]) -> Self {
+        Self::start(Self::response("200 OK", headers, body)).await
+    }
+
+    pub(crate) fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// Number of connections accepted.
+    pub(crate) fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
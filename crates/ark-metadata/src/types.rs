@@ -1,11 +1,16 @@
+use ark_starknet::CairoU256;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::Number;
-use std::{collections::HashMap, fmt};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
 
 #[derive(Debug, PartialEq)]
 pub enum MetadataType {
     Http(String),
     Ipfs(String),
+    Arweave(String),
     OnChain(String),
 }
 
@@ -24,7 +29,7 @@ pub enum StorageError {
     InvalidMintData(String),
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub enum DisplayType {
     #[serde(rename = "number")]
     Number,
@@ -47,7 +52,7 @@ impl fmt::Display for DisplayType {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 #[serde(untagged)]
 pub enum MetadataTraitValue {
     String(String),
@@ -56,7 +61,137 @@ pub enum MetadataTraitValue {
     Boolean(bool),
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl MetadataTraitValue {
+    /// Returns the value as a list of strings.
+    pub fn to_strings(&self) -> Vec<String> {
+        match self {
+            MetadataTraitValue::String(s) => vec![s.clone()],
+            MetadataTraitValue::Number(n) => vec![n.to_string()],
+            MetadataTraitValue::Array(a) => a.clone(),
+            MetadataTraitValue::Boolean(b) => vec![b.to_string()],
+        }
+    }
+}
+
+/// Policy applied to the attributes sharing the same `trait_type`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum DuplicateTraitPolicy {
+    /// Only the first attribute is kept.
+    KeepFirst,
+    /// Only the last attribute is kept.
+    KeepLast,
+    /// All the attributes are kept as is.
+    #[default]
+    KeepAll,
+    /// The values of all the attributes are merged into one array value.
+    Merge,
+}
+
+/// Image given to the tokens without image, or whose image is not found.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FallbackImage {
+    /// The same placeholder image for all the tokens.
+    Url(String),
+    /// An identicon generated from the collection address and the token id,
+    /// as an SVG data URI, see `identicon`.
+    Identicon,
+}
+
+/// Order of the normalized attributes.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum AttributeOrder {
+    /// The order of the metadata source.
+    #[default]
+    Source,
+    /// Sorted by `trait_type`, the attributes without `trait_type` last.
+    /// The attributes sharing the same `trait_type` keep their source order.
+    /// For the sources returning the attributes in a different order on
+    /// each request, which would be seen as a metadata change.
+    TraitType,
+}
+
+/// Suffix appended to the base token URIs (ending with a slash), returned
+/// by the collections expecting the token id to be appended to them.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum BaseUriSuffix {
+    /// The token id is appended: `<base>/<id>`.
+    #[default]
+    TokenId,
+    /// The token id and the JSON extension are appended: `<base>/<id>.json`.
+    TokenIdJson,
+    /// The URI is a directory of the token: `<base>/index.json`.
+    IndexJson,
+}
+
+/// Inclusive range of token ids, to only refresh the metadata of some
+/// tokens of a collection, like a sample of a collection with a huge supply.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenIdRange {
+    pub start: CairoU256,
+    pub end: CairoU256,
+}
+
+impl TokenIdRange {
+    pub fn new(start: CairoU256, end: CairoU256) -> Self {
+        Self { start, end }
+    }
+
+    pub fn contains(&self, token_id: &CairoU256) -> bool {
+        !self.is_before(token_id) && !self.is_after(token_id)
+    }
+
+    /// Returns true if the token id is lower than the start of the range.
+    pub fn is_before(&self, token_id: &CairoU256) -> bool {
+        (token_id.high, token_id.low) < (self.start.high, self.start.low)
+    }
+
+    /// Returns true if the token id is greater than the end of the range.
+    pub fn is_after(&self, token_id: &CairoU256) -> bool {
+        (token_id.high, token_id.low) > (self.end.high, self.end.low)
+    }
+
+    /// Returns the token id just before the start of the range,
+    /// `None` if the range starts at 0.
+    pub fn key_before_start(&self) -> Option<CairoU256> {
+        match (self.start.high, self.start.low) {
+            (0, 0) => None,
+            (high, 0) => Some(CairoU256 {
+                low: u128::MAX,
+                high: high - 1,
+            }),
+            (high, low) => Some(CairoU256 { low: low - 1, high }),
+        }
+    }
+}
+
+/// Field name overrides of a collection deviating from the metadata standard.
+///
+/// Each normalized field (`image`, `attributes`...) maps to the keys looked up,
+/// in order, in the raw metadata before falling back to the standard key.
+/// Nested keys are separated by dots (`properties.attributes`), or given as
+/// a JSON pointer (`/data/0/attributes`).
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NormalizationProfile {
+    pub field_overrides: HashMap<String, Vec<String>>,
+}
+
+impl NormalizationProfile {
+    /// The standard profile, without any override.
+    pub fn standard() -> Self {
+        Self::default()
+    }
+
+    /// Adds a key looked up for the given normalized field.
+    pub fn with_override(mut self, field: &str, key: &str) -> Self {
+        self.field_overrides
+            .entry(field.to_string())
+            .or_default()
+            .push(key.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct MetadataAttribute {
     pub display_type: Option<DisplayType>,
     pub trait_type: Option<String>,
@@ -68,12 +203,46 @@ pub struct TokenMetadata {
     pub normalized: NormalizedMetadata,
     pub raw: String,
     pub metadata_updated_at: Option<i64>,
+    /// Hash of the normalized metadata, used to detect changes on refresh.
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// IPFS or Arweave gateway which returned the metadata, if any.
+    #[serde(default)]
+    pub gateway_uri: Option<String>,
+    /// Key of the gzip-compressed raw metadata saved by the `FileManager`,
+    /// when offloaded (see `RawMetadataOffload`). `raw` is then empty.
+    #[serde(default)]
+    pub raw_key: Option<String>,
+    /// Raw metadata as a JSON value, for the storages keeping them natively
+    /// (a `jsonb` column, a document...) to query or project their fields.
+    /// `raw` is then empty, unless `MetadataManagerConfig::keep_raw_metadata_string`
+    /// is set. `None` if the raw metadata are offloaded, not a JSON document,
+    /// or hold numbers a JSON value can't represent exactly.
+    #[serde(default)]
+    pub raw_value: Option<serde_json::Value>,
+}
+
+impl TokenMetadata {
+    /// Returns the raw metadata as a JSON value, whether they are stored
+    /// natively in `raw_value` or as a string in `raw`.
+    /// Returns `None` if the raw metadata is not a valid JSON document,
+    /// or is offloaded (see `MetadataManager::read_raw_metadata`).
+    pub fn raw_json(&self) -> Option<serde_json::Value> {
+        match &self.raw_value {
+            Some(value) => Some(value.clone()),
+            None => serde_json::from_str(&self.raw).ok(),
+        }
+    }
 }
 
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct NormalizedMetadata {
     pub image_mime_type: Option<String>,
     pub image_key: Option<String>,
+    pub image_raster_key: Option<String>, // Key of the PNG rasterized from an SVG image, if any.
+    pub image_webp_key: Option<String>, // Key of the WebP converted from a large PNG or JPEG image, if any.
+    pub image_phash: Option<String>, // Perceptual hash (dHash) of the raster image, as 16 hex characters, if computed.
+    pub image_thumbnails: Option<Vec<ImageThumbnail>>,
     pub image: Option<String>,
     pub image_data: Option<String>, // Raw SVG image data, if you want to generate images on the fly (not recommended). Only use this if you're not including the image parameter.
     pub external_url: Option<String>,
@@ -86,6 +255,70 @@ pub struct NormalizedMetadata {
     pub animation_key: Option<String>,
     pub animation_mime_type: Option<String>,
     pub youtube_url: Option<String>,
+    #[serde(default)]
+    pub has_duplicate_traits: bool, // Some attributes were sharing the same trait_type.
+    #[serde(default)]
+    pub image_is_placeholder: bool, // The image is the fallback image, the token image being missing or not found.
+}
+
+/// A page of the token ids of a collection, by ascending token id.
+#[derive(Debug, Default, Clone)]
+pub struct TokenIdsPage {
+    pub token_ids: Vec<CairoU256>,
+    /// Key to start the next page after, `None` on the last page.
+    pub last_evaluated_key: Option<CairoU256>,
+}
+
+/// Rarity of a token within its collection, see `rarity`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TokenRarity {
+    /// Sum of the inverse frequencies of the token traits, higher being rarer.
+    pub score: f64,
+    /// Rank of the token in the collection, the rarest being 1.
+    pub rank: u64,
+}
+
+/// Spam evaluation of a collection, see `spam`.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+pub struct CollectionSpam {
+    /// Weighted average of the heuristic scores, from 0 (legit) to 1 (spam).
+    pub spam_score: f64,
+    /// The spam score reached the threshold of the detector.
+    pub is_spam: bool,
+    /// Score of each heuristic, by heuristic name.
+    pub signals: BTreeMap<String, f64>,
+}
+
+/// A resized variant of the token image.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ImageThumbnail {
+    /// Maximum width and height in pixels.
+    pub size: u32,
+    pub key: String,
+}
+
+/// Metadata of a collection, read from the `contractURI` of the contract.
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub struct CollectionMetadata {
+    pub normalized: NormalizedCollectionMetadata,
+    pub raw: String,
+    pub metadata_updated_at: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+pub struct NormalizedCollectionMetadata {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>, // Logo of the collection.
+    pub image_key: Option<String>,
+    pub banner_image: Option<String>,
+    pub banner_image_key: Option<String>,
+    pub featured_image: Option<String>,
+    pub featured_image_key: Option<String>,
+    pub external_link: Option<String>,
+    /// Royalty of the secondary sales, in basis points.
+    pub seller_fee_basis_points: Option<u64>,
+    pub fee_recipient: Option<String>,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -1,36 +1,124 @@
-use crate::types::{MetadataType, NormalizedMetadata, TokenMetadata};
+use crate::metadata_cache::MetadataCache;
+use crate::metadata_fetcher::{is_not_json_error, MetadataFetcher};
+use crate::metrics;
+use crate::types::{
+    AttributeOrder, BaseUriSuffix, DuplicateTraitPolicy, MetadataAttribute, MetadataTraitValue,
+    MetadataType, NormalizationProfile, NormalizedCollectionMetadata, NormalizedMetadata,
+    TokenMetadata,
+};
 use anyhow::{anyhow, Result};
+use ark_starknet::format::log_preview;
 use base64::{engine::general_purpose, Engine as _};
 use chrono::Utc;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use reqwest::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE};
-use reqwest::Client;
-use std::time::Duration;
-use tracing::{debug, error, trace};
+use starknet::core::utils::starknet_keccak;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use tracing::{debug, error, trace, warn};
+use url::Url;
 
+/// URL schemes accepted for the metadata `image` and `external_url`.
+const ALLOWED_URL_SCHEMES: [&str; 5] = ["http", "https", "ipfs", "ar", "data"];
+
+/// Path of the attributes in the standard metadata.
+pub const DEFAULT_ATTRIBUTES_PATH: &str = "attributes";
+
+/// Arweave gateway used when none is configured.
+pub const DEFAULT_ARWEAVE_GATEWAY_URI: &str = "https://arweave.net/";
+
+/// Largest integer a double represents exactly (2^53).
+const MAX_EXACT_DOUBLE_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+/// Fetches the metadata at the given URI.
+///
+/// IPFS metadata are fetched from the first gateway of `ipfs_gateway_uris`,
+/// and Arweave metadata (`ar://`) from the first gateway of `arweave_gateway_uris`.
+/// The next gateways are tried in order when a gateway fails or doesn't
+/// return a JSON document, and the gateway which succeeded is recorded
+/// in `TokenMetadata::gateway_uri`.
+///
+/// The metadata are normalized with `normalize_metadata`, the relative
+/// URLs being resolved against the metadata URI, so the `ipfs://` and `ar://`
+/// URIs are kept in their canonical form.
+///
+/// With a `cache`, the cached document of the URI is used instead of fetching
+/// it, and the JSON documents fetched are saved in the cache.
+#[cfg_attr(
+    feature = "tracing-spans",
+    tracing::instrument(name = "metadata_fetch", skip_all, fields(uri = %log_preview(uri)))
+)]
 pub async fn get_token_metadata(
-    client: &Client,
+    fetcher: &dyn MetadataFetcher,
+    uri: &str,
+    ipfs_gateway_uris: &[&str],
+    arweave_gateway_uris: &[&str],
+    cache: Option<&MetadataCache>,
+) -> Result<TokenMetadata> {
+    if let Some(cache) = cache {
+        if let Some(raw) = cache.get(uri).await {
+            debug!("Metadata {} read from cache", log_preview(uri));
+            return Ok(token_metadata_from_raw(raw, uri));
+        }
+    }
+
+    let source = metrics::source_label(&get_metadata_type(uri));
+    let timer = metrics::fetch_duration_seconds()
+        .with_label_values(&[source])
+        .start_timer();
+
+    let result = fetch_token_metadata(fetcher, uri, ipfs_gateway_uris, arweave_gateway_uris).await;
+    timer.observe_duration();
+
+    if let (Some(cache), Ok(metadata)) = (cache, &result) {
+        if metadata.raw_json().is_some() {
+            if let Err(e) = cache.put(uri, &metadata.raw).await {
+                warn!("Can't cache metadata {}: {}", log_preview(uri), e);
+            }
+        }
+    }
+
+    result.map_err(|e| {
+        metrics::fetch_errors_total()
+            .with_label_values(&[source])
+            .inc();
+        e
+    })
+}
+
+async fn fetch_token_metadata(
+    fetcher: &dyn MetadataFetcher,
     uri: &str,
-    ipfs_gateway_uri: &str,
-    request_timeout_duration: Duration,
-    request_referrer: &str,
+    ipfs_gateway_uris: &[&str],
+    arweave_gateway_uris: &[&str],
 ) -> Result<TokenMetadata> {
     let metadata_type = get_metadata_type(uri);
     let metadata = match metadata_type {
         MetadataType::Ipfs(uri) => {
-            let ipfs_hash = uri.trim_start_matches("ipfs://");
-            let complete_uri = format!("{}{}", ipfs_gateway_uri, ipfs_hash);
-            trace!("Fetching metadata from IPFS: {}", complete_uri.as_str());
-            fetch_metadata(
-                complete_uri.as_str(),
-                client,
-                request_timeout_duration,
-                request_referrer,
-            )
-            .await?
+            let ipfs_path = uri.trim_start_matches("ipfs://");
+            fetch_from_gateways(ipfs_path, &uri, fetcher, ipfs_gateway_uris).await?
+        }
+        MetadataType::Arweave(uri) => {
+            let arweave_path = uri.trim_start_matches("ar://");
+            fetch_from_gateways(arweave_path, &uri, fetcher, arweave_gateway_uris).await?
         }
         MetadataType::Http(uri) => {
             trace!("Fetching metadata from HTTPS: {}", uri.as_str());
-            fetch_metadata(&uri, client, request_timeout_duration, request_referrer).await?
+            match fetch_metadata(&uri, &uri, fetcher).await {
+                Ok(metadata) => metadata,
+                // A public IPFS gateway returning an error or challenge page
+                // is replaced by the configured ones.
+                Err(e) if is_not_json_error(&e) && !ipfs_gateway_uris.is_empty() => {
+                    match uri.split_once("/ipfs/") {
+                        Some((_, ipfs_path)) => {
+                            warn!("{}, fetching it from the IPFS gateways", e);
+                            fetch_from_gateways(ipfs_path, &uri, fetcher, ipfs_gateway_uris).await?
+                        }
+                        None => return Err(e),
+                    }
+                }
+                Err(e) => return Err(e),
+            }
         }
         MetadataType::OnChain(uri) => {
             trace!("Fetching on-chain metadata: {}", uri);
@@ -43,6 +131,8 @@ pub async fn get_token_metadata(
 pub fn get_metadata_type(uri: &str) -> MetadataType {
     if uri.starts_with("ipfs://") {
         MetadataType::Ipfs(uri.to_string())
+    } else if uri.starts_with("ar://") {
+        MetadataType::Arweave(uri.to_string())
     } else if uri.starts_with("http://") || uri.starts_with("https://") {
         MetadataType::Http(uri.to_string())
     } else {
@@ -50,77 +140,587 @@ pub fn get_metadata_type(uri: &str) -> MetadataType {
     }
 }
 
+/// Rewrites the `ipfs://` and `ar://` URIs to the given gateways, the other
+/// URIs being returned unchanged. The Arweave transaction id is kept with
+/// its path, if any (`ar://<txid>/1.json`).
+pub fn resolve_gateway_uri(uri: &str, ipfs_gateway_uri: &str, arweave_gateway_uri: &str) -> String {
+    match uri.strip_prefix("ar://") {
+        Some(path) => format!("{}{}", arweave_gateway_uri, path),
+        None => uri.replace("ipfs://", ipfs_gateway_uri),
+    }
+}
+
+/// Builds the URI of a token from the base URI (ending with a slash) returned
+/// by its collection, appending the given suffix. The other URIs, including
+/// the data URIs, are returned unchanged.
+pub fn resolve_base_token_uri(uri: &str, token_id: &str, suffix: BaseUriSuffix) -> String {
+    if !uri.ends_with('/') || uri.starts_with("data:") {
+        return uri.to_string();
+    }
+
+    match suffix {
+        BaseUriSuffix::TokenId => format!("{}{}", uri, token_id),
+        BaseUriSuffix::TokenIdJson => format!("{}{}.json", uri, token_id),
+        BaseUriSuffix::IndexJson => format!("{}index.json", uri),
+    }
+}
+
 fn extract_string(value: &serde_json::Value, key: &str) -> Option<String> {
     value.get(key).and_then(|v| v.as_str()).map(String::from)
 }
 
-fn normalize_metadata(raw_metadata: &str) -> Result<NormalizedMetadata> {
-    // Attempt to parse directly into NormalizedMetadata
-    if let Ok(metadata) = serde_json::from_str::<NormalizedMetadata>(raw_metadata) {
-        trace!("Successfully parsed metadata");
-        return Ok(metadata);
+/// Extracts the attributes of a document which failed to parse because
+/// of another field, like a `properties` map of values.
+fn extract_attributes(value: &serde_json::Value) -> Option<Vec<MetadataAttribute>> {
+    value
+        .get("attributes")
+        .and_then(|a| serde_json::from_value(a.clone()).ok())
+}
+
+/// Normalizes a raw metadata document, without any network or storage access.
+///
+/// The document is read as `NormalizedMetadata` when it follows the standard,
+/// the known fields being extracted one by one otherwise. Without `attributes`,
+/// the `properties` map is converted into attributes. The `image` and
+/// `external_url` are validated, relative ones being resolved against
+/// `initial_uri`, the URI of the metadata.
+pub fn normalize_metadata(raw: &serde_json::Value, initial_uri: &str) -> NormalizedMetadata {
+    let mut metadata = match serde_json::from_value::<NormalizedMetadata>(raw.clone()) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            trace!("Non standard metadata, extracting the known fields: {}", e);
+            NormalizedMetadata {
+                name: extract_string(raw, "name"),
+                animation_key: extract_string(raw, "animation_key"),
+                image: extract_string(raw, "image"),
+                animation_mime_type: extract_string(raw, "animation_mime_type"),
+                animation_url: extract_string(raw, "animation_url"),
+                background_color: extract_string(raw, "background_color"),
+                description: extract_string(raw, "description"),
+                external_url: extract_string(raw, "external_url"),
+                image_mime_type: extract_string(raw, "image_mime_type"),
+                image_data: extract_string(raw, "image_data"),
+                image_key: extract_string(raw, "image_key"),
+                youtube_url: extract_string(raw, "youtube_url"),
+                attributes: extract_attributes(raw),
+                ..Default::default()
+            }
+        }
+    };
+
+    normalize_properties_attributes(&mut metadata, raw);
+    normalize_metadata_urls(&mut metadata, initial_uri);
+
+    metadata
+}
+
+/// Keeps the media of the stored normalized metadata in metadata normalized
+/// again without being fetched: the URLs missing from `metadata` (relative
+/// ones, or the fallback image), and the keys and types of the saved media
+/// whose URL didn't change.
+pub fn keep_stored_media(metadata: &mut NormalizedMetadata, stored: &NormalizedMetadata) {
+    if metadata.image.is_none() && stored.image.is_some() {
+        metadata.image = stored.image.clone();
+        metadata.image_is_placeholder = stored.image_is_placeholder;
+    }
+    if metadata.external_url.is_none() {
+        metadata.external_url = stored.external_url.clone();
+    }
+    if metadata.animation_url.is_none() {
+        metadata.animation_url = stored.animation_url.clone();
+    }
+
+    if metadata.image == stored.image {
+        metadata.image_mime_type = stored.image_mime_type.clone();
+        metadata.image_key = stored.image_key.clone();
+        metadata.image_raster_key = stored.image_raster_key.clone();
+        metadata.image_webp_key = stored.image_webp_key.clone();
+        metadata.image_phash = stored.image_phash.clone();
+        metadata.image_thumbnails = stored.image_thumbnails.clone();
+    }
+    if metadata.animation_url == stored.animation_url {
+        metadata.animation_mime_type = stored.animation_mime_type.clone();
+        metadata.animation_key = stored.animation_key.clone();
+    }
+}
+
+/// Validates the given URL, resolving it against `base_uri` if relative.
+/// Returns `None` if the URL is invalid or its scheme is not allowed.
+fn normalize_url(value: &str, base_uri: &str) -> Option<String> {
+    let value = value.trim();
+
+    if value.is_empty() || value.eq_ignore_ascii_case("null") || value == "undefined" {
+        return None;
     }
 
-    // Fallback: Try parsing as a generic JSON Value for manual extraction
-    let value = serde_json::from_str::<serde_json::Value>(raw_metadata).map_err(|e| {
-        error!("Failed to parse metadata: {:?}", e);
-        anyhow!("Failed to parse metadata: {}", e)
-    })?;
+    match Url::parse(value) {
+        Ok(url) => ALLOWED_URL_SCHEMES
+            .contains(&url.scheme())
+            .then(|| value.to_string()),
+        Err(url::ParseError::RelativeUrlWithoutBase) => {
+            if value.contains(char::is_whitespace) {
+                return None;
+            }
 
-    let image = extract_string(&value, "image");
-    let name = extract_string(&value, "name");
-    let description = extract_string(&value, "description");
-    let external_url = extract_string(&value, "external_url");
+            let url = Url::parse(base_uri).ok()?.join(value).ok()?;
+            ALLOWED_URL_SCHEMES
+                .contains(&url.scheme())
+                .then(|| url.to_string())
+        }
+        Err(_) => None,
+    }
+}
 
-    Ok(NormalizedMetadata {
-        image,
-        name,
-        description,
-        external_url,
+/// Validates the `image` and `external_url` of the metadata, resolving the
+/// relative ones against the metadata URI. Invalid values are removed.
+pub fn normalize_metadata_urls(metadata: &mut NormalizedMetadata, base_uri: &str) {
+    for (name, field) in [
+        ("image", &mut metadata.image),
+        ("external_url", &mut metadata.external_url),
+    ] {
+        if let Some(value) = field.take() {
+            *field = normalize_url(&value, base_uri);
+
+            if field.is_none() {
+                warn!(
+                    "Invalid {} URL in metadata, skipping it: {:?}",
+                    name,
+                    log_preview(&value)
+                );
+            }
+        }
+    }
+}
+
+/// Normalizes the collection metadata read from the `contractURI`.
+///
+/// The `logo`, `banner` and `external_url` keys are accepted in place of
+/// `image`, `banner_image` and `external_link`. The image and link URLs are
+/// validated, relative ones being resolved against the contract URI.
+/// Invalid values are removed.
+pub fn normalize_collection_metadata(
+    raw_metadata: &str,
+    contract_uri: &str,
+) -> Result<NormalizedCollectionMetadata> {
+    let value = serde_json::from_str::<serde_json::Value>(raw_metadata)
+        .map_err(|e| anyhow!("Failed to parse collection metadata: {}", e))?;
+
+    let extract_url = |keys: &[&str]| {
+        let raw_url = keys.iter().find_map(|key| extract_string(&value, key))?;
+        let url = normalize_url(&raw_url, contract_uri);
+
+        if url.is_none() {
+            warn!(
+                "Invalid {} URL in collection metadata, skipping it: {:?}",
+                keys[0],
+                log_preview(&raw_url)
+            );
+        }
+
+        url
+    };
+
+    let seller_fee_basis_points = value.get("seller_fee_basis_points").and_then(|v| match v {
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        v => v.as_u64(),
+    });
+
+    Ok(NormalizedCollectionMetadata {
+        name: extract_string(&value, "name"),
+        description: extract_string(&value, "description"),
+        image: extract_url(&["image", "logo"]),
+        banner_image: extract_url(&["banner_image", "banner"]),
+        featured_image: extract_url(&["featured_image"]),
+        external_link: extract_url(&["external_link", "external_url"]),
+        seller_fee_basis_points,
+        fee_recipient: extract_string(&value, "fee_recipient"),
         ..Default::default()
     })
 }
 
-async fn fetch_metadata(
-    uri: &str,
-    client: &Client,
-    request_timeout_duration: Duration,
-    referrer: &str,
+/// Returns a hash of the normalized metadata, to detect if the
+/// metadata of a token changed since its last refresh.
+pub fn metadata_content_hash(metadata: &NormalizedMetadata) -> Result<String> {
+    // Serializing through a `Value` sorts the keys of the properties map,
+    // which is required for the hash to be deterministic.
+    let value = serde_json::to_value(metadata)?;
+    let hash = starknet_keccak(value.to_string().as_bytes());
+    Ok(format!("0x{:064x}", hash))
+}
+
+/// Compresses a raw metadata document with gzip, to offload it.
+pub fn compress_raw_metadata(raw: &str) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(raw.as_bytes())?;
+    Ok(encoder.finish()?)
+}
+
+/// Decompresses a raw metadata document compressed by `compress_raw_metadata`.
+pub fn decompress_raw_metadata(content: &[u8]) -> Result<String> {
+    let mut raw = String::new();
+    GzDecoder::new(content).read_to_string(&mut raw)?;
+    Ok(raw)
+}
+
+/// Parses the raw metadata into a JSON value, to be stored natively.
+///
+/// Returns `None` if they are not a JSON document, or hold a number parsed
+/// as a double beyond its exact range, like the integers larger than `u64`
+/// (token ids, amounts...), which would lose their precision. Those raw
+/// metadata are only stored as a string.
+pub fn raw_metadata_value(raw: &str) -> Option<serde_json::Value> {
+    match serde_json::from_str::<serde_json::Value>(raw) {
+        Ok(value) if !has_inexact_number(&value) => Some(value),
+        _ => None,
+    }
+}
+
+fn has_inexact_number(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Number(n) => {
+            n.is_f64()
+                && n.as_f64()
+                    .map_or(false, |f| f.abs() >= MAX_EXACT_DOUBLE_INTEGER)
+        }
+        serde_json::Value::Array(values) => values.iter().any(has_inexact_number),
+        serde_json::Value::Object(map) => map.values().any(has_inexact_number),
+        _ => false,
+    }
+}
+
+/// Trims the whitespaces of the attributes `trait_type` and `value`, and
+/// if `dedup` is true, removes the exact duplicates, keeping the first one.
+pub fn clean_attributes(metadata: &mut NormalizedMetadata, dedup: bool) {
+    let attributes = match metadata.attributes.take() {
+        Some(attributes) => attributes,
+        None => return,
+    };
+
+    let mut cleaned: Vec<MetadataAttribute> = Vec::with_capacity(attributes.len());
+
+    for mut attribute in attributes {
+        attribute.trait_type = attribute.trait_type.map(|t| t.trim().to_string());
+        attribute.value = match attribute.value {
+            MetadataTraitValue::String(s) => MetadataTraitValue::String(s.trim().to_string()),
+            MetadataTraitValue::Array(a) => {
+                MetadataTraitValue::Array(a.iter().map(|s| s.trim().to_string()).collect())
+            }
+            v => v,
+        };
+
+        if !dedup || !cleaned.contains(&attribute) {
+            cleaned.push(attribute);
+        }
+    }
+
+    metadata.attributes = Some(cleaned);
+}
+
+/// Applies the given policy to the attributes sharing the same `trait_type`.
+/// If any, the metadata is flagged with `has_duplicate_traits`, whatever the policy.
+pub fn apply_duplicate_trait_policy(
+    metadata: &mut NormalizedMetadata,
+    policy: DuplicateTraitPolicy,
+) {
+    let attributes = match metadata.attributes.take() {
+        Some(attributes) => attributes,
+        None => return,
+    };
+
+    let mut trait_types = HashSet::new();
+    metadata.has_duplicate_traits = attributes
+        .iter()
+        .filter_map(|a| a.trait_type.as_ref())
+        .any(|t| !trait_types.insert(t));
+
+    if !metadata.has_duplicate_traits {
+        metadata.attributes = Some(attributes);
+        return;
+    }
+
+    let keep_first = |attributes: Vec<MetadataAttribute>| -> Vec<MetadataAttribute> {
+        let mut seen = HashSet::new();
+        attributes
+            .into_iter()
+            .filter(|a| {
+                a.trait_type
+                    .as_ref()
+                    .map_or(true, |t| seen.insert(t.clone()))
+            })
+            .collect()
+    };
+
+    metadata.attributes = Some(match policy {
+        DuplicateTraitPolicy::KeepAll => attributes,
+        DuplicateTraitPolicy::KeepFirst => keep_first(attributes),
+        DuplicateTraitPolicy::KeepLast => {
+            let mut kept = keep_first(attributes.into_iter().rev().collect());
+            kept.reverse();
+            kept
+        }
+        DuplicateTraitPolicy::Merge => {
+            let mut merged: Vec<MetadataAttribute> = vec![];
+            let mut indexes: HashMap<String, usize> = HashMap::new();
+
+            for attribute in attributes {
+                let index = attribute
+                    .trait_type
+                    .as_ref()
+                    .and_then(|t| indexes.get(t).copied());
+
+                match index {
+                    Some(i) => {
+                        let mut values = merged[i].value.to_strings();
+                        values.extend(attribute.value.to_strings());
+                        merged[i].value = MetadataTraitValue::Array(values);
+                    }
+                    None => {
+                        if let Some(t) = &attribute.trait_type {
+                            indexes.insert(t.clone(), merged.len());
+                        }
+                        merged.push(attribute);
+                    }
+                }
+            }
+
+            merged
+        }
+    });
+}
+
+/// Sorts the attributes in the given order. The sort is stable, so the
+/// same attributes are always serialized, and hashed, identically.
+pub fn sort_attributes(metadata: &mut NormalizedMetadata, order: AttributeOrder) {
+    if let (AttributeOrder::TraitType, Some(attributes)) = (order, metadata.attributes.as_mut()) {
+        attributes.sort_by_key(|a| (a.trait_type.is_none(), a.trait_type.clone()));
+    }
+}
+
+/// Converts the `properties` map form of the attributes
+/// (`{ "properties": { "Background": "Blue" } }`) into the `attributes` list.
+///
+/// The `attributes` are preferred when both forms exist. The values can be
+/// given directly, or as the `value` of an object (`{ "value": "Blue" }`);
+/// the other objects, like the JSON schema of the properties, are skipped.
+fn normalize_properties_attributes(metadata: &mut NormalizedMetadata, raw: &serde_json::Value) {
+    if metadata
+        .attributes
+        .as_ref()
+        .map_or(false, |a| !a.is_empty())
+    {
+        return;
+    }
+
+    let properties = match raw.get("properties").and_then(|p| p.as_object()) {
+        Some(properties) => properties,
+        None => return,
+    };
+
+    let attributes: Vec<MetadataAttribute> = properties
+        .iter()
+        .filter_map(|(trait_type, value)| {
+            let value = match value {
+                serde_json::Value::Object(o) => o.get("value")?,
+                value => value,
+            };
+
+            let value = match value {
+                serde_json::Value::String(s) => MetadataTraitValue::String(s.clone()),
+                serde_json::Value::Number(n) => MetadataTraitValue::Number(n.clone()),
+                serde_json::Value::Bool(b) => MetadataTraitValue::Boolean(*b),
+                serde_json::Value::Array(_) => {
+                    MetadataTraitValue::Array(serde_json::from_value(value.clone()).ok()?)
+                }
+                _ => return None,
+            };
+
+            Some(MetadataAttribute {
+                display_type: None,
+                trait_type: Some(trait_type.clone()),
+                value,
+            })
+        })
+        .collect();
+
+    if !attributes.is_empty() {
+        metadata.attributes = Some(attributes);
+    }
+}
+
+/// Returns the value at the given dotted key (`properties.attributes`), or
+/// at the given JSON pointer (`/data/0/attributes`), if any.
+fn lookup_key<'v>(value: &'v serde_json::Value, key: &str) -> Option<&'v serde_json::Value> {
+    let value = if key.starts_with('/') {
+        value.pointer(key)
+    } else {
+        key.split('.').try_fold(value, |v, k| v.get(k))
+    };
+
+    value.filter(|v| !v.is_null())
+}
+
+/// Reads the attributes at the given path of the raw metadata, a dotted key
+/// (`metadata.attributes`) or a JSON pointer (`/data/attributes`), for the
+/// collections nesting them. The normalized attributes are kept if nothing
+/// is found at this path.
+pub fn apply_attributes_path(metadata: &mut TokenMetadata, path: &str) {
+    if path == DEFAULT_ATTRIBUTES_PATH {
+        return;
+    }
+
+    let raw = match metadata.raw_json() {
+        Some(raw) => raw,
+        None => return,
+    };
+
+    let value = match lookup_key(&raw, path) {
+        Some(value) => value,
+        None => return,
+    };
+
+    match serde_json::from_value::<Vec<MetadataAttribute>>(value.clone()) {
+        Ok(attributes) => metadata.normalized.attributes = Some(attributes),
+        Err(e) => warn!("Invalid attributes at {}, skipping them: {}", path, e),
+    }
+}
+
+/// Normalizes the fields overridden by the given profile from the raw metadata.
+/// The overriding keys take precedence over the standard ones, which are kept
+/// if none of the keys is found. The URLs are validated against `base_uri`.
+pub fn apply_normalization_profile(
+    metadata: &mut TokenMetadata,
+    profile: &NormalizationProfile,
+    base_uri: &str,
+) {
+    if profile.field_overrides.is_empty() {
+        return;
+    }
+
+    let raw = match metadata.raw_json() {
+        Some(raw) => raw,
+        None => return,
+    };
+
+    let normalized = &mut metadata.normalized;
+
+    for (field, keys) in profile.field_overrides.iter() {
+        let value = match keys.iter().find_map(|k| lookup_key(&raw, k)) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        if field == "attributes" {
+            match serde_json::from_value::<Vec<MetadataAttribute>>(value.clone()) {
+                Ok(attributes) => normalized.attributes = Some(attributes),
+                Err(e) => warn!("Invalid overridden attributes, skipping them: {}", e),
+            }
+            continue;
+        }
+
+        let target = match field.as_str() {
+            "name" => &mut normalized.name,
+            "description" => &mut normalized.description,
+            "image" => &mut normalized.image,
+            "image_data" => &mut normalized.image_data,
+            "external_url" => &mut normalized.external_url,
+            "animation_url" => &mut normalized.animation_url,
+            "background_color" => &mut normalized.background_color,
+            "youtube_url" => &mut normalized.youtube_url,
+            _ => {
+                warn!("Unsupported normalization override: {}", field);
+                continue;
+            }
+        };
+
+        if let Some(s) = value.as_str() {
+            *target = Some(s.to_string());
+        }
+    }
+
+    normalize_metadata_urls(normalized, base_uri);
+}
+
+/// Fetches the metadata at the given IPFS or Arweave path (`<cid>/1.json`)
+/// from the first gateway, the next ones being tried in order if the request
+/// fails or doesn't return a JSON document.
+///
+/// If no gateway returns a JSON document, the last document fetched is
+/// returned, like the metadata fetched without gateway.
+async fn fetch_from_gateways(
+    path: &str,
+    initial_uri: &str,
+    fetcher: &dyn MetadataFetcher,
+    gateway_uris: &[&str],
 ) -> Result<TokenMetadata> {
-    let request = client
-        .get(uri)
-        .header("User-Agent", "Mozilla/5.0 (compatible; YourClient/1.0)")
-        .header("Referrer", referrer)
-        .timeout(request_timeout_duration);
-
-    let response = request.send().await;
-
-    match response {
-        Ok(response) => {
-            debug!("Response status: {}", response.status());
-            if response.status().is_success() {
-                let raw_metadata = response.text().await?;
-                let metadata = match normalize_metadata(raw_metadata.as_str()) {
-                    Ok(metadata) => metadata,
-                    Err(_) => NormalizedMetadata::default(),
-                };
-
-                let now = Utc::now();
-
-                Ok(TokenMetadata {
-                    raw: raw_metadata,
-                    normalized: metadata,
-                    metadata_updated_at: Some(now.timestamp()),
-                })
-            } else {
-                error!("Request Failed. URI: {}", uri);
-                Err(anyhow!("Request Failed"))
+    let mut last_error = anyhow!("No gateway configured for {}", log_preview(initial_uri));
+    let mut not_json = None;
+
+    for gateway_uri in gateway_uris {
+        let complete_uri = format!("{}{}", gateway_uri, path);
+        trace!("Fetching metadata from gateway: {}", complete_uri.as_str());
+
+        match fetch_metadata(complete_uri.as_str(), initial_uri, fetcher).await {
+            Ok(metadata) if metadata.raw_json().is_none() => {
+                warn!(
+                    "Gateway {} didn't return JSON for {}, trying next one",
+                    gateway_uri,
+                    log_preview(initial_uri)
+                );
+                not_json = Some(metadata);
+            }
+            Ok(metadata) => {
+                debug!(
+                    "Metadata {} fetched from gateway {}",
+                    log_preview(initial_uri),
+                    gateway_uri
+                );
+                return Ok(TokenMetadata {
+                    gateway_uri: Some(gateway_uri.to_string()),
+                    ..metadata
+                });
+            }
+            Err(e) => {
+                warn!("Gateway {} failed, trying next one: {}", gateway_uri, e);
+                last_error = e;
             }
         }
+    }
+
+    not_json.ok_or(last_error)
+}
+
+/// Fetches the metadata at the given URI using the given fetcher, and normalizes
+/// them against `initial_uri`, the URI before resolving the gateways.
+async fn fetch_metadata(
+    uri: &str,
+    initial_uri: &str,
+    fetcher: &dyn MetadataFetcher,
+) -> Result<TokenMetadata> {
+    let raw_metadata = fetcher.fetch(uri).await?;
+
+    Ok(token_metadata_from_raw(raw_metadata, initial_uri))
+}
+
+/// Parses and normalizes a raw metadata document against `initial_uri`.
+fn token_metadata_from_raw(raw_metadata: String, initial_uri: &str) -> TokenMetadata {
+    let metadata = match serde_json::from_str::<serde_json::Value>(&raw_metadata) {
+        Ok(raw) => normalize_metadata(&raw, initial_uri),
         Err(e) => {
-            error!("Request Failed: {:?}", e);
-            Err(anyhow!("Request Failed. URI: {}", uri))
+            error!("Failed to parse metadata: {:?}", e);
+            NormalizedMetadata::default()
         }
+    };
+
+    let now = Utc::now();
+
+    TokenMetadata {
+        raw: raw_metadata,
+        normalized: metadata,
+        metadata_updated_at: Some(now.timestamp()),
+        content_hash: None,
+        gateway_uri: None,
+        raw_key: None,
+        raw_value: None,
     }
 }
 
@@ -132,6 +732,7 @@ pub fn file_extension_from_mime_type(mime_type: &str) -> &str {
         "image/gif" => "gif",
         "image/bmp" => "bmp",
         "image/webp" => "webp",
+        "image/avif" => "avif",
         "image/svg+xml" => "svg",
         "video/mp4" => "mp4",
         "video/quicktime" => "mov",
@@ -151,117 +752,61 @@ fn fetch_onchain_metadata(uri: &str) -> Result<TokenMetadata> {
         Err(_) => String::from(uri),
     };
 
-    let now = Utc::now();
-    match uri_string.split_once(',') {
-        Some(("data:application/json;base64", uri)) => {
+    let raw_metadata = match uri_string.split_once(',') {
+        Some(("data:application/json;base64", data)) => {
             // If it is base64 encoded, decode it, parse and return
-            let decoded = general_purpose::STANDARD.decode(uri)?;
-            let raw_metadata = std::str::from_utf8(&decoded)?;
-            match serde_json::from_str::<NormalizedMetadata>(raw_metadata) {
-                Ok(normalized_metadata) => Ok(TokenMetadata {
-                    raw: raw_metadata.to_string(),
-                    normalized: normalized_metadata,
-                    metadata_updated_at: Some(now.timestamp()),
-                }),
-                Err(_) => {
-                    let metadata = serde_json::from_str::<serde_json::Value>(raw_metadata)?;
-                    let normalized_metadata = NormalizedMetadata {
-                        name: extract_string(&metadata, "name"),
-                        animation_key: extract_string(&metadata, "animation_key"),
-                        image: extract_string(&metadata, "image"),
-                        animation_mime_type: extract_string(&metadata, "animation_mime_type"),
-                        animation_url: extract_string(&metadata, "animation_url"),
-                        background_color: extract_string(&metadata, "background_color"),
-                        description: extract_string(&metadata, "description"),
-                        external_url: extract_string(&metadata, "external_url"),
-                        image_mime_type: extract_string(&metadata, "image_mime_type"),
-                        image_data: extract_string(&metadata, "image_data"),
-                        image_key: extract_string(&metadata, "image_key"),
-                        youtube_url: extract_string(&metadata, "youtube_url"),
-                        ..Default::default()
-                    };
-
-                    Ok(TokenMetadata {
-                        raw: raw_metadata.to_string(),
-                        normalized: normalized_metadata,
-                        metadata_updated_at: Some(now.timestamp()),
-                    })
-                }
-            }
+            let decoded = general_purpose::STANDARD.decode(data)?;
+            std::str::from_utf8(&decoded)?.to_string()
         }
-        Some(("data:application/json", uri)) => {
-            match serde_json::from_str::<NormalizedMetadata>(uri) {
-                Ok(normalized_metadata) => Ok(TokenMetadata {
-                    raw: uri.to_string(),
-                    normalized: normalized_metadata,
-                    metadata_updated_at: Some(now.timestamp()),
-                }),
-                Err(_) => {
-                    let metadata = serde_json::from_str::<serde_json::Value>(uri)?;
-                    let normalized_metadata = NormalizedMetadata {
-                        name: extract_string(&metadata, "name"),
-                        animation_key: extract_string(&metadata, "animation_key"),
-                        image: extract_string(&metadata, "image"),
-                        animation_mime_type: extract_string(&metadata, "animation_mime_type"),
-                        animation_url: extract_string(&metadata, "animation_url"),
-                        background_color: extract_string(&metadata, "background_color"),
-                        description: extract_string(&metadata, "description"),
-                        external_url: extract_string(&metadata, "external_url"),
-                        image_mime_type: extract_string(&metadata, "image_mime_type"),
-                        image_data: extract_string(&metadata, "image_data"),
-                        image_key: extract_string(&metadata, "image_key"),
-                        youtube_url: extract_string(&metadata, "youtube_url"),
-                        ..Default::default()
-                    };
-
-                    Ok(TokenMetadata {
-                        raw: uri.to_string(),
-                        normalized: normalized_metadata,
-                        metadata_updated_at: Some(now.timestamp()),
-                    })
-                }
-            }
+        Some(("data:application/json", data)) | Some(("data:application/json;utf8", data)) => {
+            data.to_string()
         }
-        Some(("data:application/json;utf8", uri)) => {
-            match serde_json::from_str::<NormalizedMetadata>(uri) {
-                Ok(normalized_metadata) => Ok(TokenMetadata {
-                    raw: uri.to_string(),
-                    normalized: normalized_metadata,
-                    metadata_updated_at: Some(now.timestamp()),
-                }),
-                Err(_) => {
-                    let metadata = serde_json::from_str::<serde_json::Value>(uri)?;
-                    let normalized_metadata = NormalizedMetadata {
-                        name: extract_string(&metadata, "name"),
-                        animation_key: extract_string(&metadata, "animation_key"),
-                        image: extract_string(&metadata, "image"),
-                        animation_mime_type: extract_string(&metadata, "animation_mime_type"),
-                        animation_url: extract_string(&metadata, "animation_url"),
-                        background_color: extract_string(&metadata, "background_color"),
-                        description: extract_string(&metadata, "description"),
-                        external_url: extract_string(&metadata, "external_url"),
-                        image_mime_type: extract_string(&metadata, "image_mime_type"),
-                        image_data: extract_string(&metadata, "image_data"),
-                        image_key: extract_string(&metadata, "image_key"),
-                        youtube_url: extract_string(&metadata, "youtube_url"),
-                        ..Default::default()
-                    };
-
-                    Ok(TokenMetadata {
-                        raw: uri.to_string(),
-                        normalized: normalized_metadata,
-                        metadata_updated_at: Some(now.timestamp()),
-                    })
-                }
-            }
+        _ => {
+            return match serde_json::from_str(uri) {
+                // If it is only the URI without the data format information, try to format it
+                // and if it fails, return empty metadata
+                Ok(v) => Ok(v),
+                Err(_) => Ok(TokenMetadata::default()),
+            };
         }
-        _ => match serde_json::from_str(uri) {
-            // If it is only the URI without the data format information, try to format it
-            // and if it fails, return empty metadata
-            Ok(v) => Ok(v),
-            Err(_) => Ok(TokenMetadata::default()),
-        },
-    }
+    };
+
+    let raw = serde_json::from_str::<serde_json::Value>(&raw_metadata)?;
+
+    Ok(TokenMetadata {
+        normalized: normalize_metadata(&raw, uri),
+        raw: raw_metadata,
+        metadata_updated_at: Some(Utc::now().timestamp()),
+        content_hash: None,
+        gateway_uri: None,
+        raw_key: None,
+        raw_value: None,
+    })
+}
+
+/// Decodes a `data:` URI into its mime type and content.
+///
+/// Both base64 (`data:image/svg+xml;base64,...`) and url-encoded
+/// (`data:image/svg+xml;utf8,...`) payloads are supported.
+pub fn decode_data_uri(uri: &str) -> Result<(String, Vec<u8>)> {
+    let (header, payload) = uri
+        .strip_prefix("data:")
+        .and_then(|s| s.split_once(','))
+        .ok_or_else(|| anyhow!("Invalid data URI"))?;
+
+    let mut params = header.split(';');
+    let mime_type = match params.next() {
+        Some(m) if !m.is_empty() => m.to_string(),
+        _ => "text/plain".to_string(),
+    };
+
+    let content = if params.any(|p| p == "base64") {
+        general_purpose::STANDARD.decode(payload)?
+    } else {
+        urlencoding::decode_binary(payload.as_bytes()).into_owned()
+    };
+
+    Ok((mime_type, content))
 }
 
 pub fn extract_metadata_from_headers(headers: &HeaderMap) -> Result<(String, u64)> {
@@ -295,9 +840,91 @@ pub fn extract_metadata_from_headers(headers: &HeaderMap) -> Result<(String, u64
 mod tests {
 
     use super::*;
+    use crate::metadata_fetcher::{HttpMetadataFetcher, MockMetadataFetcher};
+    use crate::test_server::TestServer;
     use base64::engine::general_purpose::STANDARD;
     use reqwest::header::{HeaderMap, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+    use reqwest::Client;
     use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn test_normalize_collection_metadata() {
+        let raw_metadata = json!({
+            "name": "Ducks",
+            "description": "A collection of ducks",
+            "logo": "logo.png",
+            "banner": "javascript:alert(1)",
+            "external_url": "https://ducks.xyz",
+            "seller_fee_basis_points": "250",
+            "fee_recipient": "0xabc",
+        })
+        .to_string();
+
+        let metadata =
+            normalize_collection_metadata(&raw_metadata, "ipfs://QmCollection/contract.json")
+                .unwrap();
+
+        assert_eq!(metadata.name.as_deref(), Some("Ducks"));
+        assert_eq!(
+            metadata.image.as_deref(),
+            Some("ipfs://QmCollection/logo.png")
+        );
+        assert_eq!(metadata.banner_image, None);
+        assert_eq!(metadata.external_link.as_deref(), Some("https://ducks.xyz"));
+        assert_eq!(metadata.seller_fee_basis_points, Some(250));
+        assert_eq!(metadata.fee_recipient.as_deref(), Some("0xabc"));
+    }
+
+    fn normalize(raw_metadata: &str) -> NormalizedMetadata {
+        normalize_metadata(
+            &serde_json::from_str(raw_metadata).expect("failed metadata parsing"),
+            "https://example.com/metadata/1.json",
+        )
+    }
+
+    #[test]
+    fn test_normalize_metadata_shapes() {
+        let base_uri = "https://example.com/metadata/1.json";
+        let cases = [
+            (json!({ "name": "Duck" }), Some("Duck"), None, 0),
+            // Relative image, resolved against the metadata URI.
+            (
+                json!({ "image": "1.png" }),
+                None,
+                Some("https://example.com/metadata/1.png"),
+                0,
+            ),
+            // A field of an unexpected type doesn't drop the other ones.
+            (
+                json!({ "name": "Duck", "background_color": 255, "attributes": [{ "trait_type": "Hat", "value": "Cap" }] }),
+                Some("Duck"),
+                None,
+                1,
+            ),
+            (
+                json!({ "properties": { "Hat": "Cap", "Eyes": "Blue" } }),
+                None,
+                None,
+                2,
+            ),
+            (json!({ "image": "javascript:alert(1)" }), None, None, 0),
+            (json!([]), None, None, 0),
+        ];
+
+        for (raw, name, image, attributes_count) in cases {
+            let metadata = normalize_metadata(&raw, base_uri);
+
+            assert_eq!(metadata.name.as_deref(), name, "{}", raw);
+            assert_eq!(metadata.image.as_deref(), image, "{}", raw);
+            assert_eq!(
+                metadata.attributes.map_or(0, |a| a.len()),
+                attributes_count,
+                "{}",
+                raw
+            );
+        }
+    }
 
     #[test]
     fn normalize_metadata_with_array_value() {
@@ -327,8 +954,7 @@ mod tests {
             "booklet_id":"ducks_everywhere/FirefighterDuck"
         }"#;
 
-        let normalized_metadata =
-            normalize_metadata(raw_metadata).expect("failed metadata parsing");
+        let normalized_metadata = normalize(raw_metadata);
 
         assert_eq!(
             normalized_metadata.image,
@@ -338,6 +964,179 @@ mod tests {
         );
     }
 
+    fn metadata_with_duplicate_traits() -> NormalizedMetadata {
+        normalize(
+            r#"{
+            "name":"Duck",
+            "attributes":[
+                {"trait_type":"Hat","value":"Cap"},
+                {"trait_type":"Eyes","value":"Blue"},
+                {"trait_type":"Hat","value":"Crown"},
+                {"value":"Untyped"}
+            ]
+        }"#,
+        )
+    }
+
+    fn attributes_values(metadata: &NormalizedMetadata) -> Vec<(Option<String>, Vec<String>)> {
+        metadata
+            .attributes
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|a| (a.trait_type.clone(), a.value.to_strings()))
+            .collect()
+    }
+
+    #[test]
+    fn test_apply_duplicate_trait_policy() {
+        let hat = |v: &[&str]| {
+            (
+                Some("Hat".to_string()),
+                v.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+            )
+        };
+        let eyes = (Some("Eyes".to_string()), vec!["Blue".to_string()]);
+        let untyped = (None, vec!["Untyped".to_string()]);
+
+        let mut metadata = metadata_with_duplicate_traits();
+        apply_duplicate_trait_policy(&mut metadata, DuplicateTraitPolicy::KeepAll);
+        assert!(metadata.has_duplicate_traits);
+        assert_eq!(
+            attributes_values(&metadata),
+            vec![
+                hat(&["Cap"]),
+                eyes.clone(),
+                hat(&["Crown"]),
+                untyped.clone()
+            ]
+        );
+
+        let mut metadata = metadata_with_duplicate_traits();
+        apply_duplicate_trait_policy(&mut metadata, DuplicateTraitPolicy::KeepFirst);
+        assert!(metadata.has_duplicate_traits);
+        assert_eq!(
+            attributes_values(&metadata),
+            vec![hat(&["Cap"]), eyes.clone(), untyped.clone()]
+        );
+
+        let mut metadata = metadata_with_duplicate_traits();
+        apply_duplicate_trait_policy(&mut metadata, DuplicateTraitPolicy::KeepLast);
+        assert_eq!(
+            attributes_values(&metadata),
+            vec![eyes.clone(), hat(&["Crown"]), untyped.clone()]
+        );
+
+        let mut metadata = metadata_with_duplicate_traits();
+        apply_duplicate_trait_policy(&mut metadata, DuplicateTraitPolicy::Merge);
+        assert_eq!(
+            attributes_values(&metadata),
+            vec![hat(&["Cap", "Crown"]), eyes, untyped]
+        );
+    }
+
+    #[test]
+    fn test_apply_duplicate_trait_policy_without_duplicates() {
+        let mut metadata = normalize(
+            r#"{"attributes":[{"trait_type":"Hat","value":"Cap"},{"trait_type":"Eyes","value":"Blue"}]}"#,
+        );
+
+        apply_duplicate_trait_policy(&mut metadata, DuplicateTraitPolicy::KeepFirst);
+
+        assert!(!metadata.has_duplicate_traits);
+        assert_eq!(metadata.attributes.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_sort_attributes_by_trait_type() {
+        let first = r#"{"attributes":[{"trait_type":"Hat","value":"Cap"},{"value":"Untyped"},{"trait_type":"Eyes","value":"Blue"},{"trait_type":"Hat","value":"Crown"}]}"#;
+        let second = r#"{"attributes":[{"value":"Untyped"},{"trait_type":"Eyes","value":"Blue"},{"trait_type":"Hat","value":"Cap"},{"trait_type":"Hat","value":"Crown"}]}"#;
+
+        let mut metadata = normalize(first);
+        sort_attributes(&mut metadata, AttributeOrder::Source);
+        assert_eq!(
+            metadata_content_hash(&metadata).unwrap(),
+            metadata_content_hash(&normalize(first)).unwrap()
+        );
+        assert_ne!(
+            metadata_content_hash(&metadata).unwrap(),
+            metadata_content_hash(&normalize(second)).unwrap()
+        );
+
+        let sorted = |raw: &str| {
+            let mut metadata = normalize(raw);
+            sort_attributes(&mut metadata, AttributeOrder::TraitType);
+            metadata
+        };
+
+        assert_eq!(
+            attributes_values(&sorted(first)),
+            vec![
+                (Some("Eyes".to_string()), vec!["Blue".to_string()]),
+                (Some("Hat".to_string()), vec!["Cap".to_string()]),
+                (Some("Hat".to_string()), vec!["Crown".to_string()]),
+                (None, vec!["Untyped".to_string()]),
+            ]
+        );
+        assert_eq!(
+            serde_json::to_string(&sorted(first)).unwrap(),
+            serde_json::to_string(&sorted(second)).unwrap()
+        );
+        assert_eq!(
+            metadata_content_hash(&sorted(first)).unwrap(),
+            metadata_content_hash(&sorted(second)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_clean_attributes() {
+        let raw_metadata = r#"{
+            "attributes":[
+                {"trait_type":" Hat ","value":"Cap  "},
+                {"trait_type":"Eyes","value":"Blue"},
+                {"trait_type":"Hat","value":"Cap"},
+                {"trait_type":"Hat","value":"Crown"},
+                {"trait_type":"Level","value":2},
+                {"trait_type":"Level","value":2},
+                {"trait_type":"Tags","value":[" a", "b "]}
+            ]
+        }"#;
+
+        let mut metadata = normalize(raw_metadata);
+        clean_attributes(&mut metadata, true);
+
+        assert_eq!(
+            attributes_values(&metadata),
+            vec![
+                (Some("Hat".to_string()), vec!["Cap".to_string()]),
+                (Some("Eyes".to_string()), vec!["Blue".to_string()]),
+                (Some("Hat".to_string()), vec!["Crown".to_string()]),
+                (Some("Level".to_string()), vec!["2".to_string()]),
+                (
+                    Some("Tags".to_string()),
+                    vec!["a".to_string(), "b".to_string()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clean_attributes_without_dedup() {
+        let raw_metadata = r#"{
+            "attributes":[
+                {"trait_type":"Hat","value":" Cap"},
+                {"trait_type":"Hat ","value":"Cap"}
+            ]
+        }"#;
+
+        let mut metadata = normalize(raw_metadata);
+        clean_attributes(&mut metadata, false);
+
+        let attributes = metadata.attributes.unwrap();
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(attributes[0], attributes[1]);
+    }
+
     #[test]
     fn test_file_extension_from_mime_type() {
         assert_eq!(file_extension_from_mime_type("image/png"), "png");
@@ -358,6 +1157,140 @@ mod tests {
 
         let metadata_type = get_metadata_type("https://everai.xyz/metadata/1");
         assert!(metadata_type == MetadataType::Http("https://everai.xyz/metadata/1".to_string()));
+
+        let metadata_type = get_metadata_type("ar://bNbA3TEQVL60xlgCcqdz4ZPHFZ711cZ3hmkpGttDt_U");
+        assert!(
+            metadata_type
+                == MetadataType::Arweave(
+                    "ar://bNbA3TEQVL60xlgCcqdz4ZPHFZ711cZ3hmkpGttDt_U".to_string()
+                )
+        );
+    }
+
+    #[test]
+    fn test_resolve_base_token_uri() {
+        let base = "ipfs://QmHash/";
+
+        assert_eq!(
+            resolve_base_token_uri(base, "12", BaseUriSuffix::TokenId),
+            "ipfs://QmHash/12"
+        );
+        assert_eq!(
+            resolve_base_token_uri(base, "12", BaseUriSuffix::TokenIdJson),
+            "ipfs://QmHash/12.json"
+        );
+        assert_eq!(
+            resolve_base_token_uri(
+                "https://example.com/tokens/12/",
+                "12",
+                BaseUriSuffix::IndexJson
+            ),
+            "https://example.com/tokens/12/index.json"
+        );
+        // Complete URIs are kept.
+        assert_eq!(
+            resolve_base_token_uri("ipfs://QmHash/12.json", "12", BaseUriSuffix::TokenIdJson),
+            "ipfs://QmHash/12.json"
+        );
+        assert_eq!(
+            resolve_base_token_uri("data:text/plain,a/", "12", BaseUriSuffix::TokenId),
+            "data:text/plain,a/"
+        );
+    }
+
+    #[test]
+    fn test_resolve_gateway_uri() {
+        let gateway = "https://gateway.example/";
+
+        assert_eq!(
+            resolve_gateway_uri("ar://txid", "", gateway),
+            "https://gateway.example/txid"
+        );
+        assert_eq!(
+            resolve_gateway_uri("ar://txid/metadata/1.json", "", gateway),
+            "https://gateway.example/txid/metadata/1.json"
+        );
+        assert_eq!(
+            resolve_gateway_uri("ipfs://QmHash/1.png", "https://ipfs.example/ipfs/", gateway),
+            "https://ipfs.example/ipfs/QmHash/1.png"
+        );
+        assert_eq!(
+            resolve_gateway_uri("https://example.com/1.png", "", gateway),
+            "https://example.com/1.png"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_token_metadata_with_properties_only() {
+        let raw_metadata = json!({
+            "name": "Duck",
+            "properties": {
+                "Background": "Blue",
+                "Level": 3,
+                "Hat": { "value": "Cap" },
+                "image": { "type": "string", "description": "An image" },
+            },
+        });
+
+        let metadata = get_token_metadata(
+            &http_fetcher(),
+            &format!("data:application/json,{}", raw_metadata),
+            &[],
+            &[DEFAULT_ARWEAVE_GATEWAY_URI],
+            None,
+        )
+        .await
+        .unwrap();
+
+        let attributes = metadata.normalized.attributes.unwrap();
+        assert_eq!(attributes.len(), 3);
+        assert!(attributes.contains(&MetadataAttribute {
+            display_type: None,
+            trait_type: Some("Background".to_string()),
+            value: MetadataTraitValue::String("Blue".to_string()),
+        }));
+        assert!(attributes.contains(&MetadataAttribute {
+            display_type: None,
+            trait_type: Some("Hat".to_string()),
+            value: MetadataTraitValue::String("Cap".to_string()),
+        }));
+    }
+
+    #[test]
+    fn test_normalize_properties_attributes_prefers_attributes() {
+        let raw = json!({
+            "attributes": [{ "trait_type": "Background", "value": "Red" }],
+            "properties": { "Background": "Blue" },
+        });
+
+        let attributes = normalize_metadata(&raw, "").attributes.unwrap();
+        assert_eq!(attributes.len(), 1);
+        assert_eq!(
+            attributes[0].value,
+            MetadataTraitValue::String("Red".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_token_metadata_from_arweave() {
+        let gateway = serve("application/json", r#"{"name":"Duck","image":"1.png"}"#).await;
+
+        let metadata = get_token_metadata(
+            &http_fetcher(),
+            "ar://txid/metadata/1.json",
+            &[],
+            &[&format!("{}/", gateway)],
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(metadata.normalized.name, Some("Duck".to_string()));
+        // The image is stored with its canonical Arweave URI, not the gateway one.
+        assert_eq!(
+            metadata.normalized.image,
+            Some("ar://txid/metadata/1.png".to_string())
+        );
     }
 
     #[test]
@@ -412,18 +1345,18 @@ mod tests {
 
     #[tokio::test]
     async fn test_fetch_metadata() {
-        let client = Client::new();
-        let uri = "https://example.com";
-        let request_referrer = "https://arkproject.dev";
-        let request_timeout_duration = Duration::from_secs(10);
+        let fetcher = HttpMetadataFetcher::new(
+            Client::new(),
+            Duration::from_secs(10),
+            "https://arkproject.dev",
+        );
+        let uri = serve("application/json", r#"{"name":"Duck"}"#).await;
 
-        let metadata =
-            fetch_metadata(uri, &client, request_timeout_duration, request_referrer).await;
-        assert!(metadata.is_ok());
+        let metadata = fetch_metadata(&uri, &uri, &fetcher).await;
+        assert_eq!(metadata.unwrap().normalized.name, Some("Duck".to_string()));
 
         let uri = "invalid_uri";
-        let metadata =
-            fetch_metadata(uri, &client, request_timeout_duration, request_referrer).await;
+        let metadata = fetch_metadata(uri, uri, &fetcher).await;
 
         assert!(metadata.is_err());
     }
@@ -453,6 +1386,26 @@ mod tests {
         assert!(fetched_metadata.metadata_updated_at.is_some());
     }
 
+    #[tokio::test]
+    async fn test_get_token_metadata_records_metrics() {
+        let durations = metrics::fetch_duration_seconds().with_label_values(&["onchain"]);
+        let errors = metrics::fetch_errors_total().with_label_values(&["onchain"]);
+        let (durations_before, errors_before) = (durations.get_sample_count(), errors.get());
+
+        let result = get_token_metadata(
+            &http_fetcher(),
+            "data:application/json;base64,not-base64",
+            &[],
+            &[DEFAULT_ARWEAVE_GATEWAY_URI],
+            None,
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert!(durations.get_sample_count() > durations_before);
+        assert!(errors.get() > errors_before);
+    }
+
     #[test]
     fn fetch_direct_json_onchain_metadata() {
         let metadata_json = json!({
@@ -473,6 +1426,344 @@ mod tests {
         assert!(fetched_metadata.metadata_updated_at.is_some());
     }
 
+    fn http_fetcher() -> HttpMetadataFetcher {
+        HttpMetadataFetcher::new(Client::new(), Duration::from_secs(10), "")
+    }
+
+    const INTERSTITIAL: &str = "<!DOCTYPE html><html><body>Too many requests</body></html>";
+
+    /// Serves the given body to every request, and returns the server url.
+    async fn serve(content_type: &'static str, body: &'static str) -> String {
+        TestServer::ok(
+            &[("Content-Type", content_type), ("Connection", "close")],
+            body.as_bytes(),
+        )
+        .await
+        .url("/")
+    }
+
+    #[test]
+    fn test_metadata_content_hash() {
+        let mut metadata = metadata_with_duplicate_traits();
+        let hash = metadata_content_hash(&metadata).unwrap();
+
+        assert_eq!(hash, metadata_content_hash(&metadata.clone()).unwrap());
+
+        metadata.name = Some("Other name".to_string());
+        assert_ne!(hash, metadata_content_hash(&metadata).unwrap());
+    }
+
+    #[test]
+    fn test_normalize_metadata_urls() {
+        let mut metadata = NormalizedMetadata {
+            image: Some("images/1.png".to_string()),
+            external_url: Some(" https://arkproject.dev/token/1 ".to_string()),
+            ..Default::default()
+        };
+
+        normalize_metadata_urls(&mut metadata, "https://example.com/metadata/1.json");
+
+        assert_eq!(
+            metadata.image.as_deref(),
+            Some("https://example.com/metadata/images/1.png")
+        );
+        assert_eq!(
+            metadata.external_url.as_deref(),
+            Some("https://arkproject.dev/token/1")
+        );
+
+        let mut metadata = NormalizedMetadata {
+            image: Some("../1.png".to_string()),
+            ..Default::default()
+        };
+
+        normalize_metadata_urls(&mut metadata, "ipfs://QmHash/metadata/1.json");
+
+        assert_eq!(metadata.image.as_deref(), Some("ipfs://QmHash/1.png"));
+    }
+
+    #[test]
+    fn test_normalize_metadata_urls_invalid_values() {
+        for value in [
+            "null",
+            "",
+            "javascript:alert(1)",
+            "not an url",
+            "images/1.png",
+        ] {
+            let mut metadata = NormalizedMetadata {
+                image: Some(value.to_string()),
+                external_url: Some(value.to_string()),
+                ..Default::default()
+            };
+
+            // Relative URLs can't be resolved against on-chain metadata.
+            normalize_metadata_urls(&mut metadata, "data:application/json,{}");
+
+            assert_eq!(metadata.image, None, "{}", value);
+            assert_eq!(metadata.external_url, None, "{}", value);
+        }
+
+        let mut metadata = NormalizedMetadata {
+            image: Some("data:image/svg+xml,<svg></svg>".to_string()),
+            ..Default::default()
+        };
+
+        normalize_metadata_urls(&mut metadata, "data:application/json,{}");
+
+        assert_eq!(
+            metadata.image.as_deref(),
+            Some("data:image/svg+xml,<svg></svg>")
+        );
+    }
+
+    #[test]
+    fn test_apply_normalization_profile() {
+        let raw = json!({
+            "name": "Token #1",
+            "image_url": "images/1.png",
+            "properties": {
+                "attributes": [{ "trait_type": "Hat", "value": "Cap" }]
+            }
+        });
+        let base_metadata = TokenMetadata {
+            raw: raw.to_string(),
+            normalized: normalize_metadata(&raw, ""),
+            ..Default::default()
+        };
+        let base_uri = "https://example.com/metadata/1.json";
+
+        let mut metadata = base_metadata.clone();
+        apply_normalization_profile(&mut metadata, &NormalizationProfile::standard(), base_uri);
+        assert_eq!(metadata.normalized.image, None);
+        assert_eq!(metadata.normalized.attributes, None);
+
+        let profile = NormalizationProfile::default()
+            .with_override("image", "image_url")
+            .with_override("attributes", "properties.attributes")
+            .with_override("description", "missing_key");
+
+        let mut metadata = base_metadata;
+        apply_normalization_profile(&mut metadata, &profile, base_uri);
+
+        assert_eq!(
+            metadata.normalized.image.as_deref(),
+            Some("https://example.com/metadata/images/1.png")
+        );
+        assert_eq!(
+            metadata.normalized.attributes,
+            Some(vec![MetadataAttribute {
+                display_type: None,
+                trait_type: Some("Hat".to_string()),
+                value: MetadataTraitValue::String("Cap".to_string()),
+            }])
+        );
+        assert_eq!(metadata.normalized.name.as_deref(), Some("Token #1"));
+        assert_eq!(metadata.normalized.description, None);
+    }
+
+    #[test]
+    fn test_apply_attributes_path() {
+        let hat = vec![MetadataAttribute {
+            display_type: None,
+            trait_type: Some("Hat".to_string()),
+            value: MetadataTraitValue::String("Cap".to_string()),
+        }];
+
+        for (raw, path) in [
+            (
+                json!({
+                    "name": "Token #1",
+                    "metadata": { "attributes": [{ "trait_type": "Hat", "value": "Cap" }] }
+                }),
+                "metadata.attributes",
+            ),
+            (
+                json!({
+                    "data": { "name": "Token #1", "attributes": [{ "trait_type": "Hat", "value": "Cap" }] }
+                }),
+                "/data/attributes",
+            ),
+            (
+                json!({
+                    "data": [{ "attributes": [{ "trait_type": "Hat", "value": "Cap" }] }]
+                }),
+                "/data/0/attributes",
+            ),
+        ] {
+            let mut metadata = TokenMetadata {
+                raw: raw.to_string(),
+                normalized: normalize_metadata(&raw, ""),
+                ..Default::default()
+            };
+            assert_eq!(metadata.normalized.attributes, None);
+
+            apply_attributes_path(&mut metadata, path);
+            assert_eq!(
+                metadata.normalized.attributes.as_ref(),
+                Some(&hat),
+                "{}",
+                path
+            );
+        }
+
+        // The standard attributes are kept when the path is missing.
+        let raw = json!({ "attributes": [{ "trait_type": "Hat", "value": "Cap" }] });
+        let mut metadata = TokenMetadata {
+            raw: raw.to_string(),
+            normalized: normalize_metadata(&raw, ""),
+            ..Default::default()
+        };
+        apply_attributes_path(&mut metadata, "metadata.attributes");
+        assert_eq!(metadata.normalized.attributes, Some(hat));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_metadata_html_interstitial() {
+        // Even with a JSON content type, an HTML body is rejected.
+        for content_type in ["text/html", "application/json"] {
+            let uri = serve(content_type, INTERSTITIAL).await;
+            let metadata = fetch_metadata(&uri, &uri, &http_fetcher()).await;
+            assert!(metadata.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_metadata_html_body_is_not_json() {
+        let uri = serve("text/html", INTERSTITIAL).await;
+        let error = fetch_metadata(&uri, &uri, &http_fetcher())
+            .await
+            .unwrap_err();
+        assert!(is_not_json_error(&error));
+
+        let uri = serve("text/plain", "Rate limit exceeded").await;
+        let error = fetch_metadata(&uri, &uri, &http_fetcher())
+            .await
+            .unwrap_err();
+        assert!(is_not_json_error(&error));
+    }
+
+    #[tokio::test]
+    async fn test_get_token_metadata_http_ipfs_gateway_fallback() {
+        let public_gateway = serve("text/html", INTERSTITIAL).await;
+        let gateway = serve("application/json", r#"{"name":"Duck"}"#).await;
+
+        let metadata = get_token_metadata(
+            &http_fetcher(),
+            &format!("{}ipfs/QmHash/1.json", public_gateway),
+            &[&gateway],
+            &[DEFAULT_ARWEAVE_GATEWAY_URI],
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(metadata.normalized.name, Some("Duck".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_get_token_metadata_gateway_fallback() {
+        let fetcher = http_fetcher();
+        let interstitial_gateway = serve("text/html", INTERSTITIAL).await;
+        let gateway = serve("application/json", r#"{"name":"Duck"}"#).await;
+
+        let metadata = get_token_metadata(
+            &fetcher,
+            "ipfs://QmHash",
+            &[&interstitial_gateway, &gateway],
+            &[DEFAULT_ARWEAVE_GATEWAY_URI],
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(metadata.normalized.name, Some("Duck".to_string()));
+        assert_eq!(metadata.gateway_uri, Some(gateway.clone()));
+
+        let metadata = get_token_metadata(
+            &fetcher,
+            "ipfs://QmHash",
+            &[&interstitial_gateway],
+            &[DEFAULT_ARWEAVE_GATEWAY_URI],
+            None,
+        )
+        .await;
+
+        assert!(metadata.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_token_metadata_from_cache() {
+        let dir = std::env::temp_dir().join(format!("ark-metadata-utils-{}", std::process::id()));
+        let cache = MetadataCache::new(&dir);
+
+        // Only fetched once, then read from the cache.
+        let mut fetcher = MockMetadataFetcher::default();
+        fetcher
+            .expect_fetch()
+            .times(1)
+            .returning(|_| Ok(r#"{"name":"Duck"}"#.to_string()));
+
+        for _ in 0..2 {
+            let metadata = get_token_metadata(
+                &fetcher,
+                "ipfs://QmCachedHash/1.json",
+                &["https://ipfs.example/ipfs/"],
+                &[DEFAULT_ARWEAVE_GATEWAY_URI],
+                Some(&cache),
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(metadata.normalized.name, Some("Duck".to_string()));
+        }
+
+        cache.clear().await.unwrap();
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_token_metadata_arweave_gateway_rotation() {
+        // Served as JSON, but not a JSON document.
+        let broken_gateway = serve("application/json", "<html></html>").await;
+        let gateway = serve("application/json", r#"{"name":"Duck","image":"1.png"}"#).await;
+
+        let metadata = get_token_metadata(
+            &http_fetcher(),
+            "ar://txid/1.json",
+            &[],
+            &[&broken_gateway, &gateway],
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(metadata.normalized.name, Some("Duck".to_string()));
+        assert_eq!(metadata.gateway_uri, Some(gateway));
+        // Resolved against the canonical URI, not the gateway.
+        assert_eq!(
+            metadata.normalized.image,
+            Some("ar://txid/1.png".to_string())
+        );
+    }
+
+    #[test]
+    fn test_decode_data_uri() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg"></svg>"#;
+
+        let uri = format!("data:image/svg+xml;base64,{}", base64_encode(svg));
+        let (mime_type, content) = decode_data_uri(&uri).unwrap();
+        assert_eq!(mime_type, "image/svg+xml");
+        assert_eq!(content, svg.as_bytes());
+
+        let uri = format!("data:image/svg+xml;utf8,{}", urlencoding::encode(svg));
+        let (mime_type, content) = decode_data_uri(&uri).unwrap();
+        assert_eq!(mime_type, "image/svg+xml");
+        assert_eq!(content, svg.as_bytes());
+
+        assert!(decode_data_uri("https://example.com/image.svg").is_err());
+    }
+
     #[test]
     fn handle_invalid_onchain_metadata_format() {
         let invalid_uri = "data:application/json;utf8,invalid_json";
@@ -481,4 +1772,43 @@ mod tests {
 
         assert!(result.is_err() || result.unwrap().normalized.name.is_none());
     }
+
+    #[test]
+    fn test_compress_raw_metadata() {
+        let raw = format!(
+            r#"{{"name":"Duck","image":"data:image/svg+xml,{}"}}"#,
+            "<rect/>".repeat(1000)
+        );
+
+        let compressed = compress_raw_metadata(&raw).unwrap();
+        assert!(compressed.len() < raw.len() / 10);
+        assert_eq!(decompress_raw_metadata(&compressed).unwrap(), raw);
+        assert!(decompress_raw_metadata(raw.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_raw_metadata_value() {
+        let raw = r#"{"name":"Duck","attributes":[{"trait_type":"Level","value":3},{"trait_type":"Tags","value":["a",["b",{"c":null}]]}],"stats":{"speed":1.5,"balance":-42,"supply":18446744073709551615}}"#;
+
+        let value = raw_metadata_value(raw).unwrap();
+        assert_eq!(value["attributes"][0]["value"], json!(3));
+        assert_eq!(value["attributes"][1]["value"][1][1], json!({ "c": null }));
+        assert_eq!(value["stats"]["speed"].as_f64(), Some(1.5));
+        assert_eq!(value["stats"]["balance"].as_i64(), Some(-42));
+        assert_eq!(value["stats"]["supply"].as_u64(), Some(u64::MAX));
+        // Serializing the value gives back the same document.
+        assert_eq!(
+            value,
+            serde_json::from_str::<serde_json::Value>(&value.to_string()).unwrap()
+        );
+
+        // Integers larger than `u64` are only kept as a string.
+        assert!(
+            raw_metadata_value(r#"{"value":340282366920938463463374607431768211455}"#).is_none()
+        );
+        assert!(raw_metadata_value(r#"{"nested":[{"value":1e300}]}"#).is_none());
+        assert!(raw_metadata_value(r#"{"value":9007199254740991.0}"#).is_some());
+        assert!(raw_metadata_value("<html></html>").is_none());
+        assert_eq!(raw_metadata_value("[]"), Some(json!([])));
+    }
 }
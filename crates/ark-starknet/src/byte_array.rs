@@ -14,6 +14,8 @@ use std::string::FromUtf8Error;
 
 use starknet::core::types::FieldElement;
 
+use crate::cairo_string_parser::felt_to_usize;
+
 const MAX_WORD_LEN: usize = 31;
 
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
@@ -71,6 +73,56 @@ impl ByteArray {
         }
     }
 
+    /// Deserializes a `ByteArray` from the felts returned by a Cairo 1 contract:
+    /// `[data_len, data_word, ..., pending_word, pending_word_len]`.
+    /// Returns `None` if the felts don't match this layout.
+    ///
+    /// # Arguments
+    ///
+    /// * `felts` - The serialized `ByteArray`.
+    pub fn from_felts(felts: &[FieldElement]) -> Option<Self> {
+        let data_len = felt_to_usize(felts.first()?)?;
+
+        if felts.len() != data_len.checked_add(3)? {
+            return None;
+        }
+
+        let pending_word_len = felt_to_usize(&felts[felts.len() - 1])?;
+        if pending_word_len >= MAX_WORD_LEN {
+            return None;
+        }
+
+        Some(Self {
+            data: felts[1..felts.len() - 2].to_vec(),
+            pending_word: felts[felts.len() - 2],
+            pending_word_len,
+        })
+    }
+
+    /// Serializes the `ByteArray` the same way as Cairo does.
+    pub fn to_felts(&self) -> Vec<FieldElement> {
+        let mut felts = vec![FieldElement::from(self.data.len())];
+        felts.extend(&self.data);
+        felts.push(self.pending_word);
+        felts.push(FieldElement::from(self.pending_word_len));
+        felts
+    }
+
+    /// Returns the bytes of the string, without checking their encoding.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.data.len() * MAX_WORD_LEN + self.pending_word_len);
+
+        for d in &self.data {
+            bytes.extend_from_slice(&d.to_bytes_be()[1..]);
+        }
+
+        let pending_word_len = self.pending_word_len.min(MAX_WORD_LEN);
+        let pending_word = self.pending_word.to_bytes_be();
+        bytes.extend_from_slice(&pending_word[1 + MAX_WORD_LEN - pending_word_len..]);
+
+        bytes
+    }
+
     /// Converts `ByteArray` instance into a UTF-8 encoded string on success.
     /// Returns error if the `ByteArray` contains an invalid UTF-8 string.
     pub fn to_string(&self) -> Result<String, FromUtf8Error> {
@@ -129,6 +181,45 @@ mod tests {
 
     use super::ByteArray;
 
+    #[test]
+    fn test_felts_serialization() {
+        let b = ByteArray::from_string(
+            "ipfs://bafybeieocsz5txpxgp7zrx7fexrdneyj4kzq2v4x5g3asx45m65cx7rgxu/0",
+        );
+
+        let felts = b.to_felts();
+        assert_eq!(felts.len(), b.data.len() + 3);
+        assert_eq!(ByteArray::from_felts(&felts), Some(b));
+    }
+
+    #[test]
+    fn test_from_felts_invalid_layout() {
+        // Short string.
+        assert_eq!(
+            ByteArray::from_felts(&[FieldElement::from_hex_be("0x68747470").unwrap()]),
+            None
+        );
+        // Data length mismatch.
+        assert_eq!(
+            ByteArray::from_felts(&[FieldElement::TWO, FieldElement::ONE, FieldElement::ONE]),
+            None
+        );
+        // Pending word too long.
+        assert_eq!(
+            ByteArray::from_felts(&[
+                FieldElement::ZERO,
+                FieldElement::ONE,
+                FieldElement::from(31_u32)
+            ]),
+            None
+        );
+        // Empty string.
+        assert_eq!(
+            ByteArray::from_felts(&[FieldElement::ZERO, FieldElement::ZERO, FieldElement::ZERO]),
+            Some(ByteArray::default())
+        );
+    }
+
     #[test]
     fn test_from_string_empty_string_default() {
         let b = ByteArray::from_string("");
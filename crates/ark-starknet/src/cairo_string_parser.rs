@@ -1,6 +1,5 @@
 use anyhow::Result;
 use starknet::core::{types::FieldElement, utils::parse_cairo_short_string};
-use std::ops::Add;
 
 use crate::byte_array::ByteArray;
 
@@ -31,53 +30,134 @@ pub fn parse_cairo_string(field_elements: Vec<FieldElement>) -> Result<String, P
             }
             None => Err(ParseError::NoValueFound),
         },
-        // If the long_string has more than one FieldElement, parse each FieldElement sequentially
-        // and concatenate their results.
-        len => {
-            let first_element = field_elements.first().unwrap();
-
-            let a_size = first_element
-                .add(FieldElement::ONE)
+        // If the long_string has more than one FieldElement, its layout is detected.
+        _ => match detect_layout(&field_elements) {
+            Layout::Array => concat_short_strings(&field_elements[1..]),
+            Layout::ByteArray => ByteArray::from_felts(&field_elements)
+                .ok_or(ParseError::ByteArrayError)?
                 .to_string()
-                .parse::<usize>()
-                .unwrap();
-
-            if len == a_size {
-                let results: Result<Vec<_>, _> = field_elements[1..]
-                    .iter()
-                    .map(parse_cairo_short_string)
-                    .collect();
-
-                results
-                    .map(|strings| strings.concat())
-                    .map_err(|_| ParseError::ShortStringError)
-            } else {
-                let data = field_elements[1..field_elements.len() - 2].to_vec();
-                let pending_word = field_elements[field_elements.len() - 2];
-                let pending_word_len = field_elements[field_elements.len() - 1];
-                let pending_word_len = pending_word_len.to_string().parse::<usize>().unwrap();
-
-                let byte_array = ByteArray {
-                    data,
-                    pending_word,
-                    pending_word_len,
-                };
-
-                byte_array
-                    .to_string()
-                    .map_err(|_| ParseError::ByteArrayError)
-            }
-        }
+                .map_err(|_| ParseError::ByteArrayError),
+            Layout::ShortStrings => concat_short_strings(&field_elements),
+        },
+    }
+}
+
+/// Layout of a Cairo string made of several FieldElements.
+#[derive(Debug, PartialEq)]
+enum Layout {
+    /// Array<felt252>: [array_len, short_string, ...]
+    Array,
+    /// ByteArray: [data_len, data_word, ..., pending_word, pending_word_len]
+    ByteArray,
+    /// Short strings without any length prefix.
+    ShortStrings,
+}
+
+/// Identifies the layout of a string of several FieldElements
+/// from the first one, which is a length for arrays.
+fn detect_layout(field_elements: &[FieldElement]) -> Layout {
+    let len = field_elements.len();
+    let first_value = field_elements.first().and_then(felt_to_usize);
+
+    if first_value.and_then(|n| n.checked_add(1)) == Some(len) {
+        Layout::Array
+    } else if first_value.and_then(|n| n.checked_add(3)) == Some(len) {
+        Layout::ByteArray
+    } else {
+        Layout::ShortStrings
+    }
+}
+
+/// Value returned by `parse_cairo_string_lossy` for the strings which can't be printed.
+pub const UNDEFINED_STRING: &str = "undefined";
+
+/// Parses a Cairo string like `parse_cairo_string`, without failing on the
+/// bytes which are not valid UTF-8 or ASCII (some contracts return binary
+/// data as `name` or `symbol`).
+///
+/// The invalid sequences are replaced with `U+FFFD`, and the trailing NULs
+/// are trimmed. Returns `UNDEFINED_STRING` if the value is empty, contains
+/// control characters, or is mostly made of invalid sequences.
+pub fn parse_cairo_string_lossy(field_elements: &[FieldElement]) -> String {
+    let bytes = match field_elements.len() {
+        0 => vec![],
+        1 => short_string_bytes(&field_elements[0]),
+        _ => match detect_layout(field_elements) {
+            Layout::Array => field_elements[1..]
+                .iter()
+                .flat_map(short_string_bytes)
+                .collect(),
+            Layout::ByteArray => match ByteArray::from_felts(field_elements) {
+                Some(byte_array) => byte_array.to_bytes(),
+                None => field_elements.iter().flat_map(short_string_bytes).collect(),
+            },
+            Layout::ShortStrings => field_elements.iter().flat_map(short_string_bytes).collect(),
+        },
+    };
+
+    let value = String::from_utf8_lossy(&bytes);
+    let value = value.trim_end_matches('\0');
+
+    let invalid_chars = value
+        .chars()
+        .filter(|c| *c == char::REPLACEMENT_CHARACTER)
+        .count();
+    if value.is_empty()
+        || value.chars().any(char::is_control)
+        || invalid_chars * 2 > value.chars().count()
+    {
+        return UNDEFINED_STRING.to_string();
     }
+
+    value.to_string()
+}
+
+/// Bytes of a short string, without the leading zeros of the felt.
+fn short_string_bytes(felt: &FieldElement) -> Vec<u8> {
+    let bytes = felt.to_bytes_be();
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    bytes[start..].to_vec()
+}
+
+/// Returns the value of a felt used as a length, if it fits in a `usize`.
+pub(crate) fn felt_to_usize(felt: &FieldElement) -> Option<usize> {
+    felt.to_string().parse::<usize>().ok()
+}
+
+fn concat_short_strings(field_elements: &[FieldElement]) -> Result<String, ParseError> {
+    field_elements
+        .iter()
+        .map(parse_cairo_short_string)
+        .collect::<Result<Vec<_>, _>>()
+        .map(|strings| strings.concat())
+        .map_err(|_| ParseError::ShortStringError)
 }
 
 #[cfg(test)]
 mod tests {
     use crate::cairo_string_parser::ParseError;
 
-    use super::parse_cairo_string;
+    use super::{
+        detect_layout, parse_cairo_string, parse_cairo_string_lossy, Layout, UNDEFINED_STRING,
+    };
+    use crate::byte_array::ByteArray;
     use starknet::core::types::FieldElement;
 
+    #[test]
+    fn should_detect_layout() {
+        let hello = FieldElement::from_hex_be("0x68656c6c6f").unwrap();
+
+        assert_eq!(
+            detect_layout(&[FieldElement::TWO, hello, hello]),
+            Layout::Array
+        );
+        assert_eq!(
+            detect_layout(&[FieldElement::ZERO, hello, FieldElement::from(5_u8)]),
+            Layout::ByteArray
+        );
+        assert_eq!(detect_layout(&[hello, hello]), Layout::ShortStrings);
+    }
+
     #[test]
     fn should_handle_single_field_element() {
         let long_string = vec![FieldElement::from_hex_be("0x68").unwrap()];
@@ -259,4 +339,69 @@ mod tests {
         let value = result.unwrap();
         assert!(value == "ipfs://bafybeieocsz5txpxgp7zrx7fexrdneyj4kzq2v4x5g3asx45m65cx7rgxu/0");
     }
+
+    #[test]
+    fn should_parse_byte_array_to_ipfs_url() {
+        let uri = "ipfs://bafybeieocsz5txpxgp7zrx7fexrdneyj4kzq2v4x5g3asx45m65cx7rgxu/0";
+        let long_string = ByteArray::from_string(uri).to_felts();
+
+        assert_eq!(parse_cairo_string(long_string).unwrap(), uri);
+    }
+
+    #[test]
+    fn should_parse_short_strings_without_length() {
+        let long_string = vec![
+            FieldElement::from_hex_be("0x68747470733a2f2f").unwrap(), // https://
+            FieldElement::from_hex_be("0x61726b2e696f").unwrap(),     // ark.io
+        ];
+
+        assert_eq!(parse_cairo_string(long_string).unwrap(), "https://ark.io");
+    }
+
+    #[test]
+    fn should_parse_invalid_utf8_lossy() {
+        // "Ark", an invalid UTF-8 sequence and trailing NULs.
+        let name = FieldElement::from_hex_be("0x41726bff0000").unwrap();
+        assert!(parse_cairo_string(vec![name]).is_err());
+        assert_eq!(parse_cairo_string_lossy(&[name]), "Ark\u{FFFD}");
+
+        let name = ByteArray {
+            data: vec![],
+            pending_word: FieldElement::from_hex_be("0x41726bc3").unwrap(),
+            pending_word_len: 4,
+        };
+        assert_eq!(parse_cairo_string_lossy(&name.to_felts()), "Ark\u{FFFD}");
+
+        let name = ByteArray::from_string("Ark Ducks");
+        assert_eq!(parse_cairo_string_lossy(&name.to_felts()), "Ark Ducks");
+    }
+
+    #[test]
+    fn should_return_undefined_for_unprintable_strings() {
+        for felts in [
+            vec![],
+            vec![FieldElement::ZERO],
+            // Mostly invalid sequences.
+            vec![FieldElement::from_hex_be("0xfffefd41").unwrap()],
+            // Control characters.
+            vec![FieldElement::from_hex_be("0x41014142").unwrap()],
+        ] {
+            assert_eq!(parse_cairo_string_lossy(&felts), UNDEFINED_STRING);
+        }
+    }
+
+    #[test]
+    fn should_return_error_for_invalid_byte_array() {
+        // The pending word length is out of range.
+        let long_string = vec![
+            FieldElement::ZERO,
+            FieldElement::from_hex_be("0x68").unwrap(),
+            FieldElement::from(40_u32),
+        ];
+
+        assert!(matches!(
+            parse_cairo_string(long_string),
+            Err(ParseError::ByteArrayError)
+        ));
+    }
 }
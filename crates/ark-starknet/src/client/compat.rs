@@ -0,0 +1,154 @@
+//! Tolerance of the response shapes of the RPC nodes.
+//!
+//! The responses are deserialized into the JSON-RPC v0.6 types of starknet-rs.
+//! The nodes on older spec versions, and some providers, return slightly
+//! different shapes, normalized by `normalize_response` before being deserialized:
+//!
+//! - the `id` of the response given as a string;
+//! - the felts prefixed with `0X`;
+//! - the `actual_fee` of the receipts given as a felt (v0.5 and before)
+//!   instead of an amount and a unit;
+//! - the receipts without `execution_resources` (v0.4 and before);
+//! - the `l1_gas_price` of the blocks without `price_in_fri` (v0.5 and before).
+//!
+//! The fields added by v0.7, like the `l1_data_gas_price` of the blocks or
+//! the `data_availability` of the receipts, are ignored by the deserialization.
+use serde_json::{json, Map, Value};
+use starknet::providers::jsonrpc::JsonRpcMethod;
+
+/// Spec versions whose responses are supported, as returned by `starknet_specVersion`.
+pub const SUPPORTED_SPEC_VERSIONS: [&str; 3] = ["0.5", "0.6", "0.7"];
+
+/// Returns true if the given `starknet_specVersion` (like `0.7.1`) is supported.
+pub fn is_supported_spec_version(version: &str) -> bool {
+    SUPPORTED_SPEC_VERSIONS.iter().any(|supported| {
+        version == *supported
+            || version
+                .strip_prefix(supported)
+                .map_or(false, |patch| patch.starts_with('.'))
+    })
+}
+
+/// Normalizes the JSON-RPC response of the given method into its v0.6 shape.
+pub fn normalize_response(method: JsonRpcMethod, response: &mut Value) {
+    if let Some(id) = response.get("id").and_then(Value::as_str) {
+        if let Ok(id) = id.parse::<u64>() {
+            response["id"] = json!(id);
+        }
+    }
+
+    let result = match response.get_mut("result") {
+        Some(result) => result,
+        None => return,
+    };
+
+    normalize_felts(result);
+
+    match method {
+        JsonRpcMethod::GetTransactionReceipt => {
+            if let Some(receipt) = result.as_object_mut() {
+                normalize_receipt(receipt);
+            }
+        }
+        JsonRpcMethod::GetBlockWithTxHashes | JsonRpcMethod::GetBlockWithTxs => {
+            if let Some(gas_price) = result
+                .get_mut("l1_gas_price")
+                .and_then(Value::as_object_mut)
+            {
+                gas_price
+                    .entry("price_in_fri")
+                    .or_insert_with(|| json!("0x0"));
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Lowercases the `0X` prefix of the felts.
+fn normalize_felts(value: &mut Value) {
+    match value {
+        Value::String(s) if s.starts_with("0X") => {
+            s.replace_range(..2, "0x");
+        }
+        Value::Array(values) => values.iter_mut().for_each(normalize_felts),
+        Value::Object(fields) => fields.values_mut().for_each(normalize_felts),
+        _ => (),
+    }
+}
+
+fn normalize_receipt(receipt: &mut Map<String, Value>) {
+    if let Some(fee) = receipt.get_mut("actual_fee").filter(|fee| fee.is_string()) {
+        // The fees were only paid in WEI before v0.6.
+        let amount = fee.take();
+        *fee = json!({ "amount": amount, "unit": "WEI" });
+    }
+
+    // Not used by the indexer, only required by the deserialization.
+    receipt
+        .entry("execution_resources")
+        .or_insert_with(|| json!({ "steps": 0 }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_supported_spec_version() {
+        assert!(is_supported_spec_version("0.6.0"));
+        assert!(is_supported_spec_version("0.7"));
+        assert!(!is_supported_spec_version("0.60.0"));
+        assert!(!is_supported_spec_version("0.8.0"));
+    }
+
+    #[test]
+    fn test_normalize_v05_receipt() {
+        let mut response = json!({
+            "jsonrpc": "2.0",
+            "id": "1",
+            "result": {
+                "type": "INVOKE",
+                "transaction_hash": "0X1A",
+                "actual_fee": "0x2",
+                "events": []
+            }
+        });
+
+        normalize_response(JsonRpcMethod::GetTransactionReceipt, &mut response);
+
+        assert_eq!(response["id"], json!(1));
+        assert_eq!(response["result"]["transaction_hash"], json!("0x1A"));
+        assert_eq!(
+            response["result"]["actual_fee"],
+            json!({ "amount": "0x2", "unit": "WEI" })
+        );
+        assert_eq!(
+            response["result"]["execution_resources"],
+            json!({ "steps": 0 })
+        );
+    }
+
+    #[test]
+    fn test_normalize_keeps_v06_and_v07_shapes() {
+        let receipt = json!({
+            "actual_fee": { "amount": "0x2", "unit": "FRI" },
+            "execution_resources": { "steps": 10, "data_availability": { "l1_gas": 0, "l1_data_gas": 128 } }
+        });
+        let mut response = json!({ "jsonrpc": "2.0", "id": 1, "result": receipt.clone() });
+
+        normalize_response(JsonRpcMethod::GetTransactionReceipt, &mut response);
+        assert_eq!(response["result"], receipt);
+
+        let mut response = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": { "l1_gas_price": { "price_in_wei": "0x1" } }
+        });
+
+        normalize_response(JsonRpcMethod::GetBlockWithTxHashes, &mut response);
+        assert_eq!(
+            response["result"]["l1_gas_price"],
+            json!({ "price_in_wei": "0x1", "price_in_fri": "0x0" })
+        );
+    }
+}
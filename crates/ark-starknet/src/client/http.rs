@@ -1,13 +1,19 @@
 //! Starknet Client implementation using `JsonRpcHttp` provider.
+use super::compat::{is_supported_spec_version, SUPPORTED_SPEC_VERSIONS};
+use super::rate_limiter::init_global_rate_limit_from_env;
+use super::transport::{RpcTransport, RpcTransportError};
 use super::{StarknetClient, StarknetClientError};
-use crate::EventResult;
+use crate::metrics::observe_rpc;
+use crate::{BlockHeader, EventResult};
 use async_trait::async_trait;
 use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use starknet::{
     core::types::*,
-    providers::{jsonrpc::HttpTransport, AnyProvider, JsonRpcClient, Provider, ProviderError},
+    providers::{jsonrpc::JsonRpcClientError, JsonRpcClient, Provider, ProviderError},
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use url::Url;
 
 const INPUT_TOO_SHORT: &str = "0x496e70757420746f6f2073686f727420666f7220617267756d656e7473";
@@ -15,24 +21,312 @@ const INPUT_TOO_LONG: &str = "0x496e70757420746f6f206c6f6e6720666f7220617267756d
 const FAILED_DESERIALIZE: &str = "0x4661696c656420746f20646573657269616c697a6520706172616d202331";
 const ENTRYPOINT_NOT_FOUND: &str = "not found in contract";
 
+/// Messages of the errors returned by the nodes which don't have
+/// the state of the requested block, compared in lowercase.
+const BLOCK_NOT_AVAILABLE_MESSAGES: [&str; 4] = [
+    "block not found",
+    "pruned",
+    "state not available",
+    "state is not available",
+];
+
+/// Codes of the JSON-RPC errors which may succeed when retried: the internal
+/// errors of the node, and the `limit exceeded` of some RPC providers.
+const RETRIABLE_JSON_RPC_CODES: [i64; 2] = [-32603, -32005];
+
+/// Default number of attempts of `call_contract` on retriable errors.
+pub const DEFAULT_CALL_MAX_ATTEMPTS: u32 = 5;
+/// Default delay before the first retry of `call_contract`, doubled at each retry.
+pub const DEFAULT_CALL_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Environment variable with the headers sent with every RPC request
+/// by the clients created with `StarknetClient::new`, as `parse_headers` expects them.
+pub const RPC_HEADERS_ENV_VAR: &str = "STARKNET_RPC_HEADERS";
+
+/// Parses headers given as `Name: value` pairs separated by `;`,
+/// like `Authorization: Bearer xxx;x-api-key: yyy`.
+///
+/// The values are marked as sensitive, and are never shown when the
+/// headers are logged. For the same reason, errors only contain the header names.
+pub fn parse_headers(value: &str) -> Result<HeaderMap, StarknetClientError> {
+    let mut headers = HeaderMap::new();
+
+    for pair in value.split(';').filter(|p| !p.trim().is_empty()) {
+        let (name, value) = pair.split_once(':').ok_or_else(|| {
+            StarknetClientError::Other("Header must be formatted as `Name: value`".to_string())
+        })?;
+
+        let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| {
+            StarknetClientError::Other(format!("Invalid header name: {}", name.trim()))
+        })?;
+
+        let mut value = HeaderValue::from_str(value.trim()).map_err(|_| {
+            StarknetClientError::Other(format!("Invalid value for header {}", name))
+        })?;
+        value.set_sensitive(true);
+
+        headers.insert(name, value);
+    }
+
+    Ok(headers)
+}
+
 #[derive(Debug)]
 pub struct StarknetClientHttp {
     /// Provider is kept public to allow custom reuse of
     /// the raw provider elsewhere.
-    pub provider: AnyProvider,
+    pub provider: JsonRpcClient<RpcTransport>,
+    call_max_attempts: u32,
+    call_retry_delay: Duration,
+    latest_fallback: bool,
+    latest_contracts: HashSet<FieldElement>,
+}
+
+impl StarknetClientHttp {
+    /// Creates a client sending the given headers with every RPC request,
+    /// like the `Authorization` or `x-api-key` required by some RPC providers.
+    /// API keys given as query parameters can be directly set in the url.
+    pub fn with_headers(
+        rpc_url: &str,
+        mut headers: HeaderMap,
+    ) -> Result<StarknetClientHttp, StarknetClientError> {
+        let rpc_url = Url::parse(rpc_url).map_err(|_| {
+            StarknetClientError::Other("Can't parse RPC url to create the provider".to_string())
+        })?;
+
+        // Ensures the keys are never logged, even if not parsed with `parse_headers`.
+        headers.values_mut().for_each(|v| v.set_sensitive(true));
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(|e| StarknetClientError::Other(format!("Can't build HTTP client: {}", e)))?;
+
+        let provider = JsonRpcClient::new(RpcTransport::new(rpc_url, client));
+
+        Ok(Self {
+            provider,
+            call_max_attempts: DEFAULT_CALL_MAX_ATTEMPTS,
+            call_retry_delay: DEFAULT_CALL_RETRY_DELAY,
+            latest_fallback: false,
+            latest_contracts: HashSet::new(),
+        })
+    }
+
+    /// Sets the retries of `call_contract` on the retriable errors (see `is_retriable`):
+    /// at most `max_attempts` calls, the delay between them starting at `retry_delay`
+    /// and doubling at each retry, unless the RPC provider asks for another one
+    /// with a `Retry-After` header.
+    pub fn with_call_retries(mut self, max_attempts: u32, retry_delay: Duration) -> Self {
+        self.call_max_attempts = max_attempts.max(1);
+        self.call_retry_delay = retry_delay;
+        self
+    }
+
+    /// Retries at the `latest` block the calls of `call_contract` failing with
+    /// `StarknetClientError::BlockNotAvailable`, instead of returning the error.
+    ///
+    /// Only suited to the values which don't change over time, as the value
+    /// returned is not the one of the requested block anymore.
+    pub fn with_latest_fallback(mut self, latest_fallback: bool) -> Self {
+        self.latest_fallback = latest_fallback;
+        self
+    }
+
+    /// Reads the given contracts at the `latest` block, whatever the block
+    /// requested to `call_contract`, like the collections whose metadata is immutable.
+    pub fn with_latest_contracts(mut self, contracts: HashSet<FieldElement>) -> Self {
+        self.latest_contracts = contracts;
+        self
+    }
+
+    /// Returns the JSON-RPC spec version of the node (`starknet_specVersion`),
+    /// warning if its responses are not supported, see `compat`.
+    pub async fn spec_version(&self) -> Result<String, StarknetClientError> {
+        let version = observe_rpc("starknet_specVersion", self.provider.spec_version())
+            .await
+            .map_err(StarknetClientError::Provider)?;
+
+        if !is_supported_spec_version(&version) {
+            tracing::warn!(
+                "RPC spec version {} is not supported (supported: {}), some responses may not be parsed",
+                version,
+                SUPPORTED_SPEC_VERSIONS.join(", ")
+            );
+        }
+
+        Ok(version)
+    }
+
+    /// Returns the delay before retrying a call which failed with the given error,
+    /// or `None` if it must not be retried, see `is_retriable`.
+    fn retry_delay(&self, error: &ProviderError, attempt: u32) -> Option<Duration> {
+        if !is_retriable(error) {
+            return None;
+        }
+
+        match transport_error(error) {
+            Some(RpcTransportError::RateLimited {
+                retry_after: Some(retry_after),
+            }) => Some(*retry_after),
+            _ => Some(
+                self.call_retry_delay
+                    .saturating_mul(2u32.saturating_pow(attempt)),
+            ),
+        }
+    }
+
+    /// Calls the contract at the given block, with the retries of `with_call_retries`.
+    async fn call_contract_at(
+        &self,
+        contract_address: FieldElement,
+        selector: FieldElement,
+        calldata: Vec<FieldElement>,
+        block: BlockId,
+    ) -> Result<Vec<FieldElement>, StarknetClientError> {
+        let mut attempt = 0;
+
+        let r = loop {
+            let r = observe_rpc(
+                "starknet_call",
+                self.provider.call(
+                    FunctionCall {
+                        contract_address,
+                        entry_point_selector: selector,
+                        calldata: calldata.clone(),
+                    },
+                    block,
+                ),
+            )
+            .await;
+
+            attempt += 1;
+
+            match r {
+                Err(ref e) if attempt < self.call_max_attempts => {
+                    match self.retry_delay(e, attempt - 1) {
+                        Some(delay) => {
+                            tracing::debug!(
+                                "call_contract attempt {} failed ({}), retrying in {:?}",
+                                attempt,
+                                e,
+                                delay
+                            );
+                            tokio::time::sleep(delay).await;
+                        }
+                        None => break r,
+                    }
+                }
+                _ => break r,
+            }
+        };
+
+        match r {
+            Ok(felts) => Ok(felts),
+            Err(e) => {
+                if let ProviderError::StarknetError(StarknetError::ContractError(ref data)) = e {
+                    let s = data.revert_error.clone();
+                    if s.contains(ENTRYPOINT_NOT_FOUND) {
+                        Err(StarknetClientError::EntrypointNotFound(s))
+                    } else if s.contains(INPUT_TOO_SHORT) || s.contains(FAILED_DESERIALIZE) {
+                        Err(StarknetClientError::InputTooShort)
+                    } else if s.contains(INPUT_TOO_LONG) {
+                        Err(StarknetClientError::InputTooLong)
+                    } else {
+                        Err(StarknetClientError::Contract(s))
+                    }
+                } else if !matches!(block, BlockId::Tag(_)) && is_block_not_available(&e) {
+                    Err(StarknetClientError::BlockNotAvailable(e.to_string()))
+                } else {
+                    Err(StarknetClientError::Provider(e))
+                }
+            }
+        }
+    }
+}
+
+/// Returns true if the error is returned by a node which doesn't have the
+/// state of the requested block (unknown or pruned), see `BLOCK_NOT_AVAILABLE_MESSAGES`.
+fn is_block_not_available(error: &ProviderError) -> bool {
+    match error {
+        ProviderError::StarknetError(StarknetError::BlockNotFound) => true,
+        ProviderError::Other(e) => match e
+            .as_any()
+            .downcast_ref::<JsonRpcClientError<RpcTransportError>>()
+        {
+            Some(JsonRpcClientError::JsonRpcError(e)) => {
+                let message = e.message.to_lowercase();
+                BLOCK_NOT_AVAILABLE_MESSAGES
+                    .iter()
+                    .any(|m| message.contains(m))
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Returns true if a request which failed with the given error may succeed
+/// when retried: timeouts, connection errors, rate limiting (`429` or
+/// `ProviderError::RateLimited`), server errors (`5xx`) and internal errors
+/// of the node.
+///
+/// The other errors would be returned again, and are terminal: contract
+/// reverts, invalid params, not found errors (contract, class, block...),
+/// the states not available on the node and the responses which can't be parsed.
+pub fn is_retriable(error: &ProviderError) -> bool {
+    match error {
+        ProviderError::RateLimited => true,
+        ProviderError::Other(e) => match e
+            .as_any()
+            .downcast_ref::<JsonRpcClientError<RpcTransportError>>()
+        {
+            Some(JsonRpcClientError::TransportError(e)) => match e {
+                RpcTransportError::Reqwest(e) => {
+                    e.is_timeout() || e.is_connect() || e.is_request() || e.is_body()
+                }
+                RpcTransportError::RateLimited { .. } | RpcTransportError::Server { .. } => true,
+                RpcTransportError::Json(_) => false,
+            },
+            Some(JsonRpcClientError::JsonRpcError(e)) => {
+                RETRIABLE_JSON_RPC_CODES.contains(&e.code) && !is_block_not_available(error)
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Returns the transport error, if any, of an error of the provider.
+pub(super) fn transport_error(error: &ProviderError) -> Option<&RpcTransportError> {
+    match error {
+        ProviderError::Other(e) => match e
+            .as_any()
+            .downcast_ref::<JsonRpcClientError<RpcTransportError>>()
+        {
+            Some(JsonRpcClientError::TransportError(e)) => Some(e),
+            _ => None,
+        },
+        _ => None,
+    }
 }
 
 #[async_trait]
 impl StarknetClient for StarknetClientHttp {
+    /// Creates a client for the given url, sending the headers
+    /// of the `STARKNET_RPC_HEADERS` environment variable, if set.
     ///
+    /// The global rate limit is set from `STARKNET_RPC_REQUESTS_PER_SECOND`,
+    /// if set and no limit was set with `set_global_rate_limit`.
     fn new(rpc_url: &str) -> Result<StarknetClientHttp, StarknetClientError> {
-        let rpc_url = Url::parse(rpc_url).map_err(|_| {
-            StarknetClientError::Other("Can't parse RPC url to create the provider".to_string())
-        })?;
+        init_global_rate_limit_from_env();
 
-        let provider = AnyProvider::JsonRpcHttp(JsonRpcClient::new(HttpTransport::new(rpc_url)));
+        let headers = match std::env::var(RPC_HEADERS_ENV_VAR) {
+            Ok(value) => parse_headers(&value)?,
+            Err(_) => HeaderMap::new(),
+        };
 
-        Ok(Self { provider })
+        Self::with_headers(rpc_url, headers)
     }
 
     /// Transaction receipts don't have `EmittedEvent` but `Event` instead.
@@ -43,11 +337,12 @@ impl StarknetClient for StarknetClientHttp {
         transaction_hash: FieldElement,
         keys: Option<Vec<Vec<FieldElement>>>,
     ) -> Result<Vec<EmittedEvent>, StarknetClientError> {
-        let receipt = self
-            .provider
-            .get_transaction_receipt(transaction_hash)
-            .await
-            .map_err(StarknetClientError::Provider)?;
+        let receipt = observe_rpc(
+            "starknet_getTransactionReceipt",
+            self.provider.get_transaction_receipt(transaction_hash),
+        )
+        .await
+        .map_err(StarknetClientError::Provider)?;
 
         let mut block_hash = FieldElement::MAX;
         let mut block_number = u64::MAX;
@@ -116,11 +411,12 @@ impl StarknetClient for StarknetClientHttp {
     ///
     async fn block_id_to_u64(&self, id: &BlockId) -> Result<u64, StarknetClientError> {
         match id {
-            BlockId::Tag(BlockTag::Latest) => Ok(self
-                .provider
-                .block_number()
-                .await
-                .map_err(StarknetClientError::Provider)?),
+            BlockId::Tag(BlockTag::Latest) => Ok(observe_rpc(
+                "starknet_blockNumber",
+                self.provider.block_number(),
+            )
+            .await
+            .map_err(StarknetClientError::Provider)?),
             BlockId::Number(n) => Ok(*n),
             _ => Err(StarknetClientError::Conversion(
                 "BlockID can´t be converted to u64".to_string(),
@@ -165,11 +461,12 @@ impl StarknetClient for StarknetClientHttp {
 
     ///
     async fn block_time(&self, block: BlockId) -> Result<u64, StarknetClientError> {
-        let block = self
-            .provider
-            .get_block_with_tx_hashes(block)
-            .await
-            .map_err(StarknetClientError::Provider)?;
+        let block = observe_rpc(
+            "starknet_getBlockWithTxHashes",
+            self.provider.get_block_with_tx_hashes(block),
+        )
+        .await
+        .map_err(StarknetClientError::Provider)?;
 
         let timestamp = match block {
             MaybePendingBlockWithTxHashes::Block(block) => block.timestamp,
@@ -184,11 +481,12 @@ impl StarknetClient for StarknetClientHttp {
         &self,
         block: BlockId,
     ) -> Result<(u64, Vec<FieldElement>), StarknetClientError> {
-        let block = self
-            .provider
-            .get_block_with_tx_hashes(block)
-            .await
-            .map_err(StarknetClientError::Provider)?;
+        let block = observe_rpc(
+            "starknet_getBlockWithTxHashes",
+            self.provider.get_block_with_tx_hashes(block),
+        )
+        .await
+        .map_err(StarknetClientError::Provider)?;
 
         let timestamp = match block {
             MaybePendingBlockWithTxHashes::Block(block) => (block.timestamp, block.transactions),
@@ -200,13 +498,39 @@ impl StarknetClient for StarknetClientHttp {
         Ok(timestamp)
     }
 
+    async fn block_header(&self, block: BlockId) -> Result<BlockHeader, StarknetClientError> {
+        let block = observe_rpc(
+            "starknet_getBlockWithTxHashes",
+            self.provider.get_block_with_tx_hashes(block),
+        )
+        .await
+        .map_err(StarknetClientError::Provider)?;
+
+        match block {
+            MaybePendingBlockWithTxHashes::Block(block) => Ok(BlockHeader {
+                timestamp: block.timestamp,
+                hash: block.block_hash,
+                parent_hash: block.parent_hash,
+            }),
+            MaybePendingBlockWithTxHashes::PendingBlock(_) => Err(StarknetClientError::Other(
+                "Pending block has no hash".to_string(),
+            )),
+        }
+    }
+
     ///
     async fn block_number(&self) -> Result<u64, StarknetClientError> {
-        Ok(self
-            .provider
-            .block_number()
+        Ok(
+            observe_rpc("starknet_blockNumber", self.provider.block_number())
+                .await
+                .map_err(StarknetClientError::Provider)?,
+        )
+    }
+
+    async fn chain_id(&self) -> Result<FieldElement, StarknetClientError> {
+        observe_rpc("starknet_chainId", self.provider.chain_id())
             .await
-            .map_err(StarknetClientError::Provider)?)
+            .map_err(StarknetClientError::Provider)
     }
 
     async fn fetch_events(
@@ -228,11 +552,13 @@ impl StarknetClient for StarknetClientHttp {
 
         let chunk_size = 1000;
 
-        let event_page = self
-            .provider
-            .get_events(filter.clone(), continuation_token, chunk_size)
-            .await
-            .map_err(StarknetClientError::Provider)?;
+        let event_page = observe_rpc(
+            "starknet_getEvents",
+            self.provider
+                .get_events(filter.clone(), continuation_token, chunk_size),
+        )
+        .await
+        .map_err(StarknetClientError::Provider)?;
 
         event_page.events.iter().for_each(|e| {
             if let Some(block_number) = e.block_number {
@@ -268,11 +594,13 @@ impl StarknetClient for StarknetClientHttp {
         let mut continuation_token: Option<String> = None;
 
         loop {
-            let event_page = self
-                .provider
-                .get_events(filter.clone(), continuation_token, chunk_size)
-                .await
-                .map_err(StarknetClientError::Provider)?;
+            let event_page = observe_rpc(
+                "starknet_getEvents",
+                self.provider
+                    .get_events(filter.clone(), continuation_token, chunk_size),
+            )
+            .await
+            .map_err(StarknetClientError::Provider)?;
 
             event_page.events.iter().for_each(|e| {
                 if let Some(block_number) = e.block_number {
@@ -293,7 +621,8 @@ impl StarknetClient for StarknetClientHttp {
         Ok(events)
     }
 
-    ///
+    /// The retriable errors (see `is_retriable`) are retried with an exponential
+    /// backoff, as configured with `with_call_retries`. Contract errors are not.
     async fn call_contract(
         &self,
         contract_address: FieldElement,
@@ -301,36 +630,27 @@ impl StarknetClient for StarknetClientHttp {
         calldata: Vec<FieldElement>,
         block: BlockId,
     ) -> Result<Vec<FieldElement>, StarknetClientError> {
-        let r = self
-            .provider
-            .call(
-                FunctionCall {
-                    contract_address,
-                    entry_point_selector: selector,
-                    calldata,
-                },
-                block,
-            )
-            .await;
+        let latest = BlockId::Tag(BlockTag::Latest);
+        let block = if self.latest_contracts.contains(&contract_address) {
+            latest
+        } else {
+            block
+        };
 
-        match r {
-            Ok(felts) => Ok(felts),
-            Err(e) => {
-                if let ProviderError::StarknetError(StarknetError::ContractError(ref data)) = e {
-                    let s = data.revert_error.clone();
-                    if s.contains(ENTRYPOINT_NOT_FOUND) {
-                        Err(StarknetClientError::EntrypointNotFound(s))
-                    } else if s.contains(INPUT_TOO_SHORT) || s.contains(FAILED_DESERIALIZE) {
-                        Err(StarknetClientError::InputTooShort)
-                    } else if s.contains(INPUT_TOO_LONG) {
-                        Err(StarknetClientError::InputTooLong)
-                    } else {
-                        Err(StarknetClientError::Contract(s))
-                    }
-                } else {
-                    Err(StarknetClientError::Contract(e.to_string()))
-                }
+        match self
+            .call_contract_at(contract_address, selector, calldata.clone(), block)
+            .await
+        {
+            Err(StarknetClientError::BlockNotAvailable(e)) if self.latest_fallback => {
+                tracing::warn!(
+                    "Block state not available for call to 0x{:064x} ({}), falling back to latest",
+                    contract_address,
+                    e
+                );
+                self.call_contract_at(contract_address, selector, calldata, latest)
+                    .await
             }
+            r => r,
         }
     }
 }
@@ -338,9 +658,333 @@ impl StarknetClient for StarknetClientHttp {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use reqwest::StatusCode;
     use starknet::core::utils::get_selector_from_name;
+    use starknet::providers::jsonrpc::JsonRpcError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
     use tokio;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_parse_headers() {
+        let headers =
+            parse_headers("Authorization: Bearer secret; x-api-key:api-key-value ;").unwrap();
+
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers["authorization"], "Bearer secret");
+        assert_eq!(headers["x-api-key"], "api-key-value");
+
+        // Values are never shown in logs.
+        let debug = format!("{:?}", headers);
+        assert!(!debug.contains("secret"));
+        assert!(!debug.contains("api-key-value"));
+
+        assert!(parse_headers("").unwrap().is_empty());
+        assert!(parse_headers("no-value").is_err());
+        assert!(parse_headers("bad name: value").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_with_headers_sends_headers() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rpc_url = format!("http://{}", listener.local_addr().unwrap());
+
+        // Answers the RPC request only if authenticated.
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 4096];
+            let n = socket.read(&mut request).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..n]).to_lowercase();
+
+            let (status, body) = if request.contains("x-api-key: secret") {
+                ("200 OK", r#"{"jsonrpc":"2.0","id":1,"result":42}"#)
+            } else {
+                ("401 Unauthorized", "")
+            };
+
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let client =
+            StarknetClientHttp::with_headers(&rpc_url, parse_headers("x-api-key: secret").unwrap())
+                .unwrap();
+
+        assert_eq!(client.block_number().await.unwrap(), 42);
+    }
+
+    /// Serves the given responses, one per connection, and returns the url
+    /// of the server and the number of requests received.
+    async fn serve(responses: Vec<(&'static str, &'static str)>) -> (String, Arc<AtomicUsize>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rpc_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&requests);
+        tokio::spawn(async move {
+            for (status_and_headers, body) in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = vec![0u8; 4096];
+                let _ = socket.read(&mut request).await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status_and_headers,
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (rpc_url, requests)
+    }
+
+    async fn call(client: &StarknetClientHttp) -> Result<Vec<FieldElement>, StarknetClientError> {
+        client
+            .call_contract(
+                FieldElement::ONE,
+                get_selector_from_name("owner").unwrap(),
+                vec![],
+                BlockId::Tag(BlockTag::Latest),
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_call_contract_retries_rate_limited() {
+        let (rpc_url, requests) = serve(vec![
+            ("429 Too Many Requests\r\nRetry-After: 0", ""),
+            ("503 Service Unavailable", "upstream unavailable"),
+            ("200 OK", r#"{"jsonrpc":"2.0","id":1,"result":["0x2a"]}"#),
+        ])
+        .await;
+
+        let client = StarknetClientHttp::with_headers(&rpc_url, HeaderMap::new())
+            .unwrap()
+            .with_call_retries(3, Duration::from_millis(10));
+
+        assert_eq!(
+            call(&client).await.unwrap(),
+            vec![FieldElement::from(42_u32)]
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_call_contract_caps_attempts() {
+        let (rpc_url, requests) = serve(vec![
+            ("429 Too Many Requests", ""),
+            ("429 Too Many Requests", ""),
+            ("200 OK", r#"{"jsonrpc":"2.0","id":1,"result":["0x2a"]}"#),
+        ])
+        .await;
+
+        let client = StarknetClientHttp::with_headers(&rpc_url, HeaderMap::new())
+            .unwrap()
+            .with_call_retries(2, Duration::from_millis(10));
+
+        assert!(matches!(
+            call(&client).await,
+            Err(StarknetClientError::Provider(_))
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_call_contract_does_not_retry_revert() {
+        let (rpc_url, requests) = serve(vec![(
+            "200 OK",
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":40,"message":"Contract error","data":{"revert_error":"Error in the called contract"}}}"#,
+        )])
+        .await;
+
+        let client = StarknetClientHttp::with_headers(&rpc_url, HeaderMap::new())
+            .unwrap()
+            .with_call_retries(3, Duration::from_millis(10));
+
+        assert!(matches!(
+            call(&client).await,
+            Err(StarknetClientError::Contract(_))
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_call_contract_does_not_retry_invalid_params() {
+        let (rpc_url, requests) = serve(vec![
+            (
+                "200 OK",
+                r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32602,"message":"Invalid params"}}"#,
+            ),
+            ("200 OK", r#"{"jsonrpc":"2.0","id":1,"result":["0x2a"]}"#),
+        ])
+        .await;
+
+        let client = StarknetClientHttp::with_headers(&rpc_url, HeaderMap::new())
+            .unwrap()
+            .with_call_retries(3, Duration::from_millis(10));
+
+        assert!(call(&client).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    fn transport(error: RpcTransportError) -> ProviderError {
+        ProviderError::Other(Box::new(JsonRpcClientError::TransportError(error)))
+    }
+
+    fn json_rpc(code: i64, message: &str) -> ProviderError {
+        ProviderError::Other(Box::new(
+            JsonRpcClientError::<RpcTransportError>::JsonRpcError(JsonRpcError {
+                code,
+                message: message.to_string(),
+                data: None,
+            }),
+        ))
+    }
+
+    #[test]
+    fn test_is_retriable_rate_limited() {
+        assert!(is_retriable(&ProviderError::RateLimited));
+        assert!(is_retriable(&transport(RpcTransportError::RateLimited {
+            retry_after: None
+        })));
+        assert!(is_retriable(&json_rpc(-32005, "Limit exceeded")));
+    }
+
+    #[test]
+    fn test_is_retriable_server_errors() {
+        for status in [
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ] {
+            assert!(is_retriable(&transport(RpcTransportError::Server {
+                status
+            })));
+        }
+        assert!(is_retriable(&json_rpc(-32603, "Internal error")));
+    }
+
+    #[test]
+    fn test_is_retriable_terminal_errors() {
+        // Reverts and not found errors.
+        assert!(!is_retriable(&ProviderError::StarknetError(
+            StarknetError::ContractError(ContractErrorData {
+                revert_error: "Error in the called contract".to_string(),
+            })
+        )));
+        assert!(!is_retriable(&ProviderError::StarknetError(
+            StarknetError::ContractNotFound
+        )));
+        assert!(!is_retriable(&ProviderError::StarknetError(
+            StarknetError::BlockNotFound
+        )));
+        assert!(!is_retriable(&json_rpc(
+            -32603,
+            "Internal error: state is pruned"
+        )));
+
+        // Invalid requests.
+        assert!(!is_retriable(&json_rpc(-32602, "Invalid params")));
+        assert!(!is_retriable(&json_rpc(-32601, "Method not found")));
+
+        // Responses which can't be parsed.
+        let json_error = serde_json::from_str::<serde_json::Value>("<html>").unwrap_err();
+        assert!(!is_retriable(&transport(RpcTransportError::Json(
+            json_error
+        ))));
+
+        let error = StarknetClientError::Provider(json_rpc(-32602, "Invalid params"));
+        assert!(!error.is_retriable());
+        assert!(!StarknetClientError::InputTooShort.is_retriable());
+    }
+
+    #[tokio::test]
+    async fn test_is_retriable_timeout_and_connection_errors() {
+        // Accepts the connections without ever answering.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut sockets = vec![];
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let timeout = client.get(&url).send().await.unwrap_err();
+        assert!(timeout.is_timeout());
+        assert!(is_retriable(&transport(RpcTransportError::Reqwest(
+            timeout
+        ))));
+
+        // Nothing listens on a port once its listener is dropped.
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", closed.local_addr().unwrap());
+        drop(closed);
+
+        let connect = reqwest::get(&url).await.unwrap_err();
+        assert!(is_retriable(&transport(RpcTransportError::Reqwest(
+            connect
+        ))));
+
+        let error = StarknetClientError::Provider(ProviderError::RateLimited);
+        assert!(error.is_retriable());
+    }
+
+    #[tokio::test]
+    async fn test_call_contract_block_not_available() {
+        let block_not_found =
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":24,"message":"Block not found"}}"#;
+        let (rpc_url, requests) = serve(vec![
+            ("200 OK", block_not_found),
+            (
+                "200 OK",
+                r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32603,"message":"Internal error: state is pruned"}}"#,
+            ),
+            ("200 OK", block_not_found),
+            ("200 OK", r#"{"jsonrpc":"2.0","id":1,"result":["0x2a"]}"#),
+        ])
+        .await;
+
+        let client = StarknetClientHttp::with_headers(&rpc_url, HeaderMap::new())
+            .unwrap()
+            .with_call_retries(1, Duration::from_millis(10));
+        let old_block_call = |client: &StarknetClientHttp| {
+            client.call_contract(
+                FieldElement::ONE,
+                get_selector_from_name("owner").unwrap(),
+                vec![],
+                BlockId::Number(1),
+            )
+        };
+
+        for _ in 0..2 {
+            assert!(matches!(
+                old_block_call(&client).await,
+                Err(StarknetClientError::BlockNotAvailable(_))
+            ));
+        }
+
+        // Retried at the latest block.
+        let client = client.with_latest_fallback(true);
+        assert_eq!(
+            old_block_call(&client).await.unwrap(),
+            vec![FieldElement::from(42_u32)]
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
 
     #[tokio::test]
     async fn test_contract_error_entrypoint_not_found() {
@@ -418,11 +1062,11 @@ mod tests {
             )
             .await;
 
-        if let Err(StarknetClientError::InputTooShort) = r {
-            ()
-        } else {
-            panic!("Expected StarknetClientError::InputTooShort, got {:?}", r);
-        }
+        assert!(
+            matches!(r, Err(StarknetClientError::InputTooShort)),
+            "Expected StarknetClientError::InputTooShort, got {:?}",
+            r
+        );
     }
 
     #[tokio::test]
@@ -460,10 +1104,10 @@ mod tests {
             )
             .await;
 
-        if let Err(StarknetClientError::InputTooLong) = r {
-            ()
-        } else {
-            panic!("Expected StarknetClientError::InputTooLong, got {:?}", r);
-        }
+        assert!(
+            matches!(r, Err(StarknetClientError::InputTooLong)),
+            "Expected StarknetClientError::InputTooLong, got {:?}",
+            r
+        );
     }
 }
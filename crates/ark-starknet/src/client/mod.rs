@@ -1,9 +1,14 @@
+pub mod compat;
 pub mod http;
-use crate::EventResult;
+pub mod pool;
+pub mod rate_limiter;
+pub mod transport;
+use crate::{BlockHeader, EventResult};
 use async_trait::async_trait;
-pub use http::StarknetClientHttp;
+pub use http::{is_retriable, StarknetClientHttp};
 #[cfg(any(test, feature = "mock"))]
 use mockall::automock;
+pub use pool::StarknetClientPool;
 use starknet::core::{types::FieldElement, types::*};
 use starknet::providers::ProviderError;
 use std::collections::HashMap;
@@ -24,10 +29,27 @@ pub enum StarknetClientError {
     Conversion(String),
     #[error("Starknet-rs provider error: {0}")]
     Provider(ProviderError),
+    #[error("RPC serves another network: {0}")]
+    WrongNetwork(String),
+    /// The node doesn't have the state of the requested block, like a node
+    /// pruning the old states. The call may succeed on an archive node.
+    #[error("Block state not available on the node: {0}")]
+    BlockNotAvailable(String),
     #[error("Other error: {0}")]
     Other(String),
 }
 
+impl StarknetClientError {
+    /// Returns true if the request may succeed when retried, see `is_retriable`.
+    /// Only the provider errors can be retriable.
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            StarknetClientError::Provider(e) => is_retriable(e),
+            _ => false,
+        }
+    }
+}
+
 /// Starknet client interface with required methods
 /// for arkproject capabilities only.
 #[cfg_attr(any(test, feature = "mock"), automock)]
@@ -64,12 +86,22 @@ pub trait StarknetClient {
     ///
     fn parse_block_id(&self, id: &str) -> Result<BlockId, StarknetClientError>;
 
+    /// Returns the timestamp of the given block.
     ///
+    /// Only the block header and transactions hashes are fetched,
+    /// the full transactions are never loaded.
     async fn block_time(&self, block: BlockId) -> Result<u64, StarknetClientError>;
 
+    /// Returns the timestamp, the hash and the parent hash of the given block,
+    /// from a single call. The pending block has no hash yet, and returns an error.
+    async fn block_header(&self, block: BlockId) -> Result<BlockHeader, StarknetClientError>;
+
     ///
     async fn block_number(&self) -> Result<u64, StarknetClientError>;
 
+    /// Returns the chain id of the network served by the RPC.
+    async fn chain_id(&self) -> Result<FieldElement, StarknetClientError>;
+
     /// On Starknet, a chunk size limits the maximum number of events
     /// that can be retrieved with one call.
     /// To ensure all events are fetched, we must ensure all events pages
@@ -0,0 +1,371 @@
+//! Starknet Client spreading the requests over several RPC endpoints.
+//!
+//! Requests are sent to the endpoints in a round-robin fashion. When an
+//! endpoint is rate limited or unreachable, the request fails over to the
+//! next endpoint, and the failing one is put in cooldown for a while.
+//!
+//! A call at a block whose state is not available on an endpoint, like a
+//! pruning node, is also sent to the next endpoint, which may be an archive
+//! node. The endpoint is not put in cooldown, as it still serves the recent blocks.
+use super::http::transport_error;
+use super::{is_retriable, StarknetClient, StarknetClientError, StarknetClientHttp};
+use crate::{BlockHeader, EventResult};
+use async_trait::async_trait;
+use starknet::core::types::*;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default time an endpoint is skipped after a failure.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+struct Endpoint<C> {
+    client: C,
+    cooldown_until: Mutex<Option<Instant>>,
+}
+
+impl<C> Endpoint<C> {
+    fn is_healthy(&self) -> bool {
+        self.cooldown_until
+            .lock()
+            .unwrap()
+            .map_or(true, |until| Instant::now() >= until)
+    }
+
+    fn set_unhealthy(&self, cooldown: Duration) {
+        *self.cooldown_until.lock().unwrap() = Some(Instant::now() + cooldown);
+    }
+}
+
+/// A pool of Starknet clients, one per RPC endpoint.
+pub struct StarknetClientPool<C: StarknetClient = StarknetClientHttp> {
+    endpoints: Vec<Endpoint<C>>,
+    next: AtomicUsize,
+    cooldown: Duration,
+}
+
+impl<C: StarknetClient + Send + Sync> StarknetClientPool<C> {
+    /// Creates a pool from the given clients, each one targetting
+    /// a different endpoint.
+    pub fn with_clients(clients: Vec<C>, cooldown: Duration) -> Result<Self, StarknetClientError> {
+        if clients.is_empty() {
+            return Err(StarknetClientError::Other(
+                "At least one RPC endpoint is required".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            endpoints: clients
+                .into_iter()
+                .map(|client| Endpoint {
+                    client,
+                    cooldown_until: Mutex::new(None),
+                })
+                .collect(),
+            next: AtomicUsize::new(0),
+            cooldown,
+        })
+    }
+
+    /// Creates a pool with one client for each of the given RPC urls.
+    pub fn from_urls(rpc_urls: &[&str], cooldown: Duration) -> Result<Self, StarknetClientError> {
+        let clients = rpc_urls
+            .iter()
+            .map(|url| C::new(url))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::with_clients(clients, cooldown)
+    }
+
+    /// Returns the endpoints indexes in the order they must be tried
+    /// for the next request. Endpoints in cooldown are tried last.
+    fn endpoints_order(&self) -> Vec<usize> {
+        let count = self.endpoints.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % count;
+
+        let (mut healthy, cooling): (Vec<usize>, Vec<usize>) = (0..count)
+            .map(|i| (start + i) % count)
+            .partition(|&i| self.endpoints[i].is_healthy());
+
+        healthy.extend(cooling);
+        healthy
+    }
+
+    /// Runs the request on the endpoints until one of them answers
+    /// without a rate limit, transport or block not available error.
+    async fn failover<'a, T, F, Fut>(&'a self, request: F) -> Result<T, StarknetClientError>
+    where
+        F: Fn(&'a C) -> Fut,
+        Fut: Future<Output = Result<T, StarknetClientError>> + 'a,
+    {
+        let mut last_error = None;
+
+        for i in self.endpoints_order() {
+            let endpoint = &self.endpoints[i];
+
+            match request(&endpoint.client).await {
+                Err(e) if is_endpoint_error(&e) => {
+                    tracing::warn!("RPC endpoint #{} failed, trying next one: {}", i, e);
+                    endpoint.set_unhealthy(self.cooldown);
+                    last_error = Some(e);
+                }
+                Err(e @ StarknetClientError::BlockNotAvailable(_)) => {
+                    tracing::debug!(
+                        "RPC endpoint #{} can't serve the block, trying next one: {}",
+                        i,
+                        e
+                    );
+                    last_error = Some(e);
+                }
+                r => return r,
+            }
+        }
+
+        // Safe to unwrap, the pool is never empty.
+        Err(last_error.unwrap())
+    }
+}
+
+/// Returns true if the error is related to the endpoint itself (rate limit,
+/// HTTP or transport error) or may succeed when retried (see `is_retriable`),
+/// and not to the request.
+fn is_endpoint_error(e: &StarknetClientError) -> bool {
+    match e {
+        StarknetClientError::Provider(e) => is_retriable(e) || transport_error(e).is_some(),
+        _ => false,
+    }
+}
+
+#[async_trait]
+impl<C: StarknetClient + Send + Sync> StarknetClient for StarknetClientPool<C> {
+    /// Creates a pool from a comma separated list of RPC urls,
+    /// using the default cooldown.
+    fn new(rpc_url: &str) -> Result<Self, StarknetClientError> {
+        let urls: Vec<&str> = rpc_url
+            .split(',')
+            .map(str::trim)
+            .filter(|u| !u.is_empty())
+            .collect();
+
+        Self::from_urls(&urls, DEFAULT_COOLDOWN)
+    }
+
+    async fn events_from_tx_receipt(
+        &self,
+        transaction_hash: FieldElement,
+        keys: Option<Vec<Vec<FieldElement>>>,
+    ) -> Result<Vec<EmittedEvent>, StarknetClientError> {
+        self.failover(|c| c.events_from_tx_receipt(transaction_hash, keys.clone()))
+            .await
+    }
+
+    async fn block_txs_hashes(
+        &self,
+        block: BlockId,
+    ) -> Result<(u64, Vec<FieldElement>), StarknetClientError> {
+        self.failover(|c| c.block_txs_hashes(block)).await
+    }
+
+    async fn block_id_to_u64(&self, id: &BlockId) -> Result<u64, StarknetClientError> {
+        self.failover(|c| c.block_id_to_u64(id)).await
+    }
+
+    fn parse_block_range(
+        &self,
+        from: &str,
+        to: &str,
+    ) -> Result<(BlockId, BlockId), StarknetClientError> {
+        self.endpoints[0].client.parse_block_range(from, to)
+    }
+
+    fn parse_block_id(&self, id: &str) -> Result<BlockId, StarknetClientError> {
+        self.endpoints[0].client.parse_block_id(id)
+    }
+
+    async fn block_time(&self, block: BlockId) -> Result<u64, StarknetClientError> {
+        self.failover(|c| c.block_time(block)).await
+    }
+
+    async fn block_header(&self, block: BlockId) -> Result<BlockHeader, StarknetClientError> {
+        self.failover(|c| c.block_header(block)).await
+    }
+
+    async fn block_number(&self) -> Result<u64, StarknetClientError> {
+        self.failover(|c| c.block_number()).await
+    }
+
+    async fn chain_id(&self) -> Result<FieldElement, StarknetClientError> {
+        self.failover(|c| c.chain_id()).await
+    }
+
+    async fn fetch_events(
+        &self,
+        from_block: Option<BlockId>,
+        to_block: Option<BlockId>,
+        keys: Option<Vec<Vec<FieldElement>>>,
+        contract_address: Option<FieldElement>,
+        continuation_token: Option<String>,
+    ) -> Result<EventResult, StarknetClientError> {
+        self.failover(|c| {
+            c.fetch_events(
+                from_block,
+                to_block,
+                keys.clone(),
+                contract_address,
+                continuation_token.clone(),
+            )
+        })
+        .await
+    }
+
+    async fn fetch_all_block_events(
+        &self,
+        block_id: BlockId,
+        keys: Option<Vec<Vec<FieldElement>>>,
+    ) -> Result<HashMap<u64, Vec<EmittedEvent>>, StarknetClientError> {
+        self.failover(|c| c.fetch_all_block_events(block_id, keys.clone()))
+            .await
+    }
+
+    async fn call_contract(
+        &self,
+        contract_address: FieldElement,
+        selector: FieldElement,
+        calldata: Vec<FieldElement>,
+        block: BlockId,
+    ) -> Result<Vec<FieldElement>, StarknetClientError> {
+        self.failover(|c| c.call_contract(contract_address, selector, calldata.clone(), block))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::MockStarknetClient;
+    use starknet::providers::ProviderError;
+
+    fn client_with_result(result: fn() -> Result<u64, StarknetClientError>) -> MockStarknetClient {
+        let mut client = MockStarknetClient::default();
+        client.expect_block_number().returning(result);
+        client
+    }
+
+    #[tokio::test]
+    async fn test_pool_round_robin() {
+        let pool = StarknetClientPool::with_clients(
+            vec![client_with_result(|| Ok(1)), client_with_result(|| Ok(2))],
+            DEFAULT_COOLDOWN,
+        )
+        .unwrap();
+
+        assert_eq!(pool.block_number().await.unwrap(), 1);
+        assert_eq!(pool.block_number().await.unwrap(), 2);
+        assert_eq!(pool.block_number().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pool_failover_and_cooldown() {
+        let mut rate_limited = MockStarknetClient::default();
+        rate_limited
+            .expect_block_number()
+            .times(1)
+            .returning(|| Err(StarknetClientError::Provider(ProviderError::RateLimited)));
+
+        let pool = StarknetClientPool::with_clients(
+            vec![rate_limited, client_with_result(|| Ok(2))],
+            DEFAULT_COOLDOWN,
+        )
+        .unwrap();
+
+        // Fails over to the second endpoint.
+        assert_eq!(pool.block_number().await.unwrap(), 2);
+
+        // The first endpoint is in cooldown, and is not called anymore.
+        assert_eq!(pool.block_number().await.unwrap(), 2);
+        assert_eq!(pool.block_number().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_pool_no_failover_on_request_error() {
+        let mut failing = MockStarknetClient::default();
+        failing
+            .expect_call_contract()
+            .times(1)
+            .returning(|_, _, _, _| Err(StarknetClientError::InputTooShort));
+
+        let mut other = MockStarknetClient::default();
+        other.expect_call_contract().never();
+
+        let pool =
+            StarknetClientPool::with_clients(vec![failing, other], DEFAULT_COOLDOWN).unwrap();
+
+        let r = pool
+            .call_contract(
+                FieldElement::ONE,
+                FieldElement::ONE,
+                vec![],
+                BlockId::Tag(BlockTag::Latest),
+            )
+            .await;
+
+        assert!(matches!(r, Err(StarknetClientError::InputTooShort)));
+    }
+
+    #[tokio::test]
+    async fn test_pool_block_not_available_tries_next_endpoint() {
+        let mut pruned = MockStarknetClient::default();
+        pruned
+            .expect_call_contract()
+            .times(2)
+            .returning(|_, _, _, _| {
+                Err(StarknetClientError::BlockNotAvailable(
+                    "Block not found".to_string(),
+                ))
+            });
+
+        let mut archive = MockStarknetClient::default();
+        archive
+            .expect_call_contract()
+            .times(2)
+            .returning(|_, _, _, _| Ok(vec![FieldElement::ONE]));
+
+        let pool =
+            StarknetClientPool::with_clients(vec![pruned, archive], DEFAULT_COOLDOWN).unwrap();
+
+        // The pruned endpoint is not put in cooldown, and is still tried first.
+        for _ in 0..2 {
+            let r = pool
+                .call_contract(
+                    FieldElement::ONE,
+                    FieldElement::ONE,
+                    vec![],
+                    BlockId::Number(1),
+                )
+                .await;
+            assert_eq!(r.unwrap(), vec![FieldElement::ONE]);
+            pool.next.store(0, Ordering::Relaxed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pool_all_endpoints_failing() {
+        let rate_limited = || Err(StarknetClientError::Provider(ProviderError::RateLimited));
+
+        let pool = StarknetClientPool::with_clients(
+            vec![
+                client_with_result(rate_limited),
+                client_with_result(rate_limited),
+            ],
+            DEFAULT_COOLDOWN,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            pool.block_number().await,
+            Err(StarknetClientError::Provider(ProviderError::RateLimited))
+        ));
+    }
+}
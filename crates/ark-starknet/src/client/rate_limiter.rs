@@ -0,0 +1,144 @@
+//! Rate limiting of the RPC requests, shared by all the clients of the process.
+//!
+//! A token bucket allows bursts of requests up to its capacity, refilled at the
+//! configured rate. When the bucket is empty, the requests wait for their turn
+//! in order instead of failing, so the rate stays under the provider limit
+//! whatever the number of concurrent tasks.
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::trace;
+
+/// Environment variable with the maximum number of RPC requests per second.
+pub const RPC_REQUESTS_PER_SECOND_ENV_VAR: &str = "STARKNET_RPC_REQUESTS_PER_SECOND";
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests_per_second: f64,
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `requests_per_second`, with bursts of
+    /// one second of requests.
+    pub fn new(requests_per_second: f64) -> Self {
+        let requests_per_second = requests_per_second.max(f64::MIN_POSITIVE);
+        let capacity = requests_per_second.ceil().max(1.0);
+
+        Self {
+            requests_per_second,
+            capacity,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Sets the maximum number of requests sent in a burst.
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.capacity = burst.max(1) as f64;
+        self.bucket.get_mut().tokens = self.capacity;
+        self
+    }
+
+    /// Waits until a request can be sent.
+    ///
+    /// The waiting requests are queued: the lock of the bucket is held while
+    /// waiting for the next token, and is acquired in order.
+    pub async fn acquire(&self) {
+        let mut bucket = self.bucket.lock().await;
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.requests_per_second).min(self.capacity);
+        bucket.refilled_at = now;
+
+        if bucket.tokens < 1.0 {
+            let wait = Duration::from_secs_f64((1.0 - bucket.tokens) / self.requests_per_second);
+            trace!("RPC rate limit reached, waiting {:?}", wait);
+            tokio::time::sleep(wait).await;
+
+            bucket.tokens = 1.0;
+            bucket.refilled_at = Instant::now();
+        }
+
+        bucket.tokens -= 1.0;
+    }
+}
+
+fn global() -> &'static RwLock<Option<Arc<RateLimiter>>> {
+    static LIMITER: OnceLock<RwLock<Option<Arc<RateLimiter>>>> = OnceLock::new();
+    LIMITER.get_or_init(|| RwLock::new(None))
+}
+
+/// Sets the maximum number of RPC requests per second of all the clients,
+/// or removes the limit with `None`.
+pub fn set_global_rate_limit(requests_per_second: Option<f64>) {
+    *global().write().unwrap() = requests_per_second.map(|rps| Arc::new(RateLimiter::new(rps)));
+}
+
+/// Returns the limiter shared by all the clients, if a limit is set.
+pub fn global_rate_limiter() -> Option<Arc<RateLimiter>> {
+    global().read().unwrap().clone()
+}
+
+/// Sets the global limit from `STARKNET_RPC_REQUESTS_PER_SECOND`, if it is
+/// set and no limit is already set.
+pub(crate) fn init_global_rate_limit_from_env() {
+    let requests_per_second = match std::env::var(RPC_REQUESTS_PER_SECOND_ENV_VAR)
+        .ok()
+        .and_then(|v| v.trim().parse::<f64>().ok())
+    {
+        Some(rps) if rps > 0.0 => rps,
+        _ => return,
+    };
+
+    let mut limiter = global().write().unwrap();
+    if limiter.is_none() {
+        *limiter = Some(Arc::new(RateLimiter::new(requests_per_second)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_acquire_within_burst() {
+        let limiter = RateLimiter::new(1.0).with_burst(3);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_acquire_queues_concurrent_requests() {
+        let limiter = Arc::new(RateLimiter::new(50.0).with_burst(1));
+        let start = Instant::now();
+
+        let tasks: Vec<_> = (0..6)
+            .map(|_| {
+                let limiter = Arc::clone(&limiter);
+                tokio::spawn(async move { limiter.acquire().await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // The first request is sent at once, the 5 others every 20ms.
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,133 @@
+//! JSON-RPC HTTP transport aware of the rate limiting of the RPC providers.
+//!
+//! Behaves like the `HttpTransport` of starknet-rs, except that a
+//! `429 Too Many Requests` response is reported as `RpcTransportError::RateLimited`,
+//! with the delay of its `Retry-After` header, if any, and a `5xx` response
+//! which is not a JSON-RPC response as `RpcTransportError::Server`.
+//!
+//! When a global rate limit is set, the requests wait for their turn
+//! before being sent, see `rate_limiter`.
+//!
+//! The responses of the nodes on other spec versions than v0.6 are
+//! normalized before being deserialized, see `compat`.
+use super::compat::normalize_response;
+use super::rate_limiter::global_rate_limiter;
+use async_trait::async_trait;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use starknet::providers::jsonrpc::{JsonRpcMethod, JsonRpcResponse, JsonRpcTransport};
+use std::time::Duration;
+use url::Url;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RpcTransportError {
+    #[error(transparent)]
+    Reqwest(reqwest::Error),
+    #[error(transparent)]
+    Json(serde_json::Error),
+    #[error("Request rate limited (retry after: {retry_after:?})")]
+    RateLimited { retry_after: Option<Duration> },
+    /// The server failed without a JSON-RPC response, like a gateway error.
+    #[error("Server error: {status}")]
+    Server { status: StatusCode },
+}
+
+#[derive(Debug, Serialize)]
+struct JsonRpcRequest<T> {
+    id: u64,
+    jsonrpc: &'static str,
+    method: JsonRpcMethod,
+    params: T,
+}
+
+#[derive(Debug)]
+pub struct RpcTransport {
+    client: Client,
+    url: Url,
+}
+
+impl RpcTransport {
+    pub fn new(url: Url, client: Client) -> Self {
+        Self { client, url }
+    }
+}
+
+/// Parses a `Retry-After` header given in seconds.
+/// The HTTP date format is not supported, and is ignored.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+#[async_trait]
+impl JsonRpcTransport for RpcTransport {
+    type Error = RpcTransportError;
+
+    async fn send_request<P, R>(
+        &self,
+        method: JsonRpcMethod,
+        params: P,
+    ) -> Result<JsonRpcResponse<R>, Self::Error>
+    where
+        P: Serialize + Send,
+        R: DeserializeOwned,
+    {
+        let request_body = serde_json::to_string(&JsonRpcRequest {
+            id: 1,
+            jsonrpc: "2.0",
+            method,
+            params,
+        })
+        .map_err(RpcTransportError::Json)?;
+
+        if let Some(limiter) = global_rate_limiter() {
+            limiter.acquire().await;
+        }
+
+        let response = self
+            .client
+            .post(self.url.clone())
+            .body(request_body)
+            .header("Content-Type", "application/json")
+            .send()
+            .await
+            .map_err(RpcTransportError::Reqwest)?;
+
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_retry_after);
+
+            return Err(RpcTransportError::RateLimited { retry_after });
+        }
+
+        let status = response.status();
+        let response_body = response.text().await.map_err(RpcTransportError::Reqwest)?;
+
+        // Some nodes answer the JSON-RPC errors with a 5xx status, which are kept.
+        let mut response: serde_json::Value = match serde_json::from_str(&response_body) {
+            Ok(response) => response,
+            Err(_) if status.is_server_error() => {
+                return Err(RpcTransportError::Server { status });
+            }
+            Err(e) => return Err(RpcTransportError::Json(e)),
+        };
+        normalize_response(method, &mut response);
+
+        serde_json::from_value(response).map_err(RpcTransportError::Json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("3"), Some(Duration::from_secs(3)));
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+}
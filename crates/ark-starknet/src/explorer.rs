@@ -0,0 +1,137 @@
+//! Links to the block explorers of the networks.
+//!
+//! The frontends link the transactions and contracts to an explorer,
+//! whose URL depends on the network. `ExplorerLinks` builds them from
+//! the network and the explorer, or from a custom base URL (i.g. a
+//! self-hosted explorer).
+use crate::client::StarknetClientError;
+use crate::format::to_hex_str;
+use crate::network::Network;
+use starknet::core::types::FieldElement;
+use std::str::FromStr;
+
+/// Environment variable with the explorer linked, `starkscan` or `voyager`.
+pub const EXPLORER_ENV_VAR: &str = "STARKNET_EXPLORER";
+
+/// Environment variable with a custom base URL of the explorer,
+/// replacing the one of `STARKNET_EXPLORER`.
+pub const EXPLORER_URL_ENV_VAR: &str = "STARKNET_EXPLORER_URL";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Explorer {
+    #[default]
+    Starkscan,
+    Voyager,
+}
+
+impl Explorer {
+    /// Base URL of the explorer on the given network, without trailing slash.
+    pub fn base_url(&self, network: Network) -> &'static str {
+        match (self, network) {
+            (Explorer::Starkscan, Network::Mainnet) => "https://starkscan.co",
+            (Explorer::Starkscan, Network::Sepolia) => "https://sepolia.starkscan.co",
+            (Explorer::Voyager, Network::Mainnet) => "https://voyager.online",
+            (Explorer::Voyager, Network::Sepolia) => "https://sepolia.voyager.online",
+        }
+    }
+}
+
+impl FromStr for Explorer {
+    type Err = StarknetClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "starkscan" => Ok(Explorer::Starkscan),
+            "voyager" => Ok(Explorer::Voyager),
+            _ => Err(StarknetClientError::Other(format!(
+                "Unknown explorer: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Builds the explorer URLs of the transactions and contracts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExplorerLinks {
+    base_url: String,
+}
+
+impl ExplorerLinks {
+    /// Links to the given explorer on the given network.
+    pub fn new(explorer: Explorer, network: Network) -> Self {
+        Self::with_base_url(explorer.base_url(network))
+    }
+
+    /// Links to an explorer at the given base URL, which must serve the
+    /// Starkscan and Voyager paths (`/tx/{hash}`, `/contract/{address}`).
+    pub fn with_base_url(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Reads the explorer from `STARKNET_EXPLORER_URL`, or else from
+    /// `STARKNET_EXPLORER`, defaulting to Starkscan.
+    pub fn from_env(network: Network) -> Result<Self, StarknetClientError> {
+        if let Ok(base_url) = std::env::var(EXPLORER_URL_ENV_VAR) {
+            return Ok(Self::with_base_url(&base_url));
+        }
+
+        let explorer = match std::env::var(EXPLORER_ENV_VAR) {
+            Ok(value) => value.parse()?,
+            Err(_) => Explorer::default(),
+        };
+
+        Ok(Self::new(explorer, network))
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// URL of the given transaction.
+    pub fn transaction_url(&self, transaction_hash: FieldElement) -> String {
+        format!("{}/tx/{}", self.base_url, to_hex_str(&transaction_hash))
+    }
+
+    /// URL of the given contract.
+    pub fn contract_url(&self, contract_address: FieldElement) -> String {
+        format!(
+            "{}/contract/{}",
+            self.base_url,
+            to_hex_str(&contract_address)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explorer_links() {
+        let hash = FieldElement::from_hex_be("0x1a").unwrap();
+
+        let links = ExplorerLinks::new(Explorer::Starkscan, Network::Sepolia);
+        assert_eq!(
+            links.transaction_url(hash),
+            format!("https://sepolia.starkscan.co/tx/{}", to_hex_str(&hash))
+        );
+
+        let links = ExplorerLinks::new(Explorer::Voyager, Network::Mainnet);
+        assert_eq!(
+            links.contract_url(hash),
+            format!("https://voyager.online/contract/{}", to_hex_str(&hash))
+        );
+
+        let links = ExplorerLinks::with_base_url("https://explorer.example.com/");
+        assert_eq!(links.base_url(), "https://explorer.example.com");
+    }
+
+    #[test]
+    fn test_explorer_from_str() {
+        assert_eq!(" Voyager".parse::<Explorer>().unwrap(), Explorer::Voyager);
+        assert!("etherscan".parse::<Explorer>().is_err());
+    }
+}
@@ -1,4 +1,11 @@
+use std::borrow::Cow;
 use std::fmt::LowerHex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Default maximum number of characters of the values logged with `log_preview`.
+pub const DEFAULT_LOG_PREVIEW_LENGTH: usize = 256;
+
+static LOG_PREVIEW_LENGTH: AtomicUsize = AtomicUsize::new(DEFAULT_LOG_PREVIEW_LENGTH);
 
 /// Returns the padded hex of '0x' prefixed
 /// representation of the given felt.
@@ -6,6 +13,25 @@ pub fn to_hex_str<T: LowerHex>(value: &T) -> String {
     format!("0x{:064x}", value)
 }
 
+/// Sets the maximum number of characters of the values logged with `log_preview`.
+pub fn set_log_preview_length(length: usize) {
+    LOG_PREVIEW_LENGTH.store(length, Ordering::Relaxed);
+}
+
+/// Returns the given value truncated to the preview length, to log
+/// values which can be large (on-chain metadata, data URIs...) without
+/// flooding the logs. Full values must only be logged at `trace` level.
+pub fn log_preview(value: &str) -> Cow<'_, str> {
+    truncate(value, LOG_PREVIEW_LENGTH.load(Ordering::Relaxed))
+}
+
+fn truncate(value: &str, max_length: usize) -> Cow<'_, str> {
+    match value.char_indices().nth(max_length) {
+        Some((i, _)) => Cow::Owned(format!("{}... ({} bytes)", &value[..i], value.len())),
+        None => Cow::Borrowed(value),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use starknet::core::types::FieldElement;
@@ -25,6 +51,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_truncate() {
+        assert_eq!(
+            truncate("data:application/json", 30),
+            "data:application/json"
+        );
+        assert_eq!(truncate("data:application/json", 4), "data... (21 bytes)");
+        // Not truncated in the middle of a character.
+        assert_eq!(truncate("éàü", 2), "éà... (6 bytes)");
+    }
+
     #[test]
     fn test_to_hex_str_short() {
         let address = FieldElement::from_hex_be("0x1234").unwrap();
@@ -1,20 +1,47 @@
 pub mod byte_array;
 pub mod cairo_string_parser;
 pub mod client;
+pub mod explorer;
 pub mod format;
+pub mod metrics;
+pub mod network;
+pub mod token_id;
 use anyhow::Result;
 use format::to_hex_str;
 use num_bigint::BigUint;
 use num_traits::Num;
-use starknet::core::types::EmittedEvent;
+use starknet::core::types::{EmittedEvent, FieldElement};
 use std::collections::HashMap;
 
+pub use token_id::PaddedTokenId;
+
 #[derive(Debug, Clone)]
 pub struct CairoU256 {
     pub low: u128,
     pub high: u128,
 }
 
+/// Representation of a token id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TokenIdFormat {
+    /// Decimal, like `15`, to join with other datasets.
+    Decimal,
+    /// Hexadecimal without padding, like `0xf`.
+    Hex,
+    /// Hexadecimal padded to 64 characters, like `0x00..0f`. Default for
+    /// the storage keys, as they sort in the order of the ids.
+    #[default]
+    PaddedHex,
+}
+
+/// Header of a block, read at once to be consistent across a reorganization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub timestamp: u64,
+    pub hash: FieldElement,
+    pub parent_hash: FieldElement,
+}
+
 #[derive(Debug, Clone)]
 pub struct EventResult {
     pub events: HashMap<u64, Vec<EmittedEvent>>,
@@ -22,6 +49,16 @@ pub struct EventResult {
 }
 
 impl CairoU256 {
+    /// Returns the u256 of the given words, as emitted by Cairo (`low`
+    /// first), or `None` if a word doesn't fit in 128 bits.
+    pub fn from_felts(low: FieldElement, high: FieldElement) -> Option<Self> {
+        Some(Self {
+            low: low.try_into().ok()?,
+            high: high.try_into().ok()?,
+        })
+    }
+
+    /// Returns the full 256 bits value, `low + high * 2^128`.
     pub fn to_biguint(&self) -> BigUint {
         let low_bytes = self.low.to_be_bytes();
         let high_bytes = self.high.to_be_bytes();
@@ -33,11 +70,25 @@ impl CairoU256 {
         BigUint::from_bytes_be(&bytes[..])
     }
 
+    /// Hexadecimal padded to 64 characters.
     pub fn to_hex(&self) -> String {
         let token_id_big_uint = self.to_biguint();
         to_hex_str(&token_id_big_uint)
     }
 
+    /// Hexadecimal without padding, `0x0` for zero.
+    pub fn to_hex_unpadded(&self) -> String {
+        format!("0x{:x}", self.to_biguint())
+    }
+
+    pub fn format(&self, format: TokenIdFormat) -> String {
+        match format {
+            TokenIdFormat::Decimal => self.to_decimal(false),
+            TokenIdFormat::Hex => self.to_hex_unpadded(),
+            TokenIdFormat::PaddedHex => self.to_hex(),
+        }
+    }
+
     pub fn to_decimal(&self, padded: bool) -> String {
         let token_id_big_uint = self.to_biguint();
         let token_id_str: String = token_id_big_uint.to_str_radix(10);
@@ -88,6 +139,38 @@ mod tests {
         assert_eq!(result, BigUint::from_str_radix("15", 10).unwrap());
     }
 
+    #[test]
+    fn test_to_biguint_with_high_word() {
+        let u256 = CairoU256 { low: 15, high: 2 };
+
+        let expected = (BigUint::from(2_u128) << 128) + BigUint::from(15_u128);
+        assert_eq!(u256.to_biguint(), expected);
+        assert_eq!(u256.to_decimal(false), expected.to_str_radix(10));
+        assert_eq!(
+            u256.to_hex(),
+            "0x000000000000000000000000000000020000000000000000000000000000000f"
+        );
+        assert_eq!(
+            CairoU256::from_hex_be(&u256.to_hex()).unwrap().to_biguint(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_from_felts() {
+        let u256 = CairoU256::from_felts(FieldElement::from(15_u64), FieldElement::TWO).unwrap();
+        assert_eq!((u256.low, u256.high), (15, 2));
+
+        let max = FieldElement::from(u128::MAX);
+        let u256 = CairoU256::from_felts(max, max).unwrap();
+        assert_eq!(u256.to_hex(), format!("0x{}", "f".repeat(64)));
+
+        // A word over 128 bits is not a valid u256 word.
+        let over = max + FieldElement::ONE;
+        assert!(CairoU256::from_felts(over, FieldElement::ZERO).is_none());
+        assert!(CairoU256::from_felts(FieldElement::ZERO, over).is_none());
+    }
+
     #[test]
     fn test_to_hex() {
         let u256 = CairoU256 { low: 15, high: 0 };
@@ -111,6 +194,45 @@ mod tests {
         assert_eq!(result_padded, expected_padded_decimal);
     }
 
+    #[test]
+    fn test_format_u256_spanning_both_felts() {
+        let u256 = CairoU256 {
+            low: u128::MAX,
+            high: 1,
+        };
+
+        assert_eq!(
+            u256.format(TokenIdFormat::Decimal),
+            "680564733841876926926749214863536422911"
+        );
+        assert_eq!(
+            u256.format(TokenIdFormat::Hex),
+            "0x1ffffffffffffffffffffffffffffffff"
+        );
+        assert_eq!(
+            u256.format(TokenIdFormat::PaddedHex),
+            "0x00000000000000000000000000000001ffffffffffffffffffffffffffffffff"
+        );
+
+        let max = CairoU256 {
+            low: u128::MAX,
+            high: u128::MAX,
+        };
+        assert_eq!(
+            max.format(TokenIdFormat::Decimal),
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+        );
+        assert_eq!(
+            max.format(TokenIdFormat::Hex),
+            format!("0x{}", "f".repeat(64))
+        );
+        assert_eq!(max.to_hex(), max.format(TokenIdFormat::default()));
+
+        let zero = CairoU256 { low: 0, high: 0 };
+        assert_eq!(zero.format(TokenIdFormat::Decimal), "0");
+        assert_eq!(zero.format(TokenIdFormat::Hex), "0x0");
+    }
+
     #[test]
     fn test_from_hex_be() {
         let hex_string = "0x000000000000000000000000000000000000000000000000000000000000000f";
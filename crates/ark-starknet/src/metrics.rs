@@ -0,0 +1,82 @@
+//! Prometheus metrics of the Starknet client.
+//!
+//! Metrics are registered in the prometheus default registry,
+//! in order to be exposed with the metrics of the other crates.
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+use std::future::Future;
+use std::sync::OnceLock;
+
+/// Latency of the RPC calls, labelled by RPC method.
+pub fn rpc_duration_seconds() -> &'static HistogramVec {
+    static METRIC: OnceLock<HistogramVec> = OnceLock::new();
+    METRIC.get_or_init(|| {
+        register_histogram_vec!(
+            "starknet_rpc_duration_seconds",
+            "Latency of the Starknet RPC calls",
+            &["method"]
+        )
+        .expect("starknet_rpc_duration_seconds can be registered")
+    })
+}
+
+/// Failed RPC calls, labelled by RPC method.
+pub fn rpc_errors_total() -> &'static IntCounterVec {
+    static METRIC: OnceLock<IntCounterVec> = OnceLock::new();
+    METRIC.get_or_init(|| {
+        register_int_counter_vec!(
+            "starknet_rpc_errors_total",
+            "Number of failed Starknet RPC calls",
+            &["method"]
+        )
+        .expect("starknet_rpc_errors_total can be registered")
+    })
+}
+
+/// Awaits the given RPC call, recording its latency and its failure if any.
+pub(crate) async fn observe_rpc<T, E>(
+    method: &str,
+    call: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    #[cfg(feature = "tracing-spans")]
+    let call = tracing::Instrument::instrument(call, tracing::info_span!("rpc", method));
+
+    let timer = rpc_duration_seconds()
+        .with_label_values(&[method])
+        .start_timer();
+    let result = call.await;
+    timer.observe_duration();
+
+    if result.is_err() {
+        rpc_errors_total().with_label_values(&[method]).inc();
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_observe_rpc() {
+        let ok: Result<u64, ()> = observe_rpc("test_ok", async { Ok(1) }).await;
+        assert_eq!(ok, Ok(1));
+
+        let err: Result<u64, ()> = observe_rpc("test_err", async { Err(()) }).await;
+        assert_eq!(err, Err(()));
+
+        let durations = rpc_duration_seconds();
+        assert_eq!(
+            durations.with_label_values(&["test_ok"]).get_sample_count(),
+            1
+        );
+        assert_eq!(
+            durations
+                .with_label_values(&["test_err"])
+                .get_sample_count(),
+            1
+        );
+        assert_eq!(rpc_errors_total().with_label_values(&["test_ok"]).get(), 0);
+        assert_eq!(rpc_errors_total().with_label_values(&["test_err"]).get(), 1);
+    }
+}
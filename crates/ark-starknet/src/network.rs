@@ -0,0 +1,157 @@
+//! Starknet networks that can be indexed.
+//!
+//! The network is selected at runtime, and used to choose the RPC endpoint,
+//! name the storage of each network and tag the indexed records, so one
+//! deployment indexing several networks never mixes their data.
+use crate::client::{StarknetClient, StarknetClientError};
+use starknet::core::types::FieldElement;
+use starknet::core::utils::{cairo_short_string_to_felt, parse_cairo_short_string};
+use std::fmt;
+use std::str::FromStr;
+
+/// Environment variable with the network to index, `mainnet` or `sepolia`.
+pub const NETWORK_ENV_VAR: &str = "STARKNET_NETWORK";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Sepolia,
+}
+
+impl Network {
+    /// Reads the network from `STARKNET_NETWORK`, defaulting to mainnet.
+    pub fn from_env() -> Result<Self, StarknetClientError> {
+        match std::env::var(NETWORK_ENV_VAR) {
+            Ok(value) => value.parse(),
+            Err(_) => Ok(Network::default()),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Sepolia => "sepolia",
+        }
+    }
+
+    /// Chain id of the network, as a short string.
+    pub fn chain_id_name(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "SN_MAIN",
+            Network::Sepolia => "SN_SEPOLIA",
+        }
+    }
+
+    /// Chain id returned by the RPC nodes of the network.
+    pub fn chain_id(&self) -> FieldElement {
+        cairo_short_string_to_felt(self.chain_id_name()).expect("Chain id is a valid short string")
+    }
+
+    pub fn from_chain_id(chain_id: FieldElement) -> Option<Self> {
+        [Network::Mainnet, Network::Sepolia]
+            .into_iter()
+            .find(|n| n.chain_id() == chain_id)
+    }
+
+    /// Environment variable with the RPC url of the network,
+    /// like `STARKNET_SEPOLIA_RPC_URL`.
+    pub fn rpc_url_env_var(&self) -> String {
+        format!("STARKNET_{}_RPC_URL", self.as_str().to_uppercase())
+    }
+
+    /// Reads the RPC url of the network from its environment variable.
+    pub fn rpc_url_from_env(&self) -> Result<String, StarknetClientError> {
+        let var = self.rpc_url_env_var();
+        std::env::var(&var).map_err(|_| StarknetClientError::Other(format!("{} is not set", var)))
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Network {
+    type Err = StarknetClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "mainnet" => Ok(Network::Mainnet),
+            "sepolia" => Ok(Network::Sepolia),
+            _ => Err(StarknetClientError::Other(format!(
+                "Unknown network: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Checks that the RPC of the client serves the given network,
+/// by comparing its chain id with the chain id of the network.
+pub async fn verify_network<C: StarknetClient + ?Sized>(
+    client: &C,
+    network: Network,
+) -> Result<(), StarknetClientError> {
+    let chain_id = client.chain_id().await?;
+
+    if chain_id == network.chain_id() {
+        return Ok(());
+    }
+
+    let actual =
+        parse_cairo_short_string(&chain_id).unwrap_or_else(|_| format!("0x{:x}", chain_id));
+
+    Err(StarknetClientError::WrongNetwork(format!(
+        "expected {} ({}), RPC chain id is {}",
+        network,
+        network.chain_id_name(),
+        actual
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::MockStarknetClient;
+
+    #[test]
+    fn test_network_from_str() {
+        assert_eq!("mainnet".parse::<Network>().unwrap(), Network::Mainnet);
+        assert_eq!(" Sepolia ".parse::<Network>().unwrap(), Network::Sepolia);
+        assert!("testnet".parse::<Network>().is_err());
+
+        assert_eq!(
+            Network::Sepolia.rpc_url_env_var(),
+            "STARKNET_SEPOLIA_RPC_URL"
+        );
+    }
+
+    #[test]
+    fn test_network_chain_id() {
+        assert_eq!(
+            Network::Mainnet.chain_id(),
+            FieldElement::from_hex_be("0x534e5f4d41494e").unwrap()
+        );
+        assert_eq!(
+            Network::from_chain_id(Network::Sepolia.chain_id()),
+            Some(Network::Sepolia)
+        );
+        assert_eq!(Network::from_chain_id(FieldElement::ONE), None);
+    }
+
+    #[tokio::test]
+    async fn test_verify_network() {
+        let mut client = MockStarknetClient::default();
+        client
+            .expect_chain_id()
+            .returning(|| Ok(Network::Sepolia.chain_id()));
+
+        assert!(verify_network(&client, Network::Sepolia).await.is_ok());
+        assert!(matches!(
+            verify_network(&client, Network::Mainnet).await,
+            Err(StarknetClientError::WrongNetwork(_))
+        ));
+    }
+}
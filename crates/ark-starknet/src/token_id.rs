@@ -0,0 +1,155 @@
+//! Token id padded to 64 hex characters, the key of the tokens.
+//!
+//! The storage keys of the tokens are their id padded to 64 hex characters
+//! (`TokenIdFormat::PaddedHex`). `PaddedTokenId` can only hold a valid padded
+//! id, so a decimal id, an unpadded id or an URI can't be passed as a key.
+use crate::CairoU256;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of hex characters of a padded token id, after the `0x` prefix.
+const PADDED_HEX_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Invalid padded token id: {0}")]
+pub struct InvalidPaddedTokenId(pub String);
+
+/// A token id padded to 64 hex characters, like `0x00..0f`, lowercase.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PaddedTokenId(String);
+
+impl PaddedTokenId {
+    /// Validates a padded token id: `0x` followed by 64 hex characters.
+    /// The uppercase hex characters are lowercased.
+    pub fn new(value: &str) -> Result<Self, InvalidPaddedTokenId> {
+        match value.strip_prefix("0x") {
+            Some(hex)
+                if hex.len() == PADDED_HEX_LENGTH && hex.chars().all(|c| c.is_ascii_hexdigit()) =>
+            {
+                Ok(Self(value.to_ascii_lowercase()))
+            }
+            _ => Err(InvalidPaddedTokenId(value.to_string())),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+
+    pub fn to_u256(&self) -> CairoU256 {
+        CairoU256::from_hex_be(&self.0).expect("A padded token id is a valid u256")
+    }
+}
+
+/// The token id `0`.
+impl Default for PaddedTokenId {
+    fn default() -> Self {
+        Self::from(&CairoU256 { low: 0, high: 0 })
+    }
+}
+
+impl From<&CairoU256> for PaddedTokenId {
+    fn from(token_id: &CairoU256) -> Self {
+        Self(token_id.to_hex())
+    }
+}
+
+impl From<CairoU256> for PaddedTokenId {
+    fn from(token_id: CairoU256) -> Self {
+        Self::from(&token_id)
+    }
+}
+
+impl FromStr for PaddedTokenId {
+    type Err = InvalidPaddedTokenId;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<String> for PaddedTokenId {
+    type Error = InvalidPaddedTokenId;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(&value)
+    }
+}
+
+impl From<PaddedTokenId> for String {
+    fn from(token_id: PaddedTokenId) -> Self {
+        token_id.0
+    }
+}
+
+impl AsRef<str> for PaddedTokenId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for PaddedTokenId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for PaddedTokenId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for PaddedTokenId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Self::new(&value).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padded_token_id() {
+        let token_id = CairoU256 { low: 15, high: 2 };
+        let padded = PaddedTokenId::from(&token_id);
+
+        assert_eq!(padded.as_str(), token_id.to_hex());
+        assert_eq!(PaddedTokenId::new(&token_id.to_hex()).unwrap(), padded);
+        assert_eq!(padded.to_u256().to_biguint(), token_id.to_biguint());
+
+        let upper = format!("0x{}F", "0".repeat(63));
+        assert_eq!(
+            PaddedTokenId::new(&upper).unwrap(),
+            PaddedTokenId::from(CairoU256 { low: 15, high: 0 })
+        );
+
+        let not_hex = format!("0x{}g", "0".repeat(63));
+        for invalid in ["15", "0xf", "ipfs://QmCid/1.json", not_hex.as_str()] {
+            assert_eq!(
+                PaddedTokenId::new(invalid),
+                Err(InvalidPaddedTokenId(invalid.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_padded_token_id_serde() {
+        let padded = PaddedTokenId::from(CairoU256 { low: 7, high: 0 });
+        let json = serde_json::to_string(&padded).unwrap();
+
+        assert_eq!(json, format!("\"{}\"", padded));
+        assert_eq!(
+            serde_json::from_str::<PaddedTokenId>(&json).unwrap(),
+            padded
+        );
+        assert!(serde_json::from_str::<PaddedTokenId>("\"0x7\"").is_err());
+    }
+}
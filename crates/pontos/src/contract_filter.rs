@@ -0,0 +1,97 @@
+//! Filter of the contracts indexed by Pontos.
+//!
+//! The events of the filtered out contracts are dropped before any
+//! processing, saving the RPC calls to identify the contracts and to
+//! fetch the tokens of the unwanted collections.
+use starknet::core::types::FieldElement;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Environment variable with the contract filter, `all`,
+/// `allow:<address>,<address>` or `deny:<address>,<address>`.
+pub const CONTRACT_FILTER_ENV_VAR: &str = "PONTOS_CONTRACT_FILTER";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum ContractFilter {
+    /// All the contracts are indexed.
+    #[default]
+    All,
+    /// Only the given contracts are indexed.
+    Allow(HashSet<FieldElement>),
+    /// All the contracts are indexed, except the given ones.
+    Deny(HashSet<FieldElement>),
+}
+
+impl ContractFilter {
+    /// Returns true if the events of the given contract are indexed.
+    pub fn is_indexed(&self, contract_address: &FieldElement) -> bool {
+        match self {
+            ContractFilter::All => true,
+            ContractFilter::Allow(addresses) => addresses.contains(contract_address),
+            ContractFilter::Deny(addresses) => !addresses.contains(contract_address),
+        }
+    }
+
+    /// Reads the filter from `PONTOS_CONTRACT_FILTER`, defaulting to `All`.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var(CONTRACT_FILTER_ENV_VAR) {
+            Ok(v) => v.parse(),
+            Err(_) => Ok(ContractFilter::All),
+        }
+    }
+}
+
+impl FromStr for ContractFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("all") || s == "*" {
+            return Ok(ContractFilter::All);
+        }
+
+        let (mode, addresses) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid contract filter: {s}"))?;
+
+        let addresses = addresses
+            .split(',')
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .map(|a| {
+                FieldElement::from_hex_be(a).map_err(|_| format!("Invalid contract address: {a}"))
+            })
+            .collect::<Result<HashSet<_>, _>>()?;
+
+        match mode.trim().to_lowercase().as_str() {
+            "allow" => Ok(ContractFilter::Allow(addresses)),
+            "deny" => Ok(ContractFilter::Deny(addresses)),
+            _ => Err(format!("Invalid contract filter mode: {mode}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_contract_filter() {
+        assert_eq!("all".parse::<ContractFilter>(), Ok(ContractFilter::All));
+        assert_eq!(" * ".parse::<ContractFilter>(), Ok(ContractFilter::All));
+
+        // Addresses are compared as field elements, whatever their padding.
+        let filter: ContractFilter = "allow: 0x1, 0x0002".parse().unwrap();
+        assert!(filter.is_indexed(&FieldElement::ONE));
+        assert!(filter.is_indexed(&FieldElement::TWO));
+        assert!(!filter.is_indexed(&FieldElement::from(3_u64)));
+
+        let filter: ContractFilter = "DENY:0x1".parse().unwrap();
+        assert!(!filter.is_indexed(&FieldElement::ONE));
+        assert!(filter.is_indexed(&FieldElement::TWO));
+
+        assert!("allow".parse::<ContractFilter>().is_err());
+        assert!("only:0x1".parse::<ContractFilter>().is_err());
+        assert!("deny:0xzz".parse::<ContractFilter>().is_err());
+    }
+}
@@ -1,6 +1,8 @@
 //! Trait related to any events that Pontos can emit to be handled.
 use crate::storage::types::{TokenEvent, TokenInfo};
+use crate::IndexerError;
 use async_trait::async_trait;
+use starknet::core::types::EmittedEvent;
 
 /// A trait to be implemented in order to handle
 /// events emitted by Pontos, in an external code.
@@ -18,7 +20,11 @@ pub trait EventHandler {
     /// Block is processing by Pontos.
     async fn on_block_processing(&self, block_timestamp: u64, block_number: Option<u64>) {}
 
+    /// A block of a backfilled range couldn't be indexed.
+    async fn on_block_failed(&self, block_number: u64, error: &IndexerError) {}
+
     /// Invoked when Pontos has successfully indexed a range of blocks up to the given block number.
+    /// Also invoked when the indexation stops on shutdown, to flush any buffered event.
     async fn on_indexation_range_completed(&self) {}
 
     /// A new token has be registered.
@@ -27,6 +33,11 @@ pub trait EventHandler {
     /// A new event has be registered.
     async fn on_event_registered(&self, event: TokenEvent) {}
 
+    /// Events were not processed as the RPC calls cap of the block, or
+    /// the event processing timeout, was reached. They must be processed
+    /// in a later pass.
+    async fn on_events_deferred(&self, events: Vec<EmittedEvent>) {}
+
     // A new latest block has been detected.
     async fn on_new_latest_block(&self, block_number: u64) {}
 }
@@ -0,0 +1,376 @@
+//! Batched emission of the events registered by Pontos to an
+//! external sink (i.g. a Kinesis stream).
+//!
+//! Emitting one record per event wastes API calls and quickly hits
+//! per-second limits of the streaming services. The `BatchedEventSink`
+//! buffers the events and emits them in batches, flushed on size or
+//! time thresholds, and at the end of an indexation range.
+//!
+//! The records are `StreamEvent`s, whose serialized shape is versioned,
+//! see `stream_event`.
+use crate::event_handler::EventHandler;
+use crate::storage::types::TokenEvent;
+use crate::stream_event::StreamEvent;
+use async_trait::async_trait;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex as AsyncMutex;
+use tracing::{debug, error, warn};
+
+#[cfg(test)]
+use mockall::automock;
+
+/// Maximum number of records accepted by one `put_records` call.
+pub const MAX_RECORDS_PER_BATCH: usize = 500;
+
+#[derive(Debug, Clone)]
+pub enum EventSinkError {
+    /// The whole call to the sink failed.
+    Emission(String),
+    /// Some records are still failing after all the retries.
+    RecordsNotEmitted(usize),
+}
+
+impl fmt::Display for EventSinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventSinkError::Emission(s) => write!(f, "Records emission failed: {s}"),
+            EventSinkError::RecordsNotEmitted(n) => {
+                write!(f, "{n} records could not be emitted after retries")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EventSinkError {}
+
+/// A sink accepting batches of records.
+#[async_trait]
+#[cfg_attr(test, automock)]
+pub trait EventSink {
+    /// Emits the given records in one call (at most `MAX_RECORDS_PER_BATCH`).
+    ///
+    /// Returns the indexes (in `records`) of the records that failed
+    /// to be emitted, to be retried by the caller.
+    async fn put_records(&self, records: &[StreamEvent]) -> Result<Vec<usize>, EventSinkError>;
+}
+
+pub struct BatchConfig {
+    /// Number of buffered records triggering a flush, capped to `MAX_RECORDS_PER_BATCH`.
+    pub max_batch_size: usize,
+    /// Maximum time a record can wait in the buffer before a flush.
+    pub max_batch_age: Duration,
+    /// Maximum number of retries for the failed records of a batch.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled at each attempt.
+    pub retry_delay: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            max_batch_size: MAX_RECORDS_PER_BATCH,
+            max_batch_age: Duration::from_secs(5),
+            max_retries: 3,
+            retry_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+struct Buffer {
+    records: Vec<StreamEvent>,
+    oldest_at: Option<Instant>,
+}
+
+/// Buffers the events registered by Pontos and emits them by batches.
+///
+/// As it implements `EventHandler`, it can be directly given to Pontos.
+pub struct BatchedEventSink<K: EventSink> {
+    sink: K,
+    config: BatchConfig,
+    buffer: AsyncMutex<Buffer>,
+}
+
+impl<K: EventSink + Send + Sync> BatchedEventSink<K> {
+    pub fn new(sink: K, config: BatchConfig) -> Self {
+        BatchedEventSink {
+            sink,
+            config,
+            buffer: AsyncMutex::new(Buffer {
+                records: vec![],
+                oldest_at: None,
+            }),
+        }
+    }
+
+    fn batch_size(&self) -> usize {
+        self.config.max_batch_size.clamp(1, MAX_RECORDS_PER_BATCH)
+    }
+
+    /// Queues an event, flushing the buffer if a threshold is reached.
+    ///
+    /// The registered events are queued by the `EventHandler` implementation,
+    /// the other events (like `TokenEvent::MetadataUpdated`) can be queued directly.
+    pub async fn push(&self, event: StreamEvent) -> Result<(), EventSinkError> {
+        let mut buffer = self.buffer.lock().await;
+
+        buffer.records.push(event);
+        buffer.oldest_at.get_or_insert_with(Instant::now);
+
+        if buffer.records.len() >= self.batch_size() || self.is_expired(&buffer) {
+            self.flush_buffer(&mut buffer).await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Flushes the buffer only if the oldest record is older than `max_batch_age`.
+    pub async fn flush_expired(&self) -> Result<(), EventSinkError> {
+        let mut buffer = self.buffer.lock().await;
+
+        if self.is_expired(&buffer) {
+            self.flush_buffer(&mut buffer).await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Emits all the buffered records.
+    pub async fn flush(&self) -> Result<(), EventSinkError> {
+        let mut buffer = self.buffer.lock().await;
+        self.flush_buffer(&mut buffer).await
+    }
+
+    fn is_expired(&self, buffer: &Buffer) -> bool {
+        buffer
+            .oldest_at
+            .map_or(false, |t| t.elapsed() >= self.config.max_batch_age)
+    }
+
+    async fn flush_buffer(&self, buffer: &mut Buffer) -> Result<(), EventSinkError> {
+        let records = std::mem::take(&mut buffer.records);
+        buffer.oldest_at = None;
+
+        let mut not_emitted = 0;
+        for batch in records.chunks(self.batch_size()) {
+            not_emitted += self.emit_batch(batch).await;
+        }
+
+        if not_emitted > 0 {
+            Err(EventSinkError::RecordsNotEmitted(not_emitted))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Emits a batch, retrying the failed records by index.
+    /// Returns the number of records that could not be emitted.
+    async fn emit_batch(&self, batch: &[StreamEvent]) -> usize {
+        let mut pending = batch.to_vec();
+        let mut delay = self.config.retry_delay;
+        let mut attempt = 0;
+
+        loop {
+            debug!(
+                "Emitting {} records (attempt #{})",
+                pending.len(),
+                attempt + 1
+            );
+
+            pending = match self.sink.put_records(&pending).await {
+                Ok(failed) => failed
+                    .into_iter()
+                    .filter_map(|i| pending.get(i).cloned())
+                    .collect(),
+                Err(e) => {
+                    warn!("Error while emitting records: {}", e);
+                    pending
+                }
+            };
+
+            if pending.is_empty() {
+                return 0;
+            }
+
+            if attempt >= self.config.max_retries {
+                error!("{} records could not be emitted", pending.len());
+                return pending.len();
+            }
+
+            attempt += 1;
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
+
+#[async_trait]
+impl<K: EventSink + Send + Sync> EventHandler for BatchedEventSink<K> {
+    async fn on_event_registered(&self, event: TokenEvent) {
+        let event = match StreamEvent::from_registered_event(&event) {
+            Some(event) => event,
+            None => {
+                warn!("Event {} without type not emitted", event.event_id);
+                return;
+            }
+        };
+
+        if let Err(e) = self.push(event).await {
+            error!("Event sink: {}", e);
+        }
+    }
+
+    async fn on_block_processed(&self, _block_number: u64, _indexation_progress: f64) {
+        if let Err(e) = self.flush_expired().await {
+            error!("Event sink: {}", e);
+        }
+    }
+
+    async fn on_indexation_range_completed(&self) {
+        if let Err(e) = self.flush().await {
+            error!("Event sink: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream_event::{TokenEvent as StreamTokenEvent, TokenTransfer};
+    use std::sync::{Arc, Mutex};
+
+    fn events(count: usize) -> Vec<StreamEvent> {
+        (0..count)
+            .map(|i| {
+                StreamEvent::new(StreamTokenEvent::Transferred(TokenTransfer {
+                    event_id: i.to_string(),
+                    ..Default::default()
+                }))
+            })
+            .collect()
+    }
+
+    fn event_id(event: &StreamEvent) -> String {
+        match &event.event {
+            StreamTokenEvent::Minted(transfer)
+            | StreamTokenEvent::Transferred(transfer)
+            | StreamTokenEvent::Burned(transfer) => transfer.event_id.clone(),
+            StreamTokenEvent::MetadataUpdated(_) => String::new(),
+        }
+    }
+
+    fn config() -> BatchConfig {
+        BatchConfig {
+            max_batch_age: Duration::from_secs(3600),
+            retry_delay: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batched_emission_with_retry() {
+        let mut sink = MockEventSink::default();
+        let calls: Arc<Mutex<Vec<Vec<String>>>> = Arc::new(Mutex::new(vec![]));
+
+        let calls_ref = Arc::clone(&calls);
+        sink.expect_put_records().returning(move |records| {
+            let ids: Vec<String> = records.iter().map(event_id).collect();
+            let mut calls = calls_ref.lock().unwrap();
+            calls.push(ids);
+
+            // The first batch has two failed records, retried once.
+            Box::pin(futures::future::ready(if calls.len() == 1 {
+                Ok(vec![3, 7])
+            } else {
+                Ok(vec![])
+            }))
+        });
+
+        let batched = BatchedEventSink::new(sink, config());
+
+        for e in events(600) {
+            batched.push(e).await.unwrap();
+        }
+
+        // 500 records are emitted as soon as the batch is full.
+        assert_eq!(calls.lock().unwrap().len(), 2);
+
+        // Remaining records are emitted when the indexation ends.
+        batched.on_indexation_range_completed().await;
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0].len(), 500);
+        assert_eq!(calls[1], vec!["3".to_string(), "7".to_string()]);
+        assert_eq!(calls[2].len(), 100);
+        assert_eq!(calls[2][0], "500");
+    }
+
+    #[tokio::test]
+    async fn test_records_not_emitted_after_retries() {
+        let mut sink = MockEventSink::default();
+
+        sink.expect_put_records().times(4).returning(|_| {
+            Box::pin(futures::future::ready(Err(EventSinkError::Emission(
+                "throttled".to_string(),
+            ))))
+        });
+
+        let batched = BatchedEventSink::new(sink, config());
+
+        for e in events(10) {
+            batched.push(e).await.unwrap();
+        }
+
+        match batched.flush().await {
+            Err(EventSinkError::RecordsNotEmitted(n)) => assert_eq!(n, 10),
+            r => panic!("Expected RecordsNotEmitted, got {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_batch_age() {
+        let mut sink = MockEventSink::default();
+        sink.expect_put_records()
+            .times(1)
+            .returning(|_| Box::pin(futures::future::ready(Ok(vec![]))));
+
+        let batched = BatchedEventSink::new(
+            sink,
+            BatchConfig {
+                max_batch_age: Duration::ZERO,
+                ..config()
+            },
+        );
+
+        batched.push(events(1).remove(0)).await.unwrap();
+        batched.flush_expired().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_registered_events_emitted_as_stream_events() {
+        let mut sink = MockEventSink::default();
+        sink.expect_put_records()
+            .withf(|records| {
+                records.len() == 1
+                    && records[0].schema_version == crate::stream_event::STREAM_EVENT_SCHEMA_VERSION
+                    && matches!(&records[0].event, StreamTokenEvent::Minted(t) if t.event_id == "1")
+            })
+            .times(1)
+            .returning(|_| Box::pin(futures::future::ready(Ok(vec![]))));
+
+        let batched = BatchedEventSink::new(sink, config());
+
+        batched
+            .on_event_registered(TokenEvent {
+                event_id: "1".to_string(),
+                event_type: crate::storage::types::EventType::Mint,
+                ..Default::default()
+            })
+            .await;
+        // Not emitted, without type.
+        batched.on_event_registered(TokenEvent::default()).await;
+
+        batched.flush().await.unwrap();
+    }
+}
@@ -0,0 +1,856 @@
+//! Consumption of the events emitted to an external stream
+//! (i.g. a Kinesis stream) by a `BatchedEventSink`.
+//!
+//! `run_stream_consumer` polls the shards of the stream and dispatches
+//! the records to an `EventHandler`, checkpointing the last sequence number
+//! processed of each shard to resume from it. The records of a shard are
+//! processed in order, several shards being polled concurrently.
+//!
+//! On resharding, a closed shard is consumed until its end before
+//! its children are, to keep the order of the events of a token.
+//!
+//! The mints, transfers and burns are dispatched to
+//! `EventHandler::on_event_registered`, the metadata updates are skipped.
+//!
+//! To recover from a bad deploy, `replay_stream` replays the records from
+//! a point in time or a sequence number, resetting the checkpoints.
+use crate::event_handler::EventHandler;
+use crate::stream_event::StreamEvent;
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+#[cfg(test)]
+use mockall::automock;
+
+/// Checkpoint of a shard closed by a resharding and consumed until its end.
+pub const SHARD_END: &str = "SHARD_END";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventSourceError {
+    /// The call to the source failed.
+    Source(String),
+    /// The shard iterator expired, a new one must be requested.
+    ExpiredIterator,
+    /// The checkpoint couldn't be read or saved.
+    Checkpoint(String),
+}
+
+impl fmt::Display for EventSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventSourceError::Source(s) => write!(f, "Records consumption failed: {s}"),
+            EventSourceError::ExpiredIterator => write!(f, "Shard iterator expired"),
+            EventSourceError::Checkpoint(s) => write!(f, "Checkpoint failed: {s}"),
+        }
+    }
+}
+
+impl std::error::Error for EventSourceError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shard {
+    pub shard_id: String,
+    /// Shards split or merged into this one, to be consumed first.
+    pub parent_shard_ids: Vec<String>,
+}
+
+/// Position of a shard iterator.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StartingPosition {
+    /// Oldest record of the shard.
+    TrimHorizon,
+    /// Record following the given sequence number.
+    AfterSequenceNumber(String),
+    /// Record with the given sequence number.
+    AtSequenceNumber(String),
+    /// First record added at or after the given timestamp (in seconds).
+    AtTimestamp(u64),
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamRecord {
+    pub sequence_number: String,
+    pub event: StreamEvent,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RecordsPage {
+    pub records: Vec<StreamRecord>,
+    /// Iterator of the next records, `None` once the shard is closed.
+    pub next_iterator: Option<String>,
+}
+
+/// A stream source made of shards of records.
+#[async_trait]
+#[cfg_attr(test, automock)]
+pub trait EventSource {
+    /// Lists all the shards of the stream, open or closed.
+    async fn list_shards(&self) -> Result<Vec<Shard>, EventSourceError>;
+
+    /// Returns an iterator reading the shard from the given position.
+    async fn get_shard_iterator(
+        &self,
+        shard_id: &str,
+        position: &StartingPosition,
+    ) -> Result<String, EventSourceError>;
+
+    /// Reads the records at the given iterator.
+    ///
+    /// Returns `EventSourceError::ExpiredIterator` if the iterator expired.
+    async fn get_records(&self, iterator: &str) -> Result<RecordsPage, EventSourceError>;
+}
+
+/// Storage of the last sequence number processed of each shard.
+#[async_trait]
+#[cfg_attr(test, automock)]
+pub trait CheckpointStore {
+    async fn get_checkpoint(&self, shard_id: &str) -> Result<Option<String>, EventSourceError>;
+
+    async fn set_checkpoint(
+        &self,
+        shard_id: &str,
+        sequence_number: &str,
+    ) -> Result<(), EventSourceError>;
+}
+
+pub struct ConsumerConfig {
+    /// Maximum number of shards polled concurrently.
+    pub max_concurrent_shards: usize,
+    /// Delay between two polls when no record was read.
+    pub poll_interval: Duration,
+    /// Delay between two listings of the shards, to detect the reshardings.
+    pub shard_sync_interval: Duration,
+}
+
+impl Default for ConsumerConfig {
+    fn default() -> Self {
+        ConsumerConfig {
+            max_concurrent_shards: 4,
+            poll_interval: Duration::from_secs(1),
+            shard_sync_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+struct ShardState {
+    position: StartingPosition,
+    iterator: Option<String>,
+}
+
+enum PollOutcome {
+    /// Number of records processed.
+    Records(usize),
+    /// The shard is closed and was consumed until its end.
+    Closed,
+}
+
+/// Consumes the stream of the given source until the `shutdown` token
+/// is cancelled, dispatching each record to `EventHandler::on_event_registered`.
+///
+/// Errors are logged and the failing calls retried at the next poll,
+/// the shards resuming from their last checkpoint.
+pub async fn run_stream_consumer<S, C, E>(
+    source: &S,
+    checkpoints: &C,
+    handler: &E,
+    config: &ConsumerConfig,
+    shutdown: CancellationToken,
+) where
+    S: EventSource + Sync,
+    C: CheckpointStore + Sync,
+    E: EventHandler + Sync,
+{
+    let mut active: HashMap<String, ShardState> = HashMap::new();
+    let mut finished: HashSet<String> = HashSet::new();
+    let mut synced_at: Option<Instant> = None;
+
+    while !shutdown.is_cancelled() {
+        if synced_at.map_or(true, |t| t.elapsed() >= config.shard_sync_interval) {
+            match sync_shards(source, checkpoints, &mut active, &mut finished).await {
+                Ok(()) => synced_at = Some(Instant::now()),
+                Err(e) => warn!("Can't list the shards: {}", e),
+            }
+        }
+
+        let results: Vec<(String, ShardState, Result<PollOutcome, EventSourceError>)> =
+            futures::stream::iter(active.drain())
+                .map(|(shard_id, state)| poll_shard(source, checkpoints, handler, shard_id, state))
+                .buffer_unordered(config.max_concurrent_shards.max(1))
+                .collect()
+                .await;
+
+        let mut records_count = 0;
+
+        for (shard_id, state, result) in results {
+            match result {
+                Ok(PollOutcome::Records(n)) => {
+                    records_count += n;
+                    active.insert(shard_id, state);
+                }
+                Ok(PollOutcome::Closed) => {
+                    info!("Shard {} consumed until its end", shard_id);
+                    finished.insert(shard_id);
+                    // Its children can now be consumed.
+                    synced_at = None;
+                }
+                Err(e) => {
+                    warn!("Can't poll shard {}: {}", shard_id, e);
+                    active.insert(shard_id, state);
+                }
+            }
+        }
+
+        if records_count == 0 && synced_at.is_some() {
+            tokio::select! {
+                _ = shutdown.cancelled() => {}
+                _ = tokio::time::sleep(config.poll_interval) => {}
+            }
+        }
+    }
+}
+
+/// Adds to the active shards the shards not yet consumed,
+/// once their parents were consumed until their end.
+async fn sync_shards<S, C>(
+    source: &S,
+    checkpoints: &C,
+    active: &mut HashMap<String, ShardState>,
+    finished: &mut HashSet<String>,
+) -> Result<(), EventSourceError>
+where
+    S: EventSource + Sync,
+    C: CheckpointStore + Sync,
+{
+    let shards = source.list_shards().await?;
+    let listed: HashSet<&str> = shards.iter().map(|s| s.shard_id.as_str()).collect();
+
+    let mut candidates = vec![];
+    for shard in &shards {
+        if active.contains_key(&shard.shard_id) || finished.contains(&shard.shard_id) {
+            continue;
+        }
+
+        match checkpoints.get_checkpoint(&shard.shard_id).await? {
+            Some(c) if c == SHARD_END => {
+                finished.insert(shard.shard_id.clone());
+            }
+            checkpoint => candidates.push((shard, checkpoint)),
+        }
+    }
+
+    for (shard, checkpoint) in candidates {
+        // The parents not listed anymore were trimmed from the stream.
+        let parents_consumed = shard
+            .parent_shard_ids
+            .iter()
+            .all(|p| finished.contains(p) || !listed.contains(p.as_str()));
+
+        if parents_consumed {
+            debug!("Consuming shard {} from {:?}", shard.shard_id, checkpoint);
+            active.insert(
+                shard.shard_id.clone(),
+                ShardState {
+                    position: checkpoint.map_or(
+                        StartingPosition::TrimHorizon,
+                        StartingPosition::AfterSequenceNumber,
+                    ),
+                    iterator: None,
+                },
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Start of a replay of the stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayStart {
+    /// Replays all the shards from the given timestamp (in seconds).
+    Timestamp(u64),
+    /// Replays the given shards from the given sequence number, by shard id.
+    /// The other shards are not replayed.
+    SequenceNumbers(HashMap<String, String>),
+}
+
+/// Summary of a replay of the stream.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReplayReport {
+    /// Number of shards replayed.
+    pub shards: usize,
+    /// Number of records read.
+    pub records: usize,
+    /// Number of records dispatched to the handler, or to dispatch on a
+    /// dry run. The metadata updates are not dispatched.
+    pub events: usize,
+}
+
+/// Replays the records of the stream from the given start, to reprocess
+/// them after a bad deploy.
+///
+/// The shards are replayed one at a time, the parents before their children,
+/// each one until a page without records or its end. The records are
+/// dispatched to `EventHandler::on_event_registered`, and the checkpoint of
+/// each shard is reset to the last record replayed: the consumer resumes
+/// from it and must be stopped during the replay. The events already
+/// processed are dispatched again, the handler being expected to skip them
+/// by their `event_id`, like `Storage::register_events`.
+///
+/// With `dry_run`, the records are only counted, without being dispatched
+/// nor changing the checkpoints.
+pub async fn replay_stream<S, C, E>(
+    source: &S,
+    checkpoints: &C,
+    handler: &E,
+    start: &ReplayStart,
+    dry_run: bool,
+    shutdown: CancellationToken,
+) -> Result<ReplayReport, EventSourceError>
+where
+    S: EventSource + Sync,
+    C: CheckpointStore + Sync,
+    E: EventHandler + Sync,
+{
+    let mut shards = source.list_shards().await?;
+    let mut report = ReplayReport::default();
+
+    while !shards.is_empty() {
+        // A shard is replayed once its parents were, or were trimmed from the stream.
+        let index = shards
+            .iter()
+            .position(|shard| {
+                shard
+                    .parent_shard_ids
+                    .iter()
+                    .all(|p| !shards.iter().any(|s| &s.shard_id == p))
+            })
+            .unwrap_or(0);
+        let shard = shards.remove(index);
+
+        let position = match start {
+            ReplayStart::Timestamp(timestamp) => StartingPosition::AtTimestamp(*timestamp),
+            ReplayStart::SequenceNumbers(sequence_numbers) => {
+                match sequence_numbers.get(&shard.shard_id) {
+                    Some(sequence_number) => {
+                        StartingPosition::AtSequenceNumber(sequence_number.clone())
+                    }
+                    None => continue,
+                }
+            }
+        };
+
+        if shutdown.is_cancelled() {
+            warn!("Replay stopped before shard {}", shard.shard_id);
+            break;
+        }
+
+        info!(
+            "Replaying shard {} from {:?}{}",
+            shard.shard_id,
+            position,
+            if dry_run { " (dry run)" } else { "" }
+        );
+        replay_shard(
+            source,
+            checkpoints,
+            handler,
+            &shard.shard_id,
+            position,
+            dry_run,
+            &mut report,
+        )
+        .await?;
+        report.shards += 1;
+    }
+
+    info!(
+        "Replay done: {} records, {} events of {} shards{}",
+        report.records,
+        report.events,
+        report.shards,
+        if dry_run { " (dry run)" } else { "" }
+    );
+
+    Ok(report)
+}
+
+/// Replays one shard from the given position, until a page without
+/// records or its end.
+async fn replay_shard<S, C, E>(
+    source: &S,
+    checkpoints: &C,
+    handler: &E,
+    shard_id: &str,
+    mut position: StartingPosition,
+    dry_run: bool,
+    report: &mut ReplayReport,
+) -> Result<(), EventSourceError>
+where
+    S: EventSource + Sync,
+    C: CheckpointStore + Sync,
+    E: EventHandler + Sync,
+{
+    let mut iterator = None;
+
+    loop {
+        let current_iterator = match iterator.take() {
+            Some(iterator) => iterator,
+            None => source.get_shard_iterator(shard_id, &position).await?,
+        };
+
+        let page = match source.get_records(&current_iterator).await {
+            Ok(page) => page,
+            Err(EventSourceError::ExpiredIterator) => {
+                debug!("Iterator of shard {} expired", shard_id);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let records_count = page.records.len();
+        for record in page.records {
+            report.records += 1;
+            if let Some(event) = record.event.into_registered_event() {
+                report.events += 1;
+                if !dry_run {
+                    handler.on_event_registered(event).await;
+                }
+            }
+            position = StartingPosition::AfterSequenceNumber(record.sequence_number);
+        }
+
+        if !dry_run && records_count > 0 {
+            if let StartingPosition::AfterSequenceNumber(ref sequence_number) = position {
+                checkpoints
+                    .set_checkpoint(shard_id, sequence_number)
+                    .await?;
+            }
+        }
+
+        match page.next_iterator {
+            Some(_) if records_count == 0 => return Ok(()),
+            Some(next_iterator) => iterator = Some(next_iterator),
+            None => {
+                if !dry_run {
+                    checkpoints.set_checkpoint(shard_id, SHARD_END).await?;
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Reads and dispatches one page of records of the shard.
+async fn poll_shard<S, C, E>(
+    source: &S,
+    checkpoints: &C,
+    handler: &E,
+    shard_id: String,
+    mut state: ShardState,
+) -> (String, ShardState, Result<PollOutcome, EventSourceError>)
+where
+    S: EventSource + Sync,
+    C: CheckpointStore + Sync,
+    E: EventHandler + Sync,
+{
+    let iterator = match state.iterator.take() {
+        Some(iterator) => iterator,
+        None => match source.get_shard_iterator(&shard_id, &state.position).await {
+            Ok(iterator) => iterator,
+            Err(e) => return (shard_id, state, Err(e)),
+        },
+    };
+
+    let page = match source.get_records(&iterator).await {
+        Ok(page) => page,
+        Err(EventSourceError::ExpiredIterator) => {
+            // A new iterator is requested from the last processed record.
+            debug!("Iterator of shard {} expired", shard_id);
+            return (shard_id, state, Ok(PollOutcome::Records(0)));
+        }
+        Err(e) => {
+            state.iterator = Some(iterator);
+            return (shard_id, state, Err(e));
+        }
+    };
+
+    let records_count = page.records.len();
+    for record in page.records {
+        if let Some(event) = record.event.into_registered_event() {
+            handler.on_event_registered(event).await;
+        }
+        state.position = StartingPosition::AfterSequenceNumber(record.sequence_number);
+    }
+
+    if records_count > 0 {
+        if let StartingPosition::AfterSequenceNumber(ref sequence_number) = state.position {
+            if let Err(e) = checkpoints.set_checkpoint(&shard_id, sequence_number).await {
+                return (shard_id, state, Err(e));
+            }
+        }
+    }
+
+    match page.next_iterator {
+        Some(next_iterator) => {
+            state.iterator = Some(next_iterator);
+            (shard_id, state, Ok(PollOutcome::Records(records_count)))
+        }
+        None => match checkpoints.set_checkpoint(&shard_id, SHARD_END).await {
+            Ok(()) => (shard_id, state, Ok(PollOutcome::Closed)),
+            Err(e) => (shard_id, state, Err(e)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::types::TokenEvent;
+    use crate::stream_event::{TokenEvent as StreamTokenEvent, TokenTransfer};
+    use std::sync::Mutex;
+
+    struct TestSource {
+        shards: Vec<Shard>,
+        iterators: Mutex<Vec<(String, StartingPosition)>>,
+        expired_once: Mutex<bool>,
+    }
+
+    fn record(sequence_number: &str) -> StreamRecord {
+        StreamRecord {
+            sequence_number: sequence_number.to_string(),
+            event: StreamEvent::new(StreamTokenEvent::Transferred(TokenTransfer {
+                event_id: sequence_number.to_string(),
+                ..Default::default()
+            })),
+        }
+    }
+
+    #[async_trait]
+    impl EventSource for TestSource {
+        async fn list_shards(&self) -> Result<Vec<Shard>, EventSourceError> {
+            Ok(self.shards.clone())
+        }
+
+        async fn get_shard_iterator(
+            &self,
+            shard_id: &str,
+            position: &StartingPosition,
+        ) -> Result<String, EventSourceError> {
+            self.iterators
+                .lock()
+                .unwrap()
+                .push((shard_id.to_string(), position.clone()));
+
+            Ok(match (shard_id, position) {
+                ("parent", StartingPosition::TrimHorizon) => "parent-0",
+                ("child", StartingPosition::TrimHorizon) => "child-0",
+                ("child", StartingPosition::AfterSequenceNumber(_)) => "child-2",
+                _ => "unknown",
+            }
+            .to_string())
+        }
+
+        async fn get_records(&self, iterator: &str) -> Result<RecordsPage, EventSourceError> {
+            match iterator {
+                "parent-0" => Ok(RecordsPage {
+                    records: vec![record("1"), record("2")],
+                    next_iterator: None,
+                }),
+                "child-0" => Ok(RecordsPage {
+                    records: vec![record("3")],
+                    next_iterator: Some("child-1".to_string()),
+                }),
+                "child-1" => {
+                    let mut expired_once = self.expired_once.lock().unwrap();
+                    if *expired_once {
+                        Ok(RecordsPage::default())
+                    } else {
+                        *expired_once = true;
+                        Err(EventSourceError::ExpiredIterator)
+                    }
+                }
+                "child-2" => Ok(RecordsPage {
+                    records: vec![record("4")],
+                    next_iterator: Some("child-1".to_string()),
+                }),
+                _ => Err(EventSourceError::Source("Unknown iterator".to_string())),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct TestCheckpoints {
+        checkpoints: Mutex<HashMap<String, String>>,
+    }
+
+    #[async_trait]
+    impl CheckpointStore for TestCheckpoints {
+        async fn get_checkpoint(&self, shard_id: &str) -> Result<Option<String>, EventSourceError> {
+            Ok(self.checkpoints.lock().unwrap().get(shard_id).cloned())
+        }
+
+        async fn set_checkpoint(
+            &self,
+            shard_id: &str,
+            sequence_number: &str,
+        ) -> Result<(), EventSourceError> {
+            self.checkpoints
+                .lock()
+                .unwrap()
+                .insert(shard_id.to_string(), sequence_number.to_string());
+            Ok(())
+        }
+    }
+
+    struct TestHandler {
+        events: Mutex<Vec<String>>,
+        expected: usize,
+        shutdown: CancellationToken,
+    }
+
+    #[async_trait]
+    impl EventHandler for TestHandler {
+        async fn on_event_registered(&self, event: TokenEvent) {
+            let mut events = self.events.lock().unwrap();
+            events.push(event.event_id);
+            if events.len() == self.expected {
+                self.shutdown.cancel();
+            }
+        }
+    }
+
+    fn config() -> ConsumerConfig {
+        ConsumerConfig {
+            poll_interval: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_consume_resharded_stream() {
+        let source = TestSource {
+            shards: vec![
+                Shard {
+                    shard_id: "child".to_string(),
+                    parent_shard_ids: vec!["parent".to_string()],
+                },
+                Shard {
+                    shard_id: "parent".to_string(),
+                    parent_shard_ids: vec![],
+                },
+            ],
+            iterators: Mutex::new(vec![]),
+            expired_once: Mutex::new(false),
+        };
+        let checkpoints = TestCheckpoints::default();
+        let shutdown = CancellationToken::new();
+        let handler = TestHandler {
+            events: Mutex::new(vec![]),
+            expected: 4,
+            shutdown: shutdown.clone(),
+        };
+
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            run_stream_consumer(&source, &checkpoints, &handler, &config(), shutdown),
+        )
+        .await
+        .unwrap();
+
+        // The child shard is only consumed once its parent is closed.
+        assert_eq!(*handler.events.lock().unwrap(), vec!["1", "2", "3", "4"]);
+
+        // The expired iterator is replaced from the last processed record.
+        assert_eq!(
+            *source.iterators.lock().unwrap(),
+            vec![
+                ("parent".to_string(), StartingPosition::TrimHorizon),
+                ("child".to_string(), StartingPosition::TrimHorizon),
+                (
+                    "child".to_string(),
+                    StartingPosition::AfterSequenceNumber("3".to_string())
+                ),
+            ]
+        );
+
+        let checkpoints = checkpoints.checkpoints.lock().unwrap();
+        assert_eq!(checkpoints.get("parent").unwrap(), SHARD_END);
+        assert_eq!(checkpoints.get("child").unwrap(), "4");
+    }
+
+    /// A single open shard, whose records are read two by two,
+    /// the iterators being the index of the next record.
+    struct ReplaySource {
+        /// Timestamp and sequence number of the records.
+        records: Vec<(u64, &'static str)>,
+    }
+
+    #[async_trait]
+    impl EventSource for ReplaySource {
+        async fn list_shards(&self) -> Result<Vec<Shard>, EventSourceError> {
+            Ok(vec![Shard {
+                shard_id: "shard".to_string(),
+                parent_shard_ids: vec![],
+            }])
+        }
+
+        async fn get_shard_iterator(
+            &self,
+            _shard_id: &str,
+            position: &StartingPosition,
+        ) -> Result<String, EventSourceError> {
+            let index = match position {
+                StartingPosition::AtTimestamp(timestamp) => {
+                    self.records.iter().position(|(t, _)| t >= timestamp)
+                }
+                StartingPosition::AtSequenceNumber(sequence_number) => {
+                    self.records.iter().position(|(_, s)| s == sequence_number)
+                }
+                StartingPosition::AfterSequenceNumber(sequence_number) => self
+                    .records
+                    .iter()
+                    .position(|(_, s)| s == sequence_number)
+                    .map(|index| index + 1),
+                StartingPosition::TrimHorizon => Some(0),
+            };
+
+            Ok(index.unwrap_or(self.records.len()).to_string())
+        }
+
+        async fn get_records(&self, iterator: &str) -> Result<RecordsPage, EventSourceError> {
+            let index: usize = iterator.parse().unwrap();
+            let end = (index + 2).min(self.records.len());
+
+            Ok(RecordsPage {
+                records: self.records[index..end]
+                    .iter()
+                    .map(|(_, s)| record(s))
+                    .collect(),
+                next_iterator: Some(end.to_string()),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_stream() {
+        let source = ReplaySource {
+            records: vec![(10, "1"), (20, "2"), (30, "3"), (40, "4"), (50, "5")],
+        };
+        let checkpoints = TestCheckpoints::default();
+        checkpoints.set_checkpoint("shard", "3").await.unwrap();
+        let handler = TestHandler {
+            events: Mutex::new(vec![]),
+            expected: usize::MAX,
+            shutdown: CancellationToken::new(),
+        };
+
+        // The dry run only counts the records.
+        let report = replay_stream(
+            &source,
+            &checkpoints,
+            &handler,
+            &ReplayStart::Timestamp(25),
+            true,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            report,
+            ReplayReport {
+                shards: 1,
+                records: 3,
+                events: 3,
+            }
+        );
+        assert!(handler.events.lock().unwrap().is_empty());
+        assert_eq!(
+            checkpoints.get_checkpoint("shard").await.unwrap().unwrap(),
+            "3"
+        );
+
+        let report = replay_stream(
+            &source,
+            &checkpoints,
+            &handler,
+            &ReplayStart::SequenceNumbers(HashMap::from([("shard".to_string(), "2".to_string())])),
+            false,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.records, 4);
+        assert_eq!(*handler.events.lock().unwrap(), vec!["2", "3", "4", "5"]);
+        assert_eq!(
+            checkpoints.get_checkpoint("shard").await.unwrap().unwrap(),
+            "5"
+        );
+
+        // The shards without sequence number are not replayed.
+        let report = replay_stream(
+            &source,
+            &checkpoints,
+            &handler,
+            &ReplayStart::SequenceNumbers(HashMap::new()),
+            false,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(report, ReplayReport::default());
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_checkpoints() {
+        let source = TestSource {
+            shards: vec![
+                Shard {
+                    shard_id: "parent".to_string(),
+                    parent_shard_ids: vec![],
+                },
+                Shard {
+                    shard_id: "child".to_string(),
+                    parent_shard_ids: vec!["parent".to_string()],
+                },
+            ],
+            iterators: Mutex::new(vec![]),
+            expired_once: Mutex::new(true),
+        };
+        let checkpoints = TestCheckpoints::default();
+        checkpoints
+            .set_checkpoint("parent", SHARD_END)
+            .await
+            .unwrap();
+        checkpoints.set_checkpoint("child", "3").await.unwrap();
+
+        let shutdown = CancellationToken::new();
+        let handler = TestHandler {
+            events: Mutex::new(vec![]),
+            expected: 1,
+            shutdown: shutdown.clone(),
+        };
+
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            run_stream_consumer(&source, &checkpoints, &handler, &config(), shutdown),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(*handler.events.lock().unwrap(), vec!["4"]);
+        assert_eq!(
+            *source.iterators.lock().unwrap(),
+            vec![(
+                "child".to_string(),
+                StartingPosition::AfterSequenceNumber("3".to_string())
+            )]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Health check of the indexer, for the readiness and liveness probes.
+//!
+//! `serve_health` serves the report of `Pontos::health_check` at `GET /healthz`,
+//! with a `200` status only when the indexer is healthy, `503` otherwise.
+use crate::event_handler::EventHandler;
+use crate::storage::Storage;
+use crate::{IndexerError, IndexerResult, Pontos};
+use ark_starknet::client::StarknetClient;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::info;
+
+/// Result of a health check.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub rpc_reachable: bool,
+    pub storage_reachable: bool,
+    /// Latest block of the chain, if the RPC is reachable.
+    pub head_block: Option<u64>,
+    /// Last block fully processed, if any.
+    pub last_processed_block: Option<u64>,
+    /// Number of blocks between the chain head and the last processed block.
+    pub block_lag: Option<u64>,
+}
+
+impl HealthReport {
+    /// Builds the report, the lag being only checked once a block was processed.
+    pub fn new(
+        head_block: Option<u64>,
+        storage_reachable: bool,
+        last_processed_block: Option<u64>,
+        max_block_lag: u64,
+    ) -> Self {
+        let block_lag = match (head_block, last_processed_block) {
+            (Some(head), Some(last)) => Some(head.saturating_sub(last)),
+            _ => None,
+        };
+
+        let rpc_reachable = head_block.is_some();
+
+        Self {
+            healthy: rpc_reachable
+                && storage_reachable
+                && block_lag.map_or(true, |lag| lag <= max_block_lag),
+            rpc_reachable,
+            storage_reachable,
+            head_block,
+            last_processed_block,
+            block_lag,
+        }
+    }
+}
+
+/// Serves the health check of the given indexer at `GET /healthz` on the given
+/// address. The indexer is unhealthy if lagging more than `max_block_lag` blocks.
+pub async fn serve_health<S, C, E>(
+    addr: SocketAddr,
+    pontos: Arc<Pontos<S, C, E>>,
+    max_block_lag: u64,
+) -> IndexerResult<()>
+where
+    S: Storage + Send + Sync + 'static,
+    C: StarknetClient + Send + Sync + 'static,
+    E: EventHandler + Send + Sync + 'static,
+{
+    let make_service = make_service_fn(move |_| {
+        let pontos = Arc::clone(&pontos);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let pontos = Arc::clone(&pontos);
+                async move { handle_request(request, &pontos, max_block_lag).await }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)
+        .map_err(|e| IndexerError::Anyhow(e.to_string()))?
+        .serve(make_service);
+
+    info!("Serving health check on http://{}/healthz", addr);
+
+    server
+        .await
+        .map_err(|e| IndexerError::Anyhow(e.to_string()))
+}
+
+async fn handle_request<S, C, E>(
+    request: Request<Body>,
+    pontos: &Pontos<S, C, E>,
+    max_block_lag: u64,
+) -> Result<Response<Body>, Infallible>
+where
+    S: Storage,
+    C: StarknetClient + Send + Sync,
+    E: EventHandler + Send + Sync,
+{
+    let mut response = Response::new(Body::empty());
+
+    if request.method() != Method::GET || request.uri().path() != "/healthz" {
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+
+    let report = pontos.health_check(max_block_lag).await;
+
+    if !report.healthy {
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    }
+
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        "application/json"
+            .parse()
+            .expect("application/json is a valid header value"),
+    );
+    *response.body_mut() = Body::from(serde_json::to_string(&report).unwrap_or_default());
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::types::StorageError;
+    use crate::storage::MockStorage;
+    use crate::PontosConfig;
+    use ark_starknet::client::{MockStarknetClient, StarknetClientError};
+
+    struct NoopHandler;
+
+    #[async_trait::async_trait]
+    impl EventHandler for NoopHandler {}
+
+    fn pontos(
+        head_block: Option<u64>,
+        last_processed_block: Result<Option<u64>, ()>,
+    ) -> Pontos<MockStorage, MockStarknetClient, NoopHandler> {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+
+        mock_client.expect_block_number().returning(move || {
+            head_block.ok_or_else(|| StarknetClientError::Other("unreachable".to_string()))
+        });
+        mock_storage
+            .expect_get_last_processed_block()
+            .returning(move |_| {
+                Box::pin(futures::future::ready(last_processed_block.map_err(|_| {
+                    StorageError::DatabaseError("unreachable".to_string())
+                })))
+            });
+
+        Pontos::new(
+            Arc::new(mock_client),
+            Arc::new(mock_storage),
+            Arc::new(NoopHandler),
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                ..Default::default()
+            },
+        )
+    }
+
+    async fn get_healthz(
+        pontos: &Pontos<MockStorage, MockStarknetClient, NoopHandler>,
+    ) -> StatusCode {
+        let request = Request::get("/healthz").body(Body::empty()).unwrap();
+        handle_request(request, pontos, 10).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let report = pontos(Some(100), Ok(Some(95))).health_check(10).await;
+        assert!(report.healthy);
+        assert_eq!(report.block_lag, Some(5));
+
+        // Nothing processed yet.
+        assert!(pontos(Some(100), Ok(None)).health_check(10).await.healthy);
+
+        let report = pontos(Some(100), Ok(Some(80))).health_check(10).await;
+        assert!(!report.healthy);
+        assert_eq!(report.block_lag, Some(20));
+
+        let report = pontos(None, Ok(Some(80))).health_check(10).await;
+        assert!(!report.healthy);
+        assert!(!report.rpc_reachable);
+
+        let report = pontos(Some(100), Err(())).health_check(10).await;
+        assert!(!report.healthy);
+        assert!(!report.storage_reachable);
+    }
+
+    #[tokio::test]
+    async fn test_healthz_endpoint() {
+        assert_eq!(
+            get_healthz(&pontos(Some(100), Ok(Some(95)))).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_healthz(&pontos(Some(100), Ok(Some(80)))).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        let request = Request::get("/other").body(Body::empty()).unwrap();
+        let response = handle_request(request, &pontos(Some(100), Ok(None)), 10)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -1,19 +1,41 @@
+pub mod contract_filter;
 pub mod event_handler;
+pub mod event_sink;
+pub mod event_source;
+pub mod health;
+pub mod logging;
 pub mod managers;
+pub mod metrics;
+mod rpc_budget;
+pub mod shutdown;
 pub mod storage;
+pub mod stream_event;
 
 use crate::storage::types::BlockIndexingStatus;
 use anyhow::Result;
 use ark_starknet::client::{StarknetClient, StarknetClientError};
+use ark_starknet::explorer::ExplorerLinks;
 use ark_starknet::format::to_hex_str;
+use ark_starknet::network::Network;
+use ark_starknet::{CairoU256, PaddedTokenId};
+use contract_filter::ContractFilter;
 use event_handler::EventHandler;
-use managers::{BlockManager, ContractManager, EventManager, PendingBlockData, TokenManager};
+use futures::stream::{self, StreamExt};
+use health::HealthReport;
+use managers::{
+    BlockManager, ContractManager, ContractTypeDetector, ContractTypeValidation, EventManager,
+    PendingBlockData, TokenManager,
+};
+use rpc_budget::CallCountingClient;
 use starknet::core::types::*;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::sync::Arc;
-use storage::types::{ContractType, StorageError};
+use std::time::Duration;
+use storage::types::{ContractType, StorageError, TokenEvent, TokenInfo};
 use storage::Storage;
-use tokio::sync::RwLock as AsyncRwLock;
+use tokio::sync::{mpsc, RwLock as AsyncRwLock};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, trace, warn};
 
 pub type IndexerResult<T> = Result<T, IndexerError>;
@@ -56,23 +78,90 @@ impl fmt::Display for IndexerError {
 
 impl std::error::Error for IndexerError {}
 
+#[derive(Default)]
 pub struct PontosConfig {
     pub indexer_version: String,
     pub indexer_identifier: String,
+    /// Maximum number of RPC calls made while processing the events of one block.
+    /// Once reached, the remaining events of the block are not processed but
+    /// given to `EventHandler::on_events_deferred` to be processed later.
+    pub max_rpc_calls_per_block: Option<u64>,
+    /// Maximum time spent processing one event. Once reached, the event is
+    /// given to `EventHandler::on_events_deferred` to be processed later.
+    pub event_processing_timeout: Option<Duration>,
+    /// Number of blocks behind the latest block that are not indexed yet
+    /// by `index_block_range`, to reduce the exposure to chain reorganizations.
+    /// The range is extended as the latest block, read again during the range,
+    /// moves forward, up to its requested end.
+    pub confirmation_depth: Option<u64>,
+    /// Maximum number of contracts whose events are processed concurrently
+    /// within a block. The events of a same contract are always processed in
+    /// order. Defaults to 1, processing all the events sequentially.
+    pub max_concurrent_events: Option<usize>,
+    /// Drops the events of a block describing the same transfer as a previous
+    /// one (same transaction, contract, sender, recipient and token id), like
+    /// a contract emitting both a standard and a legacy `Transfer` event.
+    pub deduplicate_events: bool,
+    /// Contracts whose events are indexed. The events of the other
+    /// contracts are dropped before identifying their contract.
+    pub contract_filter: ContractFilter,
+    /// Network indexed, tagging the registered tokens and events.
+    /// `Pontos::verify_network` checks that the RPC serves this network.
+    pub network: Network,
+    /// Number of event pages of a block fetched ahead of their processing.
+    /// Once the queue is full, the fetching waits for the processing to catch
+    /// up. Defaults to `DEFAULT_EVENT_QUEUE_CAPACITY`.
+    pub event_queue_capacity: Option<usize>,
+    /// When set, the `totalSupply` of the ERC721 collections is read on-chain
+    /// when they are identified, and refreshed at most every given interval
+    /// when their events are processed. Not read by default, saving the RPC calls.
+    pub total_supply_refresh_interval: Option<Duration>,
+    /// When set, the registered events are given the explorer URL of their
+    /// transaction as `transaction_url`, see `ark_starknet::explorer`.
+    pub explorer_links: Option<ExplorerLinks>,
+    /// When set, the contract type of each collection, as stored when it was
+    /// identified, is checked against the type detected on-chain (cached by
+    /// address). The mismatches are logged, and their events dropped with
+    /// `ContractTypeValidation::Reject`. Not checked by default.
+    pub contract_type_validation: Option<ContractTypeValidation>,
 }
 
-pub struct Pontos<S: Storage, C: StarknetClient, E: EventHandler> {
+/// Default number of event pages of a block waiting to be processed.
+pub const DEFAULT_EVENT_QUEUE_CAPACITY: usize = 4;
+
+/// Number of blocks indexed by `index_block_range` before reading the latest block again.
+const HEAD_REFRESH_INTERVAL: u64 = 10;
+
+/// Maximum number of blocks rolled back on a chain reorganization.
+const MAX_REORG_DEPTH: u64 = 64;
+
+/// Summary of a block range backfill.
+#[derive(Debug, Default)]
+pub struct BackfillReport {
+    /// Number of blocks indexed.
+    pub indexed_blocks: u64,
+    /// Number of blocks skipped as already indexed.
+    pub skipped_blocks: u64,
+    /// Blocks that couldn't be indexed, with the related error.
+    pub failed_blocks: Vec<(u64, IndexerError)>,
+    /// Block to start from to resume the backfill.
+    pub next_cursor: u64,
+}
+
+pub struct Pontos<S: Storage, C: StarknetClient + Send + Sync, E: EventHandler> {
     client: Arc<C>,
     event_handler: Arc<E>,
     config: PontosConfig,
     block_manager: Arc<BlockManager<S>>,
     event_manager: Arc<EventManager<S>>,
-    token_manager: Arc<TokenManager<S, C>>,
-    contract_manager: Arc<AsyncRwLock<ContractManager<S, C>>>,
+    token_manager: Arc<TokenManager<S, CallCountingClient<C>>>,
+    contract_manager: Arc<ContractManager<S, CallCountingClient<C>>>,
+    contract_type_detector: ContractTypeDetector<CallCountingClient<C>>,
     pending_cache: Arc<AsyncRwLock<PendingBlockData>>,
+    shutdown: CancellationToken,
 }
 
-impl<S: Storage, C: StarknetClient, E: EventHandler + Send + Sync> Pontos<S, C, E> {
+impl<S: Storage, C: StarknetClient + Send + Sync, E: EventHandler + Send + Sync> Pontos<S, C, E> {
     ///
     pub fn new(
         client: Arc<C>,
@@ -80,27 +169,55 @@ impl<S: Storage, C: StarknetClient, E: EventHandler + Send + Sync> Pontos<S, C,
         event_handler: Arc<E>,
         config: PontosConfig,
     ) -> Self {
+        // Managers calls are counted to enforce `max_rpc_calls_per_block`.
+        let counting_client = Arc::new(CallCountingClient::wrap(Arc::clone(&client)));
+        let network = config.network;
+        let total_supply_refresh_interval = config.total_supply_refresh_interval;
+        let explorer_links = config.explorer_links.clone();
+
         Pontos {
             config,
             client: Arc::clone(&client),
             event_handler: Arc::clone(&event_handler),
             block_manager: Arc::new(BlockManager::new(Arc::clone(&storage))),
-            event_manager: Arc::new(EventManager::new(Arc::clone(&storage))),
-            token_manager: Arc::new(TokenManager::new(Arc::clone(&storage), Arc::clone(&client))),
-            // Contract manager has internal cache, so some functions are using `&mut self`.
-            // For this reason, we must protect the write operations in order to share
-            // the cache with any possible thread using `index_block_range` of this instance.
-            contract_manager: Arc::new(AsyncRwLock::new(ContractManager::new(
-                Arc::clone(&storage),
-                Arc::clone(&client),
-            ))),
+            event_manager: Arc::new(
+                EventManager::new(Arc::clone(&storage))
+                    .with_network(network)
+                    .with_explorer_links(explorer_links),
+            ),
+            token_manager: Arc::new(
+                TokenManager::new(Arc::clone(&storage), Arc::clone(&counting_client))
+                    .with_network(network),
+            ),
+            // Contract manager has an internal cache, locked only to read or insert
+            // a contract, to share it with any possible thread using `index_block_range`
+            // of this instance without waiting for the identification of the others.
+            contract_manager: Arc::new(
+                ContractManager::new(Arc::clone(&storage), Arc::clone(&counting_client))
+                    .with_total_supply_refresh_interval(total_supply_refresh_interval),
+            ),
+            contract_type_detector: ContractTypeDetector::new(counting_client),
             pending_cache: Arc::new(AsyncRwLock::new(PendingBlockData::new())),
+            shutdown: CancellationToken::new(),
         }
     }
 
+    /// Returns the token stopping the indexation once cancelled.
+    ///
+    /// The block being processed is finished and its cursor saved,
+    /// but no new block is started.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
     /// Starts a loop to only index the pending block.
     pub async fn index_pending(&self) -> IndexerResult<()> {
         loop {
+            if self.shutdown.is_cancelled() {
+                info!("Shutdown requested, stopping pending block indexation");
+                break;
+            }
+
             let mut cache = self.pending_cache.write().await;
 
             let (pending_ts, txs) = match self
@@ -156,6 +273,11 @@ impl<S: Storage, C: StarknetClient, E: EventHandler + Send + Sync> Pontos<S, C,
             // TODO: make this configurable?
             tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
         }
+
+        // The handler can flush its buffered events before exiting.
+        self.event_handler.on_indexation_range_completed().await;
+
+        Ok(())
     }
 
     pub async fn index_contract_events(
@@ -223,9 +345,14 @@ impl<S: Storage, C: StarknetClient, E: EventHandler + Send + Sync> Pontos<S, C,
         do_force: bool,
     ) -> IndexerResult<()> {
         let mut current_u64 = self.client.block_id_to_u64(&from_block).await?;
-        let to_u64 = self.client.block_id_to_u64(&to_block).await?;
+        let requested_to_u64 = self.client.block_id_to_u64(&to_block).await?;
         let from_u64 = current_u64;
 
+        // The head is read again every `HEAD_REFRESH_INTERVAL` blocks, and once the
+        // confirmed blocks are indexed, to follow the chain during the long ranges.
+        let (mut head_u64, mut to_u64) = self.read_head_block(requested_to_u64).await?;
+        let mut head_read_at = current_u64;
+
         // Some contracts are causing too much recursion for the Cairo VM.
         // This is restarting the full node (Juno) as it is OOM and is shutdown by the OS.
         // To mitigate this problem before scaling the full node up,
@@ -239,16 +366,38 @@ impl<S: Storage, C: StarknetClient, E: EventHandler + Send + Sync> Pontos<S, C,
         loop {
             trace!("Indexing block range: {} {}", current_u64, to_u64);
 
+            if self.shutdown.is_cancelled() {
+                info!("Shutdown requested, stopping before block {}", current_u64);
+                break;
+            }
+
+            if current_u64 >= head_read_at + HEAD_REFRESH_INTERVAL
+                || (current_u64 > to_u64
+                    && to_u64 < requested_to_u64
+                    && current_u64 != head_read_at)
+            {
+                match self.read_head_block(requested_to_u64).await {
+                    Ok((head, to)) => {
+                        head_u64 = head;
+                        to_u64 = to;
+                    }
+                    Err(e) => warn!("Couldn't refresh the latest block: {:?}", e),
+                }
+                head_read_at = current_u64;
+            }
+
             if current_u64 > to_u64 {
                 info!("End of indexing block range");
                 break;
             }
 
-            let block_ts = match self.client.block_time(BlockId::Number(current_u64)).await {
-                Ok(ts) => ts,
+            // The timestamp and hashes are read at once, to be from the same block
+            // even if a reorganization happens meanwhile.
+            let header = match self.client.block_header(BlockId::Number(current_u64)).await {
+                Ok(header) => header,
                 Err(e) => {
                     error!(
-                        "Attempt #{} - Couldn't get timestamp for block {}: {:?}",
+                        "Attempt #{} - Couldn't get header of block {}: {:?}",
                         attempt + 1,
                         current_u64,
                         e
@@ -257,16 +406,29 @@ impl<S: Storage, C: StarknetClient, E: EventHandler + Send + Sync> Pontos<S, C,
                     attempt += 1;
 
                     if attempt > max_attempt {
-                        warn!(
-                            "Skipping block {} as timestamp is not available",
-                            current_u64
-                        );
+                        warn!("Skipping block {} as header is not available", current_u64);
                         current_u64 += 1;
+                        attempt = 0;
                     }
 
                     continue;
                 }
             };
+            let block_ts = header.timestamp;
+            let block_hash = header.hash;
+
+            if let Some(first_orphaned) = self
+                .rollback_orphaned_blocks(current_u64, block_hash, header.parent_hash)
+                .await?
+            {
+                warn!(
+                    "Chain reorganization detected at block {}, reindexing from block {}",
+                    current_u64, first_orphaned
+                );
+                current_u64 = first_orphaned;
+                attempt = 0;
+                continue;
+            }
 
             if self
                 .block_manager
@@ -280,6 +442,7 @@ impl<S: Storage, C: StarknetClient, E: EventHandler + Send + Sync> Pontos<S, C,
             {
                 info!("Skipping block {}", current_u64);
                 current_u64 += 1;
+                attempt = 0;
                 continue;
             }
 
@@ -295,35 +458,40 @@ impl<S: Storage, C: StarknetClient, E: EventHandler + Send + Sync> Pontos<S, C,
                     &self.config.indexer_version,
                     &self.config.indexer_identifier,
                     BlockIndexingStatus::Processing,
+                    Some(to_hex_str(&block_hash)),
                 )
                 .await?;
 
-            let blocks_events = match self
-                .client
-                .fetch_all_block_events(
-                    BlockId::Number(current_u64),
-                    self.event_manager.keys_selector(),
-                )
-                .await
-            {
-                Ok(events) => events,
-                Err(e) => {
-                    error!("Error while fetching events: {:?}", e);
+            info!("✨ Processing block {}.", current_u64);
+
+            let total_events_count = match self.index_block_events(current_u64, block_ts).await {
+                Ok(count) => count,
+                Err(IndexerError::Starknet(e)) => {
+                    error!(
+                        "Attempt #{} - Error while fetching events of block {}: {:?}",
+                        attempt + 1,
+                        current_u64,
+                        e
+                    );
+                    attempt += 1;
+
+                    // The block stays in processing, and is indexed again by the next run.
+                    if attempt > max_attempt {
+                        return Err(IndexerError::Starknet(e));
+                    }
+
+                    // The block is processed again from its first event.
                     tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
                     continue;
                 }
+                Err(e) => return Err(e),
             };
 
-            let total_events_count: usize = blocks_events.values().map(|events| events.len()).sum();
             info!(
-                "✨ Processing block {}. Total Events Count: {}.",
+                "✨ Block {} processed. Total Events Count: {}.",
                 current_u64, total_events_count
             );
 
-            for (_, events) in blocks_events {
-                self.process_events(events, block_ts).await?;
-            }
-
             self.block_manager
                 .set_block_info(
                     current_u64,
@@ -331,8 +499,16 @@ impl<S: Storage, C: StarknetClient, E: EventHandler + Send + Sync> Pontos<S, C,
                     &self.config.indexer_version,
                     &self.config.indexer_identifier,
                     BlockIndexingStatus::Terminated,
+                    Some(to_hex_str(&block_hash)),
                 )
                 .await?;
+            metrics::blocks_processed_total().inc();
+
+            // All the events of the block are processed, the cursor can be advanced.
+            self.advance_last_processed_block(current_u64).await?;
+            if let Some(head) = head_u64 {
+                metrics::head_block_lag().set(head.saturating_sub(current_u64) as i64);
+            }
 
             let progress = if to_u64 == from_u64 {
                 if current_u64 == to_u64 {
@@ -341,7 +517,7 @@ impl<S: Storage, C: StarknetClient, E: EventHandler + Send + Sync> Pontos<S, C,
                     0.0
                 }
             } else {
-                ((current_u64 - from_u64) as f64 / (to_u64 - from_u64) as f64) * 100.0
+                (current_u64.saturating_sub(from_u64) as f64 / (to_u64 - from_u64) as f64) * 100.0
             };
 
             self.event_handler
@@ -349,6 +525,7 @@ impl<S: Storage, C: StarknetClient, E: EventHandler + Send + Sync> Pontos<S, C,
                 .await;
 
             current_u64 += 1;
+            attempt = 0;
         }
 
         self.event_handler.on_indexation_range_completed().await;
@@ -356,70 +533,2199 @@ impl<S: Storage, C: StarknetClient, E: EventHandler + Send + Sync> Pontos<S, C,
         Ok(())
     }
 
-    /// Inner function to process events.
-    async fn process_events(
+    /// Reads the latest block, and returns it with the last block of the range
+    /// to index, capped to the blocks with `confirmation_depth` confirmations.
+    /// Without confirmation depth, failing to read the latest block is not an error.
+    async fn read_head_block(&self, to_u64: u64) -> IndexerResult<(Option<u64>, u64)> {
+        let head = match self.client.block_number().await {
+            Ok(head) => head,
+            Err(e) if self.config.confirmation_depth.is_none() => {
+                warn!("Couldn't read the latest block: {:?}", e);
+                return Ok((None, to_u64));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let to_u64 = match self.config.confirmation_depth {
+            Some(depth) if to_u64 > head.saturating_sub(depth) => {
+                let confirmed_u64 = head.saturating_sub(depth);
+                info!(
+                    "Indexing up to block {} only, waiting for {} confirmations",
+                    confirmed_u64, depth
+                );
+                confirmed_u64
+            }
+            _ => to_u64,
+        };
+
+        Ok((Some(head), to_u64))
+    }
+
+    /// Returns the last block fully processed by `index_block_range` for this
+    /// indexer identifier, to resume the indexation after a restart.
+    pub async fn last_processed_block(&self) -> IndexerResult<Option<u64>> {
+        Ok(self
+            .block_manager
+            .get_last_processed_block(&self.config.indexer_identifier)
+            .await?)
+    }
+
+    /// Reprocesses a single token without replaying its events: its owner
+    /// is re-resolved on-chain and updated in the storage.
+    pub async fn reprocess_token(
         &self,
-        events: Vec<EmittedEvent>,
-        block_timestamp: u64,
-    ) -> IndexerResult<()> {
-        for e in events {
-            let contract_address = e.from_address;
-            info!(
-                "Processing event... Block Id: {:?}, Tx Hash: 0x{:064x}",
-                e.block_number, e.transaction_hash
-            );
+        contract_address: FieldElement,
+        token_id: &CairoU256,
+    ) -> IndexerResult<TokenInfo> {
+        info!(
+            "Reprocessing token {} of [0x{:064x}]",
+            token_id.to_decimal(false),
+            contract_address
+        );
 
-            let contract_type = match self
-                .contract_manager
-                .write()
-                .await
-                .identify_contract(contract_address, block_timestamp)
-                .await
-            {
-                Ok(info) => info,
-                Err(e) => {
-                    warn!(
-                        "Error while identifying contract {}: {:?}",
-                        to_hex_str(&contract_address),
-                        e
-                    );
+        Ok(self
+            .token_manager
+            .refresh_token_owner(contract_address, token_id)
+            .await?)
+    }
+
+    /// Heals the stored owners of the given tokens, drifting from their
+    /// owner on-chain when some transfers are not processed.
+    /// Returns the number of owners corrected.
+    pub async fn reconcile_owners(
+        &self,
+        contract_address: FieldElement,
+        token_ids: &[CairoU256],
+    ) -> IndexerResult<u64> {
+        Ok(self
+            .token_manager
+            .reconcile_owners(contract_address, token_ids)
+            .await?)
+    }
+
+    /// Heals the stored owners of all the registered tokens of the collection.
+    /// Returns the number of owners corrected.
+    pub async fn reconcile_collection_owners(
+        &self,
+        contract_address: FieldElement,
+    ) -> IndexerResult<u64> {
+        info!("Reconciling the owners of [0x{:064x}]", contract_address);
+
+        Ok(self
+            .token_manager
+            .reconcile_collection_owners(contract_address)
+            .await?)
+    }
+
+    /// Checks that the Starknet RPC serves the configured network,
+    /// to not index the blocks of another network in its storage.
+    pub async fn verify_network(&self) -> IndexerResult<()> {
+        ark_starknet::network::verify_network(self.client.as_ref(), self.config.network).await?;
+        Ok(())
+    }
+
+    /// Checks the Starknet RPC and storage reachability, and the lag between
+    /// the chain head and the last processed block. Healthy only if both are
+    /// reachable and the lag is at most `max_block_lag` blocks.
+    pub async fn health_check(&self, max_block_lag: u64) -> HealthReport {
+        let head_block = match self.client.block_number().await {
+            Ok(block_number) => Some(block_number),
+            Err(e) => {
+                warn!("Health check: Starknet RPC unreachable: {}", e);
+                None
+            }
+        };
+
+        let (storage_reachable, last_processed_block) = match self.last_processed_block().await {
+            Ok(block_number) => (true, block_number),
+            Err(e) => {
+                warn!("Health check: storage unreachable: {}", e);
+                (false, None)
+            }
+        };
+
+        HealthReport::new(
+            head_block,
+            storage_reachable,
+            last_processed_block,
+            max_block_lag,
+        )
+    }
+
+    /// Saves the given block as the last processed one, if it's
+    /// after the current one. The cursor never goes backward.
+    async fn advance_last_processed_block(&self, block_number: u64) -> IndexerResult<()> {
+        let last_processed_block = self.last_processed_block().await?;
+
+        if last_processed_block.map_or(true, |last| block_number > last) {
+            self.block_manager
+                .set_last_processed_block(&self.config.indexer_identifier, block_number)
+                .await?;
+            metrics::last_processed_block().set(block_number as i64);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the given block and its parent hashes match the indexed blocks.
+    ///
+    /// On a chain reorganization, the orphaned blocks are cleaned from the storage,
+    /// the cursor is moved back before them, and the first block to reindex is returned.
+    /// The owners of the tokens transferred in the orphaned blocks are read again
+    /// on-chain, to revert their updates.
+    async fn rollback_orphaned_blocks(
+        &self,
+        block_number: u64,
+        block_hash: FieldElement,
+        parent_hash: FieldElement,
+    ) -> IndexerResult<Option<u64>> {
+        let mut first_orphaned = None;
+        let mut current = block_number;
+        let mut expected_hash = block_hash;
+        let mut orphaned_tokens: HashMap<String, HashSet<PaddedTokenId>> = HashMap::new();
+
+        loop {
+            let info = match self.block_manager.get_block_info(current).await? {
+                Some(info) => info,
+                // The block itself may not be indexed yet, its parent must be checked.
+                None if current == block_number => {
+                    if current == 0 {
+                        break;
+                    }
+                    current -= 1;
+                    expected_hash = parent_hash;
                     continue;
                 }
+                None => break,
             };
 
-            if contract_type == ContractType::Other {
-                debug!(
-                    "Contract identified as OTHER: {}",
-                    to_hex_str(&contract_address),
-                );
-                continue;
+            // Blocks indexed without hash can't be checked.
+            match &info.block_hash {
+                Some(hash) if *hash != to_hex_str(&expected_hash) => (),
+                _ => break,
             }
 
-            let (token_id, token_event) = match self
-                .event_manager
-                .format_and_register_event(&e, contract_type, block_timestamp)
-                .await
+            if block_number - current >= MAX_REORG_DEPTH {
+                return Err(IndexerError::Anyhow(format!(
+                    "Chain reorganization deeper than {} blocks at block {}",
+                    MAX_REORG_DEPTH, block_number
+                )));
+            }
+
+            warn!(
+                "Block {} with hash {:?} is orphaned, rolling it back",
+                current, info.block_hash
+            );
+            for event in self
+                .block_manager
+                .get_block_events(info.block_timestamp)
+                .await?
             {
-                Ok(te) => te,
-                Err(err) => {
-                    error!("Error while registering event {:?}\n{:?}", err, e);
+                orphaned_tokens
+                    .entry(event.contract_address)
+                    .or_default()
+                    .insert(event.token_id_hex);
+            }
+            self.block_manager
+                .clean_block(info.block_timestamp, Some(current))
+                .await?;
+            first_orphaned = Some(current);
+
+            if current == 0 {
+                break;
+            }
+
+            if current == block_number {
+                expected_hash = parent_hash;
+            } else {
+                expected_hash = self
+                    .client
+                    .block_header(BlockId::Number(current))
+                    .await?
+                    .parent_hash;
+            }
+            current -= 1;
+        }
+
+        if let Some(first_orphaned) = first_orphaned {
+            if let Some(last) = self.last_processed_block().await? {
+                if last >= first_orphaned {
+                    self.block_manager
+                        .set_last_processed_block(
+                            &self.config.indexer_identifier,
+                            first_orphaned.saturating_sub(1),
+                        )
+                        .await?;
+                }
+            }
+        }
+
+        for (contract_address, token_ids) in orphaned_tokens {
+            let address = match FieldElement::from_hex_be(&contract_address) {
+                Ok(address) => address,
+                Err(_) => {
+                    warn!("Invalid contract address {}, skipping it", contract_address);
                     continue;
                 }
             };
-
-            match self
+            let token_ids: Vec<CairoU256> = token_ids.iter().map(|id| id.to_u256()).collect();
+            let corrected = self
                 .token_manager
-                .format_and_register_token(&token_id, &token_event, block_timestamp, e.block_number)
+                .reconcile_owners(address, &token_ids)
+                .await?;
+            info!(
+                "{} owners of {} restored after the rollback",
+                corrected, contract_address
+            );
+        }
+
+        Ok(first_orphaned)
+    }
+
+    /// Reprocesses the blocks from `cursor` to `to_block` (included).
+    ///
+    /// Up to `concurrency` blocks are indexed at the same time. A failing block
+    /// doesn't abort the range: it is given to `EventHandler::on_block_failed`
+    /// and reported in the returned `BackfillReport`.
+    ///
+    /// `EventHandler::on_block_processed` is called in the blocks order, so the
+    /// last block received + 1 can be used as `cursor` to resume an interrupted
+    /// backfill, like the `next_cursor` of the report.
+    pub async fn backfill_block_range(
+        &self,
+        cursor: u64,
+        to_block: u64,
+        concurrency: usize,
+        do_force: bool,
+    ) -> IndexerResult<BackfillReport> {
+        let mut report = BackfillReport {
+            next_cursor: cursor,
+            ..Default::default()
+        };
+
+        if cursor > to_block {
+            return Ok(report);
+        }
+
+        info!(
+            "Backfilling blocks {} to {} ({} concurrent blocks)",
+            cursor, to_block, concurrency
+        );
+
+        // `buffered` yields the results in the blocks order,
+        // while indexing up to `concurrency` blocks at the same time.
+        // On shutdown, the blocks being indexed are finished but no new one is started.
+        let mut results = stream::iter(cursor..=to_block)
+            .take_while(|_| futures::future::ready(!self.shutdown.is_cancelled()))
+            .map(|block_number| async move {
+                (block_number, self.index_block(block_number, do_force).await)
+            })
+            .buffered(concurrency.max(1));
+
+        while let Some((block_number, result)) = results.next().await {
+            match result {
+                Ok(true) => report.indexed_blocks += 1,
+                Ok(false) => report.skipped_blocks += 1,
+                Err(e) => {
+                    error!("Backfill of block {} failed: {}", block_number, e);
+                    metrics::errors_total().with_label_values(&["block"]).inc();
+                    self.event_handler.on_block_failed(block_number, &e).await;
+                    report.failed_blocks.push((block_number, e));
+                }
+            }
+
+            let progress = if to_block == cursor {
+                100.0
+            } else {
+                ((block_number - cursor) as f64 / (to_block - cursor) as f64) * 100.0
+            };
+
+            self.event_handler
+                .on_block_processed(block_number, progress)
+                .await;
+
+            report.next_cursor = block_number + 1;
+        }
+
+        info!(
+            "End of backfill: {} indexed, {} skipped, {} failed",
+            report.indexed_blocks,
+            report.skipped_blocks,
+            report.failed_blocks.len()
+        );
+
+        self.event_handler.on_indexation_range_completed().await;
+
+        Ok(report)
+    }
+
+    /// Indexes one block, without any retry.
+    /// Returns false if the block was skipped as already indexed.
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "block", skip(self, do_force))
+    )]
+    async fn index_block(&self, block_number: u64, do_force: bool) -> IndexerResult<bool> {
+        let header = self
+            .client
+            .block_header(BlockId::Number(block_number))
+            .await?;
+        let block_ts = header.timestamp;
+        let block_hash = header.hash;
+
+        if self
+            .block_manager
+            .should_skip_indexing(
+                block_number,
+                block_ts,
+                &self.config.indexer_version,
+                do_force,
+            )
+            .await?
+        {
+            info!("Skipping block {}", block_number);
+            return Ok(false);
+        }
+
+        self.event_handler
+            .on_block_processing(block_ts, Some(block_number))
+            .await;
+
+        self.block_manager
+            .set_block_info(
+                block_number,
+                block_ts,
+                &self.config.indexer_version,
+                &self.config.indexer_identifier,
+                BlockIndexingStatus::Processing,
+                Some(to_hex_str(&block_hash)),
+            )
+            .await?;
+
+        if let Err(e) = self.index_block_events(block_number, block_ts).await {
+            // Cleans the block, to not skip it on the next backfill.
+            if let Err(clean_error) = self
+                .block_manager
+                .clean_block(block_ts, Some(block_number))
                 .await
             {
-                Ok(()) => (),
-                Err(err) => {
-                    error!("Can't format token {:?}\ntevent: {:?}", err, token_event);
-                    continue;
+                warn!("Couldn't clean block {}: {}", block_number, clean_error);
+            }
+
+            return Err(e);
+        }
+
+        self.block_manager
+            .set_block_info(
+                block_number,
+                block_ts,
+                &self.config.indexer_version,
+                &self.config.indexer_identifier,
+                BlockIndexingStatus::Terminated,
+                Some(to_hex_str(&block_hash)),
+            )
+            .await?;
+        metrics::blocks_processed_total().inc();
+
+        Ok(true)
+    }
+
+    /// Indexes the events of one block, processing each page of events while
+    /// the next ones are fetched. The pages wait in a bounded queue, the
+    /// fetching being paused while it is full.
+    /// Returns the number of events of the block.
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "block_events", skip(self, block_ts))
+    )]
+    async fn index_block_events(&self, block_number: u64, block_ts: u64) -> IndexerResult<usize> {
+        let capacity = self
+            .config
+            .event_queue_capacity
+            .unwrap_or(DEFAULT_EVENT_QUEUE_CAPACITY)
+            .max(1);
+        let (sender, mut receiver) = mpsc::channel::<Vec<EmittedEvent>>(capacity);
+
+        // All the pages of the block share the RPC calls counter.
+        let processing = rpc_budget::with_call_counter(async move {
+            let mut token_events = vec![];
+
+            while let Some(events) = receiver.recv().await {
+                metrics::event_queue_depth().dec();
+
+                let events = self.prepare_events(events);
+                match self.process_block_events(events, block_ts).await {
+                    Ok(events) => token_events.extend(events),
+                    Err(e) => {
+                        // Stops the fetching, and drops the pages left in the queue.
+                        receiver.close();
+                        while receiver.try_recv().is_ok() {
+                            metrics::event_queue_depth().dec();
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+
+            self.register_events(token_events, block_ts).await
+        });
+
+        let (fetched, processed) =
+            tokio::join!(self.extract_block_events(block_number, sender), processing);
+
+        let events_count = fetched?;
+        processed?;
+
+        Ok(events_count)
+    }
+
+    /// Fetches the events of one block by pages, sending them to the queue.
+    /// The events of a transaction are never split between two pages, to be
+    /// deduplicated together.
+    /// Returns the number of events sent.
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "extract_events", skip(self, sender))
+    )]
+    async fn extract_block_events(
+        &self,
+        block_number: u64,
+        sender: mpsc::Sender<Vec<EmittedEvent>>,
+    ) -> IndexerResult<usize> {
+        let mut continuation_token: Option<String> = None;
+        let mut held_back: Vec<EmittedEvent> = vec![];
+        let mut events_count = 0;
+
+        loop {
+            let result = self
+                .client
+                .fetch_events(
+                    Some(BlockId::Number(block_number)),
+                    Some(BlockId::Number(block_number)),
+                    self.event_manager.keys_selector(),
+                    None,
+                    continuation_token,
+                )
+                .await?;
+
+            let mut events = held_back;
+            events.extend(result.events.into_values().flatten());
+            continuation_token = result.continuation_token;
+
+            // The last transaction of the page may continue on the next one.
+            held_back = match (&continuation_token, events.last()) {
+                (Some(_), Some(last)) => {
+                    let last_tx = last.transaction_hash;
+                    let split = events
+                        .iter()
+                        .rposition(|e| e.transaction_hash != last_tx)
+                        .map_or(0, |i| i + 1);
+                    events.split_off(split)
+                }
+                _ => vec![],
+            };
+
+            if !events.is_empty() {
+                events_count += events.len();
+
+                metrics::event_queue_depth().inc();
+                if sender.send(events).await.is_err() {
+                    // The processing failed, and returns its error.
+                    metrics::event_queue_depth().dec();
+                    break;
                 }
             }
+
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(events_count)
+    }
+
+    /// Inner function to process the events of one block.
+    async fn process_events(
+        &self,
+        events: Vec<EmittedEvent>,
+        block_timestamp: u64,
+    ) -> IndexerResult<()> {
+        let events = self.prepare_events(events);
+        let token_events =
+            rpc_budget::with_call_counter(self.process_block_events(events, block_timestamp))
+                .await?;
+
+        self.register_events(token_events, block_timestamp).await
+    }
+
+    /// Registers at once the token events of a block, once all its events
+    /// are processed, and emits them to the event handler.
+    async fn register_events(
+        &self,
+        token_events: Vec<TokenEvent>,
+        block_timestamp: u64,
+    ) -> IndexerResult<()> {
+        if token_events.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(e) = self
+            .event_manager
+            .register_events(&token_events, block_timestamp)
+            .await
+        {
+            metrics::errors_total()
+                .with_label_values(&["register_event"])
+                .inc();
+            return Err(e.into());
+        }
+
+        for token_event in token_events {
+            self.event_handler.on_event_registered(token_event).await;
         }
 
         Ok(())
     }
+
+    /// Drops the events of the filtered contracts and, if enabled, the
+    /// duplicated transfers.
+    fn prepare_events(&self, events: Vec<EmittedEvent>) -> Vec<EmittedEvent> {
+        let events: Vec<EmittedEvent> = events
+            .into_iter()
+            .filter(|e| self.config.contract_filter.is_indexed(&e.from_address))
+            .collect();
+
+        if self.config.deduplicate_events {
+            EventManager::<S>::deduplicate_events(events)
+        } else {
+            events
+        }
+    }
+
+    /// Processes the events of a block, returning the token events
+    /// to register once the block is processed.
+    async fn process_block_events(
+        &self,
+        events: Vec<EmittedEvent>,
+        block_timestamp: u64,
+    ) -> IndexerResult<Vec<TokenEvent>> {
+        let max_concurrent_events = self.config.max_concurrent_events.unwrap_or(1);
+        if max_concurrent_events <= 1 {
+            return self.process_events_in_order(events, block_timestamp).await;
+        }
+
+        // Events are grouped by contract, in order of appearance. A slow contract
+        // only delays its own events, while writes to a same token stay ordered.
+        let mut contract_events: Vec<(FieldElement, Vec<EmittedEvent>)> = vec![];
+        for e in events {
+            match contract_events
+                .iter_mut()
+                .find(|(address, _)| *address == e.from_address)
+            {
+                Some((_, events)) => events.push(e),
+                None => contract_events.push((e.from_address, vec![e])),
+            }
+        }
+
+        // The futures are polled by the current task, sharing the RPC calls counter.
+        let results: Vec<IndexerResult<Vec<TokenEvent>>> = stream::iter(contract_events)
+            .map(|(_, events)| self.process_events_in_order(events, block_timestamp))
+            .buffer_unordered(max_concurrent_events)
+            .collect()
+            .await;
+
+        let mut token_events = vec![];
+        for result in results {
+            token_events.extend(result?);
+        }
+
+        Ok(token_events)
+    }
+
+    async fn process_events_in_order(
+        &self,
+        events: Vec<EmittedEvent>,
+        block_timestamp: u64,
+    ) -> IndexerResult<Vec<TokenEvent>> {
+        let mut events = events.into_iter();
+        let mut token_events = vec![];
+
+        while let Some(e) = events.next() {
+            if let Some(max_calls) = self.config.max_rpc_calls_per_block {
+                if rpc_budget::calls_count() >= max_calls {
+                    let deferred: Vec<EmittedEvent> = std::iter::once(e).chain(events).collect();
+                    warn!(
+                        "RPC calls cap of {} reached, deferring {} events of block {:?}",
+                        max_calls,
+                        deferred.len(),
+                        deferred[0].block_number
+                    );
+                    self.event_handler.on_events_deferred(deferred).await;
+                    break;
+                }
+            }
+
+            let token_event = match self.config.event_processing_timeout {
+                Some(timeout) => {
+                    match tokio::time::timeout(timeout, self.process_event(&e, block_timestamp))
+                        .await
+                    {
+                        Ok(token_event) => token_event,
+                        Err(_) => {
+                            warn!(
+                                "Event processing timed out after {:?}, deferring event. Block Id: {:?}, Tx Hash: 0x{:064x}",
+                                timeout, e.block_number, e.transaction_hash
+                            );
+                            metrics::errors_total()
+                                .with_label_values(&["event_timeout"])
+                                .inc();
+                            self.event_handler.on_events_deferred(vec![e]).await;
+                            None
+                        }
+                    }
+                }
+                None => self.process_event(&e, block_timestamp).await,
+            };
+
+            token_events.extend(token_event);
+        }
+
+        Ok(token_events)
+    }
+
+    /// Checks the stored type of a contract against the type detected
+    /// on-chain, if `PontosConfig::contract_type_validation` is set.
+    /// Returns false if the events of the contract must be dropped.
+    async fn validate_contract_type(
+        &self,
+        contract_address: FieldElement,
+        contract_type: &ContractType,
+    ) -> bool {
+        let validation = match self.config.contract_type_validation {
+            Some(validation) => validation,
+            None => return true,
+        };
+
+        let detected_type = match self
+            .contract_type_detector
+            .detect(contract_address, BlockId::Tag(BlockTag::Pending))
+            .await
+        {
+            Ok(detected_type) => detected_type,
+            Err(err) => {
+                // Not detected, the stored type is trusted.
+                warn!(
+                    "Can't detect the type of contract {}: {:?}",
+                    to_hex_str(&contract_address),
+                    err
+                );
+                return true;
+            }
+        };
+
+        if detected_type == *contract_type {
+            return true;
+        }
+
+        warn!(
+            "Contract {} is stored as {} but detected as {} on-chain",
+            to_hex_str(&contract_address),
+            contract_type.to_string(),
+            detected_type.to_string()
+        );
+        metrics::errors_total()
+            .with_label_values(&["contract_type_mismatch"])
+            .inc();
+
+        validation == ContractTypeValidation::Warn
+    }
+
+    /// Processes one event, returning its token event to register.
+    /// Errors are logged and the event is skipped.
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "event",
+            skip_all,
+            fields(
+                collection_address = %to_hex_str(&e.from_address),
+                token_id = tracing::field::Empty,
+            )
+        )
+    )]
+    async fn process_event(&self, e: &EmittedEvent, block_timestamp: u64) -> Option<TokenEvent> {
+        let contract_address = e.from_address;
+        info!(
+            "Processing event... Block Id: {:?}, Tx Hash: 0x{:064x}",
+            e.block_number, e.transaction_hash
+        );
+
+        let contract_type = match self
+            .contract_manager
+            .identify_contract(contract_address, block_timestamp)
+            .await
+        {
+            Ok(info) => info,
+            Err(err) => {
+                warn!(
+                    "Error while identifying contract {}: {:?}",
+                    to_hex_str(&contract_address),
+                    err
+                );
+                metrics::errors_total()
+                    .with_label_values(&["identify_contract"])
+                    .inc();
+                return None;
+            }
+        };
+
+        if contract_type == ContractType::Other {
+            debug!(
+                "Contract identified as OTHER: {}",
+                to_hex_str(&contract_address),
+            );
+            return None;
+        }
+
+        if !self
+            .validate_contract_type(contract_address, &contract_type)
+            .await
+        {
+            return None;
+        }
+
+        if EventManager::<S>::is_approval_event(e) {
+            match self
+                .event_manager
+                .format_and_register_approval(e, block_timestamp)
+                .await
+            {
+                Ok(()) => metrics::events_processed_total()
+                    .with_label_values(&["APPROVAL"])
+                    .inc(),
+                Err(err) => {
+                    error!(
+                        "Error while registering approval {:?}. Tx Hash: 0x{:064x}",
+                        err, e.transaction_hash
+                    );
+                    trace!("Event: {:?}", e);
+                    metrics::errors_total()
+                        .with_label_values(&["register_approval"])
+                        .inc();
+                }
+            }
+            return None;
+        }
+
+        let (token_id, token_event) =
+            match self
+                .event_manager
+                .format_event(e, contract_type, block_timestamp)
+            {
+                Ok(te) => te,
+                Err(err) => {
+                    error!(
+                        "Error while formatting event {:?}. Tx Hash: 0x{:064x}",
+                        err, e.transaction_hash
+                    );
+                    trace!("Event: {:?}", e);
+                    metrics::errors_total()
+                        .with_label_values(&["format_event"])
+                        .inc();
+                    return None;
+                }
+            };
+
+        #[cfg(feature = "tracing-spans")]
+        tracing::Span::current().record("token_id", token_id.to_decimal(false));
+
+        metrics::events_processed_total()
+            .with_label_values(&[&token_event.event_type.to_string()])
+            .inc();
+
+        if let Err(err) = self
+            .token_manager
+            .format_and_register_token(&token_id, &token_event, block_timestamp, e.block_number)
+            .await
+        {
+            error!(
+                "Can't format token {:?}. Tx Hash: {}",
+                err, token_event.transaction_hash
+            );
+            trace!("Token event: {:?}", token_event);
+            metrics::errors_total()
+                .with_label_values(&["register_token"])
+                .inc();
+        }
+
+        Some(token_event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::types::{BlockInfo, ContractInfo, EventType, TokenEvent};
+    use crate::storage::{MemoryStorage, MockStorage};
+    use ark_starknet::client::MockStarknetClient;
+    use ark_starknet::PaddedTokenId;
+    use ark_starknet::{BlockHeader, EventResult};
+    use starknet::macros::selector;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingHandler {
+        events: Mutex<Vec<TokenEvent>>,
+        deferred: Mutex<Vec<EmittedEvent>>,
+        processed_blocks: Mutex<Vec<u64>>,
+        shutdown_on_processing: Mutex<Option<CancellationToken>>,
+        range_completed: Mutex<bool>,
+    }
+
+    #[async_trait::async_trait]
+    impl EventHandler for RecordingHandler {
+        async fn on_event_registered(&self, event: TokenEvent) {
+            self.events.lock().unwrap().push(event);
+        }
+
+        async fn on_events_deferred(&self, events: Vec<EmittedEvent>) {
+            self.deferred.lock().unwrap().extend(events);
+        }
+
+        async fn on_block_processed(&self, block_number: u64, _indexation_progress: f64) {
+            self.processed_blocks.lock().unwrap().push(block_number);
+        }
+
+        async fn on_block_processing(&self, _block_timestamp: u64, _block_number: Option<u64>) {
+            if let Some(shutdown) = self.shutdown_on_processing.lock().unwrap().take() {
+                shutdown.cancel();
+            }
+        }
+
+        async fn on_indexation_range_completed(&self) {
+            *self.range_completed.lock().unwrap() = true;
+        }
+    }
+
+    fn backfill_pontos(
+        failing_block: u64,
+        handler: Arc<RecordingHandler>,
+    ) -> Pontos<MockStorage, MockStarknetClient, RecordingHandler> {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+
+        mock_client.expect_block_header().returning(|_| {
+            Ok(BlockHeader {
+                timestamp: 1000,
+                hash: FieldElement::ONE,
+                parent_hash: FieldElement::ZERO,
+            })
+        });
+        mock_client.expect_fetch_events().returning(
+            move |from_block, _, _, _, _| match from_block {
+                Some(BlockId::Number(n)) if n == failing_block => {
+                    Err(StarknetClientError::Other("node error".to_string()))
+                }
+                _ => Ok(EventResult {
+                    events: HashMap::new(),
+                    continuation_token: None,
+                }),
+            },
+        );
+
+        mock_storage.expect_get_block_info().returning(|_| {
+            Box::pin(futures::future::ready(Err(StorageError::NotFound(
+                "block".to_string(),
+            ))))
+        });
+        mock_storage
+            .expect_set_block_info()
+            .returning(|_, _, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_storage
+            .expect_clean_block()
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+
+        Pontos::new(
+            Arc::new(mock_client),
+            Arc::new(mock_storage),
+            handler,
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_index_block_range_advances_cursor() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+
+        mock_client
+            .expect_block_id_to_u64()
+            .returning(|id| match id {
+                BlockId::Number(n) => Ok(*n),
+                _ => Ok(0),
+            });
+        mock_client.expect_block_number().returning(|| Ok(3));
+        mock_client.expect_block_header().returning(|_| {
+            Ok(BlockHeader {
+                timestamp: 1000,
+                hash: FieldElement::ONE,
+                parent_hash: FieldElement::ZERO,
+            })
+        });
+        mock_client
+            .expect_fetch_events()
+            .returning(|_, _, _, _, _| {
+                Ok(EventResult {
+                    events: HashMap::new(),
+                    continuation_token: None,
+                })
+            });
+
+        mock_storage.expect_get_block_info().returning(|_| {
+            Box::pin(futures::future::ready(Err(StorageError::NotFound(
+                "block".to_string(),
+            ))))
+        });
+        mock_storage
+            .expect_set_block_info()
+            .returning(|_, _, _| Box::pin(futures::future::ready(Ok(()))));
+
+        // Blocks 1 and 2 are re-indexed, but the cursor doesn't go backward.
+        mock_storage
+            .expect_get_last_processed_block()
+            .returning(|_| Box::pin(futures::future::ready(Ok(Some(2)))));
+        mock_storage
+            .expect_set_last_processed_block()
+            .withf(|id, block_number| id == "test" && *block_number == 3)
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+
+        let pontos = Pontos::new(
+            Arc::new(mock_client),
+            Arc::new(mock_storage),
+            Arc::new(RecordingHandler::default()),
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(pontos.last_processed_block().await.unwrap(), Some(2));
+
+        pontos
+            .index_block_range(BlockId::Number(1), BlockId::Number(3), false)
+            .await
+            .unwrap();
+    }
+
+    /// Canonical hash of the given block in the reorg tests.
+    fn canonical_hash(block_number: u64) -> FieldElement {
+        FieldElement::from(100 + block_number)
+    }
+
+    /// Pontos on a chain where the indexed blocks are the given (number, hash)
+    /// and the cursor is at the last one. The latest block is read from `head_blocks`,
+    /// the last one being kept. Returns the cleaned blocks and the cursor.
+    #[allow(clippy::type_complexity)]
+    fn reorg_pontos(
+        indexed_blocks: Vec<(u64, FieldElement)>,
+        head_blocks: Vec<u64>,
+        confirmation_depth: Option<u64>,
+        handler: Arc<RecordingHandler>,
+    ) -> (
+        Pontos<MockStorage, MockStarknetClient, RecordingHandler>,
+        Arc<Mutex<Vec<u64>>>,
+        Arc<Mutex<Option<u64>>>,
+    ) {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+
+        let cursor = Arc::new(Mutex::new(indexed_blocks.last().map(|(n, _)| *n)));
+        let cleaned = Arc::new(Mutex::new(vec![]));
+        let blocks = Arc::new(Mutex::new(
+            indexed_blocks
+                .into_iter()
+                .map(|(n, hash)| (n, to_hex_str(&hash)))
+                .collect::<std::collections::HashMap<u64, String>>(),
+        ));
+
+        mock_client
+            .expect_block_id_to_u64()
+            .returning(|id| match id {
+                BlockId::Number(n) => Ok(*n),
+                _ => Ok(0),
+            });
+        let head_blocks = Mutex::new(head_blocks);
+        mock_client.expect_block_number().returning(move || {
+            let mut head_blocks = head_blocks.lock().unwrap();
+            if head_blocks.len() > 1 {
+                Ok(head_blocks.remove(0))
+            } else {
+                Ok(head_blocks[0])
+            }
+        });
+        mock_client
+            .expect_block_header()
+            .returning(|block_id| match block_id {
+                BlockId::Number(n) => Ok(BlockHeader {
+                    timestamp: 1000 + n,
+                    hash: canonical_hash(n),
+                    parent_hash: canonical_hash(n - 1),
+                }),
+                _ => Ok(BlockHeader {
+                    timestamp: 0,
+                    hash: FieldElement::ZERO,
+                    parent_hash: FieldElement::ZERO,
+                }),
+            });
+        mock_client
+            .expect_fetch_events()
+            .returning(|_, _, _, _, _| {
+                Ok(EventResult {
+                    events: HashMap::new(),
+                    continuation_token: None,
+                })
+            });
+
+        let b = Arc::clone(&blocks);
+        mock_storage.expect_get_block_info().returning(move |n| {
+            let info = match b.lock().unwrap().get(&n) {
+                Some(hash) => Ok(BlockInfo {
+                    indexer_version: "0.0.1".to_string(),
+                    indexer_identifier: "test".to_string(),
+                    status: BlockIndexingStatus::Terminated,
+                    block_number: n,
+                    block_timestamp: 1000 + n,
+                    block_hash: Some(hash.clone()),
+                }),
+                None => Err(StorageError::NotFound("block".to_string())),
+            };
+            Box::pin(futures::future::ready(info))
+        });
+        let b = Arc::clone(&blocks);
+        mock_storage
+            .expect_set_block_info()
+            .returning(move |n, _, info| {
+                b.lock().unwrap().insert(n, info.block_hash.unwrap());
+                Box::pin(futures::future::ready(Ok(())))
+            });
+        mock_storage
+            .expect_find_block_events()
+            .returning(|_| Box::pin(futures::future::ready(Ok(vec![]))));
+        let (b, c) = (Arc::clone(&blocks), Arc::clone(&cleaned));
+        mock_storage.expect_clean_block().returning(move |ts, n| {
+            let n = n.unwrap();
+            assert_eq!(ts, 1000 + n);
+            b.lock().unwrap().remove(&n);
+            c.lock().unwrap().push(n);
+            Box::pin(futures::future::ready(Ok(())))
+        });
+        let c = Arc::clone(&cursor);
+        mock_storage
+            .expect_get_last_processed_block()
+            .returning(move |_| Box::pin(futures::future::ready(Ok(*c.lock().unwrap()))));
+        let c = Arc::clone(&cursor);
+        mock_storage
+            .expect_set_last_processed_block()
+            .returning(move |_, n| {
+                *c.lock().unwrap() = Some(n);
+                Box::pin(futures::future::ready(Ok(())))
+            });
+
+        let pontos = Pontos::new(
+            Arc::new(mock_client),
+            Arc::new(mock_storage),
+            handler,
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                confirmation_depth,
+                ..Default::default()
+            },
+        );
+
+        (pontos, cleaned, cursor)
+    }
+
+    #[tokio::test]
+    async fn test_index_block_range_rolls_back_orphaned_blocks() {
+        let handler = Arc::new(RecordingHandler::default());
+
+        // Blocks 3 and 4 were indexed on a fork.
+        let (pontos, cleaned, cursor) = reorg_pontos(
+            vec![
+                (1, canonical_hash(1)),
+                (2, canonical_hash(2)),
+                (3, FieldElement::from(999_u64)),
+                (4, FieldElement::from(998_u64)),
+            ],
+            vec![10],
+            None,
+            Arc::clone(&handler),
+        );
+
+        pontos
+            .index_block_range(BlockId::Number(5), BlockId::Number(5), false)
+            .await
+            .unwrap();
+
+        assert_eq!(*cleaned.lock().unwrap(), vec![4, 3]);
+        assert_eq!(*handler.processed_blocks.lock().unwrap(), vec![3, 4, 5]);
+        assert_eq!(*cursor.lock().unwrap(), Some(5));
+    }
+
+    #[tokio::test]
+    async fn test_index_block_range_without_reorg() {
+        let handler = Arc::new(RecordingHandler::default());
+        let (pontos, cleaned, cursor) = reorg_pontos(
+            vec![(1, canonical_hash(1)), (2, canonical_hash(2))],
+            vec![10],
+            None,
+            Arc::clone(&handler),
+        );
+
+        pontos
+            .index_block_range(BlockId::Number(3), BlockId::Number(4), false)
+            .await
+            .unwrap();
+
+        assert!(cleaned.lock().unwrap().is_empty());
+        assert_eq!(*handler.processed_blocks.lock().unwrap(), vec![3, 4]);
+        assert_eq!(*cursor.lock().unwrap(), Some(4));
+    }
+
+    #[tokio::test]
+    async fn test_index_block_range_restores_owners_of_orphaned_blocks() {
+        let contract_address = FieldElement::from_hex_be("0x1234").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+        let orphaned_owner = FieldElement::from_hex_be("0xef01").unwrap();
+        let token_id_hex = PaddedTokenId::from(CairoU256 { low: 7, high: 0 });
+
+        let mut mock_client = MockStarknetClient::default();
+        let storage = Arc::new(MemoryStorage::new());
+
+        // The token was minted in block 1, and transferred in block 2 on a fork.
+        let token = TokenInfo {
+            contract_address: to_hex_str(&contract_address),
+            token_id: "7".to_string(),
+            token_id_hex: token_id_hex.clone(),
+            owner: to_hex_str(&owner),
+            ..Default::default()
+        };
+        storage.register_token(&token, 1001).await.unwrap();
+        storage
+            .update_token(&TokenInfo {
+                owner: to_hex_str(&orphaned_owner),
+                ..token
+            })
+            .await
+            .unwrap();
+        storage
+            .register_event(
+                &TokenEvent {
+                    contract_address: to_hex_str(&contract_address),
+                    token_id: "7".to_string(),
+                    token_id_hex: token_id_hex.clone(),
+                    from_address: to_hex_str(&owner),
+                    to_address: to_hex_str(&orphaned_owner),
+                    event_type: EventType::Transfer,
+                    event_id: "0x1".to_string(),
+                    timestamp: 1002,
+                    block_number: Some(2),
+                    ..Default::default()
+                },
+                1002,
+            )
+            .await
+            .unwrap();
+        for (block_number, hash) in [(1, canonical_hash(1)), (2, FieldElement::from(999_u64))] {
+            storage
+                .set_block_info(
+                    block_number,
+                    1000 + block_number,
+                    BlockInfo {
+                        indexer_version: "0.0.1".to_string(),
+                        indexer_identifier: "test".to_string(),
+                        status: BlockIndexingStatus::Terminated,
+                        block_number,
+                        block_timestamp: 1000 + block_number,
+                        block_hash: Some(to_hex_str(&hash)),
+                    },
+                )
+                .await
+                .unwrap();
+        }
+        storage.set_last_processed_block("test", 2).await.unwrap();
+
+        mock_client
+            .expect_block_id_to_u64()
+            .returning(|id| match id {
+                BlockId::Number(n) => Ok(*n),
+                _ => Ok(0),
+            });
+        mock_client.expect_block_number().returning(|| Ok(3));
+        mock_client
+            .expect_block_header()
+            .returning(|block_id| match block_id {
+                BlockId::Number(n) => Ok(BlockHeader {
+                    timestamp: 1000 + n,
+                    hash: canonical_hash(n),
+                    parent_hash: canonical_hash(n - 1),
+                }),
+                _ => unreachable!(),
+            });
+        mock_client
+            .expect_fetch_events()
+            .returning(|_, _, _, _, _| {
+                Ok(EventResult {
+                    events: HashMap::new(),
+                    continuation_token: None,
+                })
+            });
+        // On the canonical chain, the token was never transferred.
+        mock_client
+            .expect_call_contract()
+            .returning(move |_, _, _, _| Ok(vec![owner]));
+
+        let pontos = Pontos::new(
+            Arc::new(mock_client),
+            Arc::clone(&storage),
+            Arc::new(RecordingHandler::default()),
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                ..Default::default()
+            },
+        );
+
+        pontos
+            .index_block_range(BlockId::Number(3), BlockId::Number(3), false)
+            .await
+            .unwrap();
+
+        assert!(storage.events().is_empty());
+        assert_eq!(storage.tokens()[0].owner, to_hex_str(&owner));
+        assert_eq!(pontos.last_processed_block().await.unwrap(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_index_block_range_confirmation_depth() {
+        let handler = Arc::new(RecordingHandler::default());
+
+        // The latest block is 10, only the blocks up to 7 are confirmed.
+        let (pontos, _, cursor) = reorg_pontos(vec![], vec![10], Some(3), Arc::clone(&handler));
+
+        pontos
+            .index_block_range(BlockId::Number(6), BlockId::Number(10), false)
+            .await
+            .unwrap();
+
+        assert_eq!(*handler.processed_blocks.lock().unwrap(), vec![6, 7]);
+        assert_eq!(*cursor.lock().unwrap(), Some(7));
+    }
+
+    #[tokio::test]
+    async fn test_index_block_range_follows_the_head() {
+        let handler = Arc::new(RecordingHandler::default());
+
+        // The latest block moves forward while the range is indexed.
+        let (pontos, _, cursor) =
+            reorg_pontos(vec![], vec![10, 20, 40], Some(3), Arc::clone(&handler));
+
+        pontos
+            .index_block_range(BlockId::Number(6), BlockId::Number(30), false)
+            .await
+            .unwrap();
+
+        // The head is read again once block 7 is indexed, and 10 blocks later.
+        assert_eq!(
+            *handler.processed_blocks.lock().unwrap(),
+            (6..=30).collect::<Vec<_>>()
+        );
+        assert_eq!(*cursor.lock().unwrap(), Some(30));
+    }
+
+    #[tokio::test]
+    async fn test_index_block_range_finishes_block_on_shutdown() {
+        let handler = Arc::new(RecordingHandler::default());
+        let (pontos, _, cursor) = reorg_pontos(vec![], vec![10], None, Arc::clone(&handler));
+
+        // Shutdown is requested while the first block is processed.
+        *handler.shutdown_on_processing.lock().unwrap() = Some(pontos.shutdown_token());
+
+        pontos
+            .index_block_range(BlockId::Number(3), BlockId::Number(6), false)
+            .await
+            .unwrap();
+
+        assert_eq!(*handler.processed_blocks.lock().unwrap(), vec![3]);
+        assert_eq!(*cursor.lock().unwrap(), Some(3));
+        // The handler can flush its buffered events.
+        assert!(*handler.range_completed.lock().unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_index_block_range_stops_retrying_block_events() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+
+        mock_client
+            .expect_block_id_to_u64()
+            .returning(|id| match id {
+                BlockId::Number(n) => Ok(*n),
+                _ => Ok(0),
+            });
+        mock_client.expect_block_number().returning(|| Ok(10));
+        mock_client.expect_block_header().returning(|_| {
+            Ok(BlockHeader {
+                timestamp: 1000,
+                hash: FieldElement::ONE,
+                parent_hash: FieldElement::ZERO,
+            })
+        });
+        // The events of the first block are fetched once, and retried 5 times.
+        mock_client
+            .expect_fetch_events()
+            .times(6)
+            .returning(|_, _, _, _, _| Err(StarknetClientError::Other("node error".to_string())));
+
+        mock_storage.expect_get_block_info().returning(|_| {
+            Box::pin(futures::future::ready(Err(StorageError::NotFound(
+                "block".to_string(),
+            ))))
+        });
+        mock_storage
+            .expect_set_block_info()
+            .returning(|_, _, _| Box::pin(futures::future::ready(Ok(()))));
+
+        let handler = Arc::new(RecordingHandler::default());
+        let pontos = Pontos::new(
+            Arc::new(mock_client),
+            Arc::new(mock_storage),
+            Arc::clone(&handler),
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                ..Default::default()
+            },
+        );
+
+        let result = pontos
+            .index_block_range(BlockId::Number(3), BlockId::Number(6), false)
+            .await;
+
+        assert!(matches!(result, Err(IndexerError::Starknet(_))));
+        assert!(handler.processed_blocks.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_backfill_block_range_reports_failed_blocks() {
+        let handler = Arc::new(RecordingHandler::default());
+        let pontos = backfill_pontos(3, Arc::clone(&handler));
+
+        let report = pontos.backfill_block_range(1, 5, 3, false).await.unwrap();
+
+        assert_eq!(report.indexed_blocks, 4);
+        assert_eq!(report.skipped_blocks, 0);
+        assert_eq!(report.failed_blocks.len(), 1);
+        assert_eq!(report.failed_blocks[0].0, 3);
+        assert_eq!(report.next_cursor, 6);
+
+        // Progress is reported in the blocks order.
+        assert_eq!(
+            *handler.processed_blocks.lock().unwrap(),
+            vec![1, 2, 3, 4, 5]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_backfill_block_range_resumes_from_cursor() {
+        let handler = Arc::new(RecordingHandler::default());
+        let pontos = backfill_pontos(4, Arc::clone(&handler));
+
+        let report = pontos.backfill_block_range(4, 6, 2, false).await.unwrap();
+
+        assert_eq!(report.indexed_blocks, 2);
+        assert_eq!(report.failed_blocks[0].0, 4);
+        assert_eq!(report.next_cursor, 7);
+        assert_eq!(*handler.processed_blocks.lock().unwrap(), vec![4, 5, 6]);
+    }
+
+    fn mint_event(contract_address: FieldElement, to: FieldElement, token_id: u64) -> EmittedEvent {
+        EmittedEvent {
+            from_address: contract_address,
+            keys: vec![selector!("Transfer")],
+            data: vec![
+                FieldElement::ZERO,
+                to,
+                FieldElement::from(token_id),
+                FieldElement::ZERO,
+            ],
+            block_hash: Some(FieldElement::ONE),
+            block_number: Some(1),
+            transaction_hash: FieldElement::TWO,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_events_with_mocked_client() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+
+        let contract_address = FieldElement::from_hex_be("0x1234").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+
+        mock_storage
+            .expect_get_contract_type()
+            .returning(|_| Box::pin(futures::future::ready(Ok(ContractType::ERC721))));
+        mock_storage
+            .expect_register_events()
+            .withf(|events, _| events.len() == 1)
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_storage
+            .expect_register_token()
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_storage
+            .expect_register_mint()
+            .times(1)
+            .returning(|_, _, _| Box::pin(futures::future::ready(Ok(()))));
+
+        mock_client
+            .expect_call_contract()
+            .returning(move |_, _, _, _| Ok(vec![owner]));
+
+        let handler = Arc::new(RecordingHandler::default());
+        let pontos = Pontos::new(
+            Arc::new(mock_client),
+            Arc::new(mock_storage),
+            Arc::clone(&handler),
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                ..Default::default()
+            },
+        );
+
+        pontos
+            .process_events(vec![mint_event(contract_address, owner, 7)], 1000)
+            .await
+            .unwrap();
+
+        let events = handler.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, EventType::Mint);
+        assert_eq!(events[0].token_id, "7");
+        assert_eq!(events[0].to_address, to_hex_str(&owner));
+    }
+
+    #[tokio::test]
+    async fn test_process_events_skips_filtered_contracts() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+
+        let allowed = FieldElement::from_hex_be("0x1234").unwrap();
+        let denied = FieldElement::from_hex_be("0x5678").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+
+        // Only the allowed contract is identified and registered.
+        mock_storage
+            .expect_get_contract_type()
+            .withf(move |address| *address == to_hex_str(&allowed))
+            .times(1)
+            .returning(|_| Box::pin(futures::future::ready(Ok(ContractType::ERC721))));
+        mock_storage
+            .expect_register_events()
+            .withf(|events, _| events.len() == 1)
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_storage
+            .expect_register_token()
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_storage
+            .expect_register_mint()
+            .times(1)
+            .returning(|_, _, _| Box::pin(futures::future::ready(Ok(()))));
+
+        mock_client
+            .expect_call_contract()
+            .returning(move |_, _, _, _| Ok(vec![owner]));
+
+        let handler = Arc::new(RecordingHandler::default());
+        let pontos = Pontos::new(
+            Arc::new(mock_client),
+            Arc::new(mock_storage),
+            Arc::clone(&handler),
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                contract_filter: ContractFilter::Allow([allowed].into()),
+                ..Default::default()
+            },
+        );
+
+        pontos
+            .process_events(
+                vec![mint_event(denied, owner, 1), mint_event(allowed, owner, 7)],
+                1000,
+            )
+            .await
+            .unwrap();
+
+        let events = handler.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].contract_address, to_hex_str(&allowed));
+    }
+
+    /// Pontos validating the contract types, the collection being stored
+    /// as an ERC721 but detected as an ERC1155 on-chain.
+    fn mismatched_type_pontos(
+        validation: ContractTypeValidation,
+        owner: FieldElement,
+    ) -> (
+        Pontos<MockStorage, MockStarknetClient, RecordingHandler>,
+        Arc<RecordingHandler>,
+    ) {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+
+        mock_storage
+            .expect_get_contract_type()
+            .returning(|_| Box::pin(futures::future::ready(Ok(ContractType::ERC721))));
+        mock_storage
+            .expect_register_events()
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_storage
+            .expect_register_token()
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_storage
+            .expect_register_mint()
+            .returning(|_, _, _| Box::pin(futures::future::ready(Ok(()))));
+
+        let ierc1155_id = FieldElement::from_hex_be(
+            "0x6114a8f75559e1b39fcba08ce02961a1aa082d9256a158dd3e64964e4b1b52",
+        )
+        .unwrap();
+        mock_client
+            .expect_call_contract()
+            .returning(move |_, selector, calldata, _| {
+                if selector == selector!("supports_interface") {
+                    Ok(vec![if calldata[0] == ierc1155_id {
+                        FieldElement::ONE
+                    } else {
+                        FieldElement::ZERO
+                    }])
+                } else {
+                    Ok(vec![owner])
+                }
+            });
+
+        let handler = Arc::new(RecordingHandler::default());
+        let pontos = Pontos::new(
+            Arc::new(mock_client),
+            Arc::new(mock_storage),
+            Arc::clone(&handler),
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                contract_type_validation: Some(validation),
+                ..Default::default()
+            },
+        );
+
+        (pontos, handler)
+    }
+
+    #[tokio::test]
+    async fn test_process_events_validates_contract_type() {
+        let contract_address = FieldElement::from_hex_be("0x1234").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+
+        // The mismatch is only logged, the event being registered.
+        let (pontos, handler) = mismatched_type_pontos(ContractTypeValidation::Warn, owner);
+        pontos
+            .process_events(vec![mint_event(contract_address, owner, 7)], 1000)
+            .await
+            .unwrap();
+        assert_eq!(handler.events.lock().unwrap().len(), 1);
+
+        let (pontos, handler) = mismatched_type_pontos(ContractTypeValidation::Reject, owner);
+        pontos
+            .process_events(vec![mint_event(contract_address, owner, 7)], 1000)
+            .await
+            .unwrap();
+        assert!(handler.events.lock().unwrap().is_empty());
+    }
+
+    /// Pontos indexing the given events of block 1, on a memory storage
+    /// where the contract is already identified as an ERC721.
+    async fn memory_pontos(
+        contract_address: FieldElement,
+        owner: FieldElement,
+        events: Vec<EmittedEvent>,
+    ) -> (
+        Pontos<MemoryStorage, MockStarknetClient, RecordingHandler>,
+        Arc<MemoryStorage>,
+    ) {
+        paged_memory_pontos(contract_address, owner, vec![events]).await
+    }
+
+    /// Same as `memory_pontos`, the events of block 1 being fetched by pages.
+    async fn paged_memory_pontos(
+        contract_address: FieldElement,
+        owner: FieldElement,
+        pages: Vec<Vec<EmittedEvent>>,
+    ) -> (
+        Pontos<MemoryStorage, MockStarknetClient, RecordingHandler>,
+        Arc<MemoryStorage>,
+    ) {
+        let mut mock_client = MockStarknetClient::default();
+        let storage = Arc::new(MemoryStorage::new());
+
+        storage
+            .register_contract_info(
+                &ContractInfo {
+                    contract_address: to_hex_str(&contract_address),
+                    contract_type: ContractType::ERC721.to_string(),
+                    ..Default::default()
+                },
+                0,
+            )
+            .await
+            .unwrap();
+
+        mock_client.expect_block_header().returning(|_| {
+            Ok(BlockHeader {
+                timestamp: 1_700_000_000,
+                hash: FieldElement::ONE,
+                parent_hash: FieldElement::ZERO,
+            })
+        });
+        mock_client
+            .expect_fetch_events()
+            .returning(move |_, _, _, _, continuation_token| {
+                let page = continuation_token.map_or(0, |token| token.parse::<usize>().unwrap());
+                Ok(EventResult {
+                    events: HashMap::from([(1, pages[page].clone())]),
+                    continuation_token: (page + 1 < pages.len()).then(|| (page + 1).to_string()),
+                })
+            });
+        // Only the owner is known, the contract has no royalty.
+        mock_client
+            .expect_call_contract()
+            .returning(move |_, selector, _, _| {
+                if selector == selector!("owner_of") || selector == selector!("ownerOf") {
+                    Ok(vec![owner])
+                } else {
+                    Err(StarknetClientError::EntrypointNotFound(
+                        "royalty_info".to_string(),
+                    ))
+                }
+            });
+
+        let pontos = Pontos::new(
+            Arc::new(mock_client),
+            Arc::clone(&storage),
+            Arc::new(RecordingHandler::default()),
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                ..Default::default()
+            },
+        );
+
+        (pontos, storage)
+    }
+
+    #[tokio::test]
+    async fn test_index_block_registers_mint() {
+        let contract_address = FieldElement::from_hex_be("0x1234").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+
+        let (pontos, storage) = memory_pontos(
+            contract_address,
+            owner,
+            vec![mint_event(contract_address, owner, 7)],
+        )
+        .await;
+
+        let report = pontos.backfill_block_range(1, 1, 1, false).await.unwrap();
+        assert_eq!(report.indexed_blocks, 1);
+
+        let token_id_hex = PaddedTokenId::from(CairoU256 { low: 7, high: 0 });
+
+        let events = storage.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, EventType::Mint);
+        assert_eq!(events[0].from_address, to_hex_str(&FieldElement::ZERO));
+        assert_eq!(events[0].to_address, to_hex_str(&owner));
+        assert_eq!(events[0].contract_address, to_hex_str(&contract_address));
+        assert_eq!(events[0].contract_type, "ERC721");
+        assert_eq!(events[0].token_id, "7");
+        assert_eq!(events[0].token_id_hex, token_id_hex);
+        assert_eq!(events[0].timestamp, 1_700_000_000);
+        assert_eq!(events[0].block_number, Some(1));
+        assert_eq!(events[0].network, "mainnet");
+
+        assert_eq!(
+            storage.tokens(),
+            vec![TokenInfo {
+                contract_address: to_hex_str(&contract_address),
+                token_id: "7".to_string(),
+                token_id_hex: token_id_hex.clone(),
+                owner: to_hex_str(&owner),
+                network: "mainnet".to_string(),
+            }]
+        );
+
+        let mint = storage
+            .token_mint(&to_hex_str(&contract_address), &token_id_hex)
+            .unwrap();
+        assert_eq!(mint.address, to_hex_str(&owner));
+        assert_eq!(mint.timestamp, 1_700_000_000);
+        assert_eq!(mint.transaction_hash, to_hex_str(&FieldElement::TWO));
+        assert_eq!(mint.block_number, Some(1));
+        assert!(storage
+            .token_royalty(&to_hex_str(&contract_address), &token_id_hex)
+            .is_none());
+    }
+
+    /// Records the names of the spans created.
+    #[derive(Clone, Default)]
+    struct SpanNames(Arc<Mutex<Vec<&'static str>>>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanNames {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _id: &tracing::span::Id,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            self.0.lock().unwrap().push(attrs.metadata().name());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_index_block_spans() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let contract_address = FieldElement::from_hex_be("0x1234").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+
+        let (pontos, _) = memory_pontos(
+            contract_address,
+            owner,
+            vec![mint_event(contract_address, owner, 7)],
+        )
+        .await;
+
+        let span_names = SpanNames::default();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(span_names.clone()),
+        );
+
+        pontos.backfill_block_range(1, 1, 1, false).await.unwrap();
+
+        // The `block` and `event` spans are only created with `tracing-spans`.
+        let span_names = span_names.0.lock().unwrap();
+        assert_eq!(
+            span_names.contains(&"block"),
+            cfg!(feature = "tracing-spans")
+        );
+        assert_eq!(
+            span_names.contains(&"event"),
+            cfg!(feature = "tracing-spans")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_index_block_updates_registered_token() {
+        let contract_address = FieldElement::from_hex_be("0x1234").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+
+        let (pontos, storage) = memory_pontos(
+            contract_address,
+            owner,
+            vec![mint_event(contract_address, owner, 7)],
+        )
+        .await;
+
+        // The token is already registered, with a stale owner and no mint.
+        let token_id_hex = PaddedTokenId::from(CairoU256 { low: 7, high: 0 });
+        storage
+            .register_token(
+                &TokenInfo {
+                    contract_address: to_hex_str(&contract_address),
+                    token_id: "7".to_string(),
+                    token_id_hex: token_id_hex.clone(),
+                    owner: "0x9999".to_string(),
+                    network: "mainnet".to_string(),
+                },
+                0,
+            )
+            .await
+            .unwrap();
+
+        pontos.backfill_block_range(1, 1, 1, false).await.unwrap();
+
+        let tokens = storage.tokens();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].owner, to_hex_str(&owner));
+
+        let mint = storage
+            .token_mint(&to_hex_str(&contract_address), &token_id_hex)
+            .unwrap();
+        assert_eq!(mint.address, to_hex_str(&owner));
+        assert_eq!(mint.block_number, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_index_block_events_by_pages() {
+        let contract_address = FieldElement::from_hex_be("0x1234").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+
+        let mut events: Vec<EmittedEvent> = (7..10)
+            .map(|token_id| {
+                let mut event = mint_event(contract_address, owner, token_id);
+                event.transaction_hash = FieldElement::from(token_id);
+                event
+            })
+            .collect();
+
+        // The transfer of token 8 is emitted twice, across two pages.
+        let mut keys_event = events[1].clone();
+        keys_event.keys.extend(events[1].data.iter());
+        keys_event.data = vec![];
+        events.insert(2, keys_event);
+
+        let pages = vec![events[..2].to_vec(), events[2..].to_vec()];
+        let (mut pontos, storage) = paged_memory_pontos(contract_address, owner, pages).await;
+        pontos.config.deduplicate_events = true;
+        pontos.config.event_queue_capacity = Some(1);
+
+        let events_count = pontos.index_block_events(1, 1000).await.unwrap();
+        assert_eq!(events_count, 4);
+
+        let token_ids: Vec<String> = storage.events().into_iter().map(|e| e.token_id).collect();
+        assert_eq!(token_ids, vec!["7", "8", "9"]);
+    }
+
+    #[tokio::test]
+    async fn test_index_block_registers_transfer_without_mint() {
+        let contract_address = FieldElement::from_hex_be("0x1234").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+
+        // Only the transfers from the zero address are mints.
+        let mut transfer = mint_event(contract_address, owner, 7);
+        transfer.data[0] = FieldElement::from_hex_be("0x9999").unwrap();
+
+        let (pontos, storage) = memory_pontos(contract_address, owner, vec![transfer]).await;
+
+        pontos.backfill_block_range(1, 1, 1, false).await.unwrap();
+
+        let events = storage.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, EventType::Transfer);
+        assert_eq!(
+            events[0].from_address,
+            to_hex_str(&FieldElement::from(0x9999_u64))
+        );
+
+        let tokens = storage.tokens();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].owner, to_hex_str(&owner));
+        assert!(storage
+            .token_mint(&to_hex_str(&contract_address), &tokens[0].token_id_hex)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_index_block_registers_approvals() {
+        let contract_address = FieldElement::from_hex_be("0x1234").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+        let operator = FieldElement::from_hex_be("0x5555").unwrap();
+
+        let mut approval = mint_event(contract_address, owner, 7);
+        approval.keys = vec![selector!("Approval")];
+        approval.data = vec![
+            owner,
+            operator,
+            FieldElement::from(7_u64),
+            FieldElement::ZERO,
+        ];
+
+        let mut approval_for_all = mint_event(contract_address, owner, 7);
+        approval_for_all.keys = vec![selector!("ApprovalForAll"), owner, operator];
+        approval_for_all.data = vec![FieldElement::ONE];
+
+        let (pontos, storage) = memory_pontos(
+            contract_address,
+            owner,
+            vec![
+                mint_event(contract_address, owner, 7),
+                approval,
+                approval_for_all,
+            ],
+        )
+        .await;
+
+        pontos.backfill_block_range(1, 1, 1, false).await.unwrap();
+
+        // The approvals are not token events.
+        assert_eq!(storage.events().len(), 1);
+
+        let address = to_hex_str(&contract_address);
+        let approval = storage
+            .token_approval(
+                &address,
+                &PaddedTokenId::from(CairoU256 { low: 7, high: 0 }),
+            )
+            .unwrap();
+        assert_eq!(approval.owner, to_hex_str(&owner));
+        assert_eq!(approval.approved, Some(to_hex_str(&operator)));
+        assert_eq!(approval.block_number, Some(1));
+
+        let operator_approval = storage
+            .operator_approval(&address, &to_hex_str(&owner), &to_hex_str(&operator))
+            .unwrap();
+        assert!(operator_approval.approved);
+        assert_eq!(operator_approval.timestamp, 1_700_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_process_events_deduplicates_transfers() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+
+        let contract_address = FieldElement::from_hex_be("0x1234").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+
+        mock_storage
+            .expect_get_contract_type()
+            .returning(|_| Box::pin(futures::future::ready(Ok(ContractType::ERC721))));
+        mock_storage
+            .expect_register_events()
+            .withf(|events, _| events.len() == 1)
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_storage
+            .expect_register_token()
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_storage
+            .expect_register_mint()
+            .times(1)
+            .returning(|_, _, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_client
+            .expect_call_contract()
+            .returning(move |_, _, _, _| Ok(vec![owner]));
+
+        let handler = Arc::new(RecordingHandler::default());
+        let pontos = Pontos::new(
+            Arc::new(mock_client),
+            Arc::new(mock_storage),
+            Arc::clone(&handler),
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                deduplicate_events: true,
+                ..Default::default()
+            },
+        );
+
+        // The contract emits the same transfer with the legacy and new layouts.
+        let event = mint_event(contract_address, owner, 7);
+        let mut keys_event = event.clone();
+        keys_event.keys.extend(event.data.iter());
+        keys_event.data = vec![];
+
+        pontos
+            .process_events(vec![event, keys_event], 1000)
+            .await
+            .unwrap();
+
+        let events = handler.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, EventType::Mint);
+    }
+
+    #[tokio::test]
+    async fn test_process_events_defers_events_over_rpc_cap() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+
+        let contract_address = FieldElement::from_hex_be("0x1234").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+
+        mock_storage
+            .expect_get_contract_type()
+            .returning(|_| Box::pin(futures::future::ready(Ok(ContractType::ERC721))));
+        mock_storage
+            .expect_register_events()
+            .withf(|events, _| events.len() == 2)
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_storage
+            .expect_register_token()
+            .times(2)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_storage
+            .expect_register_mint()
+            .times(2)
+            .returning(|_, _, _| Box::pin(futures::future::ready(Ok(()))));
+
+        // Two calls per event, to get the token owner and its royalty.
+        mock_client
+            .expect_call_contract()
+            .times(4)
+            .returning(move |_, _, _, _| Ok(vec![owner]));
+
+        let handler = Arc::new(RecordingHandler::default());
+        let pontos = Pontos::new(
+            Arc::new(mock_client),
+            Arc::new(mock_storage),
+            Arc::clone(&handler),
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                max_rpc_calls_per_block: Some(4),
+                ..Default::default()
+            },
+        );
+
+        let events = (1..=5)
+            .map(|i| mint_event(contract_address, owner, i))
+            .collect();
+
+        pontos.process_events(events, 1000).await.unwrap();
+
+        assert_eq!(handler.events.lock().unwrap().len(), 2);
+
+        let deferred = handler.deferred.lock().unwrap();
+        assert_eq!(deferred.len(), 3);
+        assert_eq!(deferred[0].data[2], FieldElement::from(3_u64));
+    }
+
+    #[tokio::test]
+    async fn test_process_events_concurrently_by_contract() {
+        let mut mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+
+        let slow_contract = FieldElement::from_hex_be("0x1234").unwrap();
+        let fast_contract = FieldElement::from_hex_be("0x5678").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+
+        mock_storage
+            .expect_get_contract_type()
+            .returning(|_| Box::pin(futures::future::ready(Ok(ContractType::ERC721))));
+        mock_storage
+            .expect_register_events()
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+        // The tokens of the slow contract take time to be registered.
+        mock_storage
+            .expect_register_token()
+            .returning(move |token, _| {
+                if FieldElement::from_hex_be(&token.contract_address).unwrap() == slow_contract {
+                    Box::pin(async {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        Ok(())
+                    })
+                } else {
+                    Box::pin(futures::future::ready(Ok(())))
+                }
+            });
+        mock_storage
+            .expect_register_mint()
+            .returning(|_, _, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_client
+            .expect_call_contract()
+            .returning(move |_, _, _, _| Ok(vec![owner]));
+
+        let handler = Arc::new(RecordingHandler::default());
+        let pontos = Pontos::new(
+            Arc::new(mock_client),
+            Arc::new(mock_storage),
+            Arc::clone(&handler),
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                max_concurrent_events: Some(2),
+                ..Default::default()
+            },
+        );
+
+        let events = vec![
+            mint_event(slow_contract, owner, 1),
+            mint_event(slow_contract, owner, 2),
+            mint_event(fast_contract, owner, 3),
+            mint_event(fast_contract, owner, 4),
+        ];
+
+        pontos.process_events(events, 1000).await.unwrap();
+
+        // The fast contract events don't wait for the slow contract,
+        // whose events are still processed in order.
+        let token_ids: Vec<String> = handler
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.token_id.clone())
+            .collect();
+        assert_eq!(token_ids, vec!["1", "3", "4", "2"]);
+    }
+
+    #[tokio::test]
+    async fn test_process_events_defers_timed_out_event() {
+        let mock_client = MockStarknetClient::default();
+        let mut mock_storage = MockStorage::default();
+
+        let contract_address = FieldElement::from_hex_be("0x1234").unwrap();
+        let owner = FieldElement::from_hex_be("0xabcd").unwrap();
+
+        // The storage hangs while identifying the contract.
+        mock_storage.expect_get_contract_type().returning(|_| {
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(ContractType::ERC721)
+            })
+        });
+        mock_storage.expect_register_events().never();
+
+        let handler = Arc::new(RecordingHandler::default());
+        let pontos = Pontos::new(
+            Arc::new(mock_client),
+            Arc::new(mock_storage),
+            Arc::clone(&handler),
+            PontosConfig {
+                indexer_version: "0.0.1".to_string(),
+                indexer_identifier: "test".to_string(),
+                event_processing_timeout: Some(Duration::from_millis(50)),
+                ..Default::default()
+            },
+        );
+
+        let events = (1..=2)
+            .map(|i| mint_event(contract_address, owner, i))
+            .collect();
+
+        pontos.process_events(events, 1000).await.unwrap();
+
+        assert!(handler.events.lock().unwrap().is_empty());
+
+        // Each event is deferred, the processing moving on to the next one.
+        let deferred = handler.deferred.lock().unwrap();
+        assert_eq!(deferred.len(), 2);
+        assert_eq!(deferred[1].data[2], FieldElement::from(2_u64));
+    }
 }
@@ -0,0 +1,84 @@
+//! Logging of the indexer, human readable or structured as JSON.
+//!
+//! With the `tracing-spans` feature, the logs of a block and of an event
+//! are emitted in spans carrying the `block_number`, and the
+//! `collection_address` and `token_id`.
+//! In JSON, those fields are added to every line, allowing to filter
+//! the logs of a collection in a log aggregator.
+use ark_starknet::format::set_log_preview_length;
+use std::str::FromStr;
+use tracing_subscriber::{fmt, EnvFilter};
+
+/// Environment variable with the format of the logs, `text` or `json`.
+pub const LOG_FORMAT_ENV_VAR: &str = "PONTOS_LOG_FORMAT";
+
+/// Environment variable with the maximum number of characters logged
+/// of the values which can be large, like metadata URIs.
+pub const LOG_PREVIEW_LENGTH_ENV_VAR: &str = "PONTOS_LOG_PREVIEW_LENGTH";
+
+const DEFAULT_LOG_FILTER: &str = "info";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Human readable logs, for local development.
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the current spans.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Invalid log format: {s}")),
+        }
+    }
+}
+
+impl LogFormat {
+    /// Reads the format from `PONTOS_LOG_FORMAT`, defaulting to `Text`.
+    pub fn from_env() -> Self {
+        std::env::var(LOG_FORMAT_ENV_VAR)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Sets the global subscriber of the logs, filtered with `RUST_LOG`
+/// (`info` by default). The records of the `log` crate are also emitted.
+///
+/// Values which can be large are truncated to `PONTOS_LOG_PREVIEW_LENGTH`
+/// characters if set, and are only logged in full at `trace` level.
+pub fn init_logging(format: LogFormat) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(length) = std::env::var(LOG_PREVIEW_LENGTH_ENV_VAR)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+    {
+        set_log_preview_length(length);
+    }
+
+    let filter = EnvFilter::try_from_default_env().or(EnvFilter::try_new(DEFAULT_LOG_FILTER))?;
+    let builder = fmt().with_env_filter(filter);
+
+    match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).try_init(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_format() {
+        assert_eq!("json".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert_eq!(" Text ".parse::<LogFormat>(), Ok(LogFormat::Text));
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+}
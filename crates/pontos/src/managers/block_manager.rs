@@ -1,4 +1,4 @@
-use crate::storage::types::{BlockIndexingStatus, BlockInfo, StorageError};
+use crate::storage::types::{BlockIndexingStatus, BlockInfo, StorageError, TokenEvent};
 use crate::storage::Storage;
 use starknet::core::types::FieldElement;
 use std::sync::Arc;
@@ -27,6 +27,14 @@ impl<S: Storage> BlockManager<S> {
             .await
     }
 
+    /// Returns the events registered in the block of the given timestamp.
+    pub async fn get_block_events(
+        &self,
+        block_timestamp: u64,
+    ) -> Result<Vec<TokenEvent>, StorageError> {
+        self.storage.find_block_events(block_timestamp).await
+    }
+
     /// Returns false if the given block number must be indexed.
     /// True otherwise.
     pub async fn should_skip_indexing(
@@ -80,6 +88,7 @@ impl<S: Storage> BlockManager<S> {
         indexer_version: &str,
         indexer_identifier: &str,
         status: BlockIndexingStatus,
+        block_hash: Option<String>,
     ) -> Result<(), StorageError> {
         self.storage
             .set_block_info(
@@ -90,11 +99,44 @@ impl<S: Storage> BlockManager<S> {
                     indexer_identifier: indexer_identifier.to_string(),
                     status,
                     block_number,
+                    block_timestamp,
+                    block_hash,
                 },
             )
             .await?;
         Ok(())
     }
+
+    /// Returns the info of the given block, if already indexed.
+    pub async fn get_block_info(
+        &self,
+        block_number: u64,
+    ) -> Result<Option<BlockInfo>, StorageError> {
+        match self.storage.get_block_info(block_number).await {
+            Ok(info) => Ok(Some(info)),
+            Err(StorageError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get_last_processed_block(
+        &self,
+        indexer_identifier: &str,
+    ) -> Result<Option<u64>, StorageError> {
+        self.storage
+            .get_last_processed_block(indexer_identifier)
+            .await
+    }
+
+    pub async fn set_last_processed_block(
+        &self,
+        indexer_identifier: &str,
+        block_number: u64,
+    ) -> Result<(), StorageError> {
+        self.storage
+            .set_last_processed_block(indexer_identifier, block_number)
+            .await
+    }
 }
 
 /// Data of the pending block being indexed.
@@ -177,7 +219,7 @@ mod tests {
             .await
             .unwrap();
 
-        assert!(result == false);
+        assert!(!result);
     }
 
     #[tokio::test]
@@ -199,6 +241,8 @@ mod tests {
                         indexer_version: String::from("v0.0.1"),
                         indexer_identifier: String::from("TASK#123"),
                         block_number: 123,
+                        block_timestamp: 0,
+                        block_hash: None,
                     })
                 } else {
                     Err(StorageError::NotFound("".to_string()))
@@ -219,13 +263,13 @@ mod tests {
             .should_skip_indexing(1, 0, "v0.0.2", false)
             .await
             .unwrap();
-        assert!(result == false);
+        assert!(!result);
 
         // Force but same version, should return true for indexing.
         let result = manager
             .should_skip_indexing(2, 0, "v0.0.1", true)
             .await
             .unwrap();
-        assert!(result == false);
+        assert!(!result);
     }
 }
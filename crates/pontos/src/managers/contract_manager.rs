@@ -1,26 +1,61 @@
+use crate::managers::royalty::get_default_royalty;
 use crate::storage::{
     types::{ContractInfo, ContractType, StorageError},
     Storage,
 };
 use anyhow::Result;
 use ark_starknet::{
-    cairo_string_parser::parse_cairo_string,
+    cairo_string_parser::parse_cairo_string_lossy,
     client::{StarknetClient, StarknetClientError},
     format::to_hex_str,
+    CairoU256,
 };
 use starknet::core::{
     types::{BlockId, BlockTag, FieldElement},
     utils::get_selector_from_name,
 };
+use starknet::macros::felt;
 use std::collections::HashMap;
-use std::sync::Arc;
-use tracing::{error, info, trace};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{error, info, trace, warn};
+
+/// Number of retries when reading a contract type from the storage
+/// fails for another reason than the contract not being found.
+const STORAGE_READ_RETRIES: u32 = 3;
+/// Delay before the first retry, increased at each attempt.
+const STORAGE_READ_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// SRC5 interface id of the ERC721 standard.
+const IERC721_ID: FieldElement =
+    felt!("0x33eb2f84c309543403fd69f0d0f363781ef06ef6faeb0131ff16ea3175bd943");
+/// SRC5 interface id of the ERC1155 standard.
+const IERC1155_ID: FieldElement =
+    felt!("0x6114a8f75559e1b39fcba08ce02961a1aa082d9256a158dd3e64964e4b1b52");
+/// ERC165 interface id of the ERC721 standard, used by the Cairo 0 contracts.
+const ERC165_IERC721_ID: FieldElement = felt!("0x80ac58cd");
+/// ERC165 interface id of the ERC1155 standard, used by the Cairo 0 contracts.
+const ERC165_IERC1155_ID: FieldElement = felt!("0xd9b67a26");
+
+/// Entrypoints returning the implementation address of the known proxy contracts.
+const PROXY_IMPLEMENTATION_GETTERS: [&str; 4] = [
+    "get_implementation",
+    "getImplementation",
+    "implementation",
+    "get_implementation_address",
+];
 
 pub struct ContractManager<S: Storage, C: StarknetClient> {
     storage: Arc<S>,
     client: Arc<C>,
     /// A cache with contract address mapped to its type.
-    cache: HashMap<FieldElement, ContractType>,
+    /// The lock is never held during the storage or RPC calls.
+    cache: RwLock<HashMap<FieldElement, ContractType>>,
+    /// Minimum interval between two reads of the `totalSupply` of a
+    /// collection, never read if `None`.
+    total_supply_refresh_interval: Option<Duration>,
+    /// Last read of the `totalSupply` of each collection.
+    total_supply_read_at: Mutex<HashMap<FieldElement, Instant>>,
 }
 
 impl<S: Storage, C: StarknetClient> ContractManager<S, C> {
@@ -29,17 +64,28 @@ impl<S: Storage, C: StarknetClient> ContractManager<S, C> {
         Self {
             storage,
             client,
-            cache: HashMap::new(),
+            cache: RwLock::new(HashMap::new()),
+            total_supply_refresh_interval: None,
+            total_supply_read_at: Mutex::new(HashMap::new()),
         }
     }
 
+    /// Reads the `totalSupply` of the ERC721 collections when they are
+    /// identified, and again at most every `interval` when their events are
+    /// processed. It's not read by default, saving the RPC calls.
+    pub fn with_total_supply_refresh_interval(mut self, interval: Option<Duration>) -> Self {
+        self.total_supply_refresh_interval = interval;
+        self
+    }
+
     /// Gets the contract info from local cache, or fetch is from the DB.
     async fn get_cached_or_fetch_info(
-        &mut self,
+        &self,
         address: FieldElement,
     ) -> Result<ContractType, StorageError> {
-        if let Some(contract_type) = self.cache.get(&address) {
-            return Ok(contract_type.clone());
+        let cached = self.cache.read().unwrap().get(&address).cloned();
+        if let Some(contract_type) = cached {
+            return Ok(contract_type);
         }
 
         trace!("Cache miss for contract {:#064x}", address);
@@ -49,7 +95,10 @@ impl<S: Storage, C: StarknetClient> ContractManager<S, C> {
             .get_contract_type(&to_hex_str(&address))
             .await?;
 
-        self.cache.insert(address, contract_type.clone()); // Adding to the cache
+        self.cache
+            .write()
+            .unwrap()
+            .insert(address, contract_type.clone()); // Adding to the cache
 
         Ok(contract_type)
     }
@@ -58,6 +107,8 @@ impl<S: Storage, C: StarknetClient> ContractManager<S, C> {
     ///
     /// This function attempts to identify a contract by its address,
     /// fetching its type, name, and symbol, and caching these details for future use.
+    /// The cache isn't locked during the calls, so a contract seen by several
+    /// tasks at once may be identified by each of them.
     ///
     /// # Arguments
     /// * `address` - The address of the contract as a `FieldElement`.
@@ -66,167 +117,252 @@ impl<S: Storage, C: StarknetClient> ContractManager<S, C> {
     /// # Returns
     /// * `Result<ContractType>` - The type of the contract if identified successfully.
     pub async fn identify_contract(
-        &mut self,
+        &self,
         address: FieldElement,
         block_timestamp: u64,
     ) -> Result<ContractType> {
-        match self.get_cached_or_fetch_info(address).await {
-            Ok(contract_type) => Ok(contract_type),
-            Err(_) => {
-                if let Ok(contract_type) = self.get_cached_or_fetch_info(address).await {
+        let mut attempt = 0;
+
+        loop {
+            match self.get_cached_or_fetch_info(address).await {
+                Ok(contract_type) => {
+                    self.refresh_total_supply_if_due(address, &contract_type)
+                        .await;
                     return Ok(contract_type);
                 }
+                Err(StorageError::NotFound(_)) => break,
+                Err(e) if attempt < STORAGE_READ_RETRIES => {
+                    attempt += 1;
+                    warn!(
+                        "Failed to read contract type of [0x{:064x}] (attempt #{}): {}",
+                        address, attempt, e
+                    );
+                    tokio::time::sleep(STORAGE_READ_RETRY_DELAY * attempt).await;
+                }
+                Err(e) => {
+                    // The contract may already be registered, but identifying it
+                    // again on-chain is better than skipping its events.
+                    warn!(
+                        "Failed to read contract type of [0x{:064x}], identifying it on-chain: {}",
+                        address, e
+                    );
+                    break;
+                }
+            }
+        }
 
-                // If the contract info is not cached, identify and cache it.
-                let contract_type = self.get_contract_type(address).await?;
+        // If the contract info is not cached, identify and cache it.
+        let contract_type = self.get_contract_type(address).await?;
 
-                self.cache.insert(address, contract_type.clone());
+        self.cache
+            .write()
+            .unwrap()
+            .insert(address, contract_type.clone());
 
-                let name = self
-                    .get_contract_property_string(
-                        address,
-                        "name",
-                        vec![],
-                        BlockId::Tag(BlockTag::Pending),
-                    )
-                    .await
-                    .ok();
-
-                let symbol = self
-                    .get_contract_property_string(
-                        address,
-                        "symbol",
-                        vec![],
-                        BlockId::Tag(BlockTag::Pending),
-                    )
-                    .await
-                    .ok();
-
-                info!(
-                    "Contract [0x{:064x}] details - Type: {}, Name: {:?}, Symbol: {:?}",
-                    address,
-                    contract_type.to_string(),
-                    name,
-                    symbol
-                );
+        let (name, symbol) = self.get_name_and_symbol(address).await;
 
-                let info = ContractInfo {
-                    contract_address: to_hex_str(&address),
-                    contract_type: contract_type.to_string(),
-                    name,
-                    symbol,
-                    image: None,
-                };
+        info!(
+            "Contract [0x{:064x}] details - Type: {}, Name: {:?}, Symbol: {:?}",
+            address,
+            contract_type.to_string(),
+            name,
+            symbol
+        );
 
+        let info = ContractInfo {
+            contract_address: to_hex_str(&address),
+            contract_type: contract_type.to_string(),
+            name,
+            symbol,
+            image: None,
+        };
+
+        if let Err(e) = self
+            .storage
+            .register_contract_info(&info, block_timestamp)
+            .await
+        {
+            error!(
+                "Failed to store contract info for [0x{:064x}]: {:?}",
+                address, e
+            );
+        }
+
+        if contract_type != ContractType::Other {
+            if let Some(royalty) = get_default_royalty(
+                self.client.as_ref(),
+                address,
+                BlockId::Tag(BlockTag::Pending),
+            )
+            .await
+            {
                 if let Err(e) = self
                     .storage
-                    .register_contract_info(&info, block_timestamp)
+                    .register_contract_royalty(&info.contract_address, &royalty)
                     .await
                 {
                     error!(
-                        "Failed to store contract info for [0x{:064x}]: {:?}",
+                        "Failed to store contract royalty for [0x{:064x}]: {:?}",
                         address, e
                     );
                 }
-
-                Ok(contract_type)
             }
         }
-    }
 
-    /// Verifies if the contract is an ERC721, ERC1155 or an other type.
-    /// `owner_of` is specific to ERC721.
-    /// `balance_of` is specific to ERC1155 and different from ERC20 as 2 arguments are expected.
-    pub async fn get_contract_type(&self, contract_address: FieldElement) -> Result<ContractType> {
-        let _block = BlockId::Tag(BlockTag::Pending);
-
-        if self.is_erc721(contract_address).await? {
-            Ok(ContractType::ERC721)
-        } else if self.is_erc1155(contract_address).await? {
-            Ok(ContractType::ERC1155)
-        } else {
-            Ok(ContractType::Other)
-        }
-    }
+        self.refresh_total_supply_if_due(address, &contract_type)
+            .await;
 
-    /// Returns true if the contract is ERC721, false otherwise.
-    pub async fn is_erc721(&self, contract_address: FieldElement) -> Result<bool> {
-        let block = BlockId::Tag(BlockTag::Pending);
-        let token_id = vec![FieldElement::ONE, FieldElement::ZERO]; // u256.
+        Ok(contract_type)
+    }
 
-        match self
-            .get_contract_response(contract_address, "ownerOf", token_id.clone(), block)
-            .await
-        {
-            Ok(_) => return Ok(true),
-            Err(e) => match e {
-                StarknetClientError::Contract(s) => {
-                    // Token ID may not exist, but the entrypoint was hit.
-                    if s.contains("not found in contract") {
-                        // do nothing and go to the next selector.
-                    } else {
-                        return Ok(true);
-                    }
-                }
-                StarknetClientError::EntrypointNotFound(_) => (),
-                _ => return Ok(false),
-            },
+    /// Refreshes the `totalSupply` of an ERC721 collection, if
+    /// `total_supply_refresh_interval` is set and elapsed since the last read.
+    async fn refresh_total_supply_if_due(
+        &self,
+        address: FieldElement,
+        contract_type: &ContractType,
+    ) {
+        let interval = match self.total_supply_refresh_interval {
+            Some(interval) if *contract_type == ContractType::ERC721 => interval,
+            _ => return,
         };
 
-        match self
-            .get_contract_response(contract_address, "owner_of", token_id, block)
-            .await
         {
-            Ok(_) => Ok(true),
-            Err(e) => match e {
-                StarknetClientError::Contract(s) => {
-                    // Token ID may not exist, but the entrypoint was hit.
-                    if s.contains("not found in contract") {
-                        Ok(false)
-                    } else {
-                        Ok(true)
-                    }
-                }
-                StarknetClientError::EntrypointNotFound(_) => Ok(false),
-                _ => Ok(false),
-            },
+            let mut total_supply_read_at = self.total_supply_read_at.lock().unwrap();
+            if total_supply_read_at
+                .get(&address)
+                .map_or(false, |read_at| read_at.elapsed() < interval)
+            {
+                return;
+            }
+
+            // Also delays the next read of the contracts without `totalSupply`.
+            total_supply_read_at.insert(address, Instant::now());
         }
-    }
 
-    /// Returns true if the contract is ERC1155, false otherwise.
-    pub async fn is_erc1155(&self, contract_address: FieldElement) -> Result<bool> {
-        let block = BlockId::Tag(BlockTag::Pending);
-        // felt and u256 expected.
-        let address_and_token_id = vec![FieldElement::ZERO, FieldElement::ONE, FieldElement::ZERO];
+        if let Err(e) = self.refresh_total_supply(address).await {
+            error!(
+                "Failed to store total supply for [0x{:064x}]: {:?}",
+                address, e
+            );
+        }
+    }
 
-        match self
-            .get_contract_response(
-                contract_address,
-                "balanceOf",
-                address_and_token_id.clone(),
-                block,
-            )
-            .await
+    /// Reads the `totalSupply` of the contract on-chain and saves it with
+    /// `Storage::register_contract_total_supply`. Returns `None`, and saves
+    /// nothing, if the contract doesn't implement it.
+    pub async fn refresh_total_supply(
+        &self,
+        address: FieldElement,
+    ) -> Result<Option<CairoU256>, StorageError> {
+        let total_supply = match get_total_supply(
+            self.client.as_ref(),
+            address,
+            BlockId::Tag(BlockTag::Pending),
+        )
+        .await
         {
-            Ok(_) => return Ok(true),
-            Err(e) => match e {
-                StarknetClientError::EntrypointNotFound(_) => (),
-                StarknetClientError::InputTooLong => return Ok(false), // ERC20.
-                _ => return Ok(false),
-            },
+            Some(total_supply) => total_supply,
+            None => return Ok(None),
         };
 
-        match self
-            .get_contract_response(contract_address, "balance_of", address_and_token_id, block)
-            .await
+        trace!(
+            "Total supply of [0x{:064x}]: {}",
+            address,
+            total_supply.to_decimal(false)
+        );
+
+        self.storage
+            .register_contract_total_supply(&to_hex_str(&address), &total_supply.to_decimal(false))
+            .await?;
+
+        Ok(Some(total_supply))
+    }
+
+    /// Reads the name and symbol of the contract.
+    ///
+    /// Some upgradeable proxies revert `name` and `symbol`, or return `undefined`.
+    /// In this case, they are read from the implementation of the proxy,
+    /// if it has one (see `get_proxy_implementation`).
+    async fn get_name_and_symbol(&self, address: FieldElement) -> (Option<String>, Option<String>) {
+        let mut name = self.get_defined_property_string(address, "name").await;
+        let mut symbol = self.get_defined_property_string(address, "symbol").await;
+
+        if name.is_some() && symbol.is_some() {
+            return (name, symbol);
+        }
+
+        let block = BlockId::Tag(BlockTag::Pending);
+        if let Some(implementation) =
+            get_proxy_implementation(self.client.as_ref(), address, block).await
         {
-            Ok(_) => Ok(true),
-            Err(e) => match e {
-                StarknetClientError::EntrypointNotFound(_) => Ok(false),
-                StarknetClientError::InputTooLong => Ok(false), // ERC20.
-                _ => Ok(false),
-            },
+            info!(
+                "Contract [0x{:064x}] is a proxy of [0x{:064x}], reading its name and symbol from the implementation",
+                address, implementation
+            );
+
+            if name.is_none() {
+                name = self
+                    .get_defined_property_string(implementation, "name")
+                    .await;
+            }
+            if symbol.is_none() {
+                symbol = self
+                    .get_defined_property_string(implementation, "symbol")
+                    .await;
+            }
         }
+
+        (name, symbol)
+    }
+
+    /// Reads a string property of the contract, `None` if the call fails
+    /// or returns an empty or `undefined` string.
+    async fn get_defined_property_string(
+        &self,
+        address: FieldElement,
+        selector_name: &str,
+    ) -> Option<String> {
+        self.get_contract_property_string(
+            address,
+            selector_name,
+            vec![],
+            BlockId::Tag(BlockTag::Pending),
+        )
+        .await
+        .ok()
+        .filter(|value| !value.is_empty() && value != "undefined")
+    }
+
+    /// Verifies if the contract is an ERC721, ERC1155 or an other type.
+    /// See `detect_contract_type`.
+    pub async fn get_contract_type(&self, contract_address: FieldElement) -> Result<ContractType> {
+        detect_contract_type(
+            self.client.as_ref(),
+            contract_address,
+            BlockId::Tag(BlockTag::Pending),
+        )
+        .await
+    }
+
+    /// Returns true if the contract is ERC721, false otherwise.
+    pub async fn is_erc721(&self, contract_address: FieldElement) -> Result<bool> {
+        is_erc721(
+            self.client.as_ref(),
+            contract_address,
+            BlockId::Tag(BlockTag::Pending),
+        )
+        .await
+    }
+
+    /// Returns true if the contract is ERC1155, false otherwise.
+    pub async fn is_erc1155(&self, contract_address: FieldElement) -> Result<bool> {
+        is_erc1155(
+            self.client.as_ref(),
+            contract_address,
+            BlockId::Tag(BlockTag::Pending),
+        )
+        .await
     }
 
     pub async fn get_contract_response(
@@ -236,18 +372,19 @@ impl<S: Storage, C: StarknetClient> ContractManager<S, C> {
         calldata: Vec<FieldElement>,
         block: BlockId,
     ) -> Result<Vec<FieldElement>, StarknetClientError> {
-        self.client
-            .call_contract(
-                contract_address,
-                get_selector_from_name(selector_name).map_err(|_| {
-                    StarknetClientError::Other(format!("Invalid selector: {}", selector_name))
-                })?,
-                calldata,
-                block,
-            )
-            .await
+        call(
+            self.client.as_ref(),
+            contract_address,
+            selector_name,
+            calldata,
+            block,
+        )
+        .await
     }
 
+    /// Reads a string property of the contract. The values which are not
+    /// valid UTF-8 are decoded with `parse_cairo_string_lossy`, `undefined`
+    /// being returned for the values which can't be printed.
     pub async fn get_contract_property_string(
         &self,
         contract_address: FieldElement,
@@ -267,8 +404,615 @@ impl<S: Storage, C: StarknetClient> ContractManager<S, C> {
             )
             .await?;
 
-        parse_cairo_string(response).map_err(|e| {
-            StarknetClientError::Other(format!("Impossible to decode response string: {:?}", e))
-        })
+        Ok(parse_cairo_string_lossy(&response))
+    }
+}
+
+/// Detects if the contract is an ERC721, ERC1155 or an other type, at the given block.
+///
+/// The contract is first asked if it supports the ERC721 or ERC1155 interface
+/// (SRC5 or ERC165 `supportsInterface`), its answer being trusted. Only if
+/// `supportsInterface` is missing or reverts, its entrypoints are probed:
+/// `owner_of` is specific to ERC721, and `balance_of` is specific to ERC1155
+/// and different from ERC20 as 2 arguments are expected.
+pub async fn detect_contract_type<C: StarknetClient>(
+    client: &C,
+    contract_address: FieldElement,
+    block: BlockId,
+) -> Result<ContractType> {
+    let mut supports_introspection = false;
+
+    for (interface_id, contract_type) in [
+        (IERC721_ID, ContractType::ERC721),
+        (ERC165_IERC721_ID, ContractType::ERC721),
+        (IERC1155_ID, ContractType::ERC1155),
+        (ERC165_IERC1155_ID, ContractType::ERC1155),
+    ] {
+        match supports_interface(client, contract_address, interface_id, block).await? {
+            Some(true) => return Ok(contract_type),
+            Some(false) => supports_introspection = true,
+            None => break,
+        }
+    }
+
+    if supports_introspection {
+        return Ok(ContractType::Other);
+    }
+
+    if is_erc721(client, contract_address, block).await? {
+        Ok(ContractType::ERC721)
+    } else if is_erc1155(client, contract_address, block).await? {
+        Ok(ContractType::ERC1155)
+    } else {
+        Ok(ContractType::Other)
+    }
+}
+
+/// Detects the type of the contracts, caching the result of each address.
+///
+/// Useful for tooling checking collections without indexing them, as
+/// it requires no storage, unlike the `ContractManager`.
+pub struct ContractTypeDetector<C: StarknetClient> {
+    client: Arc<C>,
+    cache: Mutex<HashMap<FieldElement, ContractType>>,
+}
+
+impl<C: StarknetClient> ContractTypeDetector<C> {
+    pub fn new(client: Arc<C>) -> Self {
+        Self {
+            client,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Detects the type of the contract, see `detect_contract_type`.
+    /// The block is only used the first time an address is detected.
+    pub async fn detect(
+        &self,
+        contract_address: FieldElement,
+        block: BlockId,
+    ) -> Result<ContractType> {
+        if let Some(contract_type) = self.cache.lock().unwrap().get(&contract_address) {
+            return Ok(contract_type.clone());
+        }
+
+        let contract_type =
+            detect_contract_type(self.client.as_ref(), contract_address, block).await?;
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(contract_address, contract_type.clone());
+
+        Ok(contract_type)
+    }
+}
+
+/// Strictness of the validation of the stored contract type of the events,
+/// against the type detected on-chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContractTypeValidation {
+    /// Logs the mismatches, the events being processed with the stored type.
+    Warn,
+    /// Logs the mismatches and drops the events.
+    Reject,
+}
+
+/// Returns the implementation address of a proxy contract, read from the
+/// first known implementation getter it exposes, or `None` if the contract
+/// is not a proxy.
+pub async fn get_proxy_implementation<C: StarknetClient>(
+    client: &C,
+    contract_address: FieldElement,
+    block: BlockId,
+) -> Option<FieldElement> {
+    for selector_name in PROXY_IMPLEMENTATION_GETTERS {
+        match call(client, contract_address, selector_name, vec![], block).await {
+            Ok(response) => {
+                return response
+                    .first()
+                    .copied()
+                    .filter(|i| *i != FieldElement::ZERO && *i != contract_address);
+            }
+            Err(StarknetClientError::EntrypointNotFound(_)) => (),
+            Err(e) => {
+                trace!(
+                    "Failed to read implementation of [0x{:064x}] with {}: {}",
+                    contract_address,
+                    selector_name,
+                    e
+                );
+            }
+        }
+    }
+
+    None
+}
+
+/// Returns the `totalSupply` of the contract, or `None` if the contract
+/// doesn't implement it (like most ERC1155) or if the call fails.
+pub async fn get_total_supply<C: StarknetClient>(
+    client: &C,
+    contract_address: FieldElement,
+    block: BlockId,
+) -> Option<CairoU256> {
+    for selector_name in ["total_supply", "totalSupply"] {
+        match call(client, contract_address, selector_name, vec![], block).await {
+            // (low, high) of the u256.
+            Ok(response) if response.len() == 2 => {
+                return CairoU256::from_felts(response[0], response[1]);
+            }
+            Ok(response) => {
+                trace!(
+                    "Unexpected total supply of [0x{:064x}]: {:?}",
+                    contract_address,
+                    response
+                );
+                return None;
+            }
+            Err(StarknetClientError::EntrypointNotFound(_)) => (),
+            Err(e) => {
+                trace!(
+                    "Failed to read total supply of [0x{:064x}] with {}: {}",
+                    contract_address,
+                    selector_name,
+                    e
+                );
+                return None;
+            }
+        }
+    }
+
+    None
+}
+
+/// Returns if the contract supports the given interface, or `None` if
+/// `supportsInterface` is missing or reverts.
+async fn supports_interface<C: StarknetClient>(
+    client: &C,
+    contract_address: FieldElement,
+    interface_id: FieldElement,
+    block: BlockId,
+) -> Result<Option<bool>> {
+    for selector_name in ["supports_interface", "supportsInterface"] {
+        match call(
+            client,
+            contract_address,
+            selector_name,
+            vec![interface_id],
+            block,
+        )
+        .await
+        {
+            Ok(response) => return Ok(Some(response.first() == Some(&FieldElement::ONE))),
+            Err(StarknetClientError::EntrypointNotFound(_)) => (),
+            Err(e @ StarknetClientError::Provider(_)) => return Err(e.into()),
+            Err(_) => return Ok(None),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns true if the contract is ERC721, false otherwise.
+async fn is_erc721<C: StarknetClient>(
+    client: &C,
+    contract_address: FieldElement,
+    block: BlockId,
+) -> Result<bool> {
+    let token_id = vec![FieldElement::ONE, FieldElement::ZERO]; // u256.
+
+    match call(client, contract_address, "ownerOf", token_id.clone(), block).await {
+        Ok(_) => return Ok(true),
+        Err(e) => match e {
+            StarknetClientError::Contract(s) => {
+                // Token ID may not exist, but the entrypoint was hit.
+                if s.contains("not found in contract") {
+                    // do nothing and go to the next selector.
+                } else {
+                    return Ok(true);
+                }
+            }
+            StarknetClientError::EntrypointNotFound(_) => (),
+            e @ StarknetClientError::Provider(_) => return Err(e.into()),
+            _ => return Ok(false),
+        },
+    };
+
+    match call(client, contract_address, "owner_of", token_id, block).await {
+        Ok(_) => Ok(true),
+        Err(e) => match e {
+            StarknetClientError::Contract(s) => {
+                // Token ID may not exist, but the entrypoint was hit.
+                if s.contains("not found in contract") {
+                    Ok(false)
+                } else {
+                    Ok(true)
+                }
+            }
+            StarknetClientError::EntrypointNotFound(_) => Ok(false),
+            e @ StarknetClientError::Provider(_) => Err(e.into()),
+            _ => Ok(false),
+        },
+    }
+}
+
+/// Returns true if the contract is ERC1155, false otherwise.
+async fn is_erc1155<C: StarknetClient>(
+    client: &C,
+    contract_address: FieldElement,
+    block: BlockId,
+) -> Result<bool> {
+    // felt and u256 expected.
+    let address_and_token_id = vec![FieldElement::ZERO, FieldElement::ONE, FieldElement::ZERO];
+
+    match call(
+        client,
+        contract_address,
+        "balanceOf",
+        address_and_token_id.clone(),
+        block,
+    )
+    .await
+    {
+        Ok(_) => return Ok(true),
+        Err(e) => match e {
+            StarknetClientError::EntrypointNotFound(_) => (),
+            StarknetClientError::InputTooLong => return Ok(false), // ERC20.
+            e @ StarknetClientError::Provider(_) => return Err(e.into()),
+            _ => return Ok(false),
+        },
+    };
+
+    match call(
+        client,
+        contract_address,
+        "balance_of",
+        address_and_token_id,
+        block,
+    )
+    .await
+    {
+        Ok(_) => Ok(true),
+        Err(e) => match e {
+            StarknetClientError::EntrypointNotFound(_) => Ok(false),
+            StarknetClientError::InputTooLong => Ok(false), // ERC20.
+            e @ StarknetClientError::Provider(_) => Err(e.into()),
+            _ => Ok(false),
+        },
+    }
+}
+
+async fn call<C: StarknetClient>(
+    client: &C,
+    contract_address: FieldElement,
+    selector_name: &str,
+    calldata: Vec<FieldElement>,
+    block: BlockId,
+) -> Result<Vec<FieldElement>, StarknetClientError> {
+    client
+        .call_contract(
+            contract_address,
+            get_selector_from_name(selector_name).map_err(|_| {
+                StarknetClientError::Other(format!("Invalid selector: {}", selector_name))
+            })?,
+            calldata,
+            block,
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MockStorage;
+    use ark_starknet::client::MockStarknetClient;
+    use mockall::Sequence;
+
+    #[tokio::test]
+    async fn test_identify_contract_retries_transient_storage_error() {
+        let mut mock_storage = MockStorage::default();
+        let mock_client = MockStarknetClient::default();
+        let mut seq = Sequence::new();
+
+        mock_storage
+            .expect_get_contract_type()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| {
+                Box::pin(futures::future::ready(Err(StorageError::DatabaseError(
+                    "timeout".to_string(),
+                ))))
+            });
+        mock_storage
+            .expect_get_contract_type()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Box::pin(futures::future::ready(Ok(ContractType::ERC721))));
+
+        let manager = ContractManager::new(Arc::new(mock_storage), Arc::new(mock_client));
+
+        let contract_type = manager
+            .identify_contract(FieldElement::ONE, 1000)
+            .await
+            .unwrap();
+
+        assert_eq!(contract_type, ContractType::ERC721);
+    }
+
+    #[tokio::test]
+    async fn test_identify_contract_on_chain_after_storage_errors() {
+        let mut mock_storage = MockStorage::default();
+        let mut mock_client = MockStarknetClient::default();
+
+        mock_storage
+            .expect_get_contract_type()
+            .times(STORAGE_READ_RETRIES as usize + 1)
+            .returning(|_| {
+                Box::pin(futures::future::ready(Err(StorageError::DatabaseError(
+                    "timeout".to_string(),
+                ))))
+            });
+        mock_storage
+            .expect_register_contract_info()
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+
+        mock_client
+            .expect_call_contract()
+            .returning(|_, _, _, _| Ok(vec![FieldElement::ONE]));
+
+        let manager = ContractManager::new(Arc::new(mock_storage), Arc::new(mock_client));
+
+        let contract_type = manager
+            .identify_contract(FieldElement::ONE, 1000)
+            .await
+            .unwrap();
+
+        assert_eq!(contract_type, ContractType::ERC721);
+    }
+
+    #[tokio::test]
+    async fn test_identify_contract_refreshes_total_supply() {
+        let mut mock_storage = MockStorage::default();
+        let mut mock_client = MockStarknetClient::default();
+
+        mock_storage
+            .expect_get_contract_type()
+            .returning(|_| Box::pin(futures::future::ready(Ok(ContractType::ERC721))));
+        mock_storage
+            .expect_register_contract_total_supply()
+            .withf(|_, total_supply| total_supply == "5")
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+
+        mock_client
+            .expect_call_contract()
+            .withf(|_, selector, _, _| *selector == get_selector_from_name("total_supply").unwrap())
+            .times(1)
+            .returning(|_, _, _, _| Ok(vec![FieldElement::from(5_u32), FieldElement::ZERO]));
+
+        let manager = ContractManager::new(Arc::new(mock_storage), Arc::new(mock_client))
+            .with_total_supply_refresh_interval(Some(Duration::from_secs(3600)));
+
+        // Only read once per interval.
+        for _ in 0..3 {
+            manager
+                .identify_contract(FieldElement::ONE, 1000)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_total_supply_not_implemented() {
+        let mut mock_client = MockStarknetClient::default();
+
+        mock_client
+            .expect_call_contract()
+            .times(2)
+            .returning(|_, _, _, _| Err(StarknetClientError::EntrypointNotFound("".to_string())));
+
+        let total_supply = get_total_supply(
+            &mock_client,
+            FieldElement::ONE,
+            BlockId::Tag(BlockTag::Pending),
+        )
+        .await;
+
+        assert!(total_supply.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_contract_type_detector_supports_interface() {
+        let mut mock_client = MockStarknetClient::default();
+
+        mock_client
+            .expect_call_contract()
+            .times(3)
+            .returning(|_, selector, calldata, _| {
+                assert_eq!(
+                    selector,
+                    get_selector_from_name("supports_interface").unwrap()
+                );
+                Ok(vec![if calldata == vec![IERC1155_ID] {
+                    FieldElement::ONE
+                } else {
+                    FieldElement::ZERO
+                }])
+            });
+
+        let detector = ContractTypeDetector::new(Arc::new(mock_client));
+
+        for _ in 0..2 {
+            let contract_type = detector
+                .detect(FieldElement::ONE, BlockId::Tag(BlockTag::Latest))
+                .await
+                .unwrap();
+
+            assert_eq!(contract_type, ContractType::ERC1155);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_detect_contract_type_erc165() {
+        let mut mock_client = MockStarknetClient::default();
+
+        mock_client
+            .expect_call_contract()
+            .returning(|_, _, calldata, _| {
+                Ok(vec![if calldata == vec![ERC165_IERC1155_ID] {
+                    FieldElement::ONE
+                } else {
+                    FieldElement::ZERO
+                }])
+            });
+
+        let contract_type = detect_contract_type(
+            &mock_client,
+            FieldElement::ONE,
+            BlockId::Tag(BlockTag::Latest),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(contract_type, ContractType::ERC1155);
+    }
+
+    #[tokio::test]
+    async fn test_detect_contract_type_probes_entrypoints_on_revert() {
+        let mut mock_client = MockStarknetClient::default();
+
+        mock_client
+            .expect_call_contract()
+            .returning(|_, selector, _, _| {
+                if selector == get_selector_from_name("supports_interface").unwrap() {
+                    Err(StarknetClientError::Contract("reverted".to_string()))
+                } else if selector == get_selector_from_name("ownerOf").unwrap() {
+                    Ok(vec![FieldElement::TWO])
+                } else {
+                    Err(StarknetClientError::EntrypointNotFound("".to_string()))
+                }
+            });
+
+        let contract_type = detect_contract_type(
+            &mock_client,
+            FieldElement::ONE,
+            BlockId::Tag(BlockTag::Latest),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(contract_type, ContractType::ERC721);
+    }
+
+    #[tokio::test]
+    async fn test_get_contract_property_string_invalid_utf8() {
+        let mock_storage = MockStorage::default();
+        let mut mock_client = MockStarknetClient::default();
+
+        mock_client
+            .expect_call_contract()
+            .returning(|_, selector, _, _| {
+                if selector == get_selector_from_name("name").unwrap() {
+                    // "Ducks" followed by an invalid UTF-8 byte and NULs.
+                    Ok(vec![
+                        FieldElement::from_hex_be("0x4475636b73ff0000").unwrap()
+                    ])
+                } else {
+                    // Binary data.
+                    Ok(vec![FieldElement::from_hex_be("0x01fffe").unwrap()])
+                }
+            });
+
+        let manager = ContractManager::new(Arc::new(mock_storage), Arc::new(mock_client));
+        let block = BlockId::Tag(BlockTag::Pending);
+
+        let name = manager
+            .get_contract_property_string(FieldElement::ONE, "name", vec![], block)
+            .await
+            .unwrap();
+        assert_eq!(name, "Ducks\u{FFFD}");
+
+        let symbol = manager
+            .get_contract_property_string(FieldElement::ONE, "symbol", vec![], block)
+            .await
+            .unwrap();
+        assert_eq!(symbol, "undefined");
+    }
+
+    #[tokio::test]
+    async fn test_identify_contract_reads_name_from_proxy_implementation() {
+        let mut mock_storage = MockStorage::default();
+        let mut mock_client = MockStarknetClient::default();
+        let proxy = FieldElement::ONE;
+        let implementation = FieldElement::TWO;
+
+        mock_storage.expect_get_contract_type().returning(|_| {
+            Box::pin(futures::future::ready(Err(StorageError::NotFound(
+                "".to_string(),
+            ))))
+        });
+        mock_storage
+            .expect_register_contract_info()
+            .withf(|info, _| {
+                info.name == Some("Ducks".to_string()) && info.symbol == Some("DCK".to_string())
+            })
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+        mock_storage
+            .expect_register_contract_royalty()
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+
+        mock_client
+            .expect_call_contract()
+            .returning(move |address, selector, _, _| {
+                let short_string = |s: &str| {
+                    Ok(vec![
+                        starknet::core::utils::cairo_short_string_to_felt(s).unwrap()
+                    ])
+                };
+
+                if selector == get_selector_from_name("supports_interface").unwrap() {
+                    Ok(vec![FieldElement::ONE])
+                } else if selector == get_selector_from_name("get_implementation").unwrap() {
+                    Err(StarknetClientError::EntrypointNotFound("".to_string()))
+                } else if selector == get_selector_from_name("getImplementation").unwrap() {
+                    Ok(vec![implementation])
+                } else if address == proxy && selector == get_selector_from_name("name").unwrap() {
+                    Err(StarknetClientError::Contract("reverted".to_string()))
+                } else if selector == get_selector_from_name("name").unwrap() {
+                    short_string("Ducks")
+                } else if selector == get_selector_from_name("symbol").unwrap() {
+                    short_string("DCK")
+                } else {
+                    Err(StarknetClientError::EntrypointNotFound("".to_string()))
+                }
+            });
+
+        let manager = ContractManager::new(Arc::new(mock_storage), Arc::new(mock_client));
+
+        let contract_type = manager.identify_contract(proxy, 1000).await.unwrap();
+
+        assert_eq!(contract_type, ContractType::ERC721);
+    }
+
+    #[tokio::test]
+    async fn test_get_proxy_implementation_not_proxy() {
+        let mut mock_client = MockStarknetClient::default();
+
+        mock_client
+            .expect_call_contract()
+            .times(PROXY_IMPLEMENTATION_GETTERS.len())
+            .returning(|_, _, _, _| Err(StarknetClientError::EntrypointNotFound("".to_string())));
+
+        let implementation = get_proxy_implementation(
+            &mock_client,
+            FieldElement::ONE,
+            BlockId::Tag(BlockTag::Latest),
+        )
+        .await;
+
+        assert_eq!(implementation, None);
     }
 }
@@ -1,33 +1,131 @@
-use crate::storage::types::{EventType, TokenEvent};
+use crate::storage::types::{EventType, OperatorApprovalInfo, TokenApprovalInfo, TokenEvent};
 use crate::storage::Storage;
 use crate::ContractType;
 use anyhow::{anyhow, Result};
-use ark_starknet::{format::to_hex_str, CairoU256};
+use ark_starknet::{
+    explorer::ExplorerLinks, format::to_hex_str, network::Network, CairoU256, PaddedTokenId,
+};
 use starknet::core::types::{EmittedEvent, FieldElement};
 use starknet::core::utils::starknet_keccak;
 use starknet::macros::selector;
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::{debug, trace};
 
 const TRANSFER_SELECTOR: FieldElement = selector!("Transfer");
+const APPROVAL_SELECTOR: FieldElement = selector!("Approval");
+const APPROVAL_FOR_ALL_SELECTOR: FieldElement = selector!("ApprovalForAll");
 
 #[derive(Debug)]
 pub struct EventManager<S: Storage> {
     storage: Arc<S>,
+    network: Network,
+    explorer_links: Option<ExplorerLinks>,
 }
 
 impl<S: Storage> EventManager<S> {
-    /// Initializes a new instance.
+    /// Initializes a new instance, tagging the events with the mainnet network.
     pub fn new(storage: Arc<S>) -> Self {
         EventManager {
             storage: Arc::clone(&storage),
+            network: Network::default(),
+            explorer_links: None,
         }
     }
 
+    /// Sets the network the registered events are tagged with.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Sets the explorer linked by the `transaction_url` of the registered
+    /// events, not set if `None`.
+    pub fn with_explorer_links(mut self, explorer_links: Option<ExplorerLinks>) -> Self {
+        self.explorer_links = explorer_links;
+        self
+    }
+
     /// Returns the selectors used to filter events.
     pub fn keys_selector(&self) -> Option<Vec<Vec<FieldElement>>> {
-        Some(vec![vec![TRANSFER_SELECTOR]])
+        Some(vec![vec![
+            TRANSFER_SELECTOR,
+            APPROVAL_SELECTOR,
+            APPROVAL_FOR_ALL_SELECTOR,
+        ]])
+    }
+
+    /// Returns true if the event is an `Approval` or `ApprovalForAll` event.
+    pub fn is_approval_event(event: &EmittedEvent) -> bool {
+        event.keys.first().map_or(false, |k| {
+            *k == APPROVAL_SELECTOR || *k == APPROVAL_FOR_ALL_SELECTOR
+        })
+    }
+
+    /// Formats & registers an `Approval` or `ApprovalForAll` event.
+    /// An approval to the zero address, or an operator approval
+    /// set to false, is registered as a revoke.
+    pub async fn format_and_register_approval(
+        &self,
+        event: &EmittedEvent,
+        block_timestamp: u64,
+    ) -> Result<()> {
+        debug!(
+            "Processing approval: tx_hash=0x{:064x}, timestamp={}",
+            event.transaction_hash, block_timestamp
+        );
+        trace!("Event: {:?}", event);
+
+        // Depending on the cairo version, the values are in the keys
+        // (after the selector) or in the data, but always in the same order.
+        let felts: Vec<FieldElement> = event.keys[1..]
+            .iter()
+            .chain(event.data.iter())
+            .copied()
+            .collect();
+
+        let contract_address = to_hex_str(&event.from_address);
+
+        if event.keys[0] == APPROVAL_SELECTOR {
+            let (owner, approved, token_id) = Self::get_event_info_from_felts(&felts)
+                .ok_or_else(|| anyhow!("Can't find approval data into this event"))?;
+
+            let info = TokenApprovalInfo {
+                owner: to_hex_str(&owner),
+                approved: (approved != FieldElement::ZERO).then(|| to_hex_str(&approved)),
+                timestamp: block_timestamp,
+                transaction_hash: to_hex_str(&event.transaction_hash),
+                block_number: event.block_number,
+            };
+
+            trace!("Registering token approval: {:?}", info);
+
+            self.storage
+                .register_token_approval(&contract_address, &PaddedTokenId::from(&token_id), &info)
+                .await?;
+        } else {
+            if felts.len() < 3 {
+                return Err(anyhow!("Can't find approval data into this event"));
+            }
+
+            let info = OperatorApprovalInfo {
+                owner: to_hex_str(&felts[0]),
+                operator: to_hex_str(&felts[1]),
+                approved: felts[2] != FieldElement::ZERO,
+                timestamp: block_timestamp,
+                transaction_hash: to_hex_str(&event.transaction_hash),
+                block_number: event.block_number,
+            };
+
+            trace!("Registering operator approval: {:?}", info);
+
+            self.storage
+                .register_operator_approval(&contract_address, &info)
+                .await?;
+        }
+
+        Ok(())
     }
 
     /// Formats & register a token event based on the event content.
@@ -37,13 +135,33 @@ impl<S: Storage> EventManager<S> {
         event: &EmittedEvent,
         contract_type: ContractType,
         block_timestamp: u64,
+    ) -> Result<(CairoU256, TokenEvent)> {
+        let (token_id, token_event) = self.format_event(event, contract_type, block_timestamp)?;
+
+        trace!("Registering event: {:?}", token_event);
+
+        self.storage
+            .register_event(&token_event, block_timestamp)
+            .await?;
+
+        Ok((token_id, token_event))
+    }
+
+    /// Formats a token event based on the event content, without registering it.
+    /// Returns the token_id if the event were identified.
+    pub fn format_event(
+        &self,
+        event: &EmittedEvent,
+        contract_type: ContractType,
+        block_timestamp: u64,
     ) -> Result<(CairoU256, TokenEvent)> {
         let mut token_event = TokenEvent::default();
 
         debug!(
-            "Processing event: event={:?}, contract_type={:?}, timestamp={}",
-            event, contract_type, block_timestamp
+            "Processing event: tx_hash=0x{:064x}, contract_type={:?}, timestamp={}",
+            event.transaction_hash, contract_type, block_timestamp
         );
+        trace!("Event: {:?}", event);
 
         // As cairo didn't have keys before, we first check if the data
         // contains the info. If not, we check into the keys, skipping the first
@@ -65,13 +183,18 @@ impl<S: Storage> EventManager<S> {
         token_event.to_address = to_hex_str(&to);
         token_event.contract_address = to_hex_str(&event.from_address);
         token_event.transaction_hash = to_hex_str(&event.transaction_hash);
-        token_event.token_id_hex = token_id.to_hex();
+        token_event.token_id_hex = PaddedTokenId::from(&token_id);
         token_event.token_id = token_id.to_decimal(false);
         token_event.timestamp = block_timestamp;
         token_event.contract_type = contract_type.to_string();
         token_event.event_type = Self::get_event_type(from, to);
         token_event.event_id = to_hex_str(&event_id);
         token_event.block_number = event.block_number;
+        token_event.network = self.network.to_string();
+        token_event.transaction_url = self
+            .explorer_links
+            .as_ref()
+            .map(|links| links.transaction_url(event.transaction_hash));
         token_event.updated_at = Some(
             SystemTime::now()
                 .duration_since(UNIX_EPOCH)
@@ -79,13 +202,67 @@ impl<S: Storage> EventManager<S> {
                 .as_secs(),
         );
 
-        trace!("Registering event: {:?}", token_event);
+        Ok((token_id, token_event))
+    }
+
+    /// Registers the events of a block at once, the events already
+    /// registered being kept.
+    pub async fn register_events(&self, events: &[TokenEvent], block_timestamp: u64) -> Result<()> {
+        trace!("Registering {} events", events.len());
 
         self.storage
-            .register_event(&token_event, block_timestamp)
+            .register_events(events, block_timestamp)
             .await?;
 
-        Ok((token_id, token_event.clone()))
+        Ok(())
+    }
+
+    /// Removes the events describing the same transfer as a previous event:
+    /// same selector, transaction, contract, sender, recipient and token id, the data
+    /// being read from the event data or keys. The first event is kept.
+    ///
+    /// The RPC doesn't give the index of the events in their transaction,
+    /// so the same token can't be transferred twice between the same
+    /// addresses in one transaction without being deduplicated.
+    pub fn deduplicate_events(events: Vec<EmittedEvent>) -> Vec<EmittedEvent> {
+        let mut seen = HashSet::new();
+
+        events
+            .into_iter()
+            .filter(|event| {
+                let info = Self::get_event_info_from_felts(&event.data).or_else(|| {
+                    event
+                        .keys
+                        .get(1..)
+                        .and_then(Self::get_event_info_from_felts)
+                });
+
+                // Events without transfer info are kept, to be handled as usual.
+                let (from, to, token_id) = match info {
+                    Some(info) => info,
+                    None => return true,
+                };
+
+                let is_new = seen.insert((
+                    event.keys.first().copied(),
+                    event.transaction_hash,
+                    event.from_address,
+                    from,
+                    to,
+                    token_id.low,
+                    token_id.high,
+                ));
+                if !is_new {
+                    debug!(
+                        "Duplicate transfer of token {} in tx 0x{:064x}, skipping it",
+                        token_id.to_decimal(false),
+                        event.transaction_hash
+                    );
+                }
+
+                is_new
+            })
+            .collect()
     }
 
     pub fn get_event_type(from: FieldElement, to: FieldElement) -> EventType {
@@ -125,6 +302,7 @@ impl<S: Storage> EventManager<S> {
     ///
     /// This methods considers that the info of the
     /// event is starting at index 0 of the input vector.
+    /// Returns `None` if a word of the token id doesn't fit in 128 bits.
     fn get_event_info_from_felts(
         felts: &[FieldElement],
     ) -> Option<(FieldElement, FieldElement, CairoU256)> {
@@ -134,11 +312,7 @@ impl<S: Storage> EventManager<S> {
         let from = felts[0];
         let to = felts[1];
 
-        // Safe to unwrap, as emitted events follow cairo sequencer specification.
-        let token_id = CairoU256 {
-            low: felts[2].try_into().unwrap(),
-            high: felts[3].try_into().unwrap(),
-        };
+        let token_id = CairoU256::from_felts(felts[2], felts[3])?;
 
         Some((from, to, token_id))
     }
@@ -148,14 +322,15 @@ impl<S: Storage> EventManager<S> {
 mod tests {
     use super::*;
     use crate::storage::MockStorage;
+    use ark_starknet::explorer::Explorer;
 
     /// Sets up sample data and event for testing purposes.
     fn setup_sample_event() -> EmittedEvent {
         EmittedEvent {
             from_address: FieldElement::from_hex_be("0x0").unwrap(),
-            block_hash: FieldElement::from_dec_str("786").unwrap(),
+            block_hash: Some(FieldElement::from_dec_str("786").unwrap()),
             transaction_hash: FieldElement::from_dec_str("5432").unwrap(),
-            block_number: 111,
+            block_number: Some(111),
             keys: vec![
                 TRANSFER_SELECTOR,
                 FieldElement::from_hex_be("0x1234").unwrap(),
@@ -196,6 +371,37 @@ mod tests {
             token_event.from_address,
             to_hex_str(&FieldElement::from_hex_be("0x1234").unwrap())
         );
+        assert_eq!(token_event.transaction_url, None);
+    }
+
+    #[tokio::test]
+    async fn test_format_event_transaction_url() {
+        let mut storage = MockStorage::default();
+
+        storage
+            .expect_register_event()
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+
+        let manager = EventManager::new(Arc::new(storage))
+            .with_network(Network::Sepolia)
+            .with_explorer_links(Some(ExplorerLinks::new(
+                Explorer::Voyager,
+                Network::Sepolia,
+            )));
+
+        let sample_event = setup_sample_event();
+        let (_, token_event) = manager
+            .format_and_register_event(&sample_event, ContractType::ERC721, 1234567890)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            token_event.transaction_url,
+            Some(format!(
+                "https://sepolia.voyager.online/tx/{}",
+                to_hex_str(&sample_event.transaction_hash)
+            ))
+        );
     }
 
     #[tokio::test]
@@ -213,9 +419,9 @@ mod tests {
         // and not in `event.keys`.
         let sample_event = EmittedEvent {
             from_address: FieldElement::from_hex_be("0x0").unwrap(),
-            block_hash: FieldElement::from_dec_str("786").unwrap(),
+            block_hash: Some(FieldElement::from_dec_str("786").unwrap()),
             transaction_hash: FieldElement::from_dec_str("5432").unwrap(),
-            block_number: 111,
+            block_number: Some(111),
             keys: vec![
                 TRANSFER_SELECTOR, // This is the selector, so it's not used to extract event data
             ],
@@ -252,6 +458,204 @@ mod tests {
         assert_eq!(token_id.high, 121314_u128);
     }
 
+    #[tokio::test]
+    async fn test_format_event_token_id_forms() {
+        let mut storage = MockStorage::default();
+
+        storage
+            .expect_register_event()
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+
+        let manager = EventManager::new(Arc::new(storage));
+
+        let (token_id, token_event) = manager
+            .format_and_register_event(&setup_sample_event(), ContractType::ERC721, 1234567890)
+            .await
+            .unwrap();
+
+        // The decimal form is not padded, and both forms are the same value.
+        assert_eq!(token_event.token_id, token_id.to_decimal(false));
+        assert!(!token_event.token_id.starts_with('0'));
+        assert_eq!(token_event.token_id_hex, PaddedTokenId::from(&token_id));
+        assert_eq!(
+            token_event.token_id_hex.to_u256().to_decimal(false),
+            token_event.token_id
+        );
+    }
+
+    #[tokio::test]
+    async fn test_format_event_large_token_id() {
+        let mut storage = MockStorage::default();
+
+        storage
+            .expect_register_event()
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+
+        let manager = EventManager::new(Arc::new(storage));
+
+        // Hashed token id, 2^255 + 2^128 - 1.
+        let mut event = setup_sample_event();
+        event.data = vec![
+            FieldElement::from_hex_be("0x1234").unwrap(),
+            FieldElement::from_hex_be("0x5678").unwrap(),
+            FieldElement::from(u128::MAX),
+            FieldElement::from(1_u128 << 127),
+        ];
+
+        let (_, token_event) = manager
+            .format_and_register_event(&event, ContractType::ERC721, 1234567890)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            token_event.token_id,
+            "57896044618658097711785492504343953926975274699741220483192166611388333031423"
+        );
+        assert_eq!(
+            token_event.token_id_hex.as_str(),
+            "0x80000000000000000000000000000000ffffffffffffffffffffffffffffffff"
+        );
+
+        // A word over 128 bits is not a token id, the event is rejected.
+        event.data[3] = FieldElement::from(u128::MAX) + FieldElement::ONE;
+        event.keys.truncate(1);
+        assert!(manager
+            .format_and_register_event(&event, ContractType::ERC721, 1234567890)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_format_and_register_approval() {
+        let mut storage = MockStorage::default();
+
+        storage
+            .expect_register_token_approval()
+            .withf(|contract_address, token_id_hex, info| {
+                *contract_address == to_hex_str(&FieldElement::ONE)
+                    && *token_id_hex == PaddedTokenId::from(CairoU256 { low: 7, high: 0 })
+                    && info.owner == to_hex_str(&FieldElement::from(2_u64))
+                    && info.approved == Some(to_hex_str(&FieldElement::from(3_u64)))
+            })
+            .times(1)
+            .returning(|_, _, _| Box::pin(futures::future::ready(Ok(()))));
+
+        let manager = EventManager::new(Arc::new(storage));
+
+        // Cairo 1 layout, everything in the keys.
+        let mut event = setup_sample_event();
+        event.from_address = FieldElement::ONE;
+        event.keys = vec![
+            APPROVAL_SELECTOR,
+            FieldElement::from(2_u64),
+            FieldElement::from(3_u64),
+            FieldElement::from(7_u64),
+            FieldElement::ZERO,
+        ];
+        event.data = vec![];
+
+        assert!(EventManager::<MockStorage>::is_approval_event(&event));
+        manager
+            .format_and_register_approval(&event, 1234567890)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_format_and_register_approval_revokes() {
+        let mut storage = MockStorage::default();
+
+        storage
+            .expect_register_token_approval()
+            .withf(|_, _, info| info.approved.is_none())
+            .times(1)
+            .returning(|_, _, _| Box::pin(futures::future::ready(Ok(()))));
+        storage
+            .expect_register_operator_approval()
+            .withf(|_, info| {
+                info.operator == to_hex_str(&FieldElement::from(3_u64)) && !info.approved
+            })
+            .times(1)
+            .returning(|_, _| Box::pin(futures::future::ready(Ok(()))));
+
+        let manager = EventManager::new(Arc::new(storage));
+
+        // Cairo 0 layout, everything in the data, approving the zero address.
+        let mut approval = setup_sample_event();
+        approval.keys = vec![APPROVAL_SELECTOR];
+        approval.data = vec![
+            FieldElement::from(2_u64),
+            FieldElement::ZERO,
+            FieldElement::from(7_u64),
+            FieldElement::ZERO,
+        ];
+
+        // Owner and operator in the keys, approved flag in the data.
+        let mut approval_for_all = setup_sample_event();
+        approval_for_all.keys = vec![
+            APPROVAL_FOR_ALL_SELECTOR,
+            FieldElement::from(2_u64),
+            FieldElement::from(3_u64),
+        ];
+        approval_for_all.data = vec![FieldElement::ZERO];
+
+        for event in [approval, approval_for_all] {
+            manager
+                .format_and_register_approval(&event, 1234567890)
+                .await
+                .unwrap();
+        }
+
+        assert!(!EventManager::<MockStorage>::is_approval_event(
+            &setup_sample_event()
+        ));
+    }
+
+    #[test]
+    fn test_deduplicate_events() {
+        let event = setup_sample_event();
+
+        // The same transfer, with its info in the keys only.
+        let mut keys_event = event.clone();
+        keys_event.keys = vec![
+            TRANSFER_SELECTOR,
+            event.data[0],
+            event.data[1],
+            event.data[2],
+            event.data[3],
+        ];
+        keys_event.data = vec![];
+
+        // Another token transferred in the same transaction.
+        let mut other_token = event.clone();
+        other_token.data[2] = FieldElement::from(1_u64);
+
+        // The same transfer, in another transaction.
+        let mut other_tx = event.clone();
+        other_tx.transaction_hash = FieldElement::from(1_u64);
+
+        let events = EventManager::<MockStorage>::deduplicate_events(vec![
+            event.clone(),
+            keys_event,
+            other_token.clone(),
+            event.clone(),
+            other_tx.clone(),
+        ]);
+
+        let kept: Vec<(FieldElement, FieldElement)> = events
+            .iter()
+            .map(|e| (e.transaction_hash, e.data[2]))
+            .collect();
+        assert_eq!(
+            kept,
+            vec![
+                (event.transaction_hash, event.data[2]),
+                (other_token.transaction_hash, other_token.data[2]),
+                (other_tx.transaction_hash, other_tx.data[2]),
+            ]
+        );
+    }
+
     #[test]
     fn test_keys_selector() {
         let storage = Arc::new(MockStorage::default());
@@ -261,7 +665,11 @@ mod tests {
         let result = manager.keys_selector().unwrap();
 
         // Define expected result
-        let expected = vec![vec![selector!("Transfer")]];
+        let expected = vec![vec![
+            selector!("Transfer"),
+            selector!("Approval"),
+            selector!("ApprovalForAll"),
+        ]];
 
         // Assert the output
         assert_eq!(result, expected);
@@ -288,7 +696,7 @@ mod tests {
         let result = EventManager::<MockStorage>::get_event_info_from_felts(&sample_data);
 
         // Assert the output
-        assert_eq!(result.is_some(), true);
+        assert!(result.is_some());
         let (from, to, token_id) = result.unwrap();
         assert_eq!(from, from_value);
         assert_eq!(to, to_value);
@@ -310,6 +718,6 @@ mod tests {
         let result = EventManager::<MockStorage>::get_event_info_from_felts(&sample_data);
 
         // Assert the output
-        assert_eq!(result.is_none(), true);
+        assert!(result.is_none());
     }
 }
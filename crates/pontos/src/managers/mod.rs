@@ -1,5 +1,8 @@
 pub mod contract_manager;
-pub use contract_manager::ContractManager;
+pub use contract_manager::{
+    detect_contract_type, get_proxy_implementation, get_total_supply, ContractManager,
+    ContractTypeDetector, ContractTypeValidation,
+};
 
 pub mod event_manager;
 pub use event_manager::EventManager;
@@ -7,5 +10,8 @@ pub use event_manager::EventManager;
 pub mod token_manager;
 pub use token_manager::TokenManager;
 
+pub mod royalty;
+pub use royalty::{get_default_royalty, get_token_royalty};
+
 pub mod block_manager;
 pub use block_manager::{BlockManager, PendingBlockData};
@@ -0,0 +1,216 @@
+//! Royalty of the tokens and collections, as defined by ERC-2981.
+//!
+//! The royalty of a token is read with `royalty_info(token_id, sale_price)`,
+//! with a sale price of 10000 so that the amount returned is in basis points.
+//! The collection default royalty is read with `default_royalty()`, returning
+//! the receiver with the numerator and denominator of the fee.
+//! Contracts not implementing ERC-2981 have no royalty.
+use crate::storage::types::RoyaltyInfo;
+use ark_starknet::client::{StarknetClient, StarknetClientError};
+use ark_starknet::format::to_hex_str;
+use ark_starknet::CairoU256;
+use starknet::core::types::{BlockId, FieldElement};
+use starknet::core::utils::get_selector_from_name;
+use tracing::trace;
+
+/// Denominator of the basis points, used as sale price of `royalty_info`.
+const BASIS_POINTS_DENOMINATOR: u64 = 10000;
+
+/// Returns the royalty of the token, or `None` if the contract
+/// doesn't implement ERC-2981 or has no royalty receiver.
+pub async fn get_token_royalty<C: StarknetClient>(
+    client: &C,
+    contract_address: FieldElement,
+    token_id: &CairoU256,
+    block: BlockId,
+) -> Option<RoyaltyInfo> {
+    let calldata = vec![
+        token_id.low.into(),
+        token_id.high.into(),
+        FieldElement::from(BASIS_POINTS_DENOMINATOR),
+        FieldElement::ZERO,
+    ];
+
+    // (receiver, amount: u256).
+    let response = call_any(
+        client,
+        contract_address,
+        &["royalty_info", "royaltyInfo"],
+        calldata,
+        block,
+    )
+    .await?;
+
+    if response.len() < 3 || response[2] != FieldElement::ZERO {
+        return None;
+    }
+
+    royalty_info(response[0], felt_to_u64(response[1])?)
+}
+
+/// Returns the default royalty of the collection, or `None` if the contract
+/// doesn't implement ERC-2981 or has no default royalty receiver.
+pub async fn get_default_royalty<C: StarknetClient>(
+    client: &C,
+    contract_address: FieldElement,
+    block: BlockId,
+) -> Option<RoyaltyInfo> {
+    // (receiver, numerator, denominator).
+    let response = call_any(
+        client,
+        contract_address,
+        &["default_royalty", "defaultRoyalty"],
+        vec![],
+        block,
+    )
+    .await?;
+
+    if response.len() < 3 {
+        return None;
+    }
+
+    let numerator = felt_to_u64(response[1])?;
+    let denominator = felt_to_u64(response[2])?;
+    if denominator == 0 {
+        return None;
+    }
+
+    let basis_points = (numerator as u128 * BASIS_POINTS_DENOMINATOR as u128) / denominator as u128;
+
+    royalty_info(response[0], u64::try_from(basis_points).ok()?)
+}
+
+fn royalty_info(receiver: FieldElement, basis_points: u64) -> Option<RoyaltyInfo> {
+    if receiver == FieldElement::ZERO {
+        return None;
+    }
+
+    Some(RoyaltyInfo {
+        receiver: to_hex_str(&receiver),
+        basis_points,
+    })
+}
+
+fn felt_to_u64(value: FieldElement) -> Option<u64> {
+    u64::try_from(value).ok()
+}
+
+/// Calls the first of the given selectors found in the contract.
+async fn call_any<C: StarknetClient>(
+    client: &C,
+    contract_address: FieldElement,
+    selector_names: &[&str],
+    calldata: Vec<FieldElement>,
+    block: BlockId,
+) -> Option<Vec<FieldElement>> {
+    for selector_name in selector_names {
+        let selector = get_selector_from_name(selector_name).ok()?;
+
+        match client
+            .call_contract(contract_address, selector, calldata.clone(), block)
+            .await
+        {
+            Ok(response) => return Some(response),
+            Err(StarknetClientError::EntrypointNotFound(_)) => (),
+            Err(e) => {
+                trace!(
+                    "No royalty for [0x{:064x}] ({}): {}",
+                    contract_address,
+                    selector_name,
+                    e
+                );
+                return None;
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_starknet::client::MockStarknetClient;
+    use starknet::core::types::BlockTag;
+
+    const BLOCK: BlockId = BlockId::Tag(BlockTag::Pending);
+
+    #[tokio::test]
+    async fn test_get_token_royalty() {
+        let mut mock_client = MockStarknetClient::default();
+
+        mock_client
+            .expect_call_contract()
+            .withf(|_, selector, calldata, _| {
+                *selector == get_selector_from_name("royalty_info").unwrap()
+                    && calldata[0] == FieldElement::from(7_u64)
+                    && calldata[2] == FieldElement::from(10000_u64)
+            })
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(vec![
+                    FieldElement::from(0xabc_u64),
+                    FieldElement::from(250_u64),
+                    FieldElement::ZERO,
+                ])
+            });
+
+        let royalty = get_token_royalty(
+            &mock_client,
+            FieldElement::ONE,
+            &CairoU256 { low: 7, high: 0 },
+            BLOCK,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(royalty.basis_points, 250);
+        assert_eq!(
+            royalty.receiver,
+            "0x0000000000000000000000000000000000000000000000000000000000000abc"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_token_royalty_not_implemented() {
+        let mut mock_client = MockStarknetClient::default();
+
+        mock_client
+            .expect_call_contract()
+            .times(2)
+            .returning(|_, _, _, _| {
+                Err(StarknetClientError::EntrypointNotFound(
+                    "not found in contract".to_string(),
+                ))
+            });
+
+        let royalty = get_token_royalty(
+            &mock_client,
+            FieldElement::ONE,
+            &CairoU256 { low: 7, high: 0 },
+            BLOCK,
+        )
+        .await;
+
+        assert_eq!(royalty, None);
+    }
+
+    #[tokio::test]
+    async fn test_get_default_royalty() {
+        let mut mock_client = MockStarknetClient::default();
+
+        mock_client.expect_call_contract().returning(|_, _, _, _| {
+            Ok(vec![
+                FieldElement::from(0xabc_u64),
+                FieldElement::from(5_u64),
+                FieldElement::from(100_u64),
+            ])
+        });
+
+        let royalty = get_default_royalty(&mock_client, FieldElement::ONE, BLOCK)
+            .await
+            .unwrap();
+
+        assert_eq!(royalty.basis_points, 500);
+    }
+}
@@ -1,29 +1,48 @@
-use crate::storage::types::{EventType, TokenEvent, TokenInfo, TokenMintInfo};
-use crate::storage::Storage;
+use crate::managers::royalty::get_token_royalty;
+use crate::storage::types::{EventType, StorageError, TokenEvent, TokenInfo, TokenMintInfo};
+use crate::storage::{Storage, DEFAULT_TOKEN_PAGE_SIZE};
 use anyhow::{anyhow, Result};
 use ark_starknet::client::StarknetClient;
 use ark_starknet::format::to_hex_str;
-use ark_starknet::CairoU256;
+use ark_starknet::network::Network;
+use ark_starknet::{CairoU256, PaddedTokenId};
 use starknet::core::types::*;
 use starknet::macros::selector;
 use std::sync::Arc;
+use tracing::{debug, info, warn};
 
 #[derive(Debug)]
 pub struct TokenManager<S: Storage, C: StarknetClient> {
     storage: Arc<S>,
     client: Arc<C>,
+    network: Network,
 }
 
 impl<S: Storage, C: StarknetClient> TokenManager<S, C> {
-    /// Initializes a new instance.
+    /// Initializes a new instance, tagging the tokens with the mainnet network.
     pub fn new(storage: Arc<S>, client: Arc<C>) -> Self {
         Self {
             storage: Arc::clone(&storage),
             client: Arc::clone(&client),
+            network: Network::default(),
         }
     }
 
+    /// Sets the network the registered tokens are tagged with.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
     /// Formats a token registry from the token event data.
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "token",
+            skip_all,
+            fields(token_id = %token_id.to_decimal(false), block_number = ?block_number)
+        )
+    )]
     pub async fn format_and_register_token(
         &self,
         token_id: &CairoU256,
@@ -35,6 +54,7 @@ impl<S: Storage, C: StarknetClient> TokenManager<S, C> {
             contract_address: event.contract_address.clone(),
             token_id: event.token_id.clone(),
             token_id_hex: event.token_id_hex.clone(),
+            network: self.network.to_string(),
             ..Default::default()
         };
 
@@ -52,7 +72,17 @@ impl<S: Storage, C: StarknetClient> TokenManager<S, C> {
             .and_then(|owner| owner.first().map(to_hex_str))
             .unwrap_or_default();
 
-        self.storage.register_token(&token, block_timestamp).await?;
+        // The token can already be registered, by a previous transfer or a
+        // pending block: its owner is updated, and a mint is still registered.
+        match self.storage.register_token(&token, block_timestamp).await {
+            Ok(()) => {}
+            Err(StorageError::AlreadyExists(_)) => {
+                if !token.owner.is_empty() {
+                    self.storage.update_token(&token).await?;
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
 
         if event.event_type == EventType::Mint {
             let info = TokenMintInfo {
@@ -65,11 +95,163 @@ impl<S: Storage, C: StarknetClient> TokenManager<S, C> {
             self.storage
                 .register_mint(&token.contract_address, &token.token_id_hex, &info)
                 .await?;
+
+            if let Some(royalty) = get_token_royalty(
+                self.client.as_ref(),
+                FieldElement::from_hex_be(&event.contract_address)?,
+                token_id,
+                BlockId::Tag(BlockTag::Pending),
+            )
+            .await
+            {
+                self.storage
+                    .register_token_royalty(&token.contract_address, &token.token_id_hex, &royalty)
+                    .await?;
+            }
         }
 
         Ok(())
     }
 
+    /// Re-resolves the owner of a registered token, and updates it in the storage.
+    pub async fn refresh_token_owner(
+        &self,
+        contract_address: FieldElement,
+        token_id: &CairoU256,
+    ) -> Result<TokenInfo> {
+        let owner = self
+            .get_token_owner(contract_address, token_id.low.into(), token_id.high.into())
+            .await?;
+
+        let token = TokenInfo {
+            contract_address: to_hex_str(&contract_address),
+            token_id: token_id.to_decimal(false),
+            token_id_hex: PaddedTokenId::from(token_id),
+            owner: owner
+                .first()
+                .map(to_hex_str)
+                .ok_or_else(|| anyhow!("Empty token owner response"))?,
+            network: self.network.to_string(),
+        };
+
+        self.storage.update_token(&token).await?;
+
+        Ok(token)
+    }
+
+    /// Compares the stored owner of the given tokens with their owner on-chain,
+    /// and updates the tokens whose owner differs. The tokens not registered,
+    /// or whose owner can't be read, are skipped.
+    /// Returns the number of owners corrected.
+    pub async fn reconcile_owners(
+        &self,
+        contract_address: FieldElement,
+        token_ids: &[CairoU256],
+    ) -> Result<u64> {
+        let address = to_hex_str(&contract_address);
+        let mut corrected = 0;
+
+        for token_id in token_ids {
+            match self
+                .storage
+                .get_token(&address, &PaddedTokenId::from(token_id))
+                .await?
+            {
+                Some(token) => {
+                    if self
+                        .reconcile_owner(contract_address, token_id, token)
+                        .await?
+                    {
+                        corrected += 1;
+                    }
+                }
+                None => debug!(
+                    "Token {} of {} not registered, skipping it",
+                    token_id.to_decimal(false),
+                    address
+                ),
+            }
+        }
+
+        Ok(corrected)
+    }
+
+    /// Reconciles the owner of all the registered tokens of the collection,
+    /// scanning them page by page. Returns the number of owners corrected.
+    pub async fn reconcile_collection_owners(&self, contract_address: FieldElement) -> Result<u64> {
+        let address = to_hex_str(&contract_address);
+        let mut corrected = 0;
+        let mut last_evaluated_key = None;
+
+        loop {
+            let page = self
+                .storage
+                .find_tokens(&address, last_evaluated_key, DEFAULT_TOKEN_PAGE_SIZE)
+                .await?;
+
+            for token in page.tokens {
+                let token_id = token.token_id_hex.to_u256();
+                if self
+                    .reconcile_owner(contract_address, &token_id, token)
+                    .await?
+                {
+                    corrected += 1;
+                }
+            }
+
+            last_evaluated_key = page.last_evaluated_key;
+            if last_evaluated_key.is_none() {
+                break;
+            }
+        }
+
+        info!("{} owners corrected for collection {}", corrected, address);
+
+        Ok(corrected)
+    }
+
+    /// Updates the stored token if its owner on-chain differs.
+    /// Returns true if the owner was corrected.
+    async fn reconcile_owner(
+        &self,
+        contract_address: FieldElement,
+        token_id: &CairoU256,
+        mut token: TokenInfo,
+    ) -> Result<bool> {
+        let owner = match self
+            .get_token_owner(contract_address, token_id.low.into(), token_id.high.into())
+            .await
+            .map(|owner| owner.first().map(to_hex_str))
+        {
+            Ok(Some(owner)) => owner,
+            _ => {
+                warn!(
+                    "Can't read the owner of token {} of {}, skipping it",
+                    token_id.to_decimal(false),
+                    token.contract_address
+                );
+                return Ok(false);
+            }
+        };
+
+        if owner == token.owner {
+            return Ok(false);
+        }
+
+        debug!(
+            "Owner of token {} of {} corrected from {} to {}",
+            token_id.to_decimal(false),
+            token.contract_address,
+            token.owner,
+            owner
+        );
+
+        token.owner = owner;
+        self.storage.update_token(&token).await?;
+
+        Ok(true)
+    }
+
     /// Retrieves the token owner for the last block.
     pub async fn get_token_owner(
         &self,
@@ -101,7 +283,7 @@ impl<S: Storage, C: StarknetClient> TokenManager<S, C> {
 
 #[cfg(test)]
 mod tests {
-    use crate::storage::MockStorage;
+    use crate::storage::{MemoryStorage, MockStorage};
     use ark_starknet::client::MockStarknetClient;
 
     use super::*;
@@ -131,4 +313,125 @@ mod tests {
         assert_eq!(owners.len(), 1);
         assert_eq!(owners[0], FieldElement::from_dec_str("1").unwrap());
     }
+
+    #[tokio::test]
+    async fn test_refresh_token_owner() {
+        let mut mock_storage = MockStorage::default();
+        let mut mock_client = MockStarknetClient::default();
+
+        mock_client
+            .expect_call_contract()
+            .returning(|_, _, _, _| Ok(vec![FieldElement::from_hex_be("0xabc").unwrap()]));
+
+        mock_storage
+            .expect_update_token()
+            .withf(|token| {
+                token.contract_address
+                    == "0x0000000000000000000000000000000000000000000000000000000000000001"
+                    && token.token_id == "2"
+                    && token.owner
+                        == "0x0000000000000000000000000000000000000000000000000000000000000abc"
+            })
+            .times(1)
+            .returning(|_| Box::pin(futures::future::ready(Ok(()))));
+
+        let token_manager = TokenManager::new(Arc::new(mock_storage), Arc::new(mock_client));
+
+        let token = token_manager
+            .refresh_token_owner(FieldElement::ONE, &CairoU256 { low: 2, high: 0 })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            token.token_id_hex,
+            PaddedTokenId::from(CairoU256 { low: 2, high: 0 })
+        );
+    }
+
+    fn stored_token(token_id: u128, owner: &str) -> TokenInfo {
+        let token_id = CairoU256 {
+            low: token_id,
+            high: 0,
+        };
+
+        TokenInfo {
+            contract_address: to_hex_str(&FieldElement::ONE),
+            token_id: token_id.to_decimal(false),
+            token_id_hex: PaddedTokenId::from(token_id),
+            owner: owner.to_string(),
+            network: "mainnet".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_owners() {
+        let mut mock_storage = MockStorage::default();
+        let mut mock_client = MockStarknetClient::default();
+
+        // Every token is owned by 0xabc on-chain.
+        let owner = to_hex_str(&FieldElement::from_hex_be("0xabc").unwrap());
+
+        mock_client
+            .expect_call_contract()
+            .returning(|_, _, _, _| Ok(vec![FieldElement::from_hex_be("0xabc").unwrap()]));
+
+        let stored_owner = owner.clone();
+        mock_storage
+            .expect_get_token()
+            .returning(move |_, token_id_hex| {
+                let token = match token_id_hex.to_u256().low {
+                    1 => Some(stored_token(1, &stored_owner)),
+                    2 => Some(stored_token(2, "0xdef")),
+                    _ => None,
+                };
+                Box::pin(futures::future::ready(Ok(token)))
+            });
+
+        let expected_owner = owner.clone();
+        mock_storage
+            .expect_update_token()
+            .withf(move |token| token.token_id == "2" && token.owner == expected_owner)
+            .times(1)
+            .returning(|_| Box::pin(futures::future::ready(Ok(()))));
+
+        let token_manager = TokenManager::new(Arc::new(mock_storage), Arc::new(mock_client));
+
+        let token_ids: Vec<CairoU256> = (1..=3).map(|low| CairoU256 { low, high: 0 }).collect();
+        let corrected = token_manager
+            .reconcile_owners(FieldElement::ONE, &token_ids)
+            .await
+            .unwrap();
+
+        assert_eq!(corrected, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_collection_owners() {
+        let storage = Arc::new(MemoryStorage::new());
+        let mut mock_client = MockStarknetClient::default();
+
+        mock_client
+            .expect_call_contract()
+            .returning(|_, _, _, _| Ok(vec![FieldElement::from_hex_be("0xabc").unwrap()]));
+
+        for token_id in 1..=3 {
+            storage
+                .register_token(&stored_token(token_id, "0xdef"), 0)
+                .await
+                .unwrap();
+        }
+
+        let token_manager = TokenManager::new(Arc::clone(&storage), Arc::new(mock_client));
+
+        let corrected = token_manager
+            .reconcile_collection_owners(FieldElement::ONE)
+            .await
+            .unwrap();
+
+        assert_eq!(corrected, 3);
+        assert!(storage
+            .tokens()
+            .iter()
+            .all(|t| t.owner == to_hex_str(&FieldElement::from_hex_be("0xabc").unwrap())));
+    }
 }
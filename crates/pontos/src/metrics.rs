@@ -0,0 +1,183 @@
+//! Prometheus metrics of the indexer.
+//!
+//! The metrics of Pontos, of the Starknet client and of the metadata
+//! fetching are all registered in the prometheus default registry,
+//! and exposed together by `serve_metrics`.
+use crate::{IndexerError, IndexerResult};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use prometheus::{
+    register_int_counter, register_int_counter_vec, register_int_gauge, Encoder, IntCounter,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use tracing::info;
+
+/// Indexed blocks.
+pub fn blocks_processed_total() -> &'static IntCounter {
+    static METRIC: OnceLock<IntCounter> = OnceLock::new();
+    METRIC.get_or_init(|| {
+        register_int_counter!("pontos_blocks_processed_total", "Number of indexed blocks")
+            .expect("pontos_blocks_processed_total can be registered")
+    })
+}
+
+/// Registered events, labelled by event type (`MINT`, `TRANSFER`, ...).
+pub fn events_processed_total() -> &'static IntCounterVec {
+    static METRIC: OnceLock<IntCounterVec> = OnceLock::new();
+    METRIC.get_or_init(|| {
+        register_int_counter_vec!(
+            "pontos_events_processed_total",
+            "Number of registered events",
+            &["event_type"]
+        )
+        .expect("pontos_events_processed_total can be registered")
+    })
+}
+
+/// Indexing errors, labelled by kind.
+pub fn errors_total() -> &'static IntCounterVec {
+    static METRIC: OnceLock<IntCounterVec> = OnceLock::new();
+    METRIC.get_or_init(|| {
+        register_int_counter_vec!(
+            "pontos_errors_total",
+            "Number of indexing errors",
+            &["kind"]
+        )
+        .expect("pontos_errors_total can be registered")
+    })
+}
+
+/// Last block fully processed, to alert when the indexation stalls.
+pub fn last_processed_block() -> &'static IntGauge {
+    static METRIC: OnceLock<IntGauge> = OnceLock::new();
+    METRIC.get_or_init(|| {
+        register_int_gauge!(
+            "pontos_last_processed_block",
+            "Last block fully processed by the indexer"
+        )
+        .expect("pontos_last_processed_block can be registered")
+    })
+}
+
+/// Blocks between the chain head and the last processed block, updated
+/// by `index_block_range` from the head read again during the range.
+pub fn head_block_lag() -> &'static IntGauge {
+    static METRIC: OnceLock<IntGauge> = OnceLock::new();
+    METRIC.get_or_init(|| {
+        register_int_gauge!(
+            "pontos_head_block_lag",
+            "Number of blocks between the chain head and the last processed block"
+        )
+        .expect("pontos_head_block_lag can be registered")
+    })
+}
+
+/// Pages of events fetched and waiting to be processed, to tell if the
+/// indexation is bound by the RPC or by the processing.
+pub fn event_queue_depth() -> &'static IntGauge {
+    static METRIC: OnceLock<IntGauge> = OnceLock::new();
+    METRIC.get_or_init(|| {
+        register_int_gauge!(
+            "pontos_event_queue_depth",
+            "Number of event pages waiting to be processed"
+        )
+        .expect("pontos_event_queue_depth can be registered")
+    })
+}
+
+/// Registers all the metrics, for them to be exposed before their first update.
+fn register_metrics() {
+    blocks_processed_total();
+    events_processed_total();
+    errors_total();
+    last_processed_block();
+    head_block_lag();
+    event_queue_depth();
+    ark_starknet::metrics::rpc_duration_seconds();
+    ark_starknet::metrics::rpc_errors_total();
+    ark_metadata::metrics::fetch_duration_seconds();
+    ark_metadata::metrics::fetch_errors_total();
+}
+
+/// Returns all the registered metrics in the prometheus text format.
+pub fn gather_metrics() -> IndexerResult<String> {
+    register_metrics();
+
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(|e| IndexerError::Anyhow(e.to_string()))?;
+
+    String::from_utf8(buffer).map_err(|e| IndexerError::Anyhow(e.to_string()))
+}
+
+/// Serves the metrics at `GET /metrics` on the given address.
+pub async fn serve_metrics(addr: SocketAddr) -> IndexerResult<()> {
+    let make_service =
+        make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle_request)) });
+
+    let server = Server::try_bind(&addr)
+        .map_err(|e| IndexerError::Anyhow(e.to_string()))?
+        .serve(make_service);
+
+    info!("Serving metrics on http://{}/metrics", addr);
+
+    server
+        .await
+        .map_err(|e| IndexerError::Anyhow(e.to_string()))
+}
+
+async fn handle_request(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let mut response = Response::new(Body::empty());
+
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+
+    match gather_metrics() {
+        Ok(metrics) => {
+            response.headers_mut().insert(
+                CONTENT_TYPE,
+                prometheus::TEXT_FORMAT
+                    .parse()
+                    .expect("prometheus text format is a valid header value"),
+            );
+            *response.body_mut() = Body::from(metrics);
+        }
+        Err(e) => {
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            *response.body_mut() = Body::from(e.to_string());
+        }
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        events_processed_total().with_label_values(&["MINT"]).inc();
+
+        let request = Request::get("/metrics").body(Body::empty()).unwrap();
+        let response = handle_request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("pontos_events_processed_total{event_type=\"MINT\"}"));
+        assert!(body.contains("pontos_last_processed_block"));
+        assert!(body.contains("pontos_head_block_lag"));
+
+        let request = Request::get("/other").body(Body::empty()).unwrap();
+        let response = handle_request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,148 @@
+//! Counting of the RPC calls made while processing a block.
+//!
+//! The managers use a `CallCountingClient` wrapping the Starknet client.
+//! Calls are counted in a task local counter, which ensures that blocks
+//! processed concurrently by the same Pontos instance are counted separately.
+use ark_starknet::client::{StarknetClient, StarknetClientError};
+use ark_starknet::{BlockHeader, EventResult};
+use async_trait::async_trait;
+use starknet::core::types::*;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+tokio::task_local! {
+    static RPC_CALLS: Cell<u64>;
+}
+
+/// Runs the given future with a new RPC calls counter.
+pub(crate) async fn with_call_counter<F: Future>(f: F) -> F::Output {
+    RPC_CALLS.scope(Cell::new(0), f).await
+}
+
+/// Returns the number of RPC calls counted in the current scope.
+pub(crate) fn calls_count() -> u64 {
+    RPC_CALLS.try_with(|c| c.get()).unwrap_or(0)
+}
+
+fn count_call() {
+    // Calls made outside of a counting scope are ignored.
+    let _ = RPC_CALLS.try_with(|c| c.set(c.get() + 1));
+}
+
+/// A Starknet client counting the calls made to the inner client.
+pub(crate) struct CallCountingClient<C: StarknetClient> {
+    inner: Arc<C>,
+}
+
+impl<C: StarknetClient> CallCountingClient<C> {
+    pub fn wrap(inner: Arc<C>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl<C: StarknetClient + Send + Sync> StarknetClient for CallCountingClient<C> {
+    fn new(rpc_url: &str) -> Result<Self, StarknetClientError> {
+        Ok(Self::wrap(Arc::new(C::new(rpc_url)?)))
+    }
+
+    async fn events_from_tx_receipt(
+        &self,
+        transaction_hash: FieldElement,
+        keys: Option<Vec<Vec<FieldElement>>>,
+    ) -> Result<Vec<EmittedEvent>, StarknetClientError> {
+        count_call();
+        self.inner
+            .events_from_tx_receipt(transaction_hash, keys)
+            .await
+    }
+
+    async fn block_txs_hashes(
+        &self,
+        block: BlockId,
+    ) -> Result<(u64, Vec<FieldElement>), StarknetClientError> {
+        count_call();
+        self.inner.block_txs_hashes(block).await
+    }
+
+    async fn block_id_to_u64(&self, id: &BlockId) -> Result<u64, StarknetClientError> {
+        count_call();
+        self.inner.block_id_to_u64(id).await
+    }
+
+    fn parse_block_range(
+        &self,
+        from: &str,
+        to: &str,
+    ) -> Result<(BlockId, BlockId), StarknetClientError> {
+        self.inner.parse_block_range(from, to)
+    }
+
+    fn parse_block_id(&self, id: &str) -> Result<BlockId, StarknetClientError> {
+        self.inner.parse_block_id(id)
+    }
+
+    async fn block_time(&self, block: BlockId) -> Result<u64, StarknetClientError> {
+        count_call();
+        self.inner.block_time(block).await
+    }
+
+    async fn block_header(&self, block: BlockId) -> Result<BlockHeader, StarknetClientError> {
+        count_call();
+        self.inner.block_header(block).await
+    }
+
+    async fn block_number(&self) -> Result<u64, StarknetClientError> {
+        count_call();
+        self.inner.block_number().await
+    }
+
+    async fn chain_id(&self) -> Result<FieldElement, StarknetClientError> {
+        count_call();
+        self.inner.chain_id().await
+    }
+
+    async fn fetch_events(
+        &self,
+        from_block: Option<BlockId>,
+        to_block: Option<BlockId>,
+        keys: Option<Vec<Vec<FieldElement>>>,
+        contract_address: Option<FieldElement>,
+        continuation_token: Option<String>,
+    ) -> Result<EventResult, StarknetClientError> {
+        count_call();
+        self.inner
+            .fetch_events(
+                from_block,
+                to_block,
+                keys,
+                contract_address,
+                continuation_token,
+            )
+            .await
+    }
+
+    async fn fetch_all_block_events(
+        &self,
+        block_id: BlockId,
+        keys: Option<Vec<Vec<FieldElement>>>,
+    ) -> Result<HashMap<u64, Vec<EmittedEvent>>, StarknetClientError> {
+        count_call();
+        self.inner.fetch_all_block_events(block_id, keys).await
+    }
+
+    async fn call_contract(
+        &self,
+        contract_address: FieldElement,
+        selector: FieldElement,
+        calldata: Vec<FieldElement>,
+        block: BlockId,
+    ) -> Result<Vec<FieldElement>, StarknetClientError> {
+        count_call();
+        self.inner
+            .call_contract(contract_address, selector, calldata, block)
+            .await
+    }
+}
@@ -0,0 +1,46 @@
+//! Graceful shutdown on termination signals.
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+/// Waits for SIGINT, or SIGTERM on unix platforms.
+pub async fn wait_for_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Couldn't listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                error!("Couldn't listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("SIGINT received"),
+        _ = terminate => info!("SIGTERM received"),
+    }
+}
+
+/// Cancels the given token on SIGINT or SIGTERM, to stop the indexation
+/// once the block being processed is finished (see `Pontos::shutdown_token`).
+pub fn cancel_on_signal(token: CancellationToken) {
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        info!("Shutting down, finishing the block being processed...");
+        token.cancel();
+    });
+}
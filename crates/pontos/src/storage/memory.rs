@@ -0,0 +1,888 @@
+//! In-memory implementation of the storage.
+//!
+//! Mostly used for testing, or to run Pontos without any database.
+//! It follows the behavior of the sqlx storage: tokens, events and contracts
+//! are only registered once, and are removed with the block they belong to.
+use ark_starknet::PaddedTokenId;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::trace;
+
+use crate::storage::types::*;
+use crate::Storage;
+
+struct StoredToken {
+    info: TokenInfo,
+    mint: Option<TokenMintInfo>,
+    royalty: Option<RoyaltyInfo>,
+    approval: Option<TokenApprovalInfo>,
+    block_timestamp: u64,
+}
+
+#[derive(Default)]
+struct MemoryData {
+    /// Tokens by (contract address, token id hex).
+    tokens: HashMap<(String, PaddedTokenId), StoredToken>,
+    /// Events in registration order, with their block timestamp.
+    events: Vec<(u64, TokenEvent)>,
+    /// Contracts by address, with their block timestamp.
+    contracts: HashMap<String, (u64, ContractInfo)>,
+    /// Default royalty of the contracts by address.
+    contract_royalties: HashMap<String, RoyaltyInfo>,
+    /// `totalSupply` of the contracts by address.
+    contract_total_supplies: HashMap<String, String>,
+    /// Operator approvals by (contract address, owner, operator).
+    operator_approvals: HashMap<(String, String, String), OperatorApprovalInfo>,
+    /// Blocks by block timestamp.
+    blocks: HashMap<u64, BlockInfo>,
+    /// Last processed block by indexer identifier.
+    cursors: HashMap<String, u64>,
+}
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    data: Mutex<MemoryData>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the registered tokens.
+    pub fn tokens(&self) -> Vec<TokenInfo> {
+        let data = self.data.lock().unwrap();
+        data.tokens.values().map(|t| t.info.clone()).collect()
+    }
+
+    /// Returns the mint info of the given token, if registered.
+    pub fn token_mint(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+    ) -> Option<TokenMintInfo> {
+        let data = self.data.lock().unwrap();
+        data.tokens
+            .get(&(contract_address.to_string(), token_id_hex.clone()))
+            .and_then(|t| t.mint.clone())
+    }
+
+    /// Returns the royalty of the given token, if registered.
+    pub fn token_royalty(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+    ) -> Option<RoyaltyInfo> {
+        let data = self.data.lock().unwrap();
+        data.tokens
+            .get(&(contract_address.to_string(), token_id_hex.clone()))
+            .and_then(|t| t.royalty.clone())
+    }
+
+    /// Returns the default royalty of the given contract, if registered.
+    pub fn contract_royalty(&self, contract_address: &str) -> Option<RoyaltyInfo> {
+        let data = self.data.lock().unwrap();
+        data.contract_royalties.get(contract_address).cloned()
+    }
+
+    /// Returns the `totalSupply` of the given contract, if registered.
+    pub fn contract_total_supply(&self, contract_address: &str) -> Option<String> {
+        let data = self.data.lock().unwrap();
+        data.contract_total_supplies.get(contract_address).cloned()
+    }
+
+    /// Returns the approval of the given token, if registered.
+    pub fn token_approval(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+    ) -> Option<TokenApprovalInfo> {
+        let data = self.data.lock().unwrap();
+        data.tokens
+            .get(&(contract_address.to_string(), token_id_hex.clone()))
+            .and_then(|t| t.approval.clone())
+    }
+
+    /// Returns the approval of the given operator by the given owner, if registered.
+    pub fn operator_approval(
+        &self,
+        contract_address: &str,
+        owner: &str,
+        operator: &str,
+    ) -> Option<OperatorApprovalInfo> {
+        let data = self.data.lock().unwrap();
+        data.operator_approvals
+            .get(&(
+                contract_address.to_string(),
+                owner.to_string(),
+                operator.to_string(),
+            ))
+            .cloned()
+    }
+
+    /// Returns the registered events, in registration order.
+    pub fn events(&self) -> Vec<TokenEvent> {
+        let data = self.data.lock().unwrap();
+        data.events.iter().map(|(_, e)| e.clone()).collect()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn register_mint(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+        info: &TokenMintInfo,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering mint {} {} {:?}",
+            contract_address,
+            token_id_hex,
+            info
+        );
+
+        let mut data = self.data.lock().unwrap();
+        if let Some(token) = data
+            .tokens
+            .get_mut(&(contract_address.to_string(), token_id_hex.clone()))
+        {
+            // An older mint, from a block processed out of order, is ignored.
+            if token
+                .mint
+                .as_ref()
+                .map_or(true, |mint| mint.timestamp <= info.timestamp)
+            {
+                token.mint = Some(info.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn register_token(
+        &self,
+        token: &TokenInfo,
+        block_timestamp: u64,
+    ) -> Result<(), StorageError> {
+        trace!("Registering token {:?}", token);
+
+        let mut data = self.data.lock().unwrap();
+        let key = (token.contract_address.clone(), token.token_id_hex.clone());
+
+        if data.tokens.contains_key(&key) {
+            return Err(StorageError::AlreadyExists(format!(
+                "token id = {}",
+                token.token_id_hex
+            )));
+        }
+
+        data.tokens.insert(
+            key,
+            StoredToken {
+                info: token.clone(),
+                mint: None,
+                royalty: None,
+                approval: None,
+                block_timestamp,
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn update_token(&self, token: &TokenInfo) -> Result<(), StorageError> {
+        trace!("Updating token {:?}", token);
+
+        let mut data = self.data.lock().unwrap();
+        let key = (token.contract_address.clone(), token.token_id_hex.clone());
+
+        match data.tokens.get_mut(&key) {
+            Some(stored) => {
+                stored.info.owner = token.owner.clone();
+                Ok(())
+            }
+            None => Err(StorageError::NotFound(format!(
+                "token id = {}",
+                token.token_id_hex
+            ))),
+        }
+    }
+
+    async fn get_token(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+    ) -> Result<Option<TokenInfo>, StorageError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .tokens
+            .get(&(contract_address.to_string(), token_id_hex.clone()))
+            .map(|t| t.info.clone()))
+    }
+
+    async fn find_tokens(
+        &self,
+        contract_address: &str,
+        exclusive_start_key: Option<PaddedTokenId>,
+        page_size: usize,
+    ) -> Result<TokenPage, StorageError> {
+        let data = self.data.lock().unwrap();
+
+        let mut tokens: Vec<TokenInfo> = data
+            .tokens
+            .iter()
+            .filter(|((address, token_id_hex), _)| {
+                address == contract_address
+                    && exclusive_start_key
+                        .as_ref()
+                        .map_or(true, |key| token_id_hex > key)
+            })
+            .map(|(_, t)| t.info.clone())
+            .collect();
+        tokens.sort_by(|a, b| a.token_id_hex.cmp(&b.token_id_hex));
+
+        let last_evaluated_key = if tokens.len() > page_size {
+            tokens.truncate(page_size);
+            tokens.last().map(|t| t.token_id_hex.clone())
+        } else {
+            None
+        };
+
+        Ok(TokenPage {
+            tokens,
+            last_evaluated_key,
+        })
+    }
+
+    async fn register_token_royalty(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+        info: &RoyaltyInfo,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering royalty {} {} {:?}",
+            contract_address,
+            token_id_hex,
+            info
+        );
+
+        let mut data = self.data.lock().unwrap();
+        if let Some(token) = data
+            .tokens
+            .get_mut(&(contract_address.to_string(), token_id_hex.clone()))
+        {
+            token.royalty = Some(info.clone());
+        }
+
+        Ok(())
+    }
+
+    async fn register_contract_royalty(
+        &self,
+        contract_address: &str,
+        info: &RoyaltyInfo,
+    ) -> Result<(), StorageError> {
+        trace!("Registering royalty {} {:?}", contract_address, info);
+
+        let mut data = self.data.lock().unwrap();
+        if data.contracts.contains_key(contract_address) {
+            data.contract_royalties
+                .insert(contract_address.to_string(), info.clone());
+        }
+
+        Ok(())
+    }
+
+    async fn register_contract_total_supply(
+        &self,
+        contract_address: &str,
+        total_supply: &str,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering total supply {} {}",
+            contract_address,
+            total_supply
+        );
+
+        let mut data = self.data.lock().unwrap();
+        if data.contracts.contains_key(contract_address) {
+            data.contract_total_supplies
+                .insert(contract_address.to_string(), total_supply.to_string());
+        }
+
+        Ok(())
+    }
+
+    async fn register_token_approval(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+        info: &TokenApprovalInfo,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering approval {} {} {:?}",
+            contract_address,
+            token_id_hex,
+            info
+        );
+
+        let mut data = self.data.lock().unwrap();
+        if let Some(token) = data
+            .tokens
+            .get_mut(&(contract_address.to_string(), token_id_hex.clone()))
+        {
+            token.approval = Some(info.clone());
+        }
+
+        Ok(())
+    }
+
+    async fn register_operator_approval(
+        &self,
+        contract_address: &str,
+        info: &OperatorApprovalInfo,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering operator approval {} {:?}",
+            contract_address,
+            info
+        );
+
+        let mut data = self.data.lock().unwrap();
+        data.operator_approvals.insert(
+            (
+                contract_address.to_string(),
+                info.owner.clone(),
+                info.operator.clone(),
+            ),
+            info.clone(),
+        );
+
+        Ok(())
+    }
+
+    async fn register_event(
+        &self,
+        event: &TokenEvent,
+        block_timestamp: u64,
+    ) -> Result<(), StorageError> {
+        trace!("Registering event {:?}", event);
+
+        let mut data = self.data.lock().unwrap();
+
+        if data
+            .events
+            .iter()
+            .any(|(_, e)| e.event_id == event.event_id)
+        {
+            return Err(StorageError::AlreadyExists(format!(
+                "event id = {}",
+                event.event_id
+            )));
+        }
+
+        data.events.push((block_timestamp, event.clone()));
+
+        Ok(())
+    }
+
+    async fn register_events(
+        &self,
+        events: &[TokenEvent],
+        block_timestamp: u64,
+    ) -> Result<(), StorageError> {
+        trace!("Registering {} events", events.len());
+
+        let mut data = self.data.lock().unwrap();
+
+        for event in events {
+            if !data
+                .events
+                .iter()
+                .any(|(_, e)| e.event_id == event.event_id)
+            {
+                data.events.push((block_timestamp, event.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn find_collection_activities(
+        &self,
+        contract_address: &str,
+        since_timestamp: u64,
+        exclusive_start_key: Option<ActivityKey>,
+        page_size: usize,
+    ) -> Result<ActivityPage, StorageError> {
+        let data = self.data.lock().unwrap();
+
+        let mut events: Vec<(u64, TokenEvent)> = data
+            .events
+            .iter()
+            .filter(|(timestamp, e)| {
+                e.contract_address == contract_address
+                    && *timestamp >= since_timestamp
+                    && exclusive_start_key.as_ref().map_or(true, |key| {
+                        (*timestamp, &e.event_id) < (key.timestamp, &key.event_id)
+                    })
+            })
+            .cloned()
+            .collect();
+        events.sort_by(|(ta, a), (tb, b)| (tb, &b.event_id).cmp(&(ta, &a.event_id)));
+
+        let last_evaluated_key = if events.len() > page_size {
+            events.truncate(page_size);
+            events.last().map(|(timestamp, e)| ActivityKey {
+                timestamp: *timestamp,
+                event_id: e.event_id.clone(),
+            })
+        } else {
+            None
+        };
+
+        Ok(ActivityPage {
+            events: events.into_iter().map(|(_, e)| e).collect(),
+            last_evaluated_key,
+        })
+    }
+
+    async fn get_contract_type(
+        &self,
+        contract_address: &str,
+    ) -> Result<ContractType, StorageError> {
+        trace!("Getting contract info for contract {}", contract_address);
+
+        let data = self.data.lock().unwrap();
+        match data.contracts.get(contract_address) {
+            Some((_, info)) => Ok(info.contract_type.parse().unwrap()),
+            None => Err(StorageError::NotFound(format!(
+                "contract_address: {contract_address}"
+            ))),
+        }
+    }
+
+    async fn register_contract_info(
+        &self,
+        info: &ContractInfo,
+        block_timestamp: u64,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering contract info {:?} for contract {}",
+            info.contract_type,
+            info.contract_address
+        );
+
+        let mut data = self.data.lock().unwrap();
+
+        if data.contracts.contains_key(&info.contract_address) {
+            return Err(StorageError::AlreadyExists(format!(
+                "contract addr = {}",
+                info.contract_address
+            )));
+        }
+
+        data.contracts.insert(
+            info.contract_address.clone(),
+            (block_timestamp, info.clone()),
+        );
+
+        Ok(())
+    }
+
+    async fn set_block_info(
+        &self,
+        block_number: u64,
+        block_timestamp: u64,
+        info: BlockInfo,
+    ) -> Result<(), StorageError> {
+        trace!("Setting block info {:?} for block #{}", info, block_number);
+
+        let mut data = self.data.lock().unwrap();
+        data.blocks.insert(
+            block_timestamp,
+            BlockInfo {
+                block_number,
+                block_timestamp,
+                ..info
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn get_block_info(&self, block_number: u64) -> Result<BlockInfo, StorageError> {
+        trace!("Getting block info for block #{}", block_number);
+
+        let data = self.data.lock().unwrap();
+        match data
+            .blocks
+            .values()
+            .find(|b| b.block_number == block_number)
+        {
+            Some(b) => Ok(b.clone()),
+            None => Err(StorageError::NotFound(format!(
+                "block number {block_number}"
+            ))),
+        }
+    }
+
+    async fn find_block_events(
+        &self,
+        block_timestamp: u64,
+    ) -> Result<Vec<TokenEvent>, StorageError> {
+        let data = self.data.lock().unwrap();
+        Ok(data
+            .events
+            .iter()
+            .filter(|(ts, _)| *ts == block_timestamp)
+            .map(|(_, e)| e.clone())
+            .collect())
+    }
+
+    async fn clean_block(
+        &self,
+        block_timestamp: u64,
+        block_number: Option<u64>,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Cleaning block #{:?} [ts: {}]",
+            block_number,
+            block_timestamp
+        );
+
+        let mut data = self.data.lock().unwrap();
+        data.blocks.remove(&block_timestamp);
+        data.contracts.retain(|_, (ts, _)| *ts != block_timestamp);
+        let MemoryData {
+            contracts,
+            contract_royalties,
+            contract_total_supplies,
+            ..
+        } = &mut *data;
+        contract_royalties.retain(|address, _| contracts.contains_key(address));
+        contract_total_supplies.retain(|address, _| contracts.contains_key(address));
+        data.tokens
+            .retain(|_, t| t.block_timestamp != block_timestamp);
+        data.events.retain(|(ts, _)| *ts != block_timestamp);
+        data.operator_approvals
+            .retain(|_, a| a.timestamp != block_timestamp);
+
+        Ok(())
+    }
+
+    async fn get_last_processed_block(
+        &self,
+        indexer_identifier: &str,
+    ) -> Result<Option<u64>, StorageError> {
+        trace!("Getting last processed block of {}", indexer_identifier);
+
+        let data = self.data.lock().unwrap();
+        Ok(data.cursors.get(indexer_identifier).copied())
+    }
+
+    async fn set_last_processed_block(
+        &self,
+        indexer_identifier: &str,
+        block_number: u64,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Setting last processed block of {} to #{}",
+            indexer_identifier,
+            block_number
+        );
+
+        let mut data = self.data.lock().unwrap();
+        data.cursors
+            .insert(indexer_identifier.to_string(), block_number);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_starknet::CairoU256;
+
+    fn token_id(id: u128) -> PaddedTokenId {
+        PaddedTokenId::from(CairoU256 { low: id, high: 0 })
+    }
+
+    fn token(id: u128) -> TokenInfo {
+        TokenInfo {
+            contract_address: "0x1".to_string(),
+            token_id: id.to_string(),
+            token_id_hex: token_id(id),
+            owner: "0x2".to_string(),
+            network: "mainnet".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_token_and_mint() {
+        let storage = MemoryStorage::new();
+        storage.register_token(&token(1), 1000).await.unwrap();
+
+        assert!(matches!(
+            storage.register_token(&token(1), 1000).await,
+            Err(StorageError::AlreadyExists(_))
+        ));
+
+        let mint = TokenMintInfo {
+            address: "0x2".to_string(),
+            timestamp: 1000,
+            transaction_hash: "0x3".to_string(),
+            block_number: Some(10),
+        };
+        storage
+            .register_mint("0x1", &token_id(1), &mint)
+            .await
+            .unwrap();
+
+        assert_eq!(storage.tokens(), vec![token(1)]);
+        assert_eq!(storage.token_mint("0x1", &token_id(1)), Some(mint));
+    }
+
+    #[tokio::test]
+    async fn test_find_tokens_pages() {
+        let storage = MemoryStorage::new();
+        for id in [3, 1, 2] {
+            storage.register_token(&token(id), 1000).await.unwrap();
+        }
+
+        let page = storage.find_tokens("0x1", None, 2).await.unwrap();
+        assert_eq!(page.tokens, vec![token(1), token(2)]);
+        assert_eq!(page.last_evaluated_key, Some(token_id(2)));
+
+        let page = storage
+            .find_tokens("0x1", page.last_evaluated_key, 2)
+            .await
+            .unwrap();
+        assert_eq!(page.tokens, vec![token(3)]);
+        assert_eq!(page.last_evaluated_key, None);
+
+        assert!(storage
+            .find_tokens("0x2", None, 2)
+            .await
+            .unwrap()
+            .tokens
+            .is_empty());
+        assert_eq!(
+            storage.get_token("0x1", &token_id(2)).await.unwrap(),
+            Some(token(2))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_find_collection_activities_since_timestamp() {
+        let storage = MemoryStorage::new();
+        for (event_id, contract_address, timestamp) in [
+            ("1", "0x1", 1000),
+            ("2", "0x1", 2000),
+            ("3", "0x2", 2500),
+            ("4", "0x1", 3000),
+            ("5", "0x1", 3000),
+        ] {
+            let event = TokenEvent {
+                event_id: event_id.to_string(),
+                contract_address: contract_address.to_string(),
+                timestamp,
+                ..Default::default()
+            };
+            storage.register_event(&event, timestamp).await.unwrap();
+        }
+
+        let event_ids = |page: &ActivityPage| -> Vec<String> {
+            page.events.iter().map(|e| e.event_id.clone()).collect()
+        };
+
+        let page = storage
+            .find_collection_activities("0x1", 2000, None, 2)
+            .await
+            .unwrap();
+        assert_eq!(event_ids(&page), vec!["5", "4"]);
+        assert_eq!(
+            page.last_evaluated_key,
+            Some(ActivityKey {
+                timestamp: 3000,
+                event_id: "4".to_string(),
+            })
+        );
+
+        let page = storage
+            .find_collection_activities("0x1", 2000, page.last_evaluated_key, 2)
+            .await
+            .unwrap();
+        assert_eq!(event_ids(&page), vec!["2"]);
+        assert_eq!(page.last_evaluated_key, None);
+    }
+
+    #[tokio::test]
+    async fn test_register_events_keeps_registered_events() {
+        let storage = MemoryStorage::new();
+        let event = |event_id: &str| TokenEvent {
+            event_id: event_id.to_string(),
+            contract_address: "0x1".to_string(),
+            timestamp: 1000,
+            ..Default::default()
+        };
+
+        storage.register_event(&event("1"), 1000).await.unwrap();
+        storage
+            .register_events(&[event("1"), event("2"), event("3")], 1000)
+            .await
+            .unwrap();
+
+        let page = storage
+            .find_collection_activities("0x1", 0, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(page.events.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_register_mint_keeps_latest() {
+        let storage = MemoryStorage::new();
+        storage.register_token(&token(1), 1000).await.unwrap();
+
+        let mint = |timestamp| TokenMintInfo {
+            address: "0x2".to_string(),
+            timestamp,
+            transaction_hash: format!("0x{timestamp}"),
+            block_number: None,
+        };
+
+        // Blocks processed out of order.
+        for timestamp in [2000, 1000, 3000, 2500] {
+            storage
+                .register_mint("0x1", &token_id(1), &mint(timestamp))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(storage.token_mint("0x1", &token_id(1)), Some(mint(3000)));
+    }
+
+    #[tokio::test]
+    async fn test_register_token_royalty() {
+        let storage = MemoryStorage::new();
+        let royalty = RoyaltyInfo {
+            receiver: "0x2".to_string(),
+            basis_points: 250,
+        };
+
+        // Unknown token.
+        storage
+            .register_token_royalty("0x1", &token_id(1), &royalty)
+            .await
+            .unwrap();
+        assert_eq!(storage.token_royalty("0x1", &token_id(1)), None);
+
+        storage.register_token(&token(1), 1000).await.unwrap();
+        storage
+            .register_token_royalty("0x1", &token_id(1), &royalty)
+            .await
+            .unwrap();
+        assert_eq!(storage.token_royalty("0x1", &token_id(1)), Some(royalty));
+    }
+
+    #[tokio::test]
+    async fn test_clean_block() {
+        let storage = MemoryStorage::new();
+
+        for (block_number, block_timestamp) in [(10, 1000), (11, 1010)] {
+            storage
+                .set_block_info(
+                    block_number,
+                    block_timestamp,
+                    BlockInfo {
+                        indexer_version: "0.0.1".to_string(),
+                        indexer_identifier: "test".to_string(),
+                        status: BlockIndexingStatus::Terminated,
+                        block_number,
+                        block_timestamp,
+                        block_hash: None,
+                    },
+                )
+                .await
+                .unwrap();
+
+            let event = TokenEvent {
+                event_id: block_number.to_string(),
+                block_number: Some(block_number),
+                ..Default::default()
+            };
+            storage
+                .register_event(&event, block_timestamp)
+                .await
+                .unwrap();
+            storage
+                .register_token(&token(&format!("0x{}", block_number)), block_timestamp)
+                .await
+                .unwrap();
+        }
+
+        storage.clean_block(1010, Some(11)).await.unwrap();
+
+        assert!(storage.get_block_info(10).await.is_ok());
+        assert!(matches!(
+            storage.get_block_info(11).await,
+            Err(StorageError::NotFound(_))
+        ));
+        assert_eq!(storage.events().len(), 1);
+        assert_eq!(storage.events()[0].block_number, Some(10));
+        assert_eq!(storage.tokens(), vec![token("0x10")]);
+    }
+
+    #[tokio::test]
+    async fn test_register_approvals() {
+        let storage = MemoryStorage::new();
+        storage.register_token(&token(1), 1000).await.unwrap();
+
+        let approval = TokenApprovalInfo {
+            owner: "0x2".to_string(),
+            approved: Some("0x3".to_string()),
+            timestamp: 1000,
+            transaction_hash: "0x4".to_string(),
+            block_number: Some(10),
+        };
+        storage
+            .register_token_approval("0x1", &token_id(1), &approval)
+            .await
+            .unwrap();
+        assert_eq!(storage.token_approval("0x1", &token_id(1)), Some(approval));
+
+        let operator_approval = OperatorApprovalInfo {
+            owner: "0x2".to_string(),
+            operator: "0x5".to_string(),
+            approved: true,
+            timestamp: 1000,
+            transaction_hash: "0x4".to_string(),
+            block_number: Some(10),
+        };
+        storage
+            .register_operator_approval("0x1", &operator_approval)
+            .await
+            .unwrap();
+
+        // The revoke replaces the approval.
+        let revoke = OperatorApprovalInfo {
+            approved: false,
+            timestamp: 1010,
+            block_number: Some(11),
+            ..operator_approval
+        };
+        storage
+            .register_operator_approval("0x1", &revoke)
+            .await
+            .unwrap();
+        assert_eq!(storage.operator_approval("0x1", "0x2", "0x5"), Some(revoke));
+
+        storage.clean_block(1010, Some(11)).await.unwrap();
+        assert_eq!(storage.operator_approval("0x1", "0x2", "0x5"), None);
+    }
+}
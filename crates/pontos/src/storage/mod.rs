@@ -1,26 +1,39 @@
+pub mod memory;
 pub mod types;
 pub mod utils;
 
+pub use memory::MemoryStorage;
+
 #[cfg(feature = "sqlxdb")]
 pub mod sqlx;
 #[cfg(feature = "sqlxdb")]
 pub use sqlx::DefaultSqlxStorage;
+#[cfg(feature = "postgres")]
+pub use sqlx::{PostgresStorage, SchemaConfig};
 
 use crate::storage::types::{
-    BlockInfo, ContractInfo, ContractType, StorageError, TokenEvent, TokenInfo, TokenMintInfo,
+    ActivityKey, ActivityPage, BlockInfo, ContractInfo, ContractType, OperatorApprovalInfo,
+    RoyaltyInfo, StorageError, TokenApprovalInfo, TokenEvent, TokenInfo, TokenMintInfo, TokenPage,
 };
+use ark_starknet::PaddedTokenId;
 use async_trait::async_trait;
 
 #[cfg(test)]
 use mockall::automock;
 
+/// Number of tokens read at once when scanning the tokens of a collection.
+pub const DEFAULT_TOKEN_PAGE_SIZE: usize = 1000;
+
 #[async_trait]
 #[cfg_attr(test, automock)]
 pub trait Storage {
+    /// Registers the mint of a registered token. A mint older than the
+    /// registered one (from a block processed out of order) is ignored,
+    /// to keep the latest mint of the token.
     async fn register_mint(
         &self,
         contract_address: &str,
-        token_id_hex: &str,
+        token_id_hex: &PaddedTokenId,
         info: &TokenMintInfo,
     ) -> Result<(), StorageError>;
 
@@ -30,12 +43,90 @@ pub trait Storage {
         block_timestamp: u64,
     ) -> Result<(), StorageError>;
 
+    /// Updates the owner of a registered token.
+    async fn update_token(&self, token: &TokenInfo) -> Result<(), StorageError>;
+
+    /// Returns the registered token, if any.
+    async fn get_token(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+    ) -> Result<Option<TokenInfo>, StorageError>;
+
+    /// Returns a page of at most `page_size` tokens of the given collection,
+    /// by ascending token id, starting after `exclusive_start_key` if any.
+    async fn find_tokens(
+        &self,
+        contract_address: &str,
+        exclusive_start_key: Option<PaddedTokenId>,
+        page_size: usize,
+    ) -> Result<TokenPage, StorageError>;
+
+    /// Registers the ERC-2981 royalty of a registered token.
+    async fn register_token_royalty(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+        info: &RoyaltyInfo,
+    ) -> Result<(), StorageError>;
+
+    /// Registers the default ERC-2981 royalty of a registered contract.
+    async fn register_contract_royalty(
+        &self,
+        contract_address: &str,
+        info: &RoyaltyInfo,
+    ) -> Result<(), StorageError>;
+
+    /// Registers the `totalSupply` of a registered contract, as a decimal string,
+    /// replacing the previous one.
+    async fn register_contract_total_supply(
+        &self,
+        contract_address: &str,
+        total_supply: &str,
+    ) -> Result<(), StorageError>;
+
+    /// Registers the approval of a registered token, replacing the previous one.
+    async fn register_token_approval(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+        info: &TokenApprovalInfo,
+    ) -> Result<(), StorageError>;
+
+    /// Registers the approval of an operator, replacing the previous one
+    /// of the same owner and operator.
+    async fn register_operator_approval(
+        &self,
+        contract_address: &str,
+        info: &OperatorApprovalInfo,
+    ) -> Result<(), StorageError>;
+
     async fn register_event(
         &self,
         event: &TokenEvent,
         block_timestamp: u64,
     ) -> Result<(), StorageError>;
 
+    /// Registers the events of a block at once, reducing the writes during
+    /// large mints. Unlike `register_event`, the events already registered
+    /// are kept without error.
+    async fn register_events(
+        &self,
+        events: &[TokenEvent],
+        block_timestamp: u64,
+    ) -> Result<(), StorageError>;
+
+    /// Returns a page of at most `page_size` events (mints, transfers and
+    /// burns) of the given collection registered since `since_timestamp`
+    /// (included), most recent first, starting after `exclusive_start_key` if any.
+    async fn find_collection_activities(
+        &self,
+        contract_address: &str,
+        since_timestamp: u64,
+        exclusive_start_key: Option<ActivityKey>,
+        page_size: usize,
+    ) -> Result<ActivityPage, StorageError>;
+
     async fn get_contract_type(&self, contract_address: &str)
         -> Result<ContractType, StorageError>;
 
@@ -55,6 +146,13 @@ pub trait Storage {
 
     async fn get_block_info(&self, block_number: u64) -> Result<BlockInfo, StorageError>;
 
+    /// Returns the events registered in the block of the given timestamp,
+    /// to restore the owners of its tokens when it's rolled back.
+    async fn find_block_events(
+        &self,
+        block_timestamp: u64,
+    ) -> Result<Vec<TokenEvent>, StorageError>;
+
     /// The block timestamps is always present. But the number can be missing
     /// for the pending block support.
     async fn clean_block(
@@ -62,4 +160,17 @@ pub trait Storage {
         block_timestamp: u64,
         block_number: Option<u64>,
     ) -> Result<(), StorageError>;
+
+    /// Returns the last block fully processed by the given indexer, if any.
+    async fn get_last_processed_block(
+        &self,
+        indexer_identifier: &str,
+    ) -> Result<Option<u64>, StorageError>;
+
+    /// Saves the last block fully processed by the given indexer.
+    async fn set_last_processed_block(
+        &self,
+        indexer_identifier: &str,
+        block_number: u64,
+    ) -> Result<(), StorageError>;
 }
@@ -3,11 +3,12 @@
 //! The implementation in this file is very naive, and mostly
 //! used for testing and as an example of implementation.
 //! No optimization was done for indexing or PK/FK managment.
+use ark_starknet::PaddedTokenId;
 use async_trait::async_trait;
 
-use log::trace;
 use sqlx::{any::AnyPoolOptions, AnyPool, Error as SqlxError, FromRow};
 use std::str::FromStr;
+use tracing::{info, trace};
 
 use super::types::*;
 use crate::storage::types::*;
@@ -42,7 +43,7 @@ impl DefaultSqlxStorage {
         let rows = sqlx::query(q).fetch_all(&self.pool).await?;
 
         rows.iter().for_each(|r| {
-            println!("{:?}", TokenData::from_row(r).unwrap());
+            info!("{:?}", TokenData::from_row(r).unwrap());
         });
 
         Ok(())
@@ -51,13 +52,13 @@ impl DefaultSqlxStorage {
     async fn get_token_by_id(
         &self,
         contract_address: &str,
-        token_id_hex: &str,
+        token_id_hex: &PaddedTokenId,
     ) -> Result<Option<TokenData>, StorageError> {
         let q = "SELECT * FROM token WHERE contract_address = ? AND token_id_hex = ?";
 
         match sqlx::query(q)
             .bind(contract_address)
-            .bind(token_id_hex)
+            .bind(token_id_hex.as_str())
             .fetch_all(&self.pool)
             .await
         {
@@ -134,7 +135,7 @@ impl Storage for DefaultSqlxStorage {
     async fn register_mint(
         &self,
         contract_address: &str,
-        token_id_hex: &str,
+        token_id_hex: &PaddedTokenId,
         info: &TokenMintInfo,
     ) -> Result<(), StorageError> {
         trace!(
@@ -144,13 +145,16 @@ impl Storage for DefaultSqlxStorage {
             info
         );
 
-        let q = "UPDATE token SET mint_address = ?, mint_timestamp = ?, mint_transaction_hash = ? WHERE token_id_hex = ?";
+        // An older mint, from a block processed out of order, is ignored.
+        let q = "UPDATE token SET mint_address = ?, mint_timestamp = ?, mint_transaction_hash = ? WHERE contract_address = ? AND token_id_hex = ? AND COALESCE(mint_timestamp, 0) <= ?";
 
         let _r = sqlx::query(q)
             .bind(info.address.clone())
-            .bind(info.timestamp.to_string())
+            .bind(info.timestamp as i64)
             .bind(info.transaction_hash.clone())
-            .bind(token_id_hex)
+            .bind(contract_address)
+            .bind(token_id_hex.as_str())
+            .bind(info.timestamp as i64)
             .execute(&self.pool)
             .await?;
 
@@ -175,14 +179,209 @@ impl Storage for DefaultSqlxStorage {
             )));
         }
 
-        let q = "INSERT INTO token (contract_address, token_id, token_id_hex, owner, block_timestamp) VALUES (?, ?, ?, ?, ?)";
+        let q = "INSERT INTO token (contract_address, token_id, token_id_hex, owner, block_timestamp, network) VALUES (?, ?, ?, ?, ?, ?)";
 
         let _r = sqlx::query(q)
             .bind(token.contract_address.clone())
             .bind(token.token_id.clone())
-            .bind(token.token_id_hex.clone())
+            .bind(token.token_id_hex.as_str())
             .bind(token.owner.clone())
             .bind(block_timestamp.to_string())
+            .bind(token.network.clone())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn update_token(&self, token: &TokenInfo) -> Result<(), StorageError> {
+        trace!("Updating token {:?}", token);
+
+        let q = "UPDATE token SET owner = ? WHERE contract_address = ? AND token_id_hex = ?";
+
+        let r = sqlx::query(q)
+            .bind(token.owner.clone())
+            .bind(token.contract_address.clone())
+            .bind(token.token_id_hex.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        if r.rows_affected() == 0 {
+            return Err(StorageError::NotFound(format!(
+                "token id = {}",
+                token.token_id_hex
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn get_token(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+    ) -> Result<Option<TokenInfo>, StorageError> {
+        Ok(self
+            .get_token_by_id(contract_address, token_id_hex)
+            .await?
+            .map(TokenInfo::from))
+    }
+
+    async fn find_tokens(
+        &self,
+        contract_address: &str,
+        exclusive_start_key: Option<PaddedTokenId>,
+        page_size: usize,
+    ) -> Result<TokenPage, StorageError> {
+        // One more token is read to know if there is a next page.
+        let q = "SELECT * FROM token WHERE contract_address = ? AND token_id_hex > ? ORDER BY token_id_hex LIMIT ?";
+
+        let rows = sqlx::query(q)
+            .bind(contract_address)
+            .bind(exclusive_start_key.map(String::from).unwrap_or_default())
+            .bind(page_size as i64 + 1)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut tokens = rows
+            .iter()
+            .map(|r| TokenData::from_row(r).map(TokenInfo::from))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let last_evaluated_key = if tokens.len() > page_size {
+            tokens.truncate(page_size);
+            tokens.last().map(|t| t.token_id_hex.clone())
+        } else {
+            None
+        };
+
+        Ok(TokenPage {
+            tokens,
+            last_evaluated_key,
+        })
+    }
+
+    async fn register_token_royalty(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+        info: &RoyaltyInfo,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering royalty {} {} {:?}",
+            contract_address,
+            token_id_hex,
+            info
+        );
+
+        let q = "UPDATE token SET royalty_receiver = ?, royalty_basis_points = ? WHERE contract_address = ? AND token_id_hex = ?";
+
+        sqlx::query(q)
+            .bind(&info.receiver)
+            .bind(info.basis_points as i64)
+            .bind(contract_address)
+            .bind(token_id_hex.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn register_contract_royalty(
+        &self,
+        contract_address: &str,
+        info: &RoyaltyInfo,
+    ) -> Result<(), StorageError> {
+        trace!("Registering royalty {} {:?}", contract_address, info);
+
+        let q = "UPDATE contract SET royalty_receiver = ?, royalty_basis_points = ? WHERE contract_address = ?";
+
+        sqlx::query(q)
+            .bind(&info.receiver)
+            .bind(info.basis_points as i64)
+            .bind(contract_address)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn register_contract_total_supply(
+        &self,
+        contract_address: &str,
+        total_supply: &str,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering total supply {} {}",
+            contract_address,
+            total_supply
+        );
+
+        let q = "UPDATE contract SET total_supply = ? WHERE contract_address = ?";
+
+        sqlx::query(q)
+            .bind(total_supply)
+            .bind(contract_address)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn register_token_approval(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+        info: &TokenApprovalInfo,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering approval {} {} {:?}",
+            contract_address,
+            token_id_hex,
+            info
+        );
+
+        let q = "UPDATE token SET approved_address = ?, approval_timestamp = ?, approval_transaction_hash = ? WHERE contract_address = ? AND token_id_hex = ?";
+
+        sqlx::query(q)
+            .bind(info.approved.clone().unwrap_or_default())
+            .bind(info.timestamp as i64)
+            .bind(&info.transaction_hash)
+            .bind(contract_address)
+            .bind(token_id_hex.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn register_operator_approval(
+        &self,
+        contract_address: &str,
+        info: &OperatorApprovalInfo,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering operator approval {} {:?}",
+            contract_address,
+            info
+        );
+
+        let q = "DELETE FROM operator_approval WHERE contract_address = ? AND owner = ? AND operator = ?";
+        sqlx::query(q)
+            .bind(contract_address)
+            .bind(&info.owner)
+            .bind(&info.operator)
+            .execute(&self.pool)
+            .await?;
+
+        let q = "INSERT INTO operator_approval (contract_address, owner, operator, approved, transaction_hash, block_timestamp) VALUES (?, ?, ?, ?, ?, ?)";
+        sqlx::query(q)
+            .bind(contract_address)
+            .bind(&info.owner)
+            .bind(&info.operator)
+            .bind(info.approved)
+            .bind(&info.transaction_hash)
+            .bind(info.timestamp as i64)
             .execute(&self.pool)
             .await?;
 
@@ -203,7 +402,7 @@ impl Storage for DefaultSqlxStorage {
             )));
         }
 
-        let q = "INSERT INTO event (block_timestamp, contract_address, from_address, to_address, transaction_hash, token_id, token_id_hex, contract_type, event_type, event_id) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+        let q = "INSERT INTO event (block_timestamp, contract_address, from_address, to_address, transaction_hash, token_id, token_id_hex, contract_type, event_type, event_id, network, transaction_url) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
 
         let _r = sqlx::query(q)
             .bind(event.timestamp.to_string())
@@ -212,16 +411,106 @@ impl Storage for DefaultSqlxStorage {
             .bind(event.contract_address.clone())
             .bind(event.transaction_hash.clone())
             .bind(event.token_id.clone())
-            .bind(event.token_id_hex.clone())
+            .bind(event.token_id_hex.as_str())
             .bind(event.contract_type.clone())
             .bind(event.event_type.to_string())
             .bind(event.event_id.clone())
+            .bind(event.network.clone())
+            .bind(event.transaction_url.clone())
             .execute(&self.pool)
             .await?;
 
         Ok(())
     }
 
+    async fn register_events(
+        &self,
+        events: &[TokenEvent],
+        block_timestamp: u64,
+    ) -> Result<(), StorageError> {
+        trace!("Registering {} events", events.len());
+
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        // The events are inserted in one transaction, skipping the events
+        // already registered.
+        let q = "INSERT INTO event (block_timestamp, contract_address, from_address, to_address, transaction_hash, token_id, token_id_hex, contract_type, event_type, event_id, network, transaction_url) SELECT ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ? WHERE NOT EXISTS (SELECT 1 FROM event WHERE event_id = ?)";
+
+        let mut tx = self.pool.begin().await?;
+
+        for event in events {
+            sqlx::query(q)
+                .bind(block_timestamp as i64)
+                .bind(event.contract_address.clone())
+                .bind(event.from_address.clone())
+                .bind(event.to_address.clone())
+                .bind(event.transaction_hash.clone())
+                .bind(event.token_id.clone())
+                .bind(event.token_id_hex.as_str())
+                .bind(event.contract_type.clone())
+                .bind(event.event_type.to_string())
+                .bind(event.event_id.clone())
+                .bind(event.network.clone())
+                .bind(event.transaction_url.clone())
+                .bind(event.event_id.clone())
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn find_collection_activities(
+        &self,
+        contract_address: &str,
+        since_timestamp: u64,
+        exclusive_start_key: Option<ActivityKey>,
+        page_size: usize,
+    ) -> Result<ActivityPage, StorageError> {
+        // Queried with the `event_activity_idx` index.
+        // One more event is read to know if there is a next page.
+        let q = "SELECT * FROM event WHERE contract_address = ? AND block_timestamp >= ? AND (block_timestamp < ? OR (block_timestamp = ? AND event_id < ?)) ORDER BY block_timestamp DESC, event_id DESC LIMIT ?";
+
+        let (start_timestamp, start_event_id) = match exclusive_start_key {
+            Some(key) => (key.timestamp as i64, key.event_id),
+            None => (i64::MAX, String::new()),
+        };
+
+        let rows = sqlx::query(q)
+            .bind(contract_address)
+            .bind(since_timestamp as i64)
+            .bind(start_timestamp)
+            .bind(start_timestamp)
+            .bind(start_event_id)
+            .bind(page_size as i64 + 1)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut events = rows
+            .iter()
+            .map(|r| EventData::from_row(r).map(TokenEvent::from))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let last_evaluated_key = if events.len() > page_size {
+            events.truncate(page_size);
+            events.last().map(|e| ActivityKey {
+                timestamp: e.timestamp,
+                event_id: e.event_id.clone(),
+            })
+        } else {
+            None
+        };
+
+        Ok(ActivityPage {
+            events,
+            last_evaluated_key,
+        })
+    }
+
     async fn get_contract_type(
         &self,
         contract_address: &str,
@@ -276,18 +565,19 @@ impl Storage for DefaultSqlxStorage {
         trace!("Setting block info {:?} for block #{}", info, block_number);
 
         let _r = if (self.get_block_by_timestamp(block_timestamp).await?).is_some() {
-            let q = "UPDATE block SET block_timestamp = ?, block_number = ?, status = ?, indexer_version = ?, indexer_identifier = ? WHERE block_timestamp = ?";
+            let q = "UPDATE block SET block_timestamp = ?, block_number = ?, status = ?, indexer_version = ?, indexer_identifier = ?, block_hash = ? WHERE block_timestamp = ?";
             sqlx::query(q)
                 .bind(block_timestamp.to_string())
                 .bind(block_number.to_string())
                 .bind(info.status.to_string())
                 .bind(info.indexer_version.clone())
                 .bind(info.indexer_identifier.clone())
+                .bind(info.block_hash.clone().unwrap_or_default())
                 .bind(block_timestamp.to_string())
                 .execute(&self.pool)
                 .await?
         } else {
-            let q = "INSERT INTO block (block_timestamp, block_number, status, indexer_version, indexer_identifier) VALUES (?, ?, ?, ?, ?)";
+            let q = "INSERT INTO block (block_timestamp, block_number, status, indexer_version, indexer_identifier, block_hash) VALUES (?, ?, ?, ?, ?, ?)";
 
             sqlx::query(q)
                 .bind(block_timestamp.to_string())
@@ -295,6 +585,7 @@ impl Storage for DefaultSqlxStorage {
                 .bind(info.status.to_string())
                 .bind(info.indexer_version.clone())
                 .bind(info.indexer_identifier.clone())
+                .bind(info.block_hash.clone().unwrap_or_default())
                 .execute(&self.pool)
                 .await?
         };
@@ -324,6 +615,8 @@ impl Storage for DefaultSqlxStorage {
                         indexer_identifier: d.indexer_identifier.clone(),
                         status: BlockIndexingStatus::from_str(&d.status).unwrap(),
                         block_number,
+                        block_timestamp: d.timestamp as u64,
+                        block_hash: Some(d.block_hash).filter(|h| !h.is_empty()),
                     })
                 }
             }
@@ -331,6 +624,23 @@ impl Storage for DefaultSqlxStorage {
         }
     }
 
+    async fn find_block_events(
+        &self,
+        block_timestamp: u64,
+    ) -> Result<Vec<TokenEvent>, StorageError> {
+        let q = "SELECT * FROM event WHERE block_timestamp = ?";
+
+        let rows = sqlx::query(q)
+            .bind(block_timestamp as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|r| EventData::from_row(r).map(TokenEvent::from))
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
     async fn clean_block(
         &self,
         block_timestamp: u64,
@@ -366,6 +676,56 @@ impl Storage for DefaultSqlxStorage {
             .fetch_all(&self.pool)
             .await?;
 
+        let q = "DELETE FROM operator_approval WHERE block_timestamp = ?";
+        sqlx::query(q)
+            .bind(block_timestamp.to_string())
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_last_processed_block(
+        &self,
+        indexer_identifier: &str,
+    ) -> Result<Option<u64>, StorageError> {
+        trace!("Getting last processed block of {}", indexer_identifier);
+
+        let q = "SELECT block_number FROM indexer_cursor WHERE indexer_identifier = ?";
+
+        match sqlx::query_as::<_, (i64,)>(q)
+            .bind(indexer_identifier.to_string())
+            .fetch_optional(&self.pool)
+            .await
+        {
+            Ok(row) => Ok(row.map(|(block_number,)| block_number as u64)),
+            Err(e) => Err(StorageError::DatabaseError(e.to_string())),
+        }
+    }
+
+    async fn set_last_processed_block(
+        &self,
+        indexer_identifier: &str,
+        block_number: u64,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Setting last processed block of {} to #{}",
+            indexer_identifier,
+            block_number
+        );
+
+        let q = if (self.get_last_processed_block(indexer_identifier).await?).is_some() {
+            "UPDATE indexer_cursor SET block_number = ? WHERE indexer_identifier = ?"
+        } else {
+            "INSERT INTO indexer_cursor (block_number, indexer_identifier) VALUES (?, ?)"
+        };
+
+        sqlx::query(q)
+            .bind(block_number as i64)
+            .bind(indexer_identifier.to_string())
+            .execute(&self.pool)
+            .await?;
+
         Ok(())
     }
 }
@@ -0,0 +1,8 @@
+-- Last block fully processed by each indexer, to resume after a restart.
+
+CREATE TABLE indexer_cursor (
+       indexer_identifier TEXT NOT NULL,
+       block_number BIGINT NOT NULL,
+
+       PRIMARY KEY (indexer_identifier)
+);
@@ -0,0 +1,3 @@
+-- Hash of the indexed blocks, to detect chain reorganizations.
+
+ALTER TABLE block ADD COLUMN block_hash TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,7 @@
+-- ERC-2981 royalty of the tokens, and default royalty of the contracts.
+
+ALTER TABLE token ADD COLUMN royalty_receiver TEXT DEFAULT '';
+ALTER TABLE token ADD COLUMN royalty_basis_points BIGINT DEFAULT 0;
+
+ALTER TABLE contract ADD COLUMN royalty_receiver TEXT DEFAULT '';
+ALTER TABLE contract ADD COLUMN royalty_basis_points BIGINT DEFAULT 0;
@@ -0,0 +1,17 @@
+-- Approvals of the tokens, and approvals of the operators for all the
+-- tokens of an owner.
+
+ALTER TABLE token ADD COLUMN approved_address TEXT DEFAULT '';
+ALTER TABLE token ADD COLUMN approval_timestamp BIGINT DEFAULT 0;
+ALTER TABLE token ADD COLUMN approval_transaction_hash TEXT DEFAULT '';
+
+CREATE TABLE operator_approval (
+       contract_address TEXT NOT NULL,
+       owner TEXT NOT NULL,
+       operator TEXT NOT NULL,
+       approved BOOLEAN NOT NULL,
+       transaction_hash TEXT NOT NULL,
+       block_timestamp BIGINT NOT NULL,
+
+       PRIMARY KEY (contract_address, owner, operator)
+);
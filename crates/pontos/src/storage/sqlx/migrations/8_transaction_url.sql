@@ -0,0 +1,3 @@
+-- Explorer URL of the transaction of the events.
+
+ALTER TABLE event ADD COLUMN transaction_url TEXT;
@@ -6,4 +6,9 @@
 pub mod default_storage;
 pub use default_storage::DefaultSqlxStorage;
 
+#[cfg(feature = "postgres")]
+pub mod postgres_storage;
+#[cfg(feature = "postgres")]
+pub use postgres_storage::{PostgresStorage, SchemaConfig};
+
 pub mod types;
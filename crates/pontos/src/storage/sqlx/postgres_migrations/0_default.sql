@@ -0,0 +1,67 @@
+-- Postgres schema of Pontos.
+--
+-- Same tables as the default migrations, with the indexes
+-- used to clean the blocks.
+
+CREATE TABLE token (
+       contract_address TEXT NOT NULL,
+       token_id TEXT NOT NULL,
+       token_id_hex TEXT NOT NULL,
+       owner TEXT NOT NULL,
+       mint_address TEXT DEFAULT '',
+       mint_timestamp BIGINT DEFAULT 0,
+       mint_transaction_hash TEXT DEFAULT '',
+       block_timestamp BIGINT NOT NULL,
+
+       PRIMARY KEY (contract_address, token_id_hex)
+);
+
+CREATE INDEX token_block_timestamp_idx ON token (block_timestamp);
+
+CREATE TABLE event (
+       block_timestamp BIGINT NOT NULL,
+       from_address TEXT NOT NULL,
+       to_address TEXT NOT NULL,
+       contract_address TEXT NOT NULL,
+       transaction_hash TEXT NOT NULL,
+       token_id TEXT NOT NULL,
+       token_id_hex TEXT NOT NULL,
+       contract_type TEXT NOT NULL,
+       event_type TEXT NOT NULL,
+       event_id TEXT NOT NULL,
+
+       PRIMARY KEY (event_id)
+);
+
+CREATE INDEX event_block_timestamp_idx ON event (block_timestamp);
+CREATE INDEX event_token_idx ON event (contract_address, token_id_hex);
+
+CREATE TABLE block (
+       block_timestamp BIGINT NOT NULL,
+       block_number BIGINT NOT NULL,
+       status TEXT NOT NULL,
+       indexer_version TEXT NOT NULL,
+       indexer_identifier TEXT NOT NULL,
+       block_hash TEXT NOT NULL DEFAULT '',
+
+       PRIMARY KEY (block_timestamp)
+);
+
+CREATE INDEX block_number_idx ON block (block_number);
+
+CREATE TABLE contract (
+       contract_address TEXT NOT NULL,
+       contract_type TEXT NOT NULL,
+       block_timestamp BIGINT NOT NULL,
+
+       PRIMARY KEY (contract_address)
+);
+
+CREATE INDEX contract_block_timestamp_idx ON contract (block_timestamp);
+
+CREATE TABLE indexer_cursor (
+       indexer_identifier TEXT NOT NULL,
+       block_number BIGINT NOT NULL,
+
+       PRIMARY KEY (indexer_identifier)
+);
@@ -0,0 +1,4 @@
+-- Network of the tokens and events, like `mainnet` or `sepolia`.
+
+ALTER TABLE token ADD COLUMN network TEXT DEFAULT '';
+ALTER TABLE event ADD COLUMN network TEXT DEFAULT '';
@@ -0,0 +1,3 @@
+-- Activity of the collections, queried by most recent events.
+
+CREATE INDEX event_activity_idx ON event (contract_address, block_timestamp, event_id);
@@ -0,0 +1,3 @@
+-- `totalSupply` of the contracts, as a decimal string.
+
+ALTER TABLE contract ADD COLUMN total_supply TEXT;
@@ -0,0 +1,723 @@
+//! Implementation of the storage for Postgres.
+//!
+//! Unlike the default storage, the values are bound with their Postgres
+//! types, and the registrations are conditional inserts (`ON CONFLICT`),
+//! which keeps them idempotent when several indexers share the database.
+//! The schema is in the `postgres_migrations` folder.
+//!
+//! Several environments (i.g. staging and production, or several networks)
+//! can share the same database, each one having its tables in its own
+//! Postgres schema, named from a `SchemaConfig`.
+use ark_starknet::network::Network;
+use ark_starknet::PaddedTokenId;
+use async_trait::async_trait;
+
+use sqlx::{postgres::PgPoolOptions, Executor, PgPool, Postgres, QueryBuilder};
+use std::str::FromStr;
+use tracing::trace;
+
+use super::types::*;
+use crate::storage::types::*;
+use crate::Storage;
+
+/// Number of events inserted by statement, each event binding 12 parameters
+/// while Postgres accepts at most 65535 parameters by statement.
+const EVENT_INSERT_CHUNK_SIZE: usize = 1000;
+
+/// Environment variable with the prefix of the schema, `ark` by default.
+pub const SCHEMA_PREFIX_ENV_VAR: &str = "PONTOS_SCHEMA_PREFIX";
+
+/// Environment variable with the environment of the schema (`mainnet`,
+/// `sepolia`, `goerli`, `staging`...), `mainnet` by default.
+pub const SCHEMA_ENVIRONMENT_ENV_VAR: &str = "PONTOS_ENVIRONMENT";
+
+/// Naming of the Postgres schema holding the tables of an environment,
+/// `{prefix}_{environment}` (i.g. `ark_mainnet` or `ark_sepolia`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaConfig {
+    pub prefix: String,
+    pub environment: String,
+}
+
+impl SchemaConfig {
+    pub fn new(prefix: &str, environment: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            environment: environment.to_string(),
+        }
+    }
+
+    /// Reads the prefix and the environment from `PONTOS_SCHEMA_PREFIX`
+    /// and `PONTOS_ENVIRONMENT`, defaulting to `ark_mainnet`.
+    pub fn from_env() -> Self {
+        Self {
+            prefix: std::env::var(SCHEMA_PREFIX_ENV_VAR).unwrap_or_else(|_| "ark".to_string()),
+            environment: std::env::var(SCHEMA_ENVIRONMENT_ENV_VAR)
+                .unwrap_or_else(|_| "mainnet".to_string()),
+        }
+    }
+
+    /// Names the schema from the network, like `ark_sepolia`, the prefix
+    /// being read from `PONTOS_SCHEMA_PREFIX`.
+    pub fn for_network(network: Network) -> Self {
+        Self {
+            prefix: std::env::var(SCHEMA_PREFIX_ENV_VAR).unwrap_or_else(|_| "ark".to_string()),
+            environment: network.to_string(),
+        }
+    }
+
+    /// Returns the name of the schema. As it's not a bound value, only
+    /// lowercase ASCII letters, digits and underscores are accepted.
+    pub fn schema_name(&self) -> Result<String, StorageError> {
+        let name = format!(
+            "{}_{}",
+            self.prefix.trim().to_lowercase(),
+            self.environment.trim().to_lowercase()
+        );
+
+        let is_valid = !self.prefix.trim().is_empty()
+            && !self.environment.trim().is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+        if is_valid {
+            Ok(name)
+        } else {
+            Err(StorageError::DatabaseError(format!(
+                "Invalid schema name: {name}"
+            )))
+        }
+    }
+}
+
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub fn get_pool_ref(&self) -> &PgPool {
+        &self.pool
+    }
+
+    pub async fn new(db_url: &str, max_connections: u32) -> Result<Self, StorageError> {
+        Ok(Self {
+            pool: PgPoolOptions::new()
+                .max_connections(max_connections)
+                .connect(db_url)
+                .await?,
+        })
+    }
+
+    /// Connects to the database, using the schema of the given environment
+    /// for the tables. The schema is created if it doesn't exist yet, and
+    /// `migrate` applies the migrations inside it.
+    pub async fn new_with_schema(
+        db_url: &str,
+        max_connections: u32,
+        schema: &SchemaConfig,
+    ) -> Result<Self, StorageError> {
+        let schema_name = schema.schema_name()?;
+
+        Ok(Self {
+            pool: PgPoolOptions::new()
+                .max_connections(max_connections)
+                .after_connect(move |conn, _| {
+                    let q = format!(
+                        "CREATE SCHEMA IF NOT EXISTS {schema_name}; SET search_path TO {schema_name}"
+                    );
+                    Box::pin(async move {
+                        conn.execute(q.as_str()).await?;
+                        Ok(())
+                    })
+                })
+                .connect(db_url)
+                .await?,
+        })
+    }
+
+    /// Runs the Postgres migrations of Pontos.
+    pub async fn migrate(&self) -> Result<(), StorageError> {
+        sqlx::migrate!("./src/storage/sqlx/postgres_migrations")
+            .run(&self.pool)
+            .await
+            .map_err(|e| StorageError::DatabaseError(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn register_mint(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+        info: &TokenMintInfo,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering mint {} {} {:?}",
+            contract_address,
+            token_id_hex,
+            info
+        );
+
+        // An older mint, from a block processed out of order, is ignored.
+        let q = "UPDATE token SET mint_address = $1, mint_timestamp = $2, mint_transaction_hash = $3 WHERE contract_address = $4 AND token_id_hex = $5 AND COALESCE(mint_timestamp, 0) <= $2";
+
+        sqlx::query(q)
+            .bind(&info.address)
+            .bind(info.timestamp as i64)
+            .bind(&info.transaction_hash)
+            .bind(contract_address)
+            .bind(token_id_hex.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn register_token(
+        &self,
+        token: &TokenInfo,
+        block_timestamp: u64,
+    ) -> Result<(), StorageError> {
+        trace!("Registering token {:?}", token);
+
+        let q = "INSERT INTO token (contract_address, token_id, token_id_hex, owner, block_timestamp, network) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (contract_address, token_id_hex) DO NOTHING";
+
+        let r = sqlx::query(q)
+            .bind(&token.contract_address)
+            .bind(&token.token_id)
+            .bind(token.token_id_hex.as_str())
+            .bind(&token.owner)
+            .bind(block_timestamp as i64)
+            .bind(&token.network)
+            .execute(&self.pool)
+            .await?;
+
+        if r.rows_affected() == 0 {
+            return Err(StorageError::AlreadyExists(format!(
+                "token id = {}",
+                token.token_id_hex
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn update_token(&self, token: &TokenInfo) -> Result<(), StorageError> {
+        trace!("Updating token {:?}", token);
+
+        let q = "UPDATE token SET owner = $1 WHERE contract_address = $2 AND token_id_hex = $3";
+
+        let r = sqlx::query(q)
+            .bind(&token.owner)
+            .bind(&token.contract_address)
+            .bind(token.token_id_hex.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        if r.rows_affected() == 0 {
+            return Err(StorageError::NotFound(format!(
+                "token id = {}",
+                token.token_id_hex
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn get_token(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+    ) -> Result<Option<TokenInfo>, StorageError> {
+        let q = "SELECT * FROM token WHERE contract_address = $1 AND token_id_hex = $2";
+
+        let token = sqlx::query_as::<_, TokenData>(q)
+            .bind(contract_address)
+            .bind(token_id_hex.as_str())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(token.map(TokenInfo::from))
+    }
+
+    async fn find_tokens(
+        &self,
+        contract_address: &str,
+        exclusive_start_key: Option<PaddedTokenId>,
+        page_size: usize,
+    ) -> Result<TokenPage, StorageError> {
+        // One more token is read to know if there is a next page.
+        let q = "SELECT * FROM token WHERE contract_address = $1 AND token_id_hex > $2 ORDER BY token_id_hex LIMIT $3";
+
+        let mut tokens: Vec<TokenInfo> = sqlx::query_as::<_, TokenData>(q)
+            .bind(contract_address)
+            .bind(exclusive_start_key.map(String::from).unwrap_or_default())
+            .bind(page_size as i64 + 1)
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(TokenInfo::from)
+            .collect();
+
+        let last_evaluated_key = if tokens.len() > page_size {
+            tokens.truncate(page_size);
+            tokens.last().map(|t| t.token_id_hex.clone())
+        } else {
+            None
+        };
+
+        Ok(TokenPage {
+            tokens,
+            last_evaluated_key,
+        })
+    }
+
+    async fn register_token_royalty(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+        info: &RoyaltyInfo,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering royalty {} {} {:?}",
+            contract_address,
+            token_id_hex,
+            info
+        );
+
+        let q = "UPDATE token SET royalty_receiver = $1, royalty_basis_points = $2 WHERE contract_address = $3 AND token_id_hex = $4";
+
+        sqlx::query(q)
+            .bind(&info.receiver)
+            .bind(info.basis_points as i64)
+            .bind(contract_address)
+            .bind(token_id_hex.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn register_contract_royalty(
+        &self,
+        contract_address: &str,
+        info: &RoyaltyInfo,
+    ) -> Result<(), StorageError> {
+        trace!("Registering royalty {} {:?}", contract_address, info);
+
+        let q = "UPDATE contract SET royalty_receiver = $1, royalty_basis_points = $2 WHERE contract_address = $3";
+
+        sqlx::query(q)
+            .bind(&info.receiver)
+            .bind(info.basis_points as i64)
+            .bind(contract_address)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn register_contract_total_supply(
+        &self,
+        contract_address: &str,
+        total_supply: &str,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering total supply {} {}",
+            contract_address,
+            total_supply
+        );
+
+        let q = "UPDATE contract SET total_supply = $1 WHERE contract_address = $2";
+
+        sqlx::query(q)
+            .bind(total_supply)
+            .bind(contract_address)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn register_token_approval(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+        info: &TokenApprovalInfo,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering approval {} {} {:?}",
+            contract_address,
+            token_id_hex,
+            info
+        );
+
+        let q = "UPDATE token SET approved_address = $1, approval_timestamp = $2, approval_transaction_hash = $3 WHERE contract_address = $4 AND token_id_hex = $5";
+
+        sqlx::query(q)
+            .bind(info.approved.clone().unwrap_or_default())
+            .bind(info.timestamp as i64)
+            .bind(&info.transaction_hash)
+            .bind(contract_address)
+            .bind(token_id_hex.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn register_operator_approval(
+        &self,
+        contract_address: &str,
+        info: &OperatorApprovalInfo,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering operator approval {} {:?}",
+            contract_address,
+            info
+        );
+
+        let q = "INSERT INTO operator_approval (contract_address, owner, operator, approved, transaction_hash, block_timestamp) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (contract_address, owner, operator) DO UPDATE SET approved = EXCLUDED.approved, transaction_hash = EXCLUDED.transaction_hash, block_timestamp = EXCLUDED.block_timestamp";
+
+        sqlx::query(q)
+            .bind(contract_address)
+            .bind(&info.owner)
+            .bind(&info.operator)
+            .bind(info.approved)
+            .bind(&info.transaction_hash)
+            .bind(info.timestamp as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn register_event(
+        &self,
+        event: &TokenEvent,
+        block_timestamp: u64,
+    ) -> Result<(), StorageError> {
+        trace!("Registering event {:?}", event);
+
+        let q = "INSERT INTO event (block_timestamp, contract_address, from_address, to_address, transaction_hash, token_id, token_id_hex, contract_type, event_type, event_id, network, transaction_url) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) ON CONFLICT (event_id) DO NOTHING";
+
+        let r = sqlx::query(q)
+            .bind(block_timestamp as i64)
+            .bind(&event.contract_address)
+            .bind(&event.from_address)
+            .bind(&event.to_address)
+            .bind(&event.transaction_hash)
+            .bind(&event.token_id)
+            .bind(event.token_id_hex.as_str())
+            .bind(&event.contract_type)
+            .bind(event.event_type.to_string())
+            .bind(&event.event_id)
+            .bind(&event.network)
+            .bind(&event.transaction_url)
+            .execute(&self.pool)
+            .await?;
+
+        if r.rows_affected() == 0 {
+            return Err(StorageError::AlreadyExists(format!(
+                "event id = {}",
+                event.event_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn register_events(
+        &self,
+        events: &[TokenEvent],
+        block_timestamp: u64,
+    ) -> Result<(), StorageError> {
+        trace!("Registering {} events", events.len());
+
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        // Multi-rows inserts, in one transaction.
+        let mut tx = self.pool.begin().await?;
+
+        for chunk in events.chunks(EVENT_INSERT_CHUNK_SIZE) {
+            let mut query = QueryBuilder::<Postgres>::new(
+                "INSERT INTO event (block_timestamp, contract_address, from_address, to_address, transaction_hash, token_id, token_id_hex, contract_type, event_type, event_id, network, transaction_url) ",
+            );
+
+            query.push_values(chunk, |mut row, event| {
+                row.push_bind(block_timestamp as i64)
+                    .push_bind(&event.contract_address)
+                    .push_bind(&event.from_address)
+                    .push_bind(&event.to_address)
+                    .push_bind(&event.transaction_hash)
+                    .push_bind(&event.token_id)
+                    .push_bind(event.token_id_hex.as_str())
+                    .push_bind(&event.contract_type)
+                    .push_bind(event.event_type.to_string())
+                    .push_bind(&event.event_id)
+                    .push_bind(&event.network)
+                    .push_bind(&event.transaction_url);
+            });
+            query.push(" ON CONFLICT (event_id) DO NOTHING");
+
+            query.build().execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn find_collection_activities(
+        &self,
+        contract_address: &str,
+        since_timestamp: u64,
+        exclusive_start_key: Option<ActivityKey>,
+        page_size: usize,
+    ) -> Result<ActivityPage, StorageError> {
+        // Queried with the `event_activity_idx` index.
+        // One more event is read to know if there is a next page.
+        let q = "SELECT * FROM event WHERE contract_address = $1 AND block_timestamp >= $2 AND (block_timestamp, event_id) < ($3, $4) ORDER BY block_timestamp DESC, event_id DESC LIMIT $5";
+
+        let (start_timestamp, start_event_id) = match exclusive_start_key {
+            Some(key) => (key.timestamp as i64, key.event_id),
+            None => (i64::MAX, String::new()),
+        };
+
+        let mut events: Vec<TokenEvent> = sqlx::query_as::<_, EventData>(q)
+            .bind(contract_address)
+            .bind(since_timestamp as i64)
+            .bind(start_timestamp)
+            .bind(start_event_id)
+            .bind(page_size as i64 + 1)
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(TokenEvent::from)
+            .collect();
+
+        let last_evaluated_key = if events.len() > page_size {
+            events.truncate(page_size);
+            events.last().map(|e| ActivityKey {
+                timestamp: e.timestamp,
+                event_id: e.event_id.clone(),
+            })
+        } else {
+            None
+        };
+
+        Ok(ActivityPage {
+            events,
+            last_evaluated_key,
+        })
+    }
+
+    async fn get_contract_type(
+        &self,
+        contract_address: &str,
+    ) -> Result<ContractType, StorageError> {
+        trace!("Getting contract info for contract {}", contract_address);
+
+        let q = "SELECT * FROM contract WHERE contract_address = $1";
+
+        match sqlx::query_as::<_, ContractData>(q)
+            .bind(contract_address)
+            .fetch_optional(&self.pool)
+            .await?
+        {
+            Some(c) => Ok(ContractType::from_str(&c.contract_type).unwrap()),
+            None => Err(StorageError::NotFound(format!(
+                "contract_address: {contract_address}"
+            ))),
+        }
+    }
+
+    async fn register_contract_info(
+        &self,
+        info: &ContractInfo,
+        block_timestamp: u64,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Registering contract info {:?} for contract {}",
+            info.contract_type,
+            info.contract_address
+        );
+
+        let q = "INSERT INTO contract (contract_address, contract_type, block_timestamp) VALUES ($1, $2, $3) ON CONFLICT (contract_address) DO NOTHING";
+
+        let r = sqlx::query(q)
+            .bind(&info.contract_address)
+            .bind(&info.contract_type)
+            .bind(block_timestamp as i64)
+            .execute(&self.pool)
+            .await?;
+
+        if r.rows_affected() == 0 {
+            return Err(StorageError::AlreadyExists(format!(
+                "contract addr = {}",
+                info.contract_address
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn set_block_info(
+        &self,
+        block_number: u64,
+        block_timestamp: u64,
+        info: BlockInfo,
+    ) -> Result<(), StorageError> {
+        trace!("Setting block info {:?} for block #{}", info, block_number);
+
+        let q = "INSERT INTO block (block_timestamp, block_number, status, indexer_version, indexer_identifier, block_hash) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (block_timestamp) DO UPDATE SET block_number = EXCLUDED.block_number, status = EXCLUDED.status, indexer_version = EXCLUDED.indexer_version, indexer_identifier = EXCLUDED.indexer_identifier, block_hash = EXCLUDED.block_hash";
+
+        sqlx::query(q)
+            .bind(block_timestamp as i64)
+            .bind(block_number as i64)
+            .bind(info.status.to_string())
+            .bind(&info.indexer_version)
+            .bind(&info.indexer_identifier)
+            .bind(info.block_hash.unwrap_or_default())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_block_info(&self, block_number: u64) -> Result<BlockInfo, StorageError> {
+        trace!("Getting block info for block #{}", block_number);
+
+        let q = "SELECT * FROM block WHERE block_number = $1";
+
+        match sqlx::query_as::<_, BlockData>(q)
+            .bind(block_number as i64)
+            .fetch_optional(&self.pool)
+            .await?
+        {
+            Some(d) => Ok(BlockInfo {
+                indexer_version: d.indexer_version,
+                indexer_identifier: d.indexer_identifier,
+                status: BlockIndexingStatus::from_str(&d.status).unwrap(),
+                block_number,
+                block_timestamp: d.timestamp as u64,
+                block_hash: Some(d.block_hash).filter(|h| !h.is_empty()),
+            }),
+            None => Err(StorageError::NotFound(format!(
+                "block number {block_number}"
+            ))),
+        }
+    }
+
+    async fn find_block_events(
+        &self,
+        block_timestamp: u64,
+    ) -> Result<Vec<TokenEvent>, StorageError> {
+        let q = "SELECT * FROM event WHERE block_timestamp = $1";
+
+        Ok(sqlx::query_as::<_, EventData>(q)
+            .bind(block_timestamp as i64)
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(TokenEvent::from)
+            .collect())
+    }
+
+    async fn clean_block(
+        &self,
+        block_timestamp: u64,
+        block_number: Option<u64>,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Cleaning block #{:?} [ts: {}]",
+            block_number,
+            block_timestamp
+        );
+
+        // The block is cleaned atomically, to never keep a partial block.
+        let mut tx = self.pool.begin().await?;
+
+        for q in [
+            "DELETE FROM block WHERE block_timestamp = $1",
+            "DELETE FROM contract WHERE block_timestamp = $1",
+            "DELETE FROM token WHERE block_timestamp = $1",
+            "DELETE FROM event WHERE block_timestamp = $1",
+            "DELETE FROM operator_approval WHERE block_timestamp = $1",
+        ] {
+            sqlx::query(q)
+                .bind(block_timestamp as i64)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_last_processed_block(
+        &self,
+        indexer_identifier: &str,
+    ) -> Result<Option<u64>, StorageError> {
+        trace!("Getting last processed block of {}", indexer_identifier);
+
+        let q = "SELECT block_number FROM indexer_cursor WHERE indexer_identifier = $1";
+
+        let row = sqlx::query_as::<_, (i64,)>(q)
+            .bind(indexer_identifier)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|(block_number,)| block_number as u64))
+    }
+
+    async fn set_last_processed_block(
+        &self,
+        indexer_identifier: &str,
+        block_number: u64,
+    ) -> Result<(), StorageError> {
+        trace!(
+            "Setting last processed block of {} to #{}",
+            indexer_identifier,
+            block_number
+        );
+
+        let q = "INSERT INTO indexer_cursor (indexer_identifier, block_number) VALUES ($1, $2) ON CONFLICT (indexer_identifier) DO UPDATE SET block_number = EXCLUDED.block_number";
+
+        sqlx::query(q)
+            .bind(indexer_identifier)
+            .bind(block_number as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_name() {
+        assert_eq!(
+            SchemaConfig::new("ark", "sepolia").schema_name().unwrap(),
+            "ark_sepolia"
+        );
+        assert_eq!(
+            SchemaConfig::new("Ark", " Mainnet ").schema_name().unwrap(),
+            "ark_mainnet"
+        );
+
+        assert!(SchemaConfig::new("ark", "").schema_name().is_err());
+        assert!(SchemaConfig::new("ark", "main-net").schema_name().is_err());
+        assert!(SchemaConfig::new("ark; DROP TABLE token", "mainnet")
+            .schema_name()
+            .is_err());
+    }
+}
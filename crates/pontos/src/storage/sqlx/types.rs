@@ -1,17 +1,34 @@
 //! Those types are decoupling the actual pontos
 //! storage types and the data annotations required
 //! for sqlx code generation.
+use crate::storage::types::{EventType, TokenEvent, TokenInfo};
+use ark_starknet::PaddedTokenId;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, sqlx::FromRow)]
 pub struct TokenData {
     pub contract_address: String,
     pub token_id: String,
-    pub token_id_hex: String,
+    #[sqlx(try_from = "String")]
+    pub token_id_hex: PaddedTokenId,
     pub owner: String,
     pub block_timestamp: i64,
     pub mint_address: Option<String>,
     pub mint_timestamp: Option<i64>,
     pub mint_transaction_hash: Option<String>,
+    pub network: Option<String>,
+}
+
+impl From<TokenData> for TokenInfo {
+    fn from(t: TokenData) -> Self {
+        TokenInfo {
+            contract_address: t.contract_address,
+            token_id: t.token_id,
+            token_id_hex: t.token_id_hex,
+            owner: t.owner,
+            network: t.network.unwrap_or_default(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, sqlx::FromRow)]
@@ -22,10 +39,34 @@ pub struct EventData {
     pub to_address: String,
     pub transaction_hash: String,
     pub token_id: String,
-    pub token_id_hex: String,
+    #[sqlx(try_from = "String")]
+    pub token_id_hex: PaddedTokenId,
     pub contract_type: String,
     pub event_type: String,
     pub event_id: String,
+    pub network: Option<String>,
+    pub transaction_url: Option<String>,
+}
+
+impl From<EventData> for TokenEvent {
+    fn from(e: EventData) -> Self {
+        TokenEvent {
+            timestamp: e.block_timestamp as u64,
+            from_address: e.from_address,
+            to_address: e.to_address,
+            contract_address: e.contract_address,
+            transaction_hash: e.transaction_hash,
+            token_id: e.token_id,
+            token_id_hex: e.token_id_hex,
+            contract_type: e.contract_type,
+            event_type: EventType::from_str(&e.event_type).unwrap_or(EventType::Uninitialized),
+            event_id: e.event_id,
+            block_number: None,
+            updated_at: None,
+            network: e.network.unwrap_or_default(),
+            transaction_url: e.transaction_url,
+        }
+    }
 }
 
 #[derive(Debug, Clone, sqlx::FromRow)]
@@ -37,6 +78,7 @@ pub struct BlockData {
     pub status: String,
     pub indexer_version: String,
     pub indexer_identifier: String,
+    pub block_hash: String,
 }
 
 #[derive(Debug, Clone, sqlx::FromRow)]
@@ -1,3 +1,4 @@
+use ark_starknet::PaddedTokenId;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::str::FromStr;
@@ -70,12 +71,18 @@ pub struct TokenEvent {
     pub contract_address: String,
     pub transaction_hash: String,
     pub token_id: String,
-    pub token_id_hex: String,
+    pub token_id_hex: PaddedTokenId,
     pub contract_type: String,
     pub event_type: EventType,
     pub event_id: String,
     pub block_number: Option<u64>,
     pub updated_at: Option<u64>,
+    /// Network of the event, like `mainnet` or `sepolia`.
+    #[serde(default)]
+    pub network: String,
+    /// Explorer URL of the transaction, if `PontosConfig::explorer_links` is set.
+    #[serde(default)]
+    pub transaction_url: Option<String>,
 }
 
 impl Default for TokenEvent {
@@ -87,12 +94,14 @@ impl Default for TokenEvent {
             contract_address: String::new(),
             transaction_hash: String::new(),
             token_id: String::new(),
-            token_id_hex: String::new(),
+            token_id_hex: PaddedTokenId::default(),
             contract_type: String::new(),
             event_type: EventType::Uninitialized,
             event_id: "0".to_string(),
             block_number: None,
             updated_at: None,
+            network: String::new(),
+            transaction_url: None,
         }
     }
 }
@@ -100,9 +109,38 @@ impl Default for TokenEvent {
 #[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 pub struct TokenInfo {
     pub contract_address: String,
+    /// Token id in decimal (`TokenIdFormat::Decimal`), to join with other datasets.
     pub token_id: String,
-    pub token_id_hex: String,
+    /// Token id padded to 64 hex characters (`TokenIdFormat::PaddedHex`),
+    /// the key of the token.
+    pub token_id_hex: PaddedTokenId,
     pub owner: String,
+    /// Network of the token, like `mainnet` or `sepolia`.
+    #[serde(default)]
+    pub network: String,
+}
+
+/// A page of the tokens of a collection, see `Storage::find_tokens`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TokenPage {
+    pub tokens: Vec<TokenInfo>,
+    /// Token id hex to start the next page after, `None` on the last page.
+    pub last_evaluated_key: Option<PaddedTokenId>,
+}
+
+/// Position of an event in the activity of a collection, most recent first.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ActivityKey {
+    pub timestamp: u64,
+    pub event_id: String,
+}
+
+/// A page of the activity of a collection, see `Storage::find_collection_activities`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ActivityPage {
+    pub events: Vec<TokenEvent>,
+    /// Event to start the next page after, `None` on the last page.
+    pub last_evaluated_key: Option<ActivityKey>,
 }
 
 #[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
@@ -113,7 +151,38 @@ pub struct TokenMintInfo {
     pub block_number: Option<u64>,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// Approval of a token, from an ERC-721 `Approval` event.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct TokenApprovalInfo {
+    pub owner: String,
+    /// Approved address, `None` when the approval is revoked.
+    pub approved: Option<String>,
+    pub timestamp: u64,
+    pub transaction_hash: String,
+    pub block_number: Option<u64>,
+}
+
+/// Approval of an operator for all the tokens of an owner,
+/// from an `ApprovalForAll` event.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct OperatorApprovalInfo {
+    pub owner: String,
+    pub operator: String,
+    pub approved: bool,
+    pub timestamp: u64,
+    pub transaction_hash: String,
+    pub block_number: Option<u64>,
+}
+
+/// ERC-2981 royalty of a token, or default royalty of a contract.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct RoyaltyInfo {
+    pub receiver: String,
+    /// Royalty in basis points of the sale price (`100` for 1%).
+    pub basis_points: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum BlockIndexingStatus {
     None,
@@ -174,12 +243,17 @@ pub struct BlockIndexing {
     pub indexer_version: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockInfo {
     pub indexer_version: String,
     pub indexer_identifier: String,
     pub status: BlockIndexingStatus,
     pub block_number: u64,
+    #[serde(default)]
+    pub block_timestamp: u64,
+    /// Hash of the block when indexed, used to detect chain reorganizations.
+    #[serde(default)]
+    pub block_hash: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
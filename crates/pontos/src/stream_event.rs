@@ -0,0 +1,243 @@
+//! Schema of the events published to an external stream (i.g. a Kinesis
+//! stream) by a `BatchedEventSink`, and read back by `run_stream_consumer`.
+//!
+//! The stream is consumed by other services: each record is a `StreamEvent`,
+//! a `TokenEvent` tagged with its `type` and the `schema_version` of its
+//! serialized shape, as JSON:
+//!
+//! ```json
+//! {"schema_version":1,"type":"minted","event_id":"0x1","network":"mainnet",...}
+//! ```
+//!
+//! The fields can only be added, as optional, without changing the schema
+//! version. Removing or renaming a field requires a new schema version.
+use crate::storage::types::{self, EventType};
+use ark_starknet::PaddedTokenId;
+use serde::{Deserialize, Serialize};
+
+/// Version of the serialized shape of the `StreamEvent`s.
+pub const STREAM_EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// A record of the stream, a `TokenEvent` and the version of its schema.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamEvent {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub event: TokenEvent,
+}
+
+/// An event of a token, published to the stream.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TokenEvent {
+    Minted(TokenTransfer),
+    Transferred(TokenTransfer),
+    Burned(TokenTransfer),
+    MetadataUpdated(TokenMetadataUpdate),
+}
+
+/// A mint, transfer or burn of a token.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct TokenTransfer {
+    pub event_id: String,
+    pub network: String,
+    pub contract_address: String,
+    pub contract_type: String,
+    pub token_id: String,
+    pub token_id_hex: PaddedTokenId,
+    pub from_address: String,
+    pub to_address: String,
+    pub transaction_hash: String,
+    pub block_number: Option<u64>,
+    /// Timestamp of the block of the event.
+    pub timestamp: u64,
+    /// Explorer URL of the transaction, if the indexer links an explorer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction_url: Option<String>,
+}
+
+/// A refresh of the metadata of a token.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct TokenMetadataUpdate {
+    pub network: String,
+    pub contract_address: String,
+    pub token_id: String,
+    pub token_id_hex: PaddedTokenId,
+    /// Timestamp of the refresh.
+    pub timestamp: u64,
+}
+
+impl StreamEvent {
+    /// Tags the event with the current schema version.
+    pub fn new(event: TokenEvent) -> Self {
+        StreamEvent {
+            schema_version: STREAM_EVENT_SCHEMA_VERSION,
+            event,
+        }
+    }
+
+    /// Returns the stream event of an event registered by Pontos,
+    /// or `None` if its type is not initialized.
+    pub fn from_registered_event(event: &types::TokenEvent) -> Option<Self> {
+        let transfer = TokenTransfer {
+            event_id: event.event_id.clone(),
+            network: event.network.clone(),
+            contract_address: event.contract_address.clone(),
+            contract_type: event.contract_type.clone(),
+            token_id: event.token_id.clone(),
+            token_id_hex: event.token_id_hex.clone(),
+            from_address: event.from_address.clone(),
+            to_address: event.to_address.clone(),
+            transaction_hash: event.transaction_hash.clone(),
+            block_number: event.block_number,
+            timestamp: event.timestamp,
+            transaction_url: event.transaction_url.clone(),
+        };
+
+        let event = match event.event_type {
+            EventType::Mint => TokenEvent::Minted(transfer),
+            EventType::Transfer => TokenEvent::Transferred(transfer),
+            EventType::Burn => TokenEvent::Burned(transfer),
+            EventType::Uninitialized => return None,
+        };
+
+        Some(StreamEvent::new(event))
+    }
+
+    /// Returns the event registered by Pontos described by this stream
+    /// event, or `None` for the metadata updates.
+    pub fn into_registered_event(self) -> Option<types::TokenEvent> {
+        let (event_type, transfer) = match self.event {
+            TokenEvent::Minted(transfer) => (EventType::Mint, transfer),
+            TokenEvent::Transferred(transfer) => (EventType::Transfer, transfer),
+            TokenEvent::Burned(transfer) => (EventType::Burn, transfer),
+            TokenEvent::MetadataUpdated(_) => return None,
+        };
+
+        Some(types::TokenEvent {
+            timestamp: transfer.timestamp,
+            from_address: transfer.from_address,
+            to_address: transfer.to_address,
+            contract_address: transfer.contract_address,
+            transaction_hash: transfer.transaction_hash,
+            token_id: transfer.token_id,
+            token_id_hex: transfer.token_id_hex,
+            contract_type: transfer.contract_type,
+            event_type,
+            event_id: transfer.event_id,
+            block_number: transfer.block_number,
+            updated_at: None,
+            network: transfer.network,
+            transaction_url: transfer.transaction_url,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_starknet::CairoU256;
+    use serde_json::json;
+
+    fn transfer() -> TokenTransfer {
+        TokenTransfer {
+            event_id: "0x1".to_string(),
+            network: "mainnet".to_string(),
+            contract_address: "0x2".to_string(),
+            contract_type: "ERC721".to_string(),
+            token_id: "3".to_string(),
+            token_id_hex: PaddedTokenId::from(CairoU256 { low: 3, high: 0 }),
+            from_address: "0x0".to_string(),
+            to_address: "0x4".to_string(),
+            transaction_hash: "0x5".to_string(),
+            block_number: Some(6),
+            timestamp: 7,
+            transaction_url: None,
+        }
+    }
+
+    #[test]
+    fn test_stream_event_schema() {
+        let event = StreamEvent::new(TokenEvent::Minted(transfer()));
+
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "schema_version": 1,
+                "type": "minted",
+                "event_id": "0x1",
+                "network": "mainnet",
+                "contract_address": "0x2",
+                "contract_type": "ERC721",
+                "token_id": "3",
+                "token_id_hex": CairoU256 { low: 3, high: 0 }.to_hex(),
+                "from_address": "0x0",
+                "to_address": "0x4",
+                "transaction_hash": "0x5",
+                "block_number": 6,
+                "timestamp": 7
+            })
+        );
+
+        let update = StreamEvent::new(TokenEvent::MetadataUpdated(TokenMetadataUpdate {
+            network: "mainnet".to_string(),
+            contract_address: "0x2".to_string(),
+            token_id: "3".to_string(),
+            token_id_hex: PaddedTokenId::from(CairoU256 { low: 3, high: 0 }),
+            timestamp: 8,
+        }));
+
+        assert_eq!(
+            serde_json::to_value(&update).unwrap()["type"],
+            json!("metadata_updated")
+        );
+
+        // The token ids which are not padded are rejected.
+        let mut unpadded = serde_json::to_value(&event).unwrap();
+        unpadded["token_id_hex"] = json!("0x3");
+        assert!(serde_json::from_value::<StreamEvent>(unpadded).is_err());
+    }
+
+    #[test]
+    fn test_stream_event_round_trip() {
+        let events = [
+            TokenEvent::Minted(transfer()),
+            TokenEvent::Transferred(transfer()),
+            TokenEvent::Burned(TokenTransfer {
+                block_number: None,
+                ..transfer()
+            }),
+            TokenEvent::MetadataUpdated(TokenMetadataUpdate::default()),
+        ];
+
+        for event in events {
+            let event = StreamEvent::new(event);
+            let serialized = serde_json::to_string(&event).unwrap();
+            assert_eq!(
+                serde_json::from_str::<StreamEvent>(&serialized).unwrap(),
+                event
+            );
+        }
+    }
+
+    #[test]
+    fn test_registered_event_round_trip() {
+        let registered = types::TokenEvent {
+            event_type: EventType::Transfer,
+            event_id: "0x1".to_string(),
+            token_id: "3".to_string(),
+            block_number: Some(6),
+            network: "mainnet".to_string(),
+            ..Default::default()
+        };
+
+        let event = StreamEvent::from_registered_event(&registered).unwrap();
+        assert!(matches!(event.event, TokenEvent::Transferred(_)));
+        assert_eq!(event.into_registered_event(), Some(registered));
+
+        assert_eq!(
+            StreamEvent::from_registered_event(&types::TokenEvent::default()),
+            None
+        );
+    }
+}
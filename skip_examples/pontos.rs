@@ -4,6 +4,8 @@
 //!
 use anyhow::Result;
 use ark_starknet::client::{StarknetClient, StarknetClientHttp};
+use ark_starknet::network::Network;
+use ark_starknet::PaddedTokenId;
 use arkproject::pontos::{
     event_handler::EventHandler, storage::types::*, storage::Storage, Pontos, PontosConfig,
 };
@@ -24,6 +26,8 @@ async fn main() -> Result<()> {
     let config = PontosConfig {
         indexer_version: String::from("0.0.1"),
         indexer_identifier: "task_1234".to_string(),
+        network: Network::Mainnet,
+        ..Default::default()
     };
 
     let pontos = Arc::new(Pontos::new(
@@ -120,7 +124,7 @@ impl Storage for DefaultStorage {
     async fn register_mint(
         &self,
         contract_address: &str,
-        token_id_hex: &str,
+        token_id_hex: &PaddedTokenId,
         info: &TokenMintInfo,
     ) -> Result<(), StorageError> {
         log::trace!(
@@ -141,6 +145,95 @@ impl Storage for DefaultStorage {
         Ok(())
     }
 
+    async fn update_token(&self, token: &TokenInfo) -> Result<(), StorageError> {
+        log::trace!("Updating token {:?}", token);
+        Ok(())
+    }
+
+    async fn get_token(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+    ) -> Result<Option<TokenInfo>, StorageError> {
+        log::trace!("Getting token {} {}", contract_address, token_id_hex);
+        Ok(None)
+    }
+
+    async fn find_tokens(
+        &self,
+        contract_address: &str,
+        _exclusive_start_key: Option<PaddedTokenId>,
+        _page_size: usize,
+    ) -> Result<TokenPage, StorageError> {
+        log::trace!("Finding tokens of {}", contract_address);
+        Ok(TokenPage::default())
+    }
+
+    async fn register_token_royalty(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+        info: &RoyaltyInfo,
+    ) -> Result<(), StorageError> {
+        log::trace!(
+            "Registering royalty {} {} {:?}",
+            contract_address,
+            token_id_hex,
+            info
+        );
+        Ok(())
+    }
+
+    async fn register_contract_royalty(
+        &self,
+        contract_address: &str,
+        info: &RoyaltyInfo,
+    ) -> Result<(), StorageError> {
+        log::trace!("Registering royalty {} {:?}", contract_address, info);
+        Ok(())
+    }
+
+    async fn register_contract_total_supply(
+        &self,
+        contract_address: &str,
+        total_supply: &str,
+    ) -> Result<(), StorageError> {
+        log::trace!(
+            "Registering total supply {} {}",
+            contract_address,
+            total_supply
+        );
+        Ok(())
+    }
+
+    async fn register_token_approval(
+        &self,
+        contract_address: &str,
+        token_id_hex: &PaddedTokenId,
+        info: &TokenApprovalInfo,
+    ) -> Result<(), StorageError> {
+        log::trace!(
+            "Registering approval {} {} {:?}",
+            contract_address,
+            token_id_hex,
+            info
+        );
+        Ok(())
+    }
+
+    async fn register_operator_approval(
+        &self,
+        contract_address: &str,
+        info: &OperatorApprovalInfo,
+    ) -> Result<(), StorageError> {
+        log::trace!(
+            "Registering operator approval {} {:?}",
+            contract_address,
+            info
+        );
+        Ok(())
+    }
+
     async fn register_event(
         &self,
         event: &TokenEvent,
@@ -150,6 +243,26 @@ impl Storage for DefaultStorage {
         Ok(())
     }
 
+    async fn register_events(
+        &self,
+        events: &[TokenEvent],
+        _block_timestamp: u64,
+    ) -> Result<(), StorageError> {
+        log::trace!("Registering {} events", events.len());
+        Ok(())
+    }
+
+    async fn find_collection_activities(
+        &self,
+        contract_address: &str,
+        _since_timestamp: u64,
+        _exclusive_start_key: Option<ActivityKey>,
+        _page_size: usize,
+    ) -> Result<ActivityPage, StorageError> {
+        log::trace!("Finding activities of {}", contract_address);
+        Ok(ActivityPage::default())
+    }
+
     async fn get_contract_type(
         &self,
         contract_address: &str,
@@ -188,9 +301,19 @@ impl Storage for DefaultStorage {
             indexer_identifier: String::from("v0"),
             status: BlockIndexingStatus::None,
             block_number,
+            block_timestamp: 0,
+            block_hash: None,
         })
     }
 
+    async fn find_block_events(
+        &self,
+        block_timestamp: u64,
+    ) -> Result<Vec<TokenEvent>, StorageError> {
+        log::trace!("Finding events of block [ts: {}]", block_timestamp);
+        Ok(vec![])
+    }
+
     async fn clean_block(
         &self,
         _block_timestamp: u64,
@@ -199,4 +322,25 @@ impl Storage for DefaultStorage {
         log::trace!("Cleaning block #{:?}", block_number);
         Ok(())
     }
+
+    async fn get_last_processed_block(
+        &self,
+        indexer_identifier: &str,
+    ) -> Result<Option<u64>, StorageError> {
+        log::trace!("Getting last processed block of {}", indexer_identifier);
+        Ok(None)
+    }
+
+    async fn set_last_processed_block(
+        &self,
+        indexer_identifier: &str,
+        block_number: u64,
+    ) -> Result<(), StorageError> {
+        log::trace!(
+            "Setting last processed block of {} to #{}",
+            indexer_identifier,
+            block_number
+        );
+        Ok(())
+    }
 }
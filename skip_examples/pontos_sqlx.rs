@@ -4,30 +4,24 @@
 //!
 use anyhow::Result;
 use ark_starknet::client::{StarknetClient, StarknetClientHttp};
+use ark_starknet::network::Network;
 use arkproject::pontos::{
-    event_handler::EventHandler, storage::types::*, storage::DefaultSqlxStorage, Pontos,
-    PontosConfig,
+    event_handler::EventHandler,
+    logging::{init_logging, LogFormat},
+    storage::types::*,
+    storage::DefaultSqlxStorage,
+    Pontos, PontosConfig,
 };
 use async_trait::async_trait;
 use starknet::core::types::BlockId;
 use std::sync::Arc;
 
-use tracing_subscriber::{fmt, layer::SubscriberExt, EnvFilter, Registry};
-
 #[tokio::main]
 async fn main() -> Result<()> {
     sqlx::any::install_default_drivers();
 
-    tracing_log::LogTracer::init().expect("Setting log tracer failed.");
-    // Create the layers
-    let env_filter = EnvFilter::from_default_env();
-    let fmt_layer = fmt::layer();
-
-    // Combine layers and set as global default
-    let subscriber = Registry::default().with(env_filter).with(fmt_layer);
-
-    tracing::subscriber::set_global_default(subscriber)
-        .expect("Setting default subscriber failed.");
+    // Set `PONTOS_LOG_FORMAT=json` for structured logs.
+    init_logging(LogFormat::from_env()).expect("Setting logging failed.");
 
     let client = Arc::new(
         StarknetClientHttp::new(
@@ -40,6 +34,8 @@ async fn main() -> Result<()> {
     let config = PontosConfig {
         indexer_version: String::from("0.0.1"),
         indexer_identifier: "task_1234".to_string(),
+        network: Network::Mainnet,
+        ..Default::default()
     };
 
     let storage = Arc::new(DefaultSqlxStorage::new_any("sqlite::memory:").await?);
@@ -55,9 +51,16 @@ async fn main() -> Result<()> {
         config,
     ));
 
-    let from = BlockId::Number(885_172);
+    // Resumes after the last block processed by this indexer, if any.
+    let from = match pontos.last_processed_block().await {
+        Ok(Some(block_number)) => BlockId::Number(block_number + 1),
+        _ => BlockId::Number(885_172),
+    };
     let to = BlockId::Number(885_180);
     let do_force = false;
+
+    // On SIGINT/SIGTERM, the block being processed is finished before exiting.
+    arkproject::pontos::shutdown::cancel_on_signal(pontos.shutdown_token());
     println!("Indexer [{:?} - {:?}] started!", from, to);
 
     match pontos.index_block_range(from, to, do_force).await {
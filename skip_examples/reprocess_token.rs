@@ -0,0 +1,234 @@
+//! How to reprocess a single token, without replaying its events.
+//!
+//! Its owner is re-resolved on-chain, and its metadata are refetched.
+//! The normalized metadata before and after the reprocessing are printed.
+//!
+//! Can be run with `cargo run --example reprocess_token -- <collection> <token_id>`,
+//! the token id being decimal or hexadecimal (`0x` prefixed).
+//!
+use anyhow::{anyhow, Result};
+use ark_starknet::client::{StarknetClient, StarknetClientHttp};
+use ark_starknet::network::Network;
+use ark_starknet::CairoU256;
+use arkproject::metadata::{
+    file_manager::LocalFileManager,
+    metadata_manager::{ImageCacheOption, MetadataManager},
+    storage::Storage as MetadataStorage,
+    types::{
+        CollectionMetadata, CollectionSpam, StorageError as MetadataStorageError, TokenIdsPage,
+        TokenMetadata, TokenRarity,
+    },
+};
+use arkproject::pontos::{
+    event_handler::EventHandler, storage::DefaultSqlxStorage, Pontos, PontosConfig,
+};
+use async_trait::async_trait;
+use starknet::core::types::FieldElement;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    sqlx::any::install_default_drivers();
+
+    let args: Vec<String> = std::env::args().collect();
+    let (collection, token_id) = match args.as_slice() {
+        [_, collection, token_id] => (collection, token_id),
+        _ => return Err(anyhow!("Usage: reprocess_token <collection> <token_id>")),
+    };
+
+    let contract_address = FieldElement::from_hex_be(collection)?;
+    let token_id = if token_id.starts_with("0x") {
+        CairoU256::from_hex_be(token_id)?
+    } else {
+        CairoU256 {
+            low: token_id.parse()?,
+            high: 0,
+        }
+    };
+
+    let client = Arc::new(
+        StarknetClientHttp::new(
+            "https://starknet-goerli.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
+        )
+        .unwrap(),
+    );
+
+    // Typically the database of your indexer.
+    let storage = DefaultSqlxStorage::new_any("sqlite::memory:").await?;
+    sqlx::migrate!("./crates/pontos/src/storage/sqlx/migrations")
+        .run(storage.get_pool_ref())
+        .await?;
+
+    let config = PontosConfig {
+        indexer_version: String::from("0.0.1"),
+        indexer_identifier: "reprocess_token".to_string(),
+        network: Network::Mainnet,
+        ..Default::default()
+    };
+
+    let pontos = Pontos::new(
+        Arc::clone(&client),
+        Arc::new(storage),
+        Arc::new(DefaultEventHandler),
+        config,
+    );
+
+    match pontos.reprocess_token(contract_address, &token_id).await {
+        Ok(token) => println!("Owner: {}", token.owner),
+        Err(e) => println!("Failed to update the owner: {}", e),
+    }
+
+    let metadata_storage = DefaultMetadataStorage::default();
+    let file_manager = LocalFileManager;
+    let mut metadata_manager =
+        MetadataManager::new(&metadata_storage, client.as_ref(), &file_manager)?;
+
+    let reprocessing = metadata_manager
+        .reprocess_token_metadata(
+            contract_address,
+            token_id,
+            ImageCacheOption::DoNotSave,
+            "https://ipfs.io/ipfs/",
+            Duration::from_secs(10),
+            "https://arkproject.dev",
+        )
+        .await?;
+
+    println!("Metadata ({:?})", reprocessing.status);
+    println!("Before: {:#?}", reprocessing.before);
+    println!("After: {:#?}", reprocessing.after);
+
+    Ok(())
+}
+
+// Default event handler.
+struct DefaultEventHandler;
+
+#[async_trait]
+impl EventHandler for DefaultEventHandler {}
+
+// Default metadata storage, only keeping the last registered metadata.
+#[derive(Default)]
+struct DefaultMetadataStorage {
+    metadata: Mutex<Option<TokenMetadata>>,
+}
+
+#[async_trait]
+impl MetadataStorage for DefaultMetadataStorage {
+    async fn register_token_metadata(
+        &self,
+        _contract_address: &FieldElement,
+        _token_id: CairoU256,
+        token_metadata: TokenMetadata,
+    ) -> Result<(), MetadataStorageError> {
+        *self.metadata.lock().unwrap() = Some(token_metadata);
+        Ok(())
+    }
+
+    async fn get_token_metadata_hash(
+        &self,
+        _contract_address: FieldElement,
+        _token_id: CairoU256,
+    ) -> Result<Option<String>, MetadataStorageError> {
+        Ok(None)
+    }
+
+    async fn get_token_metadata(
+        &self,
+        _contract_address: FieldElement,
+        _token_id: CairoU256,
+    ) -> Result<Option<TokenMetadata>, MetadataStorageError> {
+        Ok(self.metadata.lock().unwrap().clone())
+    }
+
+    async fn has_token_metadata(
+        &self,
+        _contract_address: FieldElement,
+        _token_id: CairoU256,
+    ) -> Result<bool, MetadataStorageError> {
+        Ok(self.metadata.lock().unwrap().is_some())
+    }
+
+    async fn find_token_ids_without_metadata(
+        &self,
+        _contract_address_filter: Option<FieldElement>,
+    ) -> Result<Vec<(FieldElement, CairoU256)>, MetadataStorageError> {
+        Ok(vec![])
+    }
+
+    async fn find_token_ids(
+        &self,
+        _contract_address: FieldElement,
+        _exclusive_start_key: Option<CairoU256>,
+        _page_size: usize,
+    ) -> Result<TokenIdsPage, MetadataStorageError> {
+        Ok(TokenIdsPage::default())
+    }
+
+    async fn get_reindex_checkpoint(
+        &self,
+        _contract_address: FieldElement,
+        _job_id: &str,
+    ) -> Result<Option<CairoU256>, MetadataStorageError> {
+        Ok(None)
+    }
+
+    async fn set_reindex_checkpoint(
+        &self,
+        _contract_address: FieldElement,
+        _job_id: &str,
+        _token_id: CairoU256,
+    ) -> Result<(), MetadataStorageError> {
+        Ok(())
+    }
+
+    async fn delete_reindex_checkpoint(
+        &self,
+        _contract_address: FieldElement,
+        _job_id: &str,
+    ) -> Result<(), MetadataStorageError> {
+        Ok(())
+    }
+
+    async fn get_collection_metadata(
+        &self,
+        _contract_address: FieldElement,
+    ) -> Result<Option<CollectionMetadata>, MetadataStorageError> {
+        Ok(None)
+    }
+
+    async fn register_collection_metadata(
+        &self,
+        _contract_address: FieldElement,
+        _collection_metadata: CollectionMetadata,
+    ) -> Result<(), MetadataStorageError> {
+        Ok(())
+    }
+
+    async fn register_token_rarity(
+        &self,
+        _contract_address: FieldElement,
+        _token_id: CairoU256,
+        _rarity: TokenRarity,
+    ) -> Result<(), MetadataStorageError> {
+        Ok(())
+    }
+
+    async fn register_collection_spam(
+        &self,
+        _contract_address: FieldElement,
+        _spam: CollectionSpam,
+    ) -> Result<(), MetadataStorageError> {
+        Ok(())
+    }
+
+    async fn update_token_metadata_status(
+        &self,
+        _contract_address: FieldElement,
+        _token_id: CairoU256,
+        _metadata_status: &str,
+    ) -> Result<(), MetadataStorageError> {
+        Ok(())
+    }
+}